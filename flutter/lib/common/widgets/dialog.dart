@@ -1,4 +1,5 @@
 import 'dart:async';
+import 'dart:convert';
 import 'dart:io';
 
 import 'package:flutter/material.dart';
@@ -1013,6 +1014,35 @@ void showRestartRemoteDevice(PeerInfo pi, String id, SessionID sessionId,
   if (res == true) bind.sessionRestartRemoteDevice(sessionId: sessionId);
 }
 
+void showInputControlRequest(SessionID sessionId, int connId, String requester,
+    OverlayDialogManager dialogManager) async {
+  final res = await dialogManager.show<bool>(
+      (setState, close, context) => CustomAlertDialog(
+            title: Text(translate("Control requested")),
+            content:
+                Text("$requester ${translate('input_control_request_tip')}"),
+            actions: [
+              dialogButton(
+                "Dismiss",
+                icon: Icon(Icons.close_rounded),
+                onPressed: close,
+                isOutline: true,
+              ),
+              dialogButton(
+                "Grant control",
+                icon: Icon(Icons.done_rounded),
+                onPressed: () => close(true),
+              ),
+            ],
+            onCancel: close,
+            onSubmit: () => close(true),
+          ),
+      tag: 'input-control-request-$connId');
+  if (res == true) {
+    bind.sessionGrantInputControl(sessionId: sessionId, connId: connId);
+  }
+}
+
 showSetOSPassword(
   SessionID sessionId,
   bool login,
@@ -1501,3 +1531,231 @@ void renameDialog(
     );
   });
 }
+
+void showSaveMacroDialog(FFI ffi) {
+  final controller = TextEditingController();
+  var errorText = '';
+  ffi.dialogManager.show((setState, close, context) {
+    submit() async {
+      final err = await bind.sessionStopMacroRecording(
+          sessionId: ffi.sessionId, name: controller.text.trim());
+      if (err.isNotEmpty) {
+        setState(() => errorText = err);
+        return;
+      }
+      close();
+    }
+
+    return CustomAlertDialog(
+      title: Text(translate('Stop macro recording')),
+      content: DialogTextField(
+        title: translate('Name'),
+        controller: controller,
+        errorText: errorText.isEmpty ? null : translate(errorText),
+      ),
+      actions: [
+        dialogButton('Cancel', onPressed: close, isOutline: true),
+        dialogButton('OK', onPressed: submit),
+      ],
+      onSubmit: submit,
+      onCancel: close,
+    );
+  });
+}
+
+void showMacrosDialog(FFI ffi) {
+  final names = List<String>.from(jsonDecode(bind.mainGetMacros()));
+  var speed = 1.0;
+  var errorText = '';
+  ffi.dialogManager.show((setState, close, context) {
+    play(String name) async {
+      final err = await bind.sessionPlayMacro(
+          sessionId: ffi.sessionId, name: name, speed: speed);
+      if (err.isNotEmpty) {
+        setState(() => errorText = err);
+        return;
+      }
+      close();
+    }
+
+    return CustomAlertDialog(
+      title: Text(translate('Macros')),
+      content: Column(
+        mainAxisSize: MainAxisSize.min,
+        crossAxisAlignment: CrossAxisAlignment.start,
+        children: [
+          Row(children: [
+            Text(translate('Speed')),
+            const SizedBox(width: 8),
+            DropdownButton<double>(
+              value: speed,
+              items: [0.5, 1.0, 2.0, 4.0]
+                  .map((e) =>
+                      DropdownMenuItem(value: e, child: Text('${e}x')))
+                  .toList(),
+              onChanged: (v) => setState(() => speed = v ?? 1.0),
+            ),
+          ]),
+          if (names.isEmpty) Text(translate('No macros')),
+          ...names.map((name) => Row(children: [
+                Expanded(child: Text(name)),
+                IconButton(
+                    icon: const Icon(Icons.play_arrow),
+                    tooltip: translate('Play macro'),
+                    onPressed: () => play(name)),
+                IconButton(
+                    icon: const Icon(Icons.edit),
+                    tooltip: translate('Edit'),
+                    onPressed: () {
+                      close();
+                      showEditMacroDialog(name, ffi.dialogManager);
+                    }),
+              ])),
+          if (errorText.isNotEmpty)
+            Text(translate(errorText),
+                style: TextStyle(color: Theme.of(context).colorScheme.error)),
+        ],
+      ),
+      actions: [dialogButton('Close', onPressed: close, isOutline: true)],
+      onCancel: close,
+    );
+  });
+}
+
+void showEditMacroDialog(
+    String name, OverlayDialogManager dialogManager) async {
+  final controller =
+      TextEditingController(text: await bind.mainGetMacro(name: name));
+  var errorText = '';
+  dialogManager.show((setState, close, context) {
+    submit() async {
+      // an empty macro is removed
+      final err = await bind.mainSetMacro(name: name, text: controller.text);
+      if (err.isNotEmpty) {
+        setState(() => errorText = err);
+        return;
+      }
+      close();
+    }
+
+    return CustomAlertDialog(
+      title: Text(name),
+      content: SizedBox(
+          width: 480,
+          height: 320,
+          child: TextField(
+            keyboardType: TextInputType.multiline,
+            maxLines: null,
+            expands: true,
+            style: const TextStyle(fontFamily: 'monospace', fontSize: 12),
+            decoration: InputDecoration(
+                errorText: errorText.isEmpty ? null : errorText),
+            controller: controller,
+          )),
+      actions: [
+        dialogButton('Cancel', onPressed: close, isOutline: true),
+        dialogButton('OK', onPressed: submit),
+      ],
+      onCancel: close,
+    );
+  });
+}
+
+void showTypeClipboardDialog(FFI ffi) async {
+  final sessionId = ffi.sessionId;
+  final oldDelay =
+      await bind.sessionGetOption(sessionId: sessionId, arg: 'type-delay') ??
+          '';
+  final controller =
+      TextEditingController(text: oldDelay.isEmpty ? '20' : oldDelay);
+  var errorText = '';
+  ffi.dialogManager.show((setState, close, context) {
+    submit() async {
+      final delay = controller.text.trim();
+      if (int.tryParse(delay) == null) {
+        setState(() => errorText = 'Invalid format');
+        return;
+      }
+      if (delay != oldDelay) {
+        await bind.sessionPeerOption(
+            sessionId: sessionId, name: 'type-delay', value: delay);
+      }
+      if (!await bind.sessionTypeClipboard(sessionId: sessionId)) {
+        setState(() => errorText = 'Clipboard is empty');
+        return;
+      }
+      close();
+    }
+
+    return CustomAlertDialog(
+      title: Text(translate('Type clipboard')),
+      content: Column(
+        mainAxisSize: MainAxisSize.min,
+        crossAxisAlignment: CrossAxisAlignment.start,
+        children: [
+          Text(translate('type_clipboard_tip')),
+          const SizedBox(height: 8),
+          DialogTextField(
+            title: translate('Delay between characters (ms)'),
+            controller: controller,
+            errorText: errorText.isEmpty ? null : translate(errorText),
+          ),
+        ],
+      ),
+      actions: [
+        dialogButton('Cancel', onPressed: close, isOutline: true),
+        dialogButton('OK', onPressed: submit),
+      ],
+      onSubmit: submit,
+      onCancel: close,
+    );
+  });
+}
+
+void showKeyMappingsDialog(FFI ffi) async {
+  final sessionId = ffi.sessionId;
+  final controller = TextEditingController(
+      text: await bind.sessionGetKeyMappings(sessionId: sessionId));
+  var errorText = '';
+  ffi.dialogManager.show((setState, close, context) {
+    submit() async {
+      final err = await bind.sessionSetKeyMappings(
+          sessionId: sessionId, value: controller.text);
+      if (err.isNotEmpty) {
+        setState(() => errorText = err);
+        return;
+      }
+      close();
+    }
+
+    return CustomAlertDialog(
+      title: Text(translate('Key mappings')),
+      content: Column(
+        mainAxisSize: MainAxisSize.min,
+        crossAxisAlignment: CrossAxisAlignment.start,
+        children: [
+          Text(translate('key_mappings_tip')),
+          const SizedBox(height: 8),
+          SizedBox(
+              width: 480,
+              height: 240,
+              child: TextField(
+                keyboardType: TextInputType.multiline,
+                maxLines: null,
+                expands: true,
+                style: const TextStyle(fontFamily: 'monospace', fontSize: 12),
+                decoration: InputDecoration(
+                    hintText: 'CapsLock = Escape',
+                    errorText: errorText.isEmpty ? null : errorText),
+                controller: controller,
+              )),
+        ],
+      ),
+      actions: [
+        dialogButton('Cancel', onPressed: close, isOutline: true),
+        dialogButton('OK', onPressed: submit),
+      ],
+      onCancel: close,
+    );
+  });
+}
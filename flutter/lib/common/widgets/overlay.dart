@@ -499,6 +499,8 @@ class QualityMonitor extends StatelessWidget {
                       _row(
                           "Codec", qualityMonitorModel.data.codecFormat ?? '-'),
                       _row("Chroma", qualityMonitorModel.data.chroma ?? '-'),
+                      _row("Audio Buffer",
+                          "${qualityMonitorModel.data.audioBuffer ?? '-'}ms"),
                     ],
                   ),
                 )
@@ -411,7 +411,10 @@ class RawPointerMouseRegion extends StatelessWidget {
       child: MouseRegion(
         cursor: cursor ?? MouseCursor.defer,
         onEnter: onEnter,
-        onExit: onExit,
+        onExit: (evt) {
+          inputModel.onPointExitImage(evt);
+          onExit?.call(evt);
+        },
         child: child,
       ),
     );
@@ -78,6 +78,27 @@ List<TTextMenu> toolbarControls(BuildContext context, String id, FFI ffi) {
   final sessionId = ffi.sessionId;
 
   List<TTextMenu> v = [];
+  // input control token
+  if (perms['keyboard'] != false && ffiModel.inputControlEnabled) {
+    if (ffiModel.inputControlHolding) {
+      v.add(
+        TTextMenu(
+            child: Text(translate('Release control')),
+            onPressed: () => bind.sessionRequestInputControl(
+                sessionId: sessionId, request: false)),
+      );
+    } else if (!ffiModel.inputControlRequesting) {
+      final holder = ffiModel.inputControlHolder;
+      v.add(
+        TTextMenu(
+            child: Text(holder.isEmpty
+                ? translate('Request control')
+                : '${translate('Request control')} ($holder)'),
+            onPressed: () => bind.sessionRequestInputControl(
+                sessionId: sessionId, request: true)),
+      );
+    }
+  }
   // elevation
   if (perms['keyboard'] != false && ffi.elevationModel.showRequestMenu) {
     v.add(
@@ -210,6 +231,34 @@ List<TTextMenu> toolbarControls(BuildContext context, String id, FFI ffi) {
           blockInput.value = !blockInput.value;
         }));
   }
+  // macros
+  if (isDesktop && ffiModel.keyboard && perms['keyboard'] != false) {
+    final macroState = bind.sessionGetMacroState(sessionId: sessionId);
+    v.add(TTextMenu(
+        child: Text(translate(macroState == 'recording'
+            ? 'Stop macro recording'
+            : 'Record macro')),
+        onPressed: () {
+          if (macroState == 'recording') {
+            showSaveMacroDialog(ffi);
+          } else {
+            bind.sessionStartMacroRecording(sessionId: sessionId);
+          }
+        }));
+    v.add(TTextMenu(
+        child: Text(
+            translate(macroState == 'playing' ? 'Stop macro' : 'Macros')),
+        onPressed: () {
+          if (macroState == 'playing') {
+            bind.sessionStopMacro(sessionId: sessionId);
+          } else {
+            showMacrosDialog(ffi);
+          }
+        }));
+    v.add(TTextMenu(
+        child: Text(translate('Type clipboard')),
+        onPressed: () => showTypeClipboardDialog(ffi)));
+  }
   // switchSides
   if (isDesktop &&
       ffiModel.keyboard &&
@@ -372,6 +421,60 @@ Future<List<TRadioMenu<String>>> toolbarCodec(
   ];
 }
 
+Future<List<TRadioMenu<String>>> toolbarStreamResolution(
+    BuildContext context, String id, FFI ffi) async {
+  final sessionId = ffi.sessionId;
+  final groupValue = await bind.sessionGetOption(
+          sessionId: sessionId, arg: 'stream-resolution') ??
+      '';
+  onChanged(String? value) async {
+    if (value == null) return;
+    await bind.sessionSetStreamResolution(sessionId: sessionId, value: value);
+  }
+
+  TRadioMenu<String> radio(String label, String value) {
+    return TRadioMenu<String>(
+        child: Text(translate(label)),
+        value: value,
+        groupValue: groupValue,
+        onChanged: onChanged);
+  }
+
+  return [
+    radio('Native resolution', ''),
+    radio('1920 x 1080', '1920x1080'),
+    radio('1280 x 720', '1280x720'),
+  ];
+}
+
+Future<List<TRadioMenu<String>>> toolbarAudioMode(
+    BuildContext context, String id, FFI ffi) async {
+  final sessionId = ffi.sessionId;
+  var groupValue =
+      await bind.sessionGetOption(sessionId: sessionId, arg: 'audio-mode') ??
+          '';
+  if (groupValue.isEmpty) groupValue = 'low-delay';
+  onChanged(String? value) async {
+    if (value == null) return;
+    await bind.sessionSetAudioQuality(
+        sessionId: sessionId, option: 'audio-mode', value: value);
+  }
+
+  TRadioMenu<String> radio(String label, String value) {
+    return TRadioMenu<String>(
+        child: Text(translate(label)),
+        value: value,
+        groupValue: groupValue,
+        onChanged: onChanged);
+  }
+
+  return [
+    radio('Low latency audio', 'low-delay'),
+    radio('Voice audio', 'voice'),
+    radio('Music audio', 'music'),
+  ];
+}
+
 Future<List<TToggleMenu>> toolbarDisplayToggle(
     BuildContext context, String id, FFI ffi) async {
   List<TToggleMenu> v = [];
@@ -527,6 +630,20 @@ Future<List<TToggleMenu>> toolbarDisplayToggle(
         child: Text(translate('Use all my displays for the remote session'))));
   }
 
+  // text mode
+  {
+    final option = 'text-mode';
+    final value =
+        bind.sessionGetToggleOptionSync(sessionId: sessionId, arg: option);
+    v.add(TToggleMenu(
+        value: value,
+        onChanged: (value) {
+          if (value == null) return;
+          bind.sessionToggleOption(sessionId: sessionId, value: option);
+        },
+        child: Text(translate('Sharp text when idle'))));
+  }
+
   // 444
   final codec_format = ffi.qualityMonitorModel.data.codecFormat;
   if (versionCmp(pi.version, "1.2.4") >= 0 &&
@@ -686,5 +803,24 @@ List<TToggleMenu> toolbarKeyboardToggles(FFI ffi) {
         onChanged: enabled ? onChanged : null,
         child: Text(translate('swap-left-right-mouse'))));
   }
+
+  // forward gamepads
+  if (ffiModel.keyboard &&
+      bind.mainHasGamepad() &&
+      pi.platform == kPeerPlatformLinux) {
+    final option = 'forward-gamepad';
+    final value =
+        bind.sessionGetToggleOptionSync(sessionId: sessionId, arg: option);
+    onChanged(bool? value) {
+      if (value == null) return;
+      bind.sessionToggleOption(sessionId: sessionId, value: option);
+    }
+
+    final enabled = !ffi.ffiModel.viewOnly;
+    v.add(TToggleMenu(
+        value: value,
+        onChanged: enabled ? onChanged : null,
+        child: Text(translate('Forward gamepads'))));
+  }
   return v;
 }
@@ -80,6 +80,11 @@ const int kWindowMainId = 0;
 
 const String kPointerEventKindTouch = "touch";
 const String kPointerEventKindMouse = "mouse";
+const String kPointerEventKindPen = "pen";
+
+// The bits of the barrel buttons of a pen, see `PEN_BUTTON_*` in Rust.
+const int kPenButtonPrimary = 0x01;
+const int kPenButtonSecondary = 0x02;
 
 const String kKeyShowDisplaysAsIndividualWindows =
     'displays_as_individual_windows';
@@ -343,6 +343,10 @@ class _GeneralState extends State<_General> {
       'enable-check-update',
       isServer: false,
     ));
+    if (Platform.isLinux) {
+      children.add(_OptionTextField(context, 'Share a window only',
+          'capture-window', 'capture_window_tip'));
+    }
     if (bind.mainShowOption(key: 'allow-linux-headless')) {
       children.add(_OptionCheckBox(
           context, 'Allow linux headless', 'allow-linux-headless'));
@@ -436,7 +440,16 @@ class _GeneralState extends State<_General> {
                 label: device, onChanged: (value) {
               setDevice(value);
               setState(() {});
-            }))
+            })),
+        if (Platform.isLinux)
+          _OptionCheckBox(context, 'Use voice calls as a microphone',
+              'allow-virtual-microphone'),
+        if (Platform.isLinux)
+          _OptionTextField(
+              context,
+              'Share the audio of some applications only',
+              'capture-apps',
+              'capture_apps_tip'),
       ]);
     });
   }
@@ -459,6 +472,9 @@ class _GeneralState extends State<_General> {
       return _Card(title: 'Recording', children: [
         _OptionCheckBox(context, 'Automatically record incoming sessions',
             'allow-auto-record-incoming'),
+        _OptionCheckBox(
+            context, 'Record the session events', 'allow-record-events'),
+        _OptionCheckBox(context, 'Sign the recordings', 'allow-record-signing'),
         Row(
           children: [
             Text('${translate("Directory")}:'),
@@ -657,6 +673,8 @@ class _SafetyState extends State<_Safety> with AutomaticKeepAliveClientMixin {
                 enabled: enabled, fakeValue: fakeValue),
           ],
         ),
+        _OptionCheckBox(context, 'Only one controller at a time',
+            'allow-exclusive-input-control', enabled: enabled),
       ]);
     }
 
@@ -1795,6 +1813,47 @@ Widget _SubLabeledWidget(BuildContext context, String label, Widget child,
   ).marginOnly(left: _kContentHSubMargin);
 }
 
+// ignore: non_constant_identifier_names
+Widget _OptionTextField(
+    BuildContext context, String label, String key, String tip) {
+  TextEditingController controller =
+      TextEditingController(text: bind.mainGetOptionSync(key: key));
+  RxBool applyEnabled = false.obs;
+  return Tooltip(
+    message: translate(tip),
+    child: _SubLabeledWidget(
+      context,
+      label,
+      Expanded(
+        child: Row(children: [
+          Expanded(
+            child: TextField(
+              controller: controller,
+              onChanged: (_) => applyEnabled.value = true,
+              decoration: const InputDecoration(
+                contentPadding:
+                    EdgeInsets.symmetric(vertical: 12, horizontal: 12),
+              ),
+            ).marginOnly(right: 15),
+          ),
+          Obx(() => ElevatedButton(
+                onPressed: applyEnabled.value
+                    ? () async {
+                        applyEnabled.value = false;
+                        await bind.mainSetOption(
+                            key: key, value: controller.text.trim());
+                      }
+                    : null,
+                child: Text(
+                  translate('Apply'),
+                ),
+              ))
+        ]),
+      ),
+    ),
+  );
+}
+
 Widget _lock(
   bool locked,
   String label,
@@ -646,6 +646,11 @@ class _ImagePaintState extends State<ImagePaint> {
     return CustomPaint(
       size: imageSize,
       painter: ImagePainter(image: m.image, x: 0, y: 0, scale: s),
+      foregroundPainter: CanvasLayoutPainter(
+          regions: m.parent.target!.ffiModel.canvasRegions,
+          x: 0,
+          y: 0,
+          scale: s),
     );
   }
 
@@ -654,6 +659,11 @@ class _ImagePaintState extends State<ImagePaint> {
     return CustomPaint(
       size: Size(c.size.width, c.size.height),
       painter: ImagePainter(image: m.image, x: c.x / s, y: c.y / s, scale: s),
+      foregroundPainter: CanvasLayoutPainter(
+          regions: c.parent.target!.ffiModel.canvasRegions,
+          x: c.x / s,
+          y: c.y / s,
+          scale: s),
     );
   }
 
@@ -685,6 +695,13 @@ class _ImagePaintState extends State<ImagePaint> {
         ));
       }
     }
+    children.add(Positioned.fill(
+        child: CustomPaint(
+            painter: CanvasLayoutPainter(
+                regions: ffiModel.canvasRegions,
+                x: offset.dx / s,
+                y: offset.dy / s,
+                scale: s))));
     return SizedBox(
       width: size.width,
       height: size.height,
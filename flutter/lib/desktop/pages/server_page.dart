@@ -473,7 +473,15 @@ class _CmHeaderState extends State<_CmHeader>
                         ),
                       )
                   ],
-                ))
+                )),
+                if (client.inputControl && !client.disconnected)
+                  FittedBox(
+                    child: Text(
+                      translate("Controlling"),
+                      style: TextStyle(
+                          color: Colors.white, fontWeight: FontWeight.bold),
+                    ),
+                  ).marginOnly(top: 4.0),
               ],
             ),
           ),
@@ -752,6 +760,19 @@ class _CmControlPanel extends StatelessWidget {
             ],
           ),
         ),
+        Offstage(
+          offstage: !client.inputControlRequested,
+          child: buildButton(context,
+              color: MyTheme.accent,
+              onClick: () => handleGrantInputControl(),
+              icon: Icon(
+                Icons.mouse_rounded,
+                color: Colors.white,
+                size: 14,
+              ),
+              text: "Grant control",
+              textColor: Colors.white),
+        ),
         Offstage(
           offstage: !client.fromSwitch,
           child: buildButton(context,
@@ -958,6 +979,10 @@ class _CmControlPanel extends StatelessWidget {
   void closeVoiceCall() {
     bind.cmCloseVoiceCall(id: client.id);
   }
+
+  void handleGrantInputControl() {
+    bind.cmGrantInputControl(connId: client.id);
+  }
 }
 
 void checkClickTime(int id, Function() callback) async {
@@ -1,9 +1,11 @@
 import 'dart:convert';
 import 'dart:async';
 import 'dart:io';
+import 'dart:typed_data';
 
 import 'package:flutter/material.dart';
 import 'package:flutter/services.dart';
+import 'package:flutter_hbb/common/widgets/dialog.dart';
 import 'package:flutter_hbb/common/widgets/toolbar.dart';
 import 'package:flutter_hbb/models/chat_model.dart';
 import 'package:flutter_hbb/models/state_model.dart';
@@ -651,10 +653,32 @@ class _MonitorMenu extends StatelessWidget {
         Row(children: buildMonitorList(false)),
         supportIndividualWindows ? Divider() : Offstage(),
         supportIndividualWindows ? chooseDisplayBehavior() : Offstage(),
+        ffi.ffiModel.pi.displays.length > 1 ? chooseCanvas() : Offstage(),
       ],
     );
   }
 
+  Widget chooseCanvas() {
+    final pi = ffi.ffiModel.pi;
+    return CkbMenuButton(
+        value: ffi.ffiModel.isCanvas,
+        onChanged: (value) {
+          if (value == null) return;
+          if (value) {
+            bind.sessionCaptureDisplaysCanvas(
+                sessionId: ffi.sessionId,
+                displays: Int32List.fromList(
+                    List.generate(pi.displays.length, (i) => i)));
+            // the canvas is streamed as the first display
+            ffi.ffiModel.switchToNewDisplay(0, ffi.sessionId, ffi.id);
+          } else {
+            openMonitorInTheSameTab(0, ffi, pi);
+          }
+        },
+        ffi: ffi,
+        child: Text(translate('Stream all displays as one')));
+  }
+
   Widget chooseDisplayBehavior() {
     final value =
         bind.sessionGetDisplaysAsIndividualWindows(sessionId: ffi.sessionId) ==
@@ -1043,6 +1067,8 @@ class _DisplayMenuState extends State<_DisplayMenu> {
       scrollStyle(),
       imageQuality(),
       codec(),
+      streamResolution(),
+      audioMode(),
       _ResolutionsMenu(
         id: widget.id,
         ffi: widget.ffi,
@@ -1194,6 +1220,44 @@ class _DisplayMenuState extends State<_DisplayMenu> {
         });
   }
 
+  streamResolution() {
+    return futureBuilder(
+        future: toolbarStreamResolution(context, id, ffi),
+        hasData: (data) {
+          final v = data as List<TRadioMenu<String>>;
+          return _SubmenuButton(
+              ffi: widget.ffi,
+              child: Text(translate('Stream resolution')),
+              menuChildren: v
+                  .map((e) => RdoMenuButton(
+                      value: e.value,
+                      groupValue: e.groupValue,
+                      onChanged: e.onChanged,
+                      child: e.child,
+                      ffi: ffi))
+                  .toList());
+        });
+  }
+
+  audioMode() {
+    return futureBuilder(
+        future: toolbarAudioMode(context, id, ffi),
+        hasData: (data) {
+          final v = data as List<TRadioMenu<String>>;
+          return _SubmenuButton(
+              ffi: widget.ffi,
+              child: Text(translate('Audio quality')),
+              menuChildren: v
+                  .map((e) => RdoMenuButton(
+                      value: e.value,
+                      groupValue: e.groupValue,
+                      onChanged: e.onChanged,
+                      child: e.child,
+                      ffi: ffi))
+                  .toList());
+        });
+  }
+
   toggles() {
     return futureBuilder(
         future: toolbarDisplayToggle(context, id, ffi),
@@ -1609,6 +1673,7 @@ class _KeyboardMenu extends StatelessWidget {
         menuChildren: [
           keyboardMode(modeOnly),
           localKeyboardType(),
+          keyMappings(),
           inputSource(),
           Divider(),
           viewMode(),
@@ -1686,6 +1751,21 @@ class _KeyboardMenu extends StatelessWidget {
     );
   }
 
+  keyMappings() {
+    final enabled = !ffi.ffiModel.viewOnly;
+    return Column(
+      children: [
+        Divider(),
+        MenuButton(
+          child: Text(translate('Key mappings')),
+          trailingIcon: const Icon(Icons.settings),
+          ffi: ffi,
+          onPressed: enabled ? () => showKeyMappingsDialog(ffi) : null,
+        )
+      ],
+    );
+  }
+
   inputSource() {
     final supportedInputSource = bind.mainSupportedInputSource();
     if (supportedInputSource.isEmpty) return Offstage();
@@ -467,6 +467,10 @@ class InputModel {
 
   void onPointHoverImage(PointerHoverEvent e) {
     _stopFling = true;
+    if (_isPen(e)) {
+      handlePen(e);
+      return;
+    }
     if (e.kind != ui.PointerDeviceKind.mouse) return;
     if (!isPhysicalMouse.value) {
       isPhysicalMouse.value = true;
@@ -612,6 +616,10 @@ class InputModel {
   void onPointDownImage(PointerDownEvent e) {
     debugPrint("onPointDownImage ${e.kind}");
     _stopFling = true;
+    if (_isPen(e)) {
+      handlePen(e);
+      return;
+    }
     if (e.kind != ui.PointerDeviceKind.mouse) {
       if (isPhysicalMouse.value) {
         isPhysicalMouse.value = false;
@@ -623,6 +631,10 @@ class InputModel {
   }
 
   void onPointUpImage(PointerUpEvent e) {
+    if (_isPen(e)) {
+      handlePen(e);
+      return;
+    }
     if (e.kind != ui.PointerDeviceKind.mouse) return;
     if (isPhysicalMouse.value) {
       handleMouse(_getMouseEvent(e, _kMouseEventUp), e.position);
@@ -630,12 +642,22 @@ class InputModel {
   }
 
   void onPointMoveImage(PointerMoveEvent e) {
+    if (_isPen(e)) {
+      handlePen(e);
+      return;
+    }
     if (e.kind != ui.PointerDeviceKind.mouse) return;
     if (isPhysicalMouse.value) {
       handleMouse(_getMouseEvent(e, _kMouseEventMove), e.position);
     }
   }
 
+  void onPointExitImage(PointerExitEvent e) {
+    if (_isPen(e)) {
+      handlePen(e, leave: true);
+    }
+  }
+
   void onPointerSignalImage(PointerSignalEvent e) {
     if (e is PointerScrollEvent) {
       var dx = e.scrollDelta.dx.toInt();
@@ -738,6 +760,53 @@ class InputModel {
         sessionId: sessionId, msg: json.encode(modify(evt)));
   }
 
+  // Only the uinput of Linux replays the pens.
+  bool _isPen(PointerEvent e) =>
+      (e.kind == ui.PointerDeviceKind.stylus ||
+          e.kind == ui.PointerDeviceKind.invertedStylus) &&
+      peerPlatform == kPeerPlatformLinux;
+
+  void handlePen(PointerEvent e, {bool leave = false}) {
+    double x = e.position.dx;
+    double y = e.position.dy;
+    if (_checkPeerControlProtected(x, y)) {
+      return;
+    }
+    final pos =
+        handlePointerDevicePos(kPointerEventKindPen, x, y, true, 'pen');
+    if (pos == null) {
+      return;
+    }
+    final pressureRange = e.pressureMax - e.pressureMin;
+    final pressure = pressureRange > 0
+        ? ((e.pressure - e.pressureMin) / pressureRange).clamp(0.0, 1.0)
+        : 1.0;
+    // The tilt is from the perpendicular, the orientation is its direction
+    // with 0 to the top.
+    final tiltX = atan(tan(e.tilt) * sin(e.orientation)) * 180 / pi;
+    final tiltY = -atan(tan(e.tilt) * cos(e.orientation)) * 180 / pi;
+    var buttons = 0;
+    if (e.buttons & kPrimaryStylusButton != 0) {
+      buttons |= kPenButtonPrimary;
+    }
+    if (e.buttons & kSecondaryStylusButton != 0) {
+      buttons |= kPenButtonSecondary;
+    }
+    final evt = PointerEventToRust(kPointerEventKindPen, 'pen', {
+      'x': pos.x,
+      'y': pos.y,
+      'down': e.down,
+      'pressure': (pressure * 65535).round(),
+      'tilt_x': tiltX.round(),
+      'tilt_y': tiltY.round(),
+      'eraser': e.kind == ui.PointerDeviceKind.invertedStylus,
+      'buttons': buttons,
+      'leave': leave,
+    }).toJson();
+    bind.sessionSendPointer(
+        sessionId: sessionId, msg: json.encode(modify(evt)));
+  }
+
   bool _checkPeerControlProtected(double x, double y) {
     final cursorModel = parent.target!.cursorModel;
     if (cursorModel.isPeerControlProtected) {
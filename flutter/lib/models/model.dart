@@ -92,6 +92,10 @@ class FfiModel with ChangeNotifier {
 
   var _inputBlocked = false;
   final _permissions = <String, bool>{};
+  // the input control token, empty if the peer lets everyone send input
+  final _inputControl = <String, dynamic>{};
+  // the displays of a canvas stream in image pixels, empty if not a canvas
+  final _canvasRegions = <Rect>[];
   bool? _secure;
   bool? _direct;
   bool _touchMode = false;
@@ -179,6 +183,36 @@ class FfiModel with ChangeNotifier {
 
   bool get keyboard => _permissions['keyboard'] != false;
 
+  updateInputControl(Map<String, dynamic> evt, SessionID sessionId) {
+    _inputControl.clear();
+    _inputControl.addAll(evt);
+    notifyListeners();
+    final requester = evt['requester'] ?? '';
+    if (inputControlHolding && requester.isNotEmpty) {
+      showInputControlRequest(sessionId, int.parse(evt['requester_id']),
+          requester, parent.target!.dialogManager);
+    }
+  }
+
+  bool get inputControlEnabled => _inputControl.isNotEmpty;
+  bool get inputControlHolding => _inputControl['holding'] == 'true';
+  bool get inputControlRequesting => _inputControl['requesting'] == 'true';
+  String get inputControlHolder => _inputControl['holder'] ?? '';
+
+  List<Rect> get canvasRegions => _canvasRegions;
+  bool get isCanvas => _canvasRegions.isNotEmpty;
+
+  handleCanvasLayout(Map<String, dynamic> evt) {
+    final List<dynamic> regions = json.decode(evt['regions']);
+    _canvasRegions.clear();
+    _canvasRegions.addAll(regions.map((r) => Rect.fromLTWH(
+        r['x'].toDouble(),
+        r['y'].toDouble(),
+        r['width'].toDouble(),
+        r['height'].toDouble())));
+    notifyListeners();
+  }
+
   clear() {
     _pi = PeerInfo();
     _secure = null;
@@ -217,6 +251,7 @@ class FfiModel with ChangeNotifier {
   clearPermissions() {
     _inputBlocked = false;
     _permissions.clear();
+    _inputControl.clear();
   }
 
   handleCachedPeerData(CachedPeerData data, String peerId) async {
@@ -257,6 +292,8 @@ class FfiModel with ChangeNotifier {
       } else if (name == 'switch_display') {
         // switch display is kept for backward compatibility
         handleSwitchDisplay(evt, sessionId, peerId);
+      } else if (name == 'canvas_layout') {
+        handleCanvasLayout(evt);
       } else if (name == 'cursor_data') {
         updateLastCursorId(evt);
         await handleCursorData(evt);
@@ -269,6 +306,8 @@ class FfiModel with ChangeNotifier {
         Clipboard.setData(ClipboardData(text: evt['content']));
       } else if (name == 'permission') {
         updatePermission(evt, peerId);
+      } else if (name == 'input_control') {
+        updateInputControl(evt, sessionId);
       } else if (name == 'chat_client_mode') {
         parent.target?.chatModel
             .receive(ChatModel.clientModeID, evt['text'] ?? '');
@@ -329,6 +368,8 @@ class FfiModel with ChangeNotifier {
         parent.target?.chatModel.onVoiceCallIncoming();
       } else if (name == 'update_voice_call_state') {
         parent.target?.serverModel.updateVoiceCallState(evt);
+      } else if (name == 'update_input_control_state') {
+        parent.target?.serverModel.updateInputControlState(evt);
       } else if (name == 'fingerprint') {
         FingerprintState.find(peerId).value = evt['fingerprint'] ?? '';
       } else if (name == 'plugin_manager') {
@@ -436,6 +477,8 @@ class FfiModel with ChangeNotifier {
   handleSwitchDisplay(
       Map<String, dynamic> evt, SessionID sessionId, String peerId) {
     final display = int.parse(evt['display']);
+    // a canvas stream sends its layout right after
+    _canvasRegions.clear();
 
     if (_pi.currentDisplay != kAllDisplayValue) {
       if (bind.peerGetDefaultSessionsCount(id: peerId) > 1) {
@@ -1915,6 +1958,7 @@ class QualityMonitorData {
   String? targetBitrate;
   String? codecFormat;
   String? chroma;
+  String? audioBuffer;
 }
 
 class QualityMonitorModel with ChangeNotifier {
@@ -1971,6 +2015,9 @@ class QualityMonitorModel with ChangeNotifier {
       if ((evt['chroma'] as String).isNotEmpty) {
         _data.chroma = evt['chroma'];
       }
+      if ((evt['audio_buffer'] as String).isNotEmpty) {
+        _data.audioBuffer = evt['audio_buffer'];
+      }
       notifyListeners();
     } catch (e) {
       //
@@ -673,6 +673,20 @@ class ServerModel with ChangeNotifier {
       debugPrint("updateVoiceCallState failed: $e");
     }
   }
+
+  void updateInputControlState(Map<String, dynamic> evt) {
+    try {
+      final client = Client.fromJson(jsonDecode(evt["client"]));
+      final index = _clients.indexWhere((element) => element.id == client.id);
+      if (index != -1) {
+        _clients[index].inputControl = client.inputControl;
+        _clients[index].inputControlRequested = client.inputControlRequested;
+        notifyListeners();
+      }
+    } catch (e) {
+      debugPrint("updateInputControlState failed: $e");
+    }
+  }
 }
 
 enum ClientType {
@@ -699,6 +713,8 @@ class Client {
   bool fromSwitch = false;
   bool inVoiceCall = false;
   bool incomingVoiceCall = false;
+  bool inputControl = false;
+  bool inputControlRequested = false;
 
   RxInt unreadChatMessageCount = 0.obs;
 
@@ -723,6 +739,8 @@ class Client {
     fromSwitch = json['from_switch'];
     inVoiceCall = json['in_voice_call'];
     incomingVoiceCall = json['incoming_voice_call'];
+    inputControl = json['input_control'] ?? false;
+    inputControlRequested = json['input_control_requested'] ?? false;
   }
 
   Map<String, dynamic> toJson() {
@@ -88,3 +88,40 @@ class ImagePainter extends CustomPainter {
     return oldDelegate != this;
   }
 }
+
+/// Outlines the displays of a canvas stream, drawn over an [ImagePainter]
+/// with the same [x], [y] and [scale].
+class CanvasLayoutPainter extends CustomPainter {
+  CanvasLayoutPainter({
+    required this.regions,
+    required this.x,
+    required this.y,
+    required this.scale,
+  });
+
+  List<Rect> regions;
+  double x;
+  double y;
+  double scale;
+
+  @override
+  void paint(Canvas canvas, Size size) {
+    if (regions.length < 2) return;
+    if (x.isNaN || y.isNaN) return;
+    final paint = Paint()
+      ..style = PaintingStyle.stroke
+      ..strokeWidth = 1
+      ..color = const Color(0x99FFFFFF);
+    for (final r in regions) {
+      canvas.drawRect(
+          Rect.fromLTWH((r.left + x) * scale, (r.top + y) * scale,
+              r.width * scale, r.height * scale),
+          paint);
+    }
+  }
+
+  @override
+  bool shouldRepaint(CustomPainter oldDelegate) {
+    return oldDelegate != this;
+  }
+}
@@ -157,6 +157,7 @@ macro_rules! serde_field_bool {
 pub enum NetworkType {
     Direct,
     ProxySocks,
+    ProxyHttp,
 }
 
 #[derive(Debug, Default, Serialize, Deserialize, Clone, PartialEq)]
@@ -199,6 +200,8 @@ pub struct Config2 {
     #[serde(default, deserialize_with = "deserialize_i32")]
     nat_type: i32,
     #[serde(default, deserialize_with = "deserialize_i32")]
+    nat_port_delta: i32,
+    #[serde(default, deserialize_with = "deserialize_i32")]
     serial: i32,
 
     #[serde(default)]
@@ -215,6 +218,15 @@ pub struct Resolution {
     pub h: i32,
 }
 
+/// A key sent to the peer as another key or a chord.
+#[derive(Debug, Default, Serialize, Deserialize, Clone, PartialEq)]
+pub struct KeyMapping {
+    #[serde(default, deserialize_with = "deserialize_string")]
+    pub from: String,
+    #[serde(default, deserialize_with = "deserialize_string")]
+    pub to: String,
+}
+
 #[derive(Debug, Serialize, Deserialize, Clone, PartialEq)]
 pub struct PeerConfig {
     #[serde(default, deserialize_with = "deserialize_vec_u8")]
@@ -305,6 +317,13 @@ pub struct PeerConfig {
     )]
     pub custom_resolutions: HashMap<String, Resolution>,
 
+    #[serde(
+        default,
+        deserialize_with = "deserialize_vec_key_mapping",
+        skip_serializing_if = "Vec::is_empty"
+    )]
+    pub key_mappings: Vec<KeyMapping>,
+
     // The other scalar value must before this
     #[serde(default, deserialize_with = "PeerConfig::deserialize_options")]
     pub options: HashMap<String, String>, // not use delete to represent default values
@@ -345,6 +364,7 @@ impl Default for PeerConfig {
             use_all_my_displays_for_the_remote_session:
                 Self::default_use_all_my_displays_for_the_remote_session(),
             custom_resolutions: Default::default(),
+            key_mappings: Default::default(),
             options: Self::default_options(),
             ui_flutter: Default::default(),
             info: Default::default(),
@@ -774,6 +794,8 @@ impl Config {
             return;
         }
         config.nat_type = nat_type;
+        // the port delta was measured on the former nat
+        config.nat_port_delta = 0;
         config.store();
     }
 
@@ -781,6 +803,19 @@ impl Config {
         CONFIG2.read().unwrap().nat_type
     }
 
+    pub fn set_nat_port_delta(delta: i32) {
+        let mut config = CONFIG2.write().unwrap();
+        if delta == config.nat_port_delta {
+            return;
+        }
+        config.nat_port_delta = delta;
+        config.store();
+    }
+
+    pub fn get_nat_port_delta() -> i32 {
+        CONFIG2.read().unwrap().nat_port_delta
+    }
+
     pub fn set_serial(serial: i32) {
         let mut config = CONFIG2.write().unwrap();
         if serial == config.serial {
@@ -985,8 +1020,9 @@ impl Config {
     }
 
     pub fn get_network_type() -> NetworkType {
-        match &CONFIG2.read().unwrap().socks {
+        match crate::proxy::get() {
             None => NetworkType::Direct,
+            Some(conf) if crate::proxy::is_http(&conf.proxy) => NetworkType::ProxyHttp,
             Some(_) => NetworkType::ProxySocks,
         }
     }
@@ -1796,6 +1832,7 @@ deserialize_default!(deserialize_size, Size);
 deserialize_default!(deserialize_hashmap_string_string, HashMap<String, String>);
 deserialize_default!(deserialize_hashmap_string_bool,  HashMap<String, bool>);
 deserialize_default!(deserialize_hashmap_resolutions, HashMap<String, Resolution>);
+deserialize_default!(deserialize_vec_key_mapping, Vec<KeyMapping>);
 
 #[cfg(test)]
 mod tests {
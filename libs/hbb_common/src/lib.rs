@@ -16,6 +16,8 @@ use std::{
 };
 pub use tokio;
 pub use tokio_util;
+pub mod proxy;
+pub mod punch;
 pub mod socket_client;
 pub mod tcp;
 pub mod udp;
@@ -0,0 +1,420 @@
+//! Hole punching helpers for peers behind symmetric NAT.
+//!
+//! A symmetric NAT allocates a new public port for every destination, so the
+//! address the rendezvous server observed is not the one the peer will use to
+//! reach us. Most carrier-grade NATs allocate those ports sequentially, so the
+//! step measured by the NAT test (`Config::get_nat_port_delta`) lets us guess
+//! the next few mappings: the controlled side opens its gateway towards all
+//! of them and the controller races connections to the same batch. Over udp
+//! both sides probe the batch until one answers.
+
+use crate::{
+    bytes::BytesMut,
+    futures::future::{join_all, select_ok},
+    protobuf::Message as _,
+    rendezvous_proto::{rendezvous_message, PunchProbe, RendezvousMessage},
+    socket_client::connect_tcp_local,
+    tcp::FramedStream,
+    udp::FramedSocket,
+    ResultType, TargetAddr,
+};
+use std::{
+    net::SocketAddr,
+    time::{Duration, Instant},
+};
+
+/// Number of predicted ports tried before falling back to relay.
+pub const PUNCH_BATCH: usize = 16;
+const PROBE_INTERVAL: u64 = 200;
+
+#[derive(Debug, Clone, Copy)]
+pub struct PortPredictor {
+    observed: SocketAddr,
+    delta: i32,
+}
+
+impl PortPredictor {
+    /// `observed` is the peer address seen by the rendezvous server,
+    /// `delta` the port allocation step of the peer's NAT.
+    pub fn new(observed: SocketAddr, delta: i32) -> Self {
+        Self { observed, delta }
+    }
+
+    #[inline]
+    pub fn is_predictable(&self) -> bool {
+        self.delta != 0
+    }
+
+    /// The next `batch` addresses the NAT is expected to allocate, nearest first.
+    pub fn candidates(&self, batch: usize) -> Vec<SocketAddr> {
+        if !self.is_predictable() {
+            return vec![self.observed];
+        }
+        let base = self.observed.port() as i32;
+        (1..=batch as i32)
+            .map(|k| base + self.delta * k)
+            .take_while(|port| *port > 0 && *port <= u16::MAX as i32)
+            .map(|port| SocketAddr::new(self.observed.ip(), port as u16))
+            .collect()
+    }
+}
+
+/// Controlled side: open our gateway towards all `candidates`, the peer will
+/// come from one of them. Failures are expected, only the outgoing SYN matters.
+pub async fn open_gateway(candidates: &[SocketAddr], local: SocketAddr, ms_timeout: u64) {
+    join_all(
+        candidates
+            .iter()
+            .map(|addr| connect_tcp_local(*addr, Some(local), ms_timeout)),
+    )
+    .await;
+}
+
+/// Controller side: race connections to all `candidates`, the first one
+/// established wins.
+pub async fn connect_predicted(
+    candidates: &[SocketAddr],
+    local: Option<SocketAddr>,
+    ms_timeout: u64,
+) -> ResultType<FramedStream> {
+    select_ok(
+        candidates
+            .iter()
+            .map(|addr| Box::pin(connect_tcp_local(*addr, local, ms_timeout))),
+    )
+    .await
+    .map(|(conn, _)| conn)
+}
+
+trait ProbeSocket {
+    async fn send_probe(&mut self, msg: &RendezvousMessage, addr: SocketAddr) -> ResultType<()>;
+    async fn next_probe(&mut self, ms: u64) -> Option<(BytesMut, SocketAddr)>;
+}
+
+impl ProbeSocket for FramedSocket {
+    #[inline]
+    async fn send_probe(&mut self, msg: &RendezvousMessage, addr: SocketAddr) -> ResultType<()> {
+        self.send(msg, addr).await
+    }
+
+    #[inline]
+    async fn next_probe(&mut self, ms: u64) -> Option<(BytesMut, SocketAddr)> {
+        match self.next_timeout(ms).await {
+            Some(Ok((bytes, TargetAddr::Ip(addr)))) => Some((bytes, addr)),
+            _ => None,
+        }
+    }
+}
+
+/// Punch a UDP hole to the peer by probing all `candidates` until one answers.
+///
+/// Both sides are expected to call this at the same time with the same `token`.
+/// Returns the confirmed peer address, or `None` if nothing answered within `ms_timeout`.
+pub async fn punch_udp(
+    socket: &mut FramedSocket,
+    candidates: &[SocketAddr],
+    token: &[u8],
+    ms_timeout: u64,
+) -> ResultType<Option<SocketAddr>> {
+    punch_with(socket, candidates, token, ms_timeout).await
+}
+
+async fn punch_with<S: ProbeSocket>(
+    socket: &mut S,
+    candidates: &[SocketAddr],
+    token: &[u8],
+    ms_timeout: u64,
+) -> ResultType<Option<SocketAddr>> {
+    let probe = new_probe(token, false);
+    let ack = new_probe(token, true);
+    let start = Instant::now();
+    let timeout = Duration::from_millis(ms_timeout);
+    while start.elapsed() < timeout {
+        for addr in candidates {
+            socket.send_probe(&probe, *addr).await?;
+        }
+        let round = Instant::now();
+        let interval = Duration::from_millis(PROBE_INTERVAL);
+        while round.elapsed() < interval {
+            let left = (interval - round.elapsed()).as_millis() as u64;
+            let Some((bytes, from)) = socket.next_probe(left.max(1)).await else {
+                continue;
+            };
+            let Ok(msg_in) = RendezvousMessage::parse_from_bytes(&bytes) else {
+                continue;
+            };
+            if let Some(rendezvous_message::Union::PunchProbe(p)) = msg_in.union {
+                if p.token != token {
+                    continue;
+                }
+                if !p.ack {
+                    // our mapping towards `from` exists, otherwise the probe would
+                    // have been dropped, so the ack takes the same way back.
+                    socket.send_probe(&ack, from).await?;
+                }
+                log::debug!("Punched udp hole to {} in {:?}", from, start.elapsed());
+                return Ok(Some(from));
+            }
+        }
+    }
+    Ok(None)
+}
+
+fn new_probe(token: &[u8], ack: bool) -> RendezvousMessage {
+    let mut msg = RendezvousMessage::new();
+    msg.set_punch_probe(PunchProbe {
+        token: token.to_vec().into(),
+        ack,
+        ..Default::default()
+    });
+    msg
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use std::collections::{HashMap, HashSet};
+    use tokio::{net::TcpListener, sync::mpsc};
+
+    const LOCALHOST: &str = "127.0.0.1";
+
+    enum NatKind {
+        // a new public port per destination, `delta` above the previous one
+        Symmetric(i32),
+        // one public port, inbound only from addresses contacted before
+        PortRestrictedCone,
+    }
+
+    type Outbound = mpsc::UnboundedSender<(RendezvousMessage, SocketAddr)>;
+    type Inbound = (SocketAddr, BytesMut, SocketAddr);
+
+    /// Simulated NAT in front of a host; every public mapping is a real
+    /// `FramedSocket` on localhost, so the peers talk over actual udp.
+    struct SimNat {
+        kind: NatKind,
+        next_port: u16,
+        mappings: HashMap<SocketAddr, SocketAddr>,
+        contacted: HashSet<SocketAddr>,
+        sockets: HashMap<SocketAddr, Outbound>,
+        inbound_tx: mpsc::UnboundedSender<Inbound>,
+        inbound_rx: mpsc::UnboundedReceiver<Inbound>,
+    }
+
+    impl SimNat {
+        fn new(kind: NatKind, first_port: u16) -> Self {
+            let (inbound_tx, inbound_rx) = mpsc::unbounded_channel();
+            Self {
+                kind,
+                next_port: first_port,
+                mappings: Default::default(),
+                contacted: Default::default(),
+                sockets: Default::default(),
+                inbound_tx,
+                inbound_rx,
+            }
+        }
+
+        async fn allocate(&mut self) -> SocketAddr {
+            let step = match self.kind {
+                NatKind::Symmetric(delta) => delta as u16,
+                NatKind::PortRestrictedCone => 1,
+            };
+            loop {
+                let port = self.next_port;
+                self.next_port += step;
+                // a real nat skips ports that are in use as well
+                let Ok(mut socket) = FramedSocket::new(format!("{LOCALHOST}:{port}")).await else {
+                    continue;
+                };
+                let public = socket.local_addr().unwrap();
+                let (tx, mut rx) = mpsc::unbounded_channel::<(RendezvousMessage, SocketAddr)>();
+                let inbound = self.inbound_tx.clone();
+                tokio::spawn(async move {
+                    loop {
+                        tokio::select! {
+                            out = rx.recv() => match out {
+                                Some((msg, to)) => { socket.send(&msg, to).await.ok(); }
+                                None => break,
+                            },
+                            res = socket.next() => {
+                                if let Some(Ok((bytes, TargetAddr::Ip(from)))) = res {
+                                    inbound.send((public, bytes, from)).ok();
+                                }
+                            }
+                        }
+                    }
+                });
+                self.sockets.insert(public, tx);
+                return public;
+            }
+        }
+
+        async fn public_for(&mut self, to: SocketAddr) -> SocketAddr {
+            match self.kind {
+                NatKind::Symmetric(_) => {
+                    if let Some(public) = self.mappings.get(&to) {
+                        return *public;
+                    }
+                    let public = self.allocate().await;
+                    self.mappings.insert(to, public);
+                    public
+                }
+                NatKind::PortRestrictedCone => {
+                    if let Some(public) = self.sockets.keys().next() {
+                        return *public;
+                    }
+                    self.allocate().await
+                }
+            }
+        }
+
+        fn allowed(&self, public: SocketAddr, from: SocketAddr) -> bool {
+            match self.kind {
+                NatKind::Symmetric(_) => self.mappings.get(&from) == Some(&public),
+                NatKind::PortRestrictedCone => self.contacted.contains(&from),
+            }
+        }
+    }
+
+    impl ProbeSocket for SimNat {
+        async fn send_probe(&mut self, msg: &RendezvousMessage, to: SocketAddr) -> ResultType<()> {
+            let public = self.public_for(to).await;
+            self.contacted.insert(to);
+            self.sockets[&public].send((msg.clone(), to)).ok();
+            Ok(())
+        }
+
+        async fn next_probe(&mut self, ms: u64) -> Option<(BytesMut, SocketAddr)> {
+            let deadline = Instant::now() + Duration::from_millis(ms);
+            loop {
+                let left = deadline.checked_duration_since(Instant::now())?;
+                let (public, bytes, from) = tokio::time::timeout(left, self.inbound_rx.recv())
+                    .await
+                    .ok()??;
+                if self.allowed(public, from) {
+                    return Some((bytes, from));
+                }
+            }
+        }
+    }
+
+    fn first_port() -> u16 {
+        20_000 + crate::rand::random::<u16>() % 20_000
+    }
+
+    // What the rendezvous server sees: the mapping created by contacting it.
+    async fn register(nat: &mut SimNat, server: &mut FramedSocket) -> SocketAddr {
+        let server_addr = server.local_addr().unwrap();
+        nat.send_probe(&new_probe(b"register", false), server_addr)
+            .await
+            .unwrap();
+        match server.next_timeout(1000).await {
+            Some(Ok((_, TargetAddr::Ip(addr)))) => addr,
+            _ => panic!("rendezvous server got nothing"),
+        }
+    }
+
+    struct Punched {
+        candidates: Vec<SocketAddr>,
+        // the mapping the symmetric nat allocated towards the cone peer
+        sym_mapping: Option<SocketAddr>,
+        cone_got: Option<SocketAddr>,
+        sym_got: Option<SocketAddr>,
+    }
+
+    async fn run(delta: i32, predict: bool) -> Punched {
+        let mut server1 = FramedSocket::new(format!("{LOCALHOST}:0")).await.unwrap();
+        let mut server2 = FramedSocket::new(format!("{LOCALHOST}:0")).await.unwrap();
+        let mut sym = SimNat::new(NatKind::Symmetric(delta), first_port());
+        let mut cone = SimNat::new(NatKind::PortRestrictedCone, first_port());
+        // the nat test: two servers see two mappings, their distance is the delta
+        let sym_observed = register(&mut sym, &mut server1).await;
+        let measured =
+            register(&mut sym, &mut server2).await.port() as i32 - sym_observed.port() as i32;
+        let cone_observed = register(&mut cone, &mut server1).await;
+        let candidates = if predict {
+            PortPredictor::new(sym_observed, measured).candidates(PUNCH_BATCH)
+        } else {
+            vec![sym_observed]
+        };
+        let token = b"session";
+        let sym_candidates = candidates.clone();
+        let a = tokio::spawn(async move {
+            punch_with(&mut cone, &sym_candidates, token, 1000)
+                .await
+                .unwrap()
+        });
+        let b = tokio::spawn(async move {
+            let got = punch_with(&mut sym, &[cone_observed], token, 1000)
+                .await
+                .unwrap();
+            (got, sym.mappings.get(&cone_observed).cloned())
+        });
+        let cone_got = a.await.unwrap();
+        let (sym_got, sym_mapping) = b.await.unwrap();
+        Punched {
+            candidates,
+            sym_mapping,
+            cone_got,
+            sym_got,
+        }
+    }
+
+    #[test]
+    fn test_candidates() {
+        let observed: SocketAddr = "1.2.3.4:40000".parse().unwrap();
+        assert_eq!(
+            PortPredictor::new(observed, 0).candidates(PUNCH_BATCH),
+            vec![observed]
+        );
+        let ports: Vec<u16> = PortPredictor::new(observed, 2)
+            .candidates(3)
+            .iter()
+            .map(|x| x.port())
+            .collect();
+        assert_eq!(ports, vec![40002, 40004, 40006]);
+        let ports: Vec<u16> = PortPredictor::new("1.2.3.4:65530".parse().unwrap(), 4)
+            .candidates(PUNCH_BATCH)
+            .iter()
+            .map(|x| x.port())
+            .collect();
+        assert_eq!(ports, vec![65534]);
+    }
+
+    #[tokio::test]
+    async fn test_connect_predicted() {
+        const DELTA: i32 = 3;
+        // the peer's nat opened the 4th port after the one the server observed
+        let listener = TcpListener::bind("127.0.0.1:0").await.unwrap();
+        let opened = listener.local_addr().unwrap();
+        let observed = SocketAddr::new(opened.ip(), opened.port() - 4 * DELTA as u16);
+        let accept = tokio::spawn(async move { listener.accept().await.map(|x| x.1) });
+        let candidates = PortPredictor::new(observed, DELTA).candidates(PUNCH_BATCH);
+        let conn = connect_predicted(&candidates, None, 3000).await.unwrap();
+        assert_eq!(conn.local_addr(), accept.await.unwrap().unwrap());
+
+        // only the observed port, which nobody listens on
+        let candidates = PortPredictor::new(observed, 0).candidates(PUNCH_BATCH);
+        assert!(connect_predicted(&candidates, None, 1000).await.is_err());
+    }
+
+    #[tokio::test]
+    async fn test_punch_symmetric_with_prediction() {
+        for delta in [1, 3] {
+            let punched = run(delta, true).await;
+            // the measured delta predicted the port the nat really allocated
+            let sym_mapping = punched.sym_mapping.unwrap();
+            assert!(punched.candidates.contains(&sym_mapping));
+            assert_eq!(punched.cone_got, Some(sym_mapping));
+            assert!(punched.sym_got.is_some());
+        }
+    }
+
+    #[tokio::test]
+    async fn test_punch_symmetric_without_prediction() {
+        // probing the observed port only hits the mapping towards the
+        // rendezvous server, which drops everything from the peer
+        let punched = run(3, false).await;
+        assert_eq!((punched.cone_got, punched.sym_got), (None, None));
+    }
+}
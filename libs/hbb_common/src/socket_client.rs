@@ -1,11 +1,12 @@
 use crate::{
     config::{Config, NetworkType},
+    proxy,
     tcp::FramedStream,
     udp::FramedSocket,
     ResultType,
 };
 use anyhow::Context;
-use std::net::SocketAddr;
+use std::net::{IpAddr, Ipv6Addr, SocketAddr};
 use tokio::net::ToSocketAddrs;
 use tokio_socks::{IntoTargetAddr, TargetAddr};
 
@@ -58,7 +59,7 @@ pub fn test_if_valid_server(host: &str) -> String {
             Err(err) => err.to_string(),
             Ok(_) => "".to_owned(),
         },
-        NetworkType::ProxySocks => match &host.into_target_addr() {
+        NetworkType::ProxySocks | NetworkType::ProxyHttp => match &host.into_target_addr() {
             Err(err) => err.to_string(),
             Ok(_) => "".to_owned(),
         },
@@ -87,6 +88,22 @@ impl IsResolvedSocketAddr for &str {
     }
 }
 
+/// The `websocket` option, `ws` or `wss` to tunnel server connections in websocket.
+fn websocket_scheme() -> Option<String> {
+    let scheme = Config::get_option("websocket");
+    if scheme == "ws" || scheme == "wss" {
+        Some(scheme)
+    } else {
+        None
+    }
+}
+
+/// Only tcp gets out to the servers, behind an http proxy or in a websocket tunnel.
+pub fn is_udp_disabled() -> bool {
+    websocket_scheme().is_some() || Config::get_network_type() == NetworkType::ProxyHttp
+}
+
+/// Connect to a rendezvous or relay server, via websocket if configured.
 #[inline]
 pub async fn connect_tcp<
     't,
@@ -95,6 +112,13 @@ pub async fn connect_tcp<
     target: T,
     ms_timeout: u64,
 ) -> ResultType<FramedStream> {
+    if let Some(scheme) = websocket_scheme() {
+        // the websocket endpoint listens two ports above the tcp one
+        let target = increase_port(target.to_string(), 2);
+        let url = format!("{scheme}://{target}");
+        let stream = connect_tcp_local(target, None, ms_timeout).await?;
+        return stream.into_websocket(&url, ms_timeout).await;
+    }
     connect_tcp_local(target, None, ms_timeout).await
 }
 
@@ -106,7 +130,18 @@ pub async fn connect_tcp_local<
     local: Option<SocketAddr>,
     ms_timeout: u64,
 ) -> ResultType<FramedStream> {
-    if let Some(conf) = Config::get_socks() {
+    if let Some(conf) = proxy::get() {
+        if proxy::is_http(&conf.proxy) {
+            return FramedStream::connect_http(
+                conf.proxy.as_str(),
+                &target.to_string(),
+                local,
+                conf.username.as_str(),
+                conf.password.as_str(),
+                ms_timeout,
+            )
+            .await;
+        }
         return FramedStream::connect(
             conf.proxy.as_str(),
             target,
@@ -154,6 +189,44 @@ pub fn ipv4_to_ipv6(addr: String, ipv4: bool) -> String {
     addr
 }
 
+/// Whether `ip` is routable on the internet,
+/// i.e. not loopback, link-local, unique local, multicast or documentation.
+pub fn is_global_ipv6(ip: &Ipv6Addr) -> bool {
+    let seg = ip.segments();
+    !(ip.is_unspecified()
+        || ip.is_loopback()
+        || ip.is_multicast()
+        || ip.to_ipv4_mapped().is_some()
+        || (seg[0] & 0xfe00) == 0xfc00
+        || (seg[0] & 0xffc0) == 0xfe80
+        || (seg[0] == 0x2001 && seg[1] == 0xdb8))
+}
+
+/// The global ipv6 address outgoing traffic leaves from, if the host has one.
+pub fn get_global_ipv6() -> Option<Ipv6Addr> {
+    let socket = std::net::UdpSocket::bind("[::]:0").ok()?;
+    // connect only selects the route and source address, nothing is sent
+    socket.connect("[2001:4860:4860::8888]:80").ok()?;
+    match socket.local_addr().ok()?.ip() {
+        IpAddr::V6(ip) if is_global_ipv6(&ip) => Some(ip),
+        _ => None,
+    }
+}
+
+/// A global ipv6 address with a free port to punch from,
+/// `None` if the host has no global ipv6 or the traffic goes via proxy.
+pub fn new_ipv6_punch_addr() -> Option<SocketAddr> {
+    if Config::get_network_type() != NetworkType::Direct
+        || websocket_scheme().is_some()
+        || Config::get_option("enable-ipv6-punch") == "N"
+    {
+        return None;
+    }
+    let ip = get_global_ipv6()?;
+    let listener = std::net::TcpListener::bind((ip, 0)).ok()?;
+    listener.local_addr().ok()
+}
+
 async fn test_target(target: &str) -> ResultType<SocketAddr> {
     if let Ok(Ok(s)) = super::timeout(1000, tokio::net::TcpStream::connect(target)).await {
         if let Ok(addr) = s.peer_addr() {
@@ -171,6 +244,9 @@ pub async fn new_udp_for(
     target: &str,
     ms_timeout: u64,
 ) -> ResultType<(FramedSocket, TargetAddr<'static>)> {
+    if is_udp_disabled() {
+        anyhow::bail!("No udp via http proxy or websocket");
+    }
     let (ipv4, target) = if NetworkType::Direct == Config::get_network_type() {
         let addr = test_target(target).await?;
         (addr.is_ipv4(), addr.into_target_addr()?)
@@ -184,7 +260,7 @@ pub async fn new_udp_for(
 }
 
 async fn new_udp<T: ToSocketAddrs>(local: T, ms_timeout: u64) -> ResultType<FramedSocket> {
-    match Config::get_socks() {
+    match proxy::get() {
         None => Ok(FramedSocket::new(local).await?),
         Some(conf) => {
             let socket = FramedSocket::new_proxy(
@@ -261,6 +337,20 @@ mod tests {
         assert!(test_if_valid_server("1.1.1.1:1").is_empty());
     }
 
+    #[test]
+    fn test_is_global_ipv6() {
+        let global = |s: &str| is_global_ipv6(&s.parse().unwrap());
+        assert!(global("2400:cb00::1"));
+        assert!(global("2a01:4f8::1"));
+        assert!(!global("::"));
+        assert!(!global("::1"));
+        assert!(!global("fe80::1"));
+        assert!(!global("fd12:3456::1"));
+        assert!(!global("ff02::1"));
+        assert!(!global("2001:db8::1"));
+        assert!(!global("::ffff:1.1.1.1"));
+    }
+
     #[test]
     fn test_check_port() {
         assert_eq!(check_port("[1:2]:12", 32), "[1:2]:12");
@@ -1,7 +1,7 @@
 use crate::{bail, bytes_codec::BytesCodec, ResultType};
 use anyhow::Context as AnyhowCtx;
 use bytes::{BufMut, Bytes, BytesMut};
-use futures::{SinkExt, StreamExt};
+use futures::{Sink, SinkExt, Stream, StreamExt};
 use protobuf::Message;
 use sodiumoxide::crypto::{
     box_,
@@ -19,6 +19,7 @@ use tokio::{
     net::{lookup_host, TcpListener, TcpSocket, ToSocketAddrs},
 };
 use tokio_socks::{tcp::Socks5Stream, IntoTargetAddr, ToProxyAddrs};
+use tokio_tungstenite::{tungstenite::Message as WsMessage, WebSocketStream};
 use tokio_util::codec::Framed;
 
 pub trait TcpStreamTrait: AsyncRead + AsyncWrite + Unpin {}
@@ -156,6 +157,53 @@ impl FramedStream {
         bail!("could not resolve to any address");
     }
 
+    /// Connect to `target` through the HTTP CONNECT proxy `proxy` (`http://host:port`).
+    pub async fn connect_http(
+        proxy: &str,
+        target: &str,
+        local_addr: Option<SocketAddr>,
+        username: &str,
+        password: &str,
+        ms_timeout: u64,
+    ) -> ResultType<Self> {
+        let proxy = proxy.trim_start_matches(crate::proxy::HTTP_SCHEME);
+        for proxy_addr in lookup_host(proxy).await? {
+            let local = if let Some(addr) = local_addr {
+                addr
+            } else {
+                crate::config::Config::get_any_listen_addr(proxy_addr.is_ipv4())
+            };
+            let Ok(socket) = new_socket(local, true) else {
+                continue;
+            };
+            let Ok(Ok(mut stream)) = super::timeout(ms_timeout, socket.connect(proxy_addr)).await
+            else {
+                continue;
+            };
+            stream.set_nodelay(true).ok();
+            super::timeout(
+                ms_timeout,
+                crate::proxy::http_connect(&mut stream, target, username, password),
+            )
+            .await??;
+            let addr = stream.local_addr()?;
+            return Ok(Self::from(stream, addr));
+        }
+        bail!("Failed to connect to http proxy {proxy}");
+    }
+
+    /// Run the rest of the connection inside a websocket tunnel to `url`.
+    ///
+    /// The framed byte stream is carried as is in binary messages, so the
+    /// other end only has to unwrap the tunnel.
+    pub async fn into_websocket(self, url: &str, ms_timeout: u64) -> ResultType<Self> {
+        let addr = self.1;
+        let stream = self.0.into_inner();
+        let (ws, _) =
+            super::timeout(ms_timeout, tokio_tungstenite::client_async_tls(url, stream)).await??;
+        Ok(Self::from(WsStream::new(ws), addr))
+    }
+
     pub fn local_addr(&self) -> SocketAddr {
         self.1
     }
@@ -324,6 +372,77 @@ impl AsyncWrite for DynTcpStream {
 
 impl<R: AsyncRead + AsyncWrite + Unpin> TcpStreamTrait for R {}
 
+/// Byte stream over the binary messages of a websocket.
+pub struct WsStream<S> {
+    inner: WebSocketStream<S>,
+    read_buf: Bytes,
+}
+
+impl<S> WsStream<S> {
+    pub fn new(inner: WebSocketStream<S>) -> Self {
+        Self {
+            inner,
+            read_buf: Bytes::new(),
+        }
+    }
+}
+
+fn ws_error(err: tokio_tungstenite::tungstenite::Error) -> Error {
+    Error::new(ErrorKind::Other, err)
+}
+
+impl<S: AsyncRead + AsyncWrite + Unpin> AsyncRead for WsStream<S> {
+    fn poll_read(
+        self: Pin<&mut Self>,
+        cx: &mut Context<'_>,
+        buf: &mut ReadBuf<'_>,
+    ) -> Poll<io::Result<()>> {
+        let this = self.get_mut();
+        loop {
+            if !this.read_buf.is_empty() {
+                let n = this.read_buf.len().min(buf.remaining());
+                buf.put_slice(&this.read_buf.split_to(n));
+                return Poll::Ready(Ok(()));
+            }
+            match futures::ready!(Pin::new(&mut this.inner).poll_next(cx)) {
+                Some(Ok(WsMessage::Binary(data))) => this.read_buf = data.into(),
+                // eof
+                Some(Ok(WsMessage::Close(_))) | None => return Poll::Ready(Ok(())),
+                // ping and pong are answered by tungstenite
+                Some(Ok(_)) => {}
+                Some(Err(err)) => return Poll::Ready(Err(ws_error(err))),
+            }
+        }
+    }
+}
+
+impl<S: AsyncRead + AsyncWrite + Unpin> AsyncWrite for WsStream<S> {
+    fn poll_write(
+        self: Pin<&mut Self>,
+        cx: &mut Context<'_>,
+        buf: &[u8],
+    ) -> Poll<io::Result<usize>> {
+        let this = self.get_mut();
+        futures::ready!(Pin::new(&mut this.inner).poll_ready(cx)).map_err(ws_error)?;
+        Pin::new(&mut this.inner)
+            .start_send(WsMessage::Binary(buf.to_vec()))
+            .map_err(ws_error)?;
+        Poll::Ready(Ok(buf.len()))
+    }
+
+    fn poll_flush(self: Pin<&mut Self>, cx: &mut Context<'_>) -> Poll<io::Result<()>> {
+        Pin::new(&mut self.get_mut().inner)
+            .poll_flush(cx)
+            .map_err(ws_error)
+    }
+
+    fn poll_shutdown(self: Pin<&mut Self>, cx: &mut Context<'_>) -> Poll<io::Result<()>> {
+        Pin::new(&mut self.get_mut().inner)
+            .poll_close(cx)
+            .map_err(ws_error)
+    }
+}
+
 impl Encrypt {
     pub fn new(key: Key) -> Self {
         Self(key, 0, 0)
@@ -4,7 +4,7 @@ use bytes::{Bytes, BytesMut};
 use futures::{SinkExt, StreamExt};
 use protobuf::Message;
 use socket2::{Domain, Socket, Type};
-use std::net::SocketAddr;
+use std::net::{Ipv6Addr, SocketAddr};
 use tokio::net::{lookup_host, ToSocketAddrs, UdpSocket};
 use tokio_socks::{udp::Socks5UdpFramed, IntoTargetAddr, TargetAddr, ToProxyAddrs};
 use tokio_util::{codec::BytesCodec, udp::UdpFramed};
@@ -44,6 +44,15 @@ fn new_socket(addr: SocketAddr, reuse: bool, buf_size: usize) -> Result<Socket,
     Ok(socket)
 }
 
+/// A blocking udp socket on `[::]:port` that does not accept ipv4,
+/// so it can share the port with an ipv4 socket.
+pub fn bind_ipv6_only(port: u16) -> ResultType<std::net::UdpSocket> {
+    let socket = Socket::new(Domain::ipv6(), Type::dgram(), None)?;
+    socket.set_only_v6(true)?;
+    socket.bind(&SocketAddr::new(Ipv6Addr::UNSPECIFIED.into(), port).into())?;
+    Ok(socket.into_udp_socket())
+}
+
 impl FramedSocket {
     pub async fn new<T: ToSocketAddrs>(addr: T) -> ResultType<Self> {
         Self::new_reuse(addr, false, 0).await
@@ -109,6 +109,7 @@ fn test_vpx(
         quality,
         codec: codec_id,
         keyframe_interval: None,
+        screen_content: false,
     });
     let mut encoder = VpxEncoder::new(config, i444).unwrap();
     let mut vpxs = vec![];
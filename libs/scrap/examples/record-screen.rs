@@ -117,6 +117,7 @@ fn main() -> io::Result<()> {
             quality,
             codec: vpx_codec,
             keyframe_interval: None,
+            screen_content: false,
         }),
         false,
     )
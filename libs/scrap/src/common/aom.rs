@@ -260,7 +260,7 @@ impl EncoderApi for AomEncoder {
         if frames.len() > 0 {
             Ok(Self::create_video_frame(frames))
         } else {
-            Err(anyhow!("no valid frame"))
+            Err(anyhow!(crate::codec::ENCODE_NO_FRAME))
         }
     }
 
@@ -10,9 +10,12 @@ use crate::hwcodec::*;
 use crate::mediacodec::{
     MediaCodecDecoder, MediaCodecDecoders, H264_DECODER_SUPPORT, H265_DECODER_SUPPORT,
 };
+#[cfg(feature = "openh264")]
+use crate::openh264codec::{self, OpenH264Decoder, OpenH264Encoder};
 use crate::{
     aom::{self, AomDecoder, AomEncoder, AomEncoderConfig},
     common::GoogleImage,
+    dirty::{self, Rect},
     vpxcodec::{self, VpxDecoder, VpxDecoderConfig, VpxEncoder, VpxEncoderConfig, VpxVideoCodecId},
     CodecName, EncodeYuvFormat, ImageRgb,
 };
@@ -39,6 +42,9 @@ lazy_static::lazy_static! {
     static ref THREAD_LOG_TIME: Arc<Mutex<Option<Instant>>> = Arc::new(Mutex::new(None));
 }
 
+/// The error of an encoder that took the frame but has nothing to output yet.
+pub const ENCODE_NO_FRAME: &str = "no valid frame";
+
 #[derive(Debug, Clone)]
 pub struct HwEncoderConfig {
     pub name: String,
@@ -67,6 +73,11 @@ pub trait EncoderApi {
     fn set_quality(&mut self, quality: Quality) -> ResultType<()>;
 
     fn bitrate(&self) -> u32;
+
+    /// Only encode the blocks inside `rects`, everything when `None`.
+    fn set_active_region(&mut self, _rects: Option<&[Rect]>) -> ResultType<()> {
+        Ok(())
+    }
 }
 
 pub struct Encoder {
@@ -97,6 +108,8 @@ pub struct Decoder {
     i420: Vec<u8>,
     #[cfg(feature = "mediacodec")]
     media_codec: MediaCodecDecoders,
+    #[cfg(feature = "openh264")]
+    openh264: Option<OpenH264Decoder>,
 }
 
 #[derive(Debug, Clone)]
@@ -116,6 +129,10 @@ impl Encoder {
             EncoderCfg::AOM(_) => Ok(Encoder {
                 codec: Box::new(AomEncoder::new(config, i444)?),
             }),
+            #[cfg(feature = "openh264")]
+            EncoderCfg::HW(ref hw) if hw.name == openh264codec::NAME => Ok(Encoder {
+                codec: Box::new(OpenH264Encoder::new(config.clone(), i444)?),
+            }),
 
             #[cfg(feature = "hwcodec")]
             EncoderCfg::HW(_) => match HwEncoder::new(config, i444) {
@@ -175,6 +192,13 @@ impl Encoder {
                 }
             }
         }
+        #[cfg(feature = "openh264")]
+        if h264_name.is_none()
+            && decodings.len() > 0
+            && decodings.iter().all(|(_, s)| s.ability_h264 > 0)
+        {
+            h264_name = Some(openh264codec::NAME.to_owned());
+        }
 
         let mut name = CODEC_NAME.lock().unwrap();
         let mut preference = PreferCodec::Auto;
@@ -204,6 +228,12 @@ impl Encoder {
             // 4 Gb
             auto_codec = CodecName::VP8
         }
+        // viewers with only a h264 decoder, e.g. browsers
+        if decodings.iter().any(|(_, s)| s.ability_vp9 <= 0) {
+            if let Some(h264_name) = h264_name.clone() {
+                auto_codec = CodecName::H264(h264_name);
+            }
+        }
 
         match preference {
             PreferCodec::VP8 => *name = CodecName::VP8,
@@ -246,14 +276,25 @@ impl Encoder {
             encoding.h264 = best.h264.is_some();
             encoding.h265 = best.h265.is_some();
         }
+        #[cfg(feature = "openh264")]
+        {
+            encoding.h264 = true;
+        }
         encoding
     }
 
     pub fn use_i444(config: &EncoderCfg) -> bool {
-        let decodings = PEER_DECODINGS.lock().unwrap().clone();
-        let prefer_i444 = decodings
+        let prefer_i444 = PEER_DECODINGS
+            .lock()
+            .unwrap()
             .iter()
             .all(|d| d.1.prefer_chroma == Chroma::I444.into());
+        prefer_i444 && Self::i444_usable(config)
+    }
+
+    /// Whether all peers can decode I444 from the encoder of `config`.
+    pub fn i444_usable(config: &EncoderCfg) -> bool {
+        let decodings = PEER_DECODINGS.lock().unwrap().clone();
         let i444_useable = match config {
             EncoderCfg::VPX(vpx) => match vpx.codec {
                 VpxVideoCodecId::VP8 => false,
@@ -262,7 +303,12 @@ impl Encoder {
             EncoderCfg::AOM(_) => decodings.iter().all(|d| d.1.i444.av1),
             EncoderCfg::HW(_) => false,
         };
-        prefer_i444 && i444_useable && !decodings.is_empty()
+        i444_useable && !decodings.is_empty()
+    }
+
+    pub fn use_tiles() -> bool {
+        let decodings = PEER_DECODINGS.lock().unwrap();
+        !decodings.is_empty() && decodings.iter().all(|d| d.1.tiles)
     }
 }
 
@@ -283,6 +329,7 @@ impl Decoder {
             .into(),
             prefer: prefer.into(),
             prefer_chroma: prefer_chroma.into(),
+            tiles: true,
             ..Default::default()
         };
         #[cfg(feature = "hwcodec")]
@@ -306,6 +353,10 @@ impl Decoder {
                     0
                 };
         }
+        #[cfg(feature = "openh264")]
+        {
+            decoding.ability_h264 = 1;
+        }
         decoding
     }
 
@@ -337,6 +388,8 @@ impl Decoder {
             } else {
                 MediaCodecDecoders::default()
             },
+            #[cfg(feature = "openh264")]
+            openh264: OpenH264Decoder::new().ok(),
         }
     }
 
@@ -369,6 +422,15 @@ impl Decoder {
                     bail!("av1 decoder not available");
                 }
             }
+            #[cfg(feature = "openh264")]
+            video_frame::Union::H264s(h264s) if !self.has_hw_h264() => {
+                *chroma = Some(Chroma::I420);
+                if let Some(decoder) = &mut self.openh264 {
+                    decoder.decode(h264s, rgb)
+                } else {
+                    Err(anyhow!("don't support h264!"))
+                }
+            }
             #[cfg(feature = "hwcodec")]
             video_frame::Union::H264s(h264s) => {
                 *chroma = Some(Chroma::I420);
@@ -405,10 +467,25 @@ impl Decoder {
                     Err(anyhow!("don't support h265!"))
                 }
             }
+            video_frame::Union::Tiles(tiles) => Ok(dirty::apply_tiles(tiles, rgb)),
             _ => Err(anyhow!("unsupported video frame type!")),
         }
     }
 
+    // the software decoder is the fallback
+    #[cfg(feature = "openh264")]
+    fn has_hw_h264(&self) -> bool {
+        #[cfg(feature = "hwcodec")]
+        if self.hw.h264.is_some() {
+            return true;
+        }
+        #[cfg(feature = "mediacodec")]
+        if self.media_codec.h264.is_some() {
+            return true;
+        }
+        false
+    }
+
     // rgb [in/out] fmt and stride must be set in ImageRgb
     fn handle_vpxs_video_frame(
         decoder: &mut VpxDecoder,
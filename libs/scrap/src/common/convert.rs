@@ -6,8 +6,6 @@
 
 include!(concat!(env!("OUT_DIR"), "/yuv_ffi.rs"));
 
-#[cfg(not(target_os = "ios"))]
-use crate::Frame;
 use crate::{generate_call_macro, EncodeYuvFormat, TraitFrame};
 use hbb_common::{bail, log, ResultType};
 
@@ -195,7 +193,7 @@ pub mod hw {
 }
 #[cfg(not(target_os = "ios"))]
 pub fn convert_to_yuv(
-    captured: &Frame,
+    captured: &dyn TraitFrame,
     dst_fmt: EncodeYuvFormat,
     dst: &mut Vec<u8>,
     mid_data: &mut Vec<u8>,
@@ -324,3 +322,81 @@ pub fn convert_to_yuv(
     }
     Ok(())
 }
+
+/// A BGRA or RGBA frame scaled to a smaller size, to encode a display at less
+/// than its resolution.
+#[cfg(not(target_os = "ios"))]
+pub struct ScaledFrame {
+    data: Vec<u8>,
+    width: usize,
+    height: usize,
+    pixfmt: crate::Pixfmt,
+}
+
+#[cfg(not(target_os = "ios"))]
+impl ScaledFrame {
+    pub fn new(width: usize, height: usize) -> Self {
+        Self {
+            data: Vec::new(),
+            width,
+            height,
+            pixfmt: crate::Pixfmt::BGRA,
+        }
+    }
+
+    pub fn scale(&mut self, captured: &dyn TraitFrame) -> ResultType<()> {
+        let src = captured.data();
+        let src_stride = captured.stride()[0];
+        let src_pixfmt = captured.pixfmt();
+        let (src_width, src_height) = (captured.width(), captured.height());
+        if src_pixfmt != crate::Pixfmt::BGRA && src_pixfmt != crate::Pixfmt::RGBA {
+            bail!("scale not support, {src_pixfmt:?}");
+        }
+        if src.len() < src_stride * src_height {
+            bail!(
+                "wrong src len, {} < {} * {}",
+                src.len(),
+                src_stride,
+                src_height
+            );
+        }
+        // the filter does not depend on the channel order
+        self.pixfmt = src_pixfmt;
+        self.data.resize(self.width * self.height * 4, 0);
+        call_yuv!(ARGBScale(
+            src.as_ptr(),
+            src_stride as _,
+            src_width as _,
+            src_height as _,
+            self.data.as_mut_ptr(),
+            (self.width * 4) as _,
+            self.width as _,
+            self.height as _,
+            FilterMode::kFilterBox,
+        ));
+        Ok(())
+    }
+}
+
+#[cfg(not(target_os = "ios"))]
+impl TraitFrame for ScaledFrame {
+    fn data(&self) -> &[u8] {
+        &self.data
+    }
+
+    fn width(&self) -> usize {
+        self.width
+    }
+
+    fn height(&self) -> usize {
+        self.height
+    }
+
+    fn stride(&self) -> Vec<usize> {
+        vec![self.width * 4]
+    }
+
+    fn pixfmt(&self) -> crate::Pixfmt {
+        self.pixfmt
+    }
+}
@@ -0,0 +1,290 @@
+// Changed regions between captured frames.
+//
+// Each frame is cut into blocks whose hashes are compared with the previous
+// frame, so it works the same with every capturer. Small changes, e.g. a
+// blinking cursor, are sent as raw tiles instead of a whole encoded frame.
+
+use crate::{ImageFormat, ImageRgb, Pixfmt};
+use hbb_common::{
+    compress::{compress, decompress},
+    message_proto::{TileFrame, TileFrames, VideoFrame},
+};
+
+pub const BLOCK_SIZE: usize = 64;
+// Tiles are at most this wide and BLOCK_SIZE high, 256K raw, so they fit
+// the buffer `decompress` uses.
+const MAX_TILE_WIDTH: usize = 16 * BLOCK_SIZE;
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct Rect {
+    pub x: usize,
+    pub y: usize,
+    pub w: usize,
+    pub h: usize,
+}
+
+impl Rect {
+    #[inline]
+    pub fn area(&self) -> usize {
+        self.w * self.h
+    }
+}
+
+#[derive(Default)]
+pub struct DirtyTracker {
+    width: usize,
+    height: usize,
+    hashes: Vec<u64>,
+}
+
+impl DirtyTracker {
+    pub fn new() -> Self {
+        Default::default()
+    }
+
+    /// The next diff reports the whole frame.
+    pub fn reset(&mut self) {
+        self.hashes.clear();
+    }
+
+    /// The regions of `data`, 4 bytes per pixel, changed since the last call.
+    pub fn diff(&mut self, data: &[u8], width: usize, height: usize, stride: usize) -> Vec<Rect> {
+        if width == 0 || height == 0 {
+            return vec![];
+        }
+        if stride < width * 4 || data.len() < (height - 1) * stride + width * 4 {
+            self.reset();
+            return vec![Rect {
+                x: 0,
+                y: 0,
+                w: width,
+                h: height,
+            }];
+        }
+        let cols = (width + BLOCK_SIZE - 1) / BLOCK_SIZE;
+        let rows = (height + BLOCK_SIZE - 1) / BLOCK_SIZE;
+        let full = self.width != width || self.height != height || self.hashes.len() != cols * rows;
+        if full {
+            self.width = width;
+            self.height = height;
+            self.hashes = vec![0; cols * rows];
+        }
+        let mut rects: Vec<Rect> = Vec::new();
+        for row in 0..rows {
+            let y = row * BLOCK_SIZE;
+            let h = BLOCK_SIZE.min(height - y);
+            let mut run: Option<Rect> = None;
+            for col in 0..cols {
+                let x = col * BLOCK_SIZE;
+                let w = BLOCK_SIZE.min(width - x);
+                let hash = hash_block(data, stride, x, y, w, h);
+                let i = row * cols + col;
+                let changed = full || self.hashes[i] != hash;
+                self.hashes[i] = hash;
+                if changed {
+                    match run.as_mut() {
+                        Some(r) => r.w += w,
+                        None => run = Some(Rect { x, y, w, h }),
+                    }
+                } else if let Some(r) = run.take() {
+                    merge_row(&mut rects, r);
+                }
+            }
+            if let Some(r) = run {
+                merge_row(&mut rects, r);
+            }
+        }
+        rects
+    }
+}
+
+// Extend the rect right above `r` if it spans the same columns.
+fn merge_row(rects: &mut Vec<Rect>, r: Rect) {
+    if let Some(above) = rects
+        .iter_mut()
+        .find(|a| a.x == r.x && a.w == r.w && a.y + a.h == r.y)
+    {
+        above.h += r.h;
+    } else {
+        rects.push(r);
+    }
+}
+
+fn hash_block(data: &[u8], stride: usize, x: usize, y: usize, w: usize, h: usize) -> u64 {
+    // fnv-1a over 8 byte words, enough to notice a change
+    let mut hash = 0xcbf29ce484222325u64;
+    for row in y..y + h {
+        let start = row * stride + x * 4;
+        for chunk in data[start..start + w * 4].chunks(8) {
+            let mut word = [0u8; 8];
+            word[..chunk.len()].copy_from_slice(chunk);
+            hash = (hash ^ u64::from_le_bytes(word)).wrapping_mul(0x100000001b3);
+        }
+    }
+    hash
+}
+
+/// Pack the `rects` of a BGRA or RGBA frame as tiles.
+pub fn encode_tiles(
+    data: &[u8],
+    width: usize,
+    height: usize,
+    stride: usize,
+    pixfmt: Pixfmt,
+    rects: &[Rect],
+) -> VideoFrame {
+    let mut tiles = Vec::with_capacity(rects.len());
+    let mut buf = Vec::new();
+    let pieces = rects.iter().flat_map(|r| {
+        (r.y..r.y + r.h).step_by(BLOCK_SIZE).flat_map(move |y| {
+            (r.x..r.x + r.w).step_by(MAX_TILE_WIDTH).map(move |x| Rect {
+                x,
+                y,
+                w: MAX_TILE_WIDTH.min(r.x + r.w - x),
+                h: BLOCK_SIZE.min(r.y + r.h - y),
+            })
+        })
+    });
+    for r in pieces {
+        buf.clear();
+        for row in r.y..r.y + r.h {
+            let start = row * stride + r.x * 4;
+            buf.extend_from_slice(&data[start..start + r.w * 4]);
+        }
+        if pixfmt == Pixfmt::RGBA {
+            buf.chunks_exact_mut(4).for_each(|p| p.swap(0, 2));
+        }
+        tiles.push(TileFrame {
+            x: r.x as _,
+            y: r.y as _,
+            width: r.w as _,
+            height: r.h as _,
+            data: compress(&buf).into(),
+            ..Default::default()
+        });
+    }
+    let mut vf = VideoFrame::new();
+    vf.set_tiles(TileFrames {
+        tiles: tiles.into(),
+        width: width as _,
+        height: height as _,
+        ..Default::default()
+    });
+    vf
+}
+
+/// Paint tiles over the last decoded image, false if they do not belong to it.
+pub fn apply_tiles(tiles: &TileFrames, rgb: &mut ImageRgb) -> bool {
+    if rgb.w == 0 || rgb.w != tiles.width as usize || rgb.h != tiles.height as usize {
+        return false;
+    }
+    let bytes_per_pixel = match rgb.fmt() {
+        ImageFormat::Raw => 3,
+        ImageFormat::ARGB | ImageFormat::ABGR => 4,
+    };
+    let align = rgb.stride().max(1);
+    let bytes_per_row = (rgb.w * bytes_per_pixel + align - 1) & !(align - 1);
+    let mut painted = false;
+    for tile in tiles.tiles.iter() {
+        let (x, y, w, h) = (
+            tile.x as usize,
+            tile.y as usize,
+            tile.width as usize,
+            tile.height as usize,
+        );
+        if tile.x < 0 || tile.y < 0 || x + w > rgb.w || y + h > rgb.h {
+            continue;
+        }
+        let data = decompress(&tile.data);
+        if data.len() != w * h * 4 {
+            continue;
+        }
+        for (row, src) in data.chunks_exact(w * 4).enumerate() {
+            let start = (y + row) * bytes_per_row + x * bytes_per_pixel;
+            let dst = &mut rgb.raw[start..start + w * bytes_per_pixel];
+            match rgb.fmt() {
+                // libyuv ARGB is BGRA in memory
+                ImageFormat::ARGB => dst.copy_from_slice(src),
+                ImageFormat::ABGR => {
+                    for (d, s) in dst.chunks_exact_mut(4).zip(src.chunks_exact(4)) {
+                        d.copy_from_slice(&[s[2], s[1], s[0], s[3]]);
+                    }
+                }
+                ImageFormat::Raw => {
+                    for (d, s) in dst.chunks_exact_mut(3).zip(src.chunks_exact(4)) {
+                        d.copy_from_slice(&[s[2], s[1], s[0]]);
+                    }
+                }
+            }
+        }
+        painted = true;
+    }
+    painted
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_diff() {
+        let (w, h) = (200, 100);
+        let stride = w * 4;
+        let mut data = vec![0u8; stride * h];
+        let mut tracker = DirtyTracker::new();
+        assert_eq!(
+            tracker.diff(&data, w, h, stride),
+            vec![Rect { x: 0, y: 0, w, h }]
+        );
+        assert!(tracker.diff(&data, w, h, stride).is_empty());
+
+        // a cursor on the border of four blocks
+        for y in 60..70 {
+            data[y * stride + 63 * 4..y * stride + 66 * 4].fill(0xff);
+        }
+        assert_eq!(
+            tracker.diff(&data, w, h, stride),
+            vec![Rect {
+                x: 0,
+                y: 0,
+                w: 128,
+                h: 100
+            }]
+        );
+
+        data[99 * stride + 199 * 4] = 1;
+        assert_eq!(
+            tracker.diff(&data, w, h, stride),
+            vec![Rect {
+                x: 192,
+                y: 64,
+                w: 8,
+                h: 36
+            }]
+        );
+    }
+
+    #[test]
+    fn test_tiles() {
+        let (w, h) = (4, 2);
+        let stride = w * 4;
+        let data: Vec<u8> = (0..stride * h).map(|i| i as u8).collect();
+        let rect = Rect {
+            x: 1,
+            y: 1,
+            w: 2,
+            h: 1,
+        };
+        let vf = encode_tiles(&data, w, h, stride, Pixfmt::BGRA, &[rect]);
+        let mut rgb = ImageRgb::new(ImageFormat::ABGR, 1);
+        rgb.w = w;
+        rgb.h = h;
+        rgb.raw = vec![0; stride * h];
+        assert!(apply_tiles(vf.tiles(), &mut rgb));
+        assert_eq!(&rgb.raw[20..28], &[22, 21, 20, 23, 26, 25, 24, 27]);
+        assert!(rgb.raw[..20].iter().all(|x| *x == 0));
+
+        rgb.w = 8;
+        assert!(!apply_tiles(vf.tiles(), &mut rgb));
+    }
+}
@@ -112,7 +112,7 @@ impl EncoderApi for HwEncoder {
             }
             Ok(vf)
         } else {
-            Err(anyhow!("no valid frame"))
+            Err(anyhow!(crate::codec::ENCODE_NO_FRAME))
         }
     }
 
@@ -5,6 +5,8 @@ use crate::common::{
 };
 use std::{io, time::Duration};
 
+pub use x11::{activate_window, WindowCapturer};
+
 pub enum Capturer {
     X11(x11::Capturer),
     WAYLAND(wayland::Capturer),
@@ -0,0 +1,784 @@
+// Fragmented MP4 for the H.264/H.265 recordings, with the Opus audio of the
+// session as a second track.
+//
+// The header is written with the first key frame, whose parameter sets make
+// the codec configuration, then every second or key frame the pending samples
+// go out as a `moof` and `mdat` pair, so a recording cut short still plays.
+// The parameter sets stay in the samples too (`avc3`/`hev1`), as the stream
+// may change its resolution. Closing the file adds a `mfra` index for seeking
+// and fills in the duration and the chapters reserved in the header.
+
+use std::{
+    fs::File,
+    io::{self, Seek, SeekFrom, Write},
+};
+
+const VIDEO_TRACK: u32 = 1;
+const AUDIO_TRACK: u32 = 2;
+// the video pts are milliseconds
+const VIDEO_TIMESCALE: u32 = 1000;
+// Opus is always decoded at 48kHz in MP4
+const AUDIO_TIMESCALE: u32 = 48000;
+const FRAGMENT_MS: u64 = 1000;
+// the space in `moov` for the chapters, a `free` box until the file is closed
+const CHAPTER_SPACE: usize = 768;
+const DEFAULT_FRAME_MS: u32 = 33;
+
+const SAMPLE_SYNC: u32 = 0x0200_0000;
+const SAMPLE_NON_SYNC: u32 = 0x0101_0000;
+
+struct Sample {
+    data: Vec<u8>,
+    time: u64,
+    duration: u32,
+    key: bool,
+}
+
+pub struct Mp4Writer {
+    file: File,
+    hevc: bool,
+    width: u16,
+    height: u16,
+    // (channels, sample rate) of the Opus track
+    audio: Option<(u32, u32)>,
+    // the file offsets of the `mehd` duration and the chapter space, once the
+    // header is written
+    header: Option<(u64, u64)>,
+    offset: u64,
+    sequence: u32,
+    video: Vec<Sample>,
+    audio_samples: Vec<Sample>,
+    // the decode time of the next audio sample
+    audio_time: Option<u64>,
+    video_end: u64,
+    // (time, moof offset, sample number) of the key frames
+    random_access: Vec<(u64, u64, u32)>,
+    chapters: Vec<(u64, String)>,
+}
+
+impl Mp4Writer {
+    pub fn new(
+        file: File,
+        hevc: bool,
+        width: usize,
+        height: usize,
+        audio: Option<(u32, u32)>,
+    ) -> Self {
+        Mp4Writer {
+            file,
+            hevc,
+            width: width.min(u16::MAX as _) as _,
+            height: height.min(u16::MAX as _) as _,
+            audio,
+            header: None,
+            offset: 0,
+            sequence: 0,
+            video: vec![],
+            audio_samples: vec![],
+            audio_time: None,
+            video_end: 0,
+            random_access: vec![],
+            chapters: vec![],
+        }
+    }
+
+    /// Write an Annex B access unit at `pts` milliseconds. Returns false if it
+    /// is dropped, which every frame before the first key frame is.
+    pub fn write_video(&mut self, data: &[u8], pts: i64, key: bool) -> io::Result<bool> {
+        let nals = nal_units(data);
+        if self.header.is_none() {
+            if !key {
+                return Ok(false);
+            }
+            let config = if self.hevc { hvcc(&nals) } else { avcc(&nals) };
+            let Some(config) = config else {
+                return Ok(false);
+            };
+            self.write_header(config)?;
+        }
+        let time = pts.max(0) as u64;
+        if let Some(last) = self.video.last_mut() {
+            if time < last.time {
+                return Ok(false);
+            }
+            last.duration = (time - last.time).max(1) as _;
+            if key || time - self.video[0].time >= FRAGMENT_MS {
+                self.flush()?;
+            }
+        }
+        let mut sample = Vec::with_capacity(data.len() + 4 * nals.len());
+        for nal in nals.iter().filter(|nal| !is_aud(nal, self.hevc)) {
+            sample.extend_from_slice(&(nal.len() as u32).to_be_bytes());
+            sample.extend_from_slice(nal);
+        }
+        self.video.push(Sample {
+            data: sample,
+            time,
+            duration: DEFAULT_FRAME_MS,
+            key,
+        });
+        Ok(true)
+    }
+
+    /// Write an Opus packet at `pts` milliseconds on the clock of the video.
+    pub fn write_audio(&mut self, data: &[u8], pts: i64) -> bool {
+        if self.header.is_none() || self.audio.is_none() {
+            return false;
+        }
+        let duration = opus_samples(data);
+        if duration == 0 {
+            return false;
+        }
+        let stamp = pts.max(0) as u64 * (AUDIO_TIMESCALE / VIDEO_TIMESCALE) as u64;
+        let time = self.audio_time.get_or_insert(stamp);
+        // resync after a gap, e.g. the peer muted, but not on jitter
+        if stamp > *time + AUDIO_TIMESCALE as u64 / 5 {
+            *time = stamp;
+        }
+        self.audio_samples.push(Sample {
+            data: data.to_vec(),
+            time: *time,
+            duration,
+            key: true,
+        });
+        *time += duration as u64;
+        // the screen may not change for long, the pending frame waits for
+        // the next one to know its duration
+        let first = self.audio_samples[0].time;
+        if *time - first >= FRAGMENT_MS * (AUDIO_TIMESCALE / VIDEO_TIMESCALE) as u64 {
+            let last = self.video.pop();
+            let res = self.flush();
+            self.video.extend(last);
+            return res.is_ok();
+        }
+        true
+    }
+
+    /// Mark a chapter at `pts` milliseconds.
+    pub fn add_chapter(&mut self, pts: i64, title: &str) {
+        self.chapters.push((pts.max(0) as _, title.to_owned()));
+    }
+
+    /// Write the pending samples and the index, and fill in the header.
+    pub fn finish(mut self) -> io::Result<()> {
+        let Some((mehd, chapters)) = self.header else {
+            return Ok(());
+        };
+        self.flush()?;
+        let mut mfra = self.mfra();
+        let size = mfra.len() as u32 + 16;
+        mfra.extend(full_box(b"mfro", 0, 0, &size.to_be_bytes()));
+        write_u32(&mut mfra, 0, size);
+        self.file.write_all(&mfra)?;
+        let audio_end =
+            self.audio_time.unwrap_or_default() / (AUDIO_TIMESCALE / VIDEO_TIMESCALE) as u64;
+        self.file.seek(SeekFrom::Start(mehd))?;
+        self.file
+            .write_all(&self.video_end.max(audio_end).to_be_bytes())?;
+        if !self.chapters.is_empty() {
+            self.file.seek(SeekFrom::Start(chapters))?;
+            self.file
+                .write_all(&chapter_box(&self.chapters, CHAPTER_SPACE))?;
+        }
+        self.file.sync_all()
+    }
+
+    fn write_header(&mut self, config: Vec<u8>) -> io::Result<()> {
+        let mut brands = b"isom".to_vec();
+        brands.extend_from_slice(&0x200u32.to_be_bytes());
+        brands.extend_from_slice(b"isomiso6mp41");
+        let ftyp = mp4_box(b"ftyp", &brands);
+        let mvhd = self.mvhd();
+        let mvex = self.mvex();
+        let mut moov = mvhd.clone();
+        moov.extend_from_slice(&mvex);
+        moov.extend(mp4_box(b"free", &vec![0; CHAPTER_SPACE - 8]));
+        moov.extend(self.video_trak(config));
+        if let Some((channels, sample_rate)) = self.audio {
+            moov.extend(self.audio_trak(channels, sample_rate));
+        }
+        let moov = mp4_box(b"moov", &moov);
+        let mut header = ftyp;
+        let mehd = header.len() + 8 + mvhd.len() + 8 + 12;
+        let chapters = header.len() + 8 + mvhd.len() + mvex.len();
+        header.extend(moov);
+        self.file.write_all(&header)?;
+        self.offset = header.len() as _;
+        self.header = Some((mehd as _, chapters as _));
+        Ok(())
+    }
+
+    fn flush(&mut self) -> io::Result<()> {
+        if self.video.is_empty() && self.audio_samples.is_empty() {
+            return Ok(());
+        }
+        self.sequence += 1;
+        // the sizes do not depend on the data offsets, so the first pass
+        // gives the size of `moof`
+        let moof_len = self.moof(0).len();
+        let moof = self.moof(moof_len as u32 + 8);
+        let data_len: usize = self
+            .video
+            .iter()
+            .chain(self.audio_samples.iter())
+            .map(|s| s.data.len())
+            .sum();
+        let mut fragment = moof;
+        fragment.extend_from_slice(&(data_len as u32 + 8).to_be_bytes());
+        fragment.extend_from_slice(b"mdat");
+        for sample in self.video.iter().chain(self.audio_samples.iter()) {
+            fragment.extend_from_slice(&sample.data);
+        }
+        if let Some(i) = self.video.iter().position(|s| s.key) {
+            self.random_access
+                .push((self.video[i].time, self.offset, i as u32 + 1));
+        }
+        if let Some(last) = self.video.last() {
+            self.video_end = last.time + last.duration as u64;
+        }
+        self.file.write_all(&fragment)?;
+        self.offset += fragment.len() as u64;
+        self.video.clear();
+        self.audio_samples.clear();
+        Ok(())
+    }
+
+    fn moof(&self, data_offset: u32) -> Vec<u8> {
+        let mut moof = full_box(b"mfhd", 0, 0, &self.sequence.to_be_bytes());
+        let mut offset = data_offset;
+        for (track, samples) in [
+            (VIDEO_TRACK, &self.video),
+            (AUDIO_TRACK, &self.audio_samples),
+        ] {
+            if samples.is_empty() {
+                continue;
+            }
+            let video = track == VIDEO_TRACK;
+            // default-base-is-moof
+            let mut traf = full_box(b"tfhd", 0, 0x020000, &track.to_be_bytes());
+            traf.extend(full_box(b"tfdt", 1, 0, &samples[0].time.to_be_bytes()));
+            let mut trun = (samples.len() as u32).to_be_bytes().to_vec();
+            trun.extend_from_slice(&offset.to_be_bytes());
+            for sample in samples.iter() {
+                trun.extend_from_slice(&sample.duration.to_be_bytes());
+                trun.extend_from_slice(&(sample.data.len() as u32).to_be_bytes());
+                if video {
+                    let flags = if sample.key {
+                        SAMPLE_SYNC
+                    } else {
+                        SAMPLE_NON_SYNC
+                    };
+                    trun.extend_from_slice(&flags.to_be_bytes());
+                }
+                offset += sample.data.len() as u32;
+            }
+            // data offset, sample duration and size, and sample flags for the video
+            let flags = if video { 0x000701 } else { 0x000301 };
+            traf.extend(full_box(b"trun", 0, flags, &trun));
+            moof.extend(mp4_box(b"traf", &traf));
+        }
+        mp4_box(b"moof", &moof)
+    }
+
+    fn mfra(&self) -> Vec<u8> {
+        let mut tfra = VIDEO_TRACK.to_be_bytes().to_vec();
+        // 1 byte traf and trun numbers, 4 bytes sample numbers
+        tfra.extend_from_slice(&3u32.to_be_bytes());
+        tfra.extend_from_slice(&(self.random_access.len() as u32).to_be_bytes());
+        for (time, moof, sample) in self.random_access.iter() {
+            tfra.extend_from_slice(&time.to_be_bytes());
+            tfra.extend_from_slice(&moof.to_be_bytes());
+            tfra.extend_from_slice(&[1, 1]);
+            tfra.extend_from_slice(&sample.to_be_bytes());
+        }
+        mp4_box(b"mfra", &full_box(b"tfra", 1, 0, &tfra))
+    }
+
+    fn mvhd(&self) -> Vec<u8> {
+        let mut mvhd = vec![0; 8]; // creation and modification time
+        mvhd.extend_from_slice(&VIDEO_TIMESCALE.to_be_bytes());
+        mvhd.extend_from_slice(&0u32.to_be_bytes()); // duration, in mehd
+        mvhd.extend_from_slice(&0x0001_0000u32.to_be_bytes()); // rate
+        mvhd.extend_from_slice(&0x0100u16.to_be_bytes()); // volume
+        mvhd.extend_from_slice(&[0; 10]);
+        mvhd.extend(matrix());
+        mvhd.extend_from_slice(&[0; 24]);
+        mvhd.extend_from_slice(&(AUDIO_TRACK + 1).to_be_bytes());
+        full_box(b"mvhd", 0, 0, &mvhd)
+    }
+
+    fn mvex(&self) -> Vec<u8> {
+        let mut mvex = full_box(b"mehd", 1, 0, &0u64.to_be_bytes());
+        let tracks = if self.audio.is_some() { 2 } else { 1 };
+        for &(track, flags) in [(VIDEO_TRACK, SAMPLE_NON_SYNC), (AUDIO_TRACK, SAMPLE_SYNC)]
+            .iter()
+            .take(tracks)
+        {
+            let mut trex = track.to_be_bytes().to_vec();
+            trex.extend_from_slice(&1u32.to_be_bytes()); // sample description
+            trex.extend_from_slice(&[0; 8]); // duration and size
+            trex.extend_from_slice(&flags.to_be_bytes());
+            mvex.extend(full_box(b"trex", 0, 0, &trex));
+        }
+        mp4_box(b"mvex", &mvex)
+    }
+
+    fn video_trak(&self, config: Vec<u8>) -> Vec<u8> {
+        let mut entry = vec![0; 6];
+        entry.extend_from_slice(&1u16.to_be_bytes()); // data reference
+        entry.extend_from_slice(&[0; 16]);
+        entry.extend_from_slice(&self.width.to_be_bytes());
+        entry.extend_from_slice(&self.height.to_be_bytes());
+        entry.extend_from_slice(&0x0048_0000u32.to_be_bytes()); // 72 dpi
+        entry.extend_from_slice(&0x0048_0000u32.to_be_bytes());
+        entry.extend_from_slice(&0u32.to_be_bytes());
+        entry.extend_from_slice(&1u16.to_be_bytes()); // frame count
+        entry.extend_from_slice(&[0; 32]); // compressor name
+        entry.extend_from_slice(&0x0018u16.to_be_bytes()); // depth
+        entry.extend_from_slice(&(-1i16).to_be_bytes());
+        entry.extend(config);
+        let entry = mp4_box(if self.hevc { b"hev1" } else { b"avc3" }, &entry);
+        let mut vmhd = 0u16.to_be_bytes().to_vec(); // graphics mode
+        vmhd.extend_from_slice(&[0; 6]);
+        trak(
+            VIDEO_TRACK,
+            (self.width, self.height, 0),
+            VIDEO_TIMESCALE,
+            b"vide",
+            "VideoHandler",
+            full_box(b"vmhd", 0, 1, &vmhd),
+            entry,
+        )
+    }
+
+    fn audio_trak(&self, channels: u32, sample_rate: u32) -> Vec<u8> {
+        let mut entry = vec![0; 6];
+        entry.extend_from_slice(&1u16.to_be_bytes()); // data reference
+        entry.extend_from_slice(&[0; 8]);
+        entry.extend_from_slice(&(channels as u16).to_be_bytes());
+        entry.extend_from_slice(&16u16.to_be_bytes()); // sample size
+        entry.extend_from_slice(&[0; 4]);
+        entry.extend_from_slice(&(AUDIO_TIMESCALE << 16).to_be_bytes());
+        // https://opus-codec.org/docs/opus_in_isobmff.html
+        let mut dops = vec![0, channels as u8];
+        dops.extend_from_slice(&0u16.to_be_bytes()); // pre-skip
+        dops.extend_from_slice(&sample_rate.to_be_bytes());
+        dops.extend_from_slice(&0i16.to_be_bytes()); // output gain
+        dops.push(0); // channel mapping family, mono or stereo
+        entry.extend(mp4_box(b"dOps", &dops));
+        trak(
+            AUDIO_TRACK,
+            (0, 0, 0x0100),
+            AUDIO_TIMESCALE,
+            b"soun",
+            "SoundHandler",
+            full_box(b"smhd", 0, 0, &[0; 4]),
+            mp4_box(b"Opus", &entry),
+        )
+    }
+}
+
+// (width, height, volume) of the track
+fn trak(
+    track: u32,
+    (width, height, volume): (u16, u16, u16),
+    timescale: u32,
+    handler: &[u8; 4],
+    name: &str,
+    media_header: Vec<u8>,
+    entry: Vec<u8>,
+) -> Vec<u8> {
+    let mut tkhd = vec![0; 8]; // creation and modification time
+    tkhd.extend_from_slice(&track.to_be_bytes());
+    tkhd.extend_from_slice(&[0; 20]); // duration, layer and alternate group
+    tkhd.extend_from_slice(&volume.to_be_bytes());
+    tkhd.extend_from_slice(&[0; 2]);
+    tkhd.extend(matrix());
+    tkhd.extend_from_slice(&((width as u32) << 16).to_be_bytes());
+    tkhd.extend_from_slice(&((height as u32) << 16).to_be_bytes());
+    // enabled and in movie
+    let mut trak = full_box(b"tkhd", 0, 3, &tkhd);
+
+    let mut mdhd = vec![0; 8];
+    mdhd.extend_from_slice(&timescale.to_be_bytes());
+    mdhd.extend_from_slice(&0u32.to_be_bytes());
+    mdhd.extend_from_slice(&0x55c4u16.to_be_bytes()); // und
+    mdhd.extend_from_slice(&[0; 2]);
+    let mut mdia = full_box(b"mdhd", 0, 0, &mdhd);
+    let mut hdlr = vec![0; 4];
+    hdlr.extend_from_slice(handler);
+    hdlr.extend_from_slice(&[0; 12]);
+    hdlr.extend_from_slice(name.as_bytes());
+    hdlr.push(0);
+    mdia.extend(full_box(b"hdlr", 0, 0, &hdlr));
+
+    let mut minf = media_header;
+    let mut dref = 1u32.to_be_bytes().to_vec();
+    // the data is in this file
+    dref.extend(full_box(b"url ", 0, 1, &[]));
+    minf.extend(mp4_box(b"dinf", &full_box(b"dref", 0, 0, &dref)));
+    let mut stsd = 1u32.to_be_bytes().to_vec();
+    stsd.extend(entry);
+    // the samples are in the fragments
+    let mut stbl = full_box(b"stsd", 0, 0, &stsd);
+    stbl.extend(full_box(b"stts", 0, 0, &[0; 4]));
+    stbl.extend(full_box(b"stsc", 0, 0, &[0; 4]));
+    stbl.extend(full_box(b"stsz", 0, 0, &[0; 8]));
+    stbl.extend(full_box(b"stco", 0, 0, &[0; 4]));
+    minf.extend(mp4_box(b"stbl", &stbl));
+    mdia.extend(mp4_box(b"minf", &minf));
+    trak.extend(mp4_box(b"mdia", &mdia));
+    mp4_box(b"trak", &trak)
+}
+
+fn matrix() -> Vec<u8> {
+    [0x0001_0000u32, 0, 0, 0, 0x0001_0000, 0, 0, 0, 0x4000_0000]
+        .iter()
+        .flat_map(|v| v.to_be_bytes())
+        .collect()
+}
+
+fn mp4_box(typ: &[u8; 4], payload: &[u8]) -> Vec<u8> {
+    let mut b = Vec::with_capacity(payload.len() + 8);
+    b.extend_from_slice(&(payload.len() as u32 + 8).to_be_bytes());
+    b.extend_from_slice(typ);
+    b.extend_from_slice(payload);
+    b
+}
+
+fn full_box(typ: &[u8; 4], version: u8, flags: u32, payload: &[u8]) -> Vec<u8> {
+    let mut p = ((version as u32) << 24 | flags).to_be_bytes().to_vec();
+    p.extend_from_slice(payload);
+    mp4_box(typ, &p)
+}
+
+fn write_u32(buf: &mut [u8], pos: usize, v: u32) {
+    buf[pos..pos + 4].copy_from_slice(&v.to_be_bytes());
+}
+
+// Nero chapters in `udta`, which ffmpeg and VLC read, padded with `free` to
+// exactly `space` bytes. The last chapters are dropped if they do not fit.
+fn chapter_box(chapters: &[(u64, String)], space: usize) -> Vec<u8> {
+    let mut count = chapters.len().min(u8::MAX as _);
+    loop {
+        let mut chpl = vec![0; 4];
+        chpl.push(count as u8);
+        for (pts, title) in chapters[..count].iter() {
+            let title = truncate(title, u8::MAX as _);
+            // in 100ns
+            chpl.extend_from_slice(&(pts * 10_000).to_be_bytes());
+            chpl.push(title.len() as u8);
+            chpl.extend_from_slice(title.as_bytes());
+        }
+        let mut udta = mp4_box(b"udta", &full_box(b"chpl", 1, 0, &chpl));
+        let left = space.saturating_sub(udta.len());
+        if udta.len() == space || (udta.len() < space && left >= 8) {
+            if left > 0 {
+                udta.extend(mp4_box(b"free", &vec![0; left - 8]));
+            }
+            return udta;
+        }
+        if count == 0 {
+            return mp4_box(b"free", &vec![0; space - 8]);
+        }
+        count -= 1;
+    }
+}
+
+fn truncate(s: &str, len: usize) -> &str {
+    let mut end = s.len().min(len);
+    while !s.is_char_boundary(end) {
+        end -= 1;
+    }
+    &s[..end]
+}
+
+// The NAL units of an Annex B access unit, or the whole of it without start
+// codes.
+fn nal_units(data: &[u8]) -> Vec<&[u8]> {
+    let mut starts = vec![];
+    let mut i = 0;
+    while i + 3 <= data.len() {
+        if data[i] == 0 && data[i + 1] == 0 && data[i + 2] == 1 {
+            starts.push((i, i + 3));
+            i += 3;
+        } else {
+            i += 1;
+        }
+    }
+    if starts.is_empty() {
+        return if data.is_empty() { vec![] } else { vec![data] };
+    }
+    let mut nals = vec![];
+    for (n, (_, begin)) in starts.iter().enumerate() {
+        let mut end = starts.get(n + 1).map_or(data.len(), |s| s.0);
+        // the leading zero of a 4 bytes start code, or trailing zeros
+        while end > *begin && data[end - 1] == 0 {
+            end -= 1;
+        }
+        if end > *begin {
+            nals.push(&data[*begin..end]);
+        }
+    }
+    nals
+}
+
+fn nal_type(nal: &[u8], hevc: bool) -> u8 {
+    if hevc {
+        (nal[0] >> 1) & 0x3f
+    } else {
+        nal[0] & 0x1f
+    }
+}
+
+fn is_aud(nal: &[u8], hevc: bool) -> bool {
+    nal_type(nal, hevc) == if hevc { 35 } else { 9 }
+}
+
+fn find_nal<'a>(nals: &[&'a [u8]], hevc: bool, typ: u8, min_len: usize) -> Option<&'a [u8]> {
+    nals.iter()
+        .find(|nal| nal.len() >= min_len && nal_type(nal, hevc) == typ)
+        .copied()
+}
+
+fn avcc(nals: &[&[u8]]) -> Option<Vec<u8>> {
+    let sps = find_nal(nals, false, 7, 4)?;
+    let pps = find_nal(nals, false, 8, 1)?;
+    // profile, compatibility and level from the sps, 4 bytes lengths
+    let mut avcc = vec![1, sps[1], sps[2], sps[3], 0xff, 0xe1];
+    avcc.extend_from_slice(&(sps.len() as u16).to_be_bytes());
+    avcc.extend_from_slice(sps);
+    avcc.push(1);
+    avcc.extend_from_slice(&(pps.len() as u16).to_be_bytes());
+    avcc.extend_from_slice(pps);
+    Some(mp4_box(b"avcC", &avcc))
+}
+
+fn hvcc(nals: &[&[u8]]) -> Option<Vec<u8>> {
+    let vps = find_nal(nals, true, 32, 3)?;
+    let sps = find_nal(nals, true, 33, 3)?;
+    let pps = find_nal(nals, true, 34, 3)?;
+    // the general profile, tier and level follow the first byte of the sps
+    let rbsp = unescape(&sps[..sps.len().min(32)]);
+    let ptl = rbsp.get(3..15)?;
+    let sub_layers = (rbsp[2] >> 1) & 0x07;
+    let nested = rbsp[2] & 0x01;
+    let mut hvcc = vec![1];
+    hvcc.extend_from_slice(ptl);
+    hvcc.extend_from_slice(&0xf000u16.to_be_bytes()); // min spatial segmentation
+    hvcc.push(0xfc); // parallelism type
+    hvcc.push(0xfd); // 4:2:0, which the encoders write
+    hvcc.push(0xf8); // 8 bits luma
+    hvcc.push(0xf8); // 8 bits chroma
+    hvcc.extend_from_slice(&0u16.to_be_bytes()); // average frame rate
+                                                 // temporal layers, nesting and 4 bytes lengths
+    hvcc.push((sub_layers + 1) << 3 | nested << 2 | 3);
+    hvcc.push(3);
+    for (typ, nal) in [(32, vps), (33, sps), (34, pps)] {
+        // complete, every parameter set is also in the samples
+        hvcc.push(typ);
+        hvcc.extend_from_slice(&1u16.to_be_bytes());
+        hvcc.extend_from_slice(&(nal.len() as u16).to_be_bytes());
+        hvcc.extend_from_slice(nal);
+    }
+    Some(mp4_box(b"hvcC", &hvcc))
+}
+
+// Remove the emulation prevention bytes.
+fn unescape(nal: &[u8]) -> Vec<u8> {
+    let mut out = Vec::with_capacity(nal.len());
+    let mut zeros = 0;
+    for &b in nal {
+        if zeros >= 2 && b == 3 {
+            zeros = 0;
+            continue;
+        }
+        zeros = if b == 0 { zeros + 1 } else { 0 };
+        out.push(b);
+    }
+    out
+}
+
+// The samples at 48kHz of an Opus packet, from its TOC byte, see
+// https://www.rfc-editor.org/rfc/rfc6716#section-3.1
+fn opus_samples(packet: &[u8]) -> u32 {
+    let Some(&toc) = packet.first() else {
+        return 0;
+    };
+    let config = (toc >> 3) as usize;
+    let frame = match config {
+        0..=11 => [480, 960, 1920, 2880][config % 4],
+        12..=15 => [480, 960][config % 2],
+        _ => [120, 240, 480, 960][config % 4],
+    };
+    let frames = match toc & 0x03 {
+        0 => 1,
+        1 | 2 => 2,
+        _ => packet.get(1).map_or(0, |b| (b & 0x3f) as u32),
+    };
+    frame * frames
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use std::convert::TryInto;
+
+    // (type, payload) of the boxes in `data`
+    fn boxes(data: &[u8]) -> Vec<(String, &[u8])> {
+        let mut boxes = vec![];
+        let mut pos = 0;
+        while pos + 8 <= data.len() {
+            let size = u32::from_be_bytes(data[pos..pos + 4].try_into().unwrap()) as usize;
+            assert!(size >= 8 && pos + size <= data.len());
+            let typ = String::from_utf8_lossy(&data[pos + 4..pos + 8]).to_string();
+            boxes.push((typ, &data[pos + 8..pos + size]));
+            pos += size;
+        }
+        assert_eq!(pos, data.len());
+        boxes
+    }
+
+    fn find<'a>(data: &'a [u8], typ: &str) -> &'a [u8] {
+        boxes(data).into_iter().find(|b| b.0 == typ).unwrap().1
+    }
+
+    const SPS: &[u8] = &[0x67, 0x42, 0xc0, 0x1f, 0xda, 0x01, 0x40];
+    const PPS: &[u8] = &[0x68, 0xce, 0x3c, 0x80];
+
+    fn access_unit(key: bool) -> Vec<u8> {
+        let mut data = vec![];
+        if key {
+            for nal in [SPS, PPS] {
+                data.extend_from_slice(&[0, 0, 0, 1]);
+                data.extend_from_slice(nal);
+            }
+        }
+        data.extend_from_slice(&[0, 0, 1]);
+        data.extend_from_slice(if key {
+            &[0x65, 0x88, 0x84][..]
+        } else {
+            &[0x41, 0x9a, 0x00, 0x02][..]
+        });
+        data
+    }
+
+    #[test]
+    fn test_nal_units() {
+        let data = access_unit(true);
+        let nals = nal_units(&data);
+        assert_eq!(nals, vec![SPS, PPS, &[0x65, 0x88, 0x84][..]]);
+        assert_eq!(nal_units(&[0x41, 0x9a]), vec![&[0x41, 0x9a][..]]);
+        assert_eq!(unescape(&[1, 0, 0, 3, 1, 0, 0, 3]), vec![1, 0, 0, 1, 0, 0]);
+    }
+
+    #[test]
+    fn test_opus_samples() {
+        // 20ms CELT
+        assert_eq!(opus_samples(&[31 << 3]), 960);
+        // two 10ms SILK frames
+        assert_eq!(opus_samples(&[1]), 960);
+        // three 2.5ms CELT frames
+        assert_eq!(opus_samples(&[(16 << 3) | 3, 3]), 360);
+        assert_eq!(opus_samples(&[]), 0);
+    }
+
+    #[test]
+    fn test_chapter_box() {
+        let chapters = vec![(0, "Display 1".to_owned()), (1500, "Display 2".to_owned())];
+        let b = chapter_box(&chapters, CHAPTER_SPACE);
+        assert_eq!(b.len(), CHAPTER_SPACE);
+        // version, flags and 4 unknown bytes before the count
+        let chpl = find(find(&b, "udta"), "chpl");
+        assert_eq!(chpl[8], 2);
+        assert_eq!(chpl[9..17], 0u64.to_be_bytes());
+        assert_eq!(chpl[17] as usize, "Display 1".len());
+        assert_eq!(chpl[27..35], 15_000_000u64.to_be_bytes());
+        let many: Vec<_> = (0..100).map(|i| (i, "Display 1".to_owned())).collect();
+        let b = chapter_box(&many, CHAPTER_SPACE);
+        assert_eq!(b.len(), CHAPTER_SPACE);
+        assert!(find(find(&b, "udta"), "chpl")[8] < 100);
+    }
+
+    #[test]
+    fn test_writer() {
+        let path = std::env::temp_dir().join(format!("mp4_test_{}.mp4", std::process::id()));
+        let mut mp4 = Mp4Writer::new(
+            File::create(&path).unwrap(),
+            false,
+            640,
+            480,
+            Some((2, 48000)),
+        );
+        assert!(!mp4.write_video(&access_unit(false), 0, false).unwrap());
+        assert!(!mp4.write_audio(&[31 << 3, 0], 0));
+        for i in 0..60 {
+            assert!(mp4
+                .write_video(&access_unit(i % 40 == 0), 100 + i * 33, i % 40 == 0)
+                .unwrap());
+            assert!(mp4.write_audio(&[31 << 3, 0], 100 + i * 20));
+        }
+        mp4.add_chapter(500, "Display 2");
+        mp4.finish().unwrap();
+        let data = std::fs::read(&path).unwrap();
+        std::fs::remove_file(&path).ok();
+
+        let top: Vec<_> = boxes(&data).into_iter().map(|b| b.0).collect();
+        assert_eq!(top[..2], ["ftyp", "moov"]);
+        assert_eq!(top.last().unwrap(), "mfra");
+        let fragments = top.iter().filter(|t| *t == "moof").count();
+        assert!(fragments >= 2);
+        assert_eq!(fragments, top.iter().filter(|t| *t == "mdat").count());
+
+        let moov = find(&data, "moov");
+        let mehd = find(find(moov, "mvex"), "mehd");
+        // the last frame at 100 + 59 * 33 ms lasts 33 ms
+        assert_eq!(&mehd[4..], &(100 + 60 * 33u64).to_be_bytes());
+        assert_eq!(find(find(moov, "udta"), "chpl")[8], 1);
+        let traks: Vec<_> = boxes(moov).into_iter().filter(|b| b.0 == "trak").collect();
+        assert_eq!(traks.len(), 2);
+        let stbl = find(find(find(traks[0].1, "mdia"), "minf"), "stbl");
+        let avc3 = &find(stbl, "stsd")[8..];
+        assert_eq!(&avc3[4..8], b"avc3");
+        let avcc = find(&avc3[8 + 78..], "avcC");
+        assert_eq!(&avcc[1..4], &SPS[1..4]);
+
+        // the samples of every fragment are where trun says, in the order written
+        let mut pos = 0;
+        let mut times = vec![];
+        for (typ, payload) in boxes(&data) {
+            if typ == "moof" {
+                for (_, traf) in boxes(payload).into_iter().filter(|b| b.0 == "traf") {
+                    let tfhd = find(traf, "tfhd");
+                    let trun = find(traf, "trun");
+                    let count = u32::from_be_bytes(trun[4..8].try_into().unwrap()) as usize;
+                    let offset = u32::from_be_bytes(trun[8..12].try_into().unwrap()) as usize;
+                    let stride = if trun[3] == 0x01 && trun[2] == 0x07 {
+                        12
+                    } else {
+                        8
+                    };
+                    let size = u32::from_be_bytes(trun[16..20].try_into().unwrap()) as usize;
+                    let sample = &data[pos + offset..pos + offset + size];
+                    if tfhd[4..8] == VIDEO_TRACK.to_be_bytes() {
+                        // length prefixed nal units
+                        let mut nal = 0;
+                        while nal < size {
+                            nal += 4 + u32::from_be_bytes(sample[nal..nal + 4].try_into().unwrap())
+                                as usize;
+                        }
+                        assert_eq!(nal, size);
+                        let tfdt = find(traf, "tfdt");
+                        times.push(u64::from_be_bytes(tfdt[4..12].try_into().unwrap()));
+                    } else {
+                        assert_eq!(sample, &[31 << 3, 0]);
+                    }
+                    assert_eq!(trun.len(), 12 + count * stride);
+                }
+            }
+            pos += payload.len() + 8;
+        }
+        assert_eq!(times[0], 100);
+        assert!(times.contains(&(100 + 40 * 33)));
+    }
+}
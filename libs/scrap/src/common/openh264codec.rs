@@ -0,0 +1,229 @@
+// Software H.264 through openh264, for hosts and viewers without hardware codecs.
+// https://github.com/ralfbiedert/openh264-rs
+
+use crate::{
+    codec::{base_bitrate, EncoderApi, EncoderCfg, Quality},
+    EncodeYuvFormat, GoogleImage, ImageRgb, Pixfmt,
+};
+use hbb_common::{
+    anyhow::anyhow,
+    bail,
+    message_proto::{Chroma, EncodedVideoFrame, EncodedVideoFrames, VideoFrame},
+    ResultType,
+};
+use openh264::{
+    decoder::{DecodedYUV, Decoder},
+    encoder::{Encoder, EncoderConfig, FrameType},
+    formats::YUVSource,
+};
+
+/// The encoder name in `CodecName::H264`, hardware encoders use their ffmpeg names.
+pub const NAME: &str = "openh264";
+const STRIDE_ALIGN: usize = 32;
+
+pub struct OpenH264Encoder {
+    encoder: Encoder,
+    yuvfmt: EncodeYuvFormat,
+    bitrate: u32, // kbs
+    keyframe_interval: Option<usize>,
+    frames: usize,
+}
+
+impl EncoderApi for OpenH264Encoder {
+    fn new(cfg: EncoderCfg, _i444: bool) -> ResultType<Self>
+    where
+        Self: Sized,
+    {
+        match cfg {
+            EncoderCfg::HW(config) => {
+                // openh264 takes even sizes only
+                let width = (config.width + 1) & !1;
+                let height = (config.height + 1) & !1;
+                let bitrate = Self::convert_bitrate(config.quality, width, height);
+                Ok(Self {
+                    encoder: Self::create(width, height, bitrate)?,
+                    yuvfmt: Self::get_yuvfmt(width, height),
+                    bitrate,
+                    keyframe_interval: config.keyframe_interval,
+                    frames: 0,
+                })
+            }
+            _ => Err(anyhow!("encoder type mismatch")),
+        }
+    }
+
+    fn encode_to_message(&mut self, frame: &[u8], ms: i64) -> ResultType<VideoFrame> {
+        let fmt = &self.yuvfmt;
+        if frame.len() < fmt.v + fmt.stride[2] * fmt.h / 2 {
+            bail!("len not enough");
+        }
+        if let Some(interval) = self.keyframe_interval {
+            if interval > 0 && self.frames % interval == 0 {
+                self.encoder.force_intra_frame();
+            }
+        }
+        self.frames += 1;
+        let bitstream = self.encoder.encode(&YuvFrame { data: frame, fmt })?;
+        let key = matches!(bitstream.frame_type(), FrameType::IDR | FrameType::I);
+        let data = bitstream.to_vec();
+        if data.is_empty() {
+            bail!(crate::codec::ENCODE_NO_FRAME);
+        }
+        let mut vf = VideoFrame::new();
+        vf.set_h264s(EncodedVideoFrames {
+            frames: vec![EncodedVideoFrame {
+                data: data.into(),
+                key,
+                pts: ms,
+                ..Default::default()
+            }]
+            .into(),
+            ..Default::default()
+        });
+        Ok(vf)
+    }
+
+    fn yuvfmt(&self) -> EncodeYuvFormat {
+        self.yuvfmt.clone()
+    }
+
+    fn set_quality(&mut self, quality: Quality) -> ResultType<()> {
+        let bitrate = Self::convert_bitrate(quality, self.yuvfmt.w, self.yuvfmt.h);
+        if bitrate != self.bitrate {
+            // no bitrate setter in the safe api, a new encoder starts with a key frame
+            self.encoder = Self::create(self.yuvfmt.w, self.yuvfmt.h, bitrate)?;
+            self.bitrate = bitrate;
+        }
+        Ok(())
+    }
+
+    fn bitrate(&self) -> u32 {
+        self.bitrate
+    }
+}
+
+impl OpenH264Encoder {
+    fn create(width: usize, height: usize, bitrate: u32) -> ResultType<Encoder> {
+        let config = EncoderConfig::new(width as _, height as _)
+            .set_bitrate_bps(bitrate * 1000)
+            .max_frame_rate(30.0)
+            .enable_skip_frame(false);
+        Ok(Encoder::with_config(config)?)
+    }
+
+    fn convert_bitrate(quality: Quality, width: usize, height: usize) -> u32 {
+        let b = match quality {
+            Quality::Best => 150,
+            Quality::Balanced => 100,
+            Quality::Low => 50,
+            Quality::Custom(b) => b,
+        };
+        base_bitrate(width as _, height as _) * b / 100
+    }
+
+    fn get_yuvfmt(width: usize, height: usize) -> EncodeYuvFormat {
+        let stride_y = (width + STRIDE_ALIGN - 1) & !(STRIDE_ALIGN - 1);
+        let stride_uv = stride_y / 2;
+        let u = stride_y * height;
+        EncodeYuvFormat {
+            pixfmt: Pixfmt::I420,
+            w: width,
+            h: height,
+            stride: vec![stride_y, stride_uv, stride_uv],
+            u,
+            v: u + stride_uv * height / 2,
+        }
+    }
+}
+
+struct YuvFrame<'a> {
+    data: &'a [u8],
+    fmt: &'a EncodeYuvFormat,
+}
+
+impl YUVSource for YuvFrame<'_> {
+    fn width(&self) -> i32 {
+        self.fmt.w as _
+    }
+
+    fn height(&self) -> i32 {
+        self.fmt.h as _
+    }
+
+    fn y(&self) -> &[u8] {
+        &self.data[..self.fmt.u]
+    }
+
+    fn u(&self) -> &[u8] {
+        &self.data[self.fmt.u..self.fmt.v]
+    }
+
+    fn v(&self) -> &[u8] {
+        &self.data[self.fmt.v..]
+    }
+
+    fn y_stride(&self) -> i32 {
+        self.fmt.stride[0] as _
+    }
+
+    fn u_stride(&self) -> i32 {
+        self.fmt.stride[1] as _
+    }
+
+    fn v_stride(&self) -> i32 {
+        self.fmt.stride[2] as _
+    }
+}
+
+pub struct OpenH264Decoder {
+    decoder: Decoder,
+}
+
+impl OpenH264Decoder {
+    pub fn new() -> ResultType<Self> {
+        Ok(Self {
+            decoder: Decoder::new()?,
+        })
+    }
+
+    // rgb [in/out] fmt and stride must be set in ImageRgb
+    pub fn decode(&mut self, frames: &EncodedVideoFrames, rgb: &mut ImageRgb) -> ResultType<bool> {
+        let mut ret = false;
+        for h264 in frames.frames.iter() {
+            if let Some(yuv) = self.decoder.decode(&h264.data)? {
+                DecodedImage(&yuv).to(rgb);
+                ret = true;
+            }
+        }
+        Ok(ret)
+    }
+}
+
+struct DecodedImage<'a, 'b>(&'a DecodedYUV<'b>);
+
+impl GoogleImage for DecodedImage<'_, '_> {
+    fn width(&self) -> usize {
+        self.0.dimension_rgb().0
+    }
+
+    fn height(&self) -> usize {
+        self.0.dimension_rgb().1
+    }
+
+    fn stride(&self) -> Vec<i32> {
+        let (y, u, v) = self.0.strides_yuv();
+        vec![y as _, u as _, v as _]
+    }
+
+    fn planes(&self) -> Vec<*mut u8> {
+        vec![
+            self.0.y_with_stride().as_ptr() as _,
+            self.0.u_with_stride().as_ptr() as _,
+            self.0.v_with_stride().as_ptr() as _,
+        ]
+    }
+
+    fn chroma(&self) -> Chroma {
+        Chroma::I420
+    }
+}
@@ -1,24 +1,27 @@
+use super::mp4::Mp4Writer;
 use crate::CodecFormat;
-#[cfg(feature = "hwcodec")]
-use hbb_common::anyhow::anyhow;
 use hbb_common::{
     bail, chrono,
     config::Config,
     log,
-    message_proto::{message, video_frame, EncodedVideoFrame, Message},
+    message_proto::{
+        message, video_frame, AudioFormat, AudioFrame, EncodedVideoFrame, Message, VideoFrame,
+    },
+    sodiumoxide::{
+        base64,
+        crypto::{box_, hash::sha256, sealedbox, secretbox, sign},
+    },
     ResultType,
 };
-#[cfg(feature = "hwcodec")]
-use hwcodec::mux::{MuxContext, Muxer};
 use std::{
     fs::{File, OpenOptions},
-    io,
+    io::{self, BufWriter, Read, Seek, SeekFrom, Write},
     ops::{Deref, DerefMut},
-    path::PathBuf,
+    path::{Path, PathBuf},
     sync::mpsc::Sender,
     time::Instant,
 };
-use webm::mux::{self, Segment, Track, VideoTrack, Writer};
+use webm::mux::{self, AudioTrack, Segment, Track, VideoTrack, Writer};
 
 const MIN_SECS: u64 = 1;
 
@@ -31,6 +34,10 @@ pub struct RecorderContext {
     pub width: usize,
     pub height: usize,
     pub format: CodecFormat,
+    // the Opus audio of the session, recorded along if known when it starts
+    pub audio: Option<AudioFormat>,
+    // write the events of the session beside the recording, see `EventLog`
+    pub events: bool,
     pub tx: Option<Sender<RecordState>>,
 }
 
@@ -73,6 +80,12 @@ pub trait RecorderApi {
     where
         Self: Sized;
     fn write_video(&mut self, frame: &EncodedVideoFrame) -> bool;
+    /// `pts` is in milliseconds, on the clock of the video.
+    fn write_audio(&mut self, _frame: &AudioFrame, _pts: i64) -> bool {
+        false
+    }
+    /// Mark a chapter at `pts`, written when the recording is closed.
+    fn add_chapter(&mut self, _pts: i64, _title: &str) {}
 }
 
 #[derive(Debug)]
@@ -81,12 +94,26 @@ pub enum RecordState {
     NewFrame,
     WriteTail,
     RemoveFile,
+    // the recording is encrypted into this file, which replaces it
+    Encrypted(String),
+    // the signature of the recording is written to this file
+    Signed(String),
 }
 
 pub struct Recorder {
     pub inner: Box<dyn RecorderApi>,
+    // after `inner`, which removes the recording if it is too short
+    events: Option<EventLog>,
     ctx: RecorderContext,
     pts: Option<i64>,
+    // the pts of the first video frame, the recording starts at 0
+    base: Option<i64>,
+    // from the video pts to the clock of the audio pts
+    pts_offset: i64,
+    // the last video pts and when it was written, to stamp the audio and events
+    clock: Option<(i64, Instant)>,
+    // the title of the last chapter, which goes on in a new file
+    chapter: Option<String>,
 }
 
 impl Deref for Recorder {
@@ -106,20 +133,15 @@ impl DerefMut for Recorder {
 impl Recorder {
     pub fn new(mut ctx: RecorderContext) -> ResultType<Self> {
         ctx.set_filename()?;
-        let recorder = match ctx.format {
-            CodecFormat::VP8 | CodecFormat::VP9 | CodecFormat::AV1 => Recorder {
-                inner: Box::new(WebmRecorder::new(ctx.clone())?),
-                ctx,
-                pts: None,
-            },
-            #[cfg(feature = "hwcodec")]
-            _ => Recorder {
-                inner: Box::new(HwRecorder::new(ctx.clone())?),
-                ctx,
-                pts: None,
-            },
-            #[cfg(not(feature = "hwcodec"))]
-            _ => bail!("unsupported codec type"),
+        let recorder = Recorder {
+            inner: Self::new_inner(&ctx)?,
+            events: EventLog::new(&ctx),
+            ctx,
+            pts: None,
+            base: None,
+            pts_offset: 0,
+            clock: None,
+            chapter: None,
         };
         recorder.send_state(RecordState::NewFile(recorder.ctx.filename.clone()));
         Ok(recorder)
@@ -127,26 +149,40 @@ impl Recorder {
 
     fn change(&mut self, mut ctx: RecorderContext) -> ResultType<()> {
         ctx.set_filename()?;
-        self.inner = match ctx.format {
-            CodecFormat::VP8 | CodecFormat::VP9 | CodecFormat::AV1 => {
-                Box::new(WebmRecorder::new(ctx.clone())?)
-            }
-            #[cfg(feature = "hwcodec")]
-            _ => Box::new(HwRecorder::new(ctx.clone())?),
-            #[cfg(not(feature = "hwcodec"))]
-            _ => bail!("unsupported codec type"),
-        };
+        self.inner = Self::new_inner(&ctx)?;
+        if let Some(title) = self.chapter.as_ref() {
+            self.inner.add_chapter(0, title);
+        }
+        self.events = EventLog::new(&ctx);
         self.ctx = ctx;
         self.pts = None;
+        self.base = None;
+        self.clock = None;
         self.send_state(RecordState::NewFile(self.ctx.filename.clone()));
         Ok(())
     }
 
+    fn new_inner(ctx: &RecorderContext) -> ResultType<Box<dyn RecorderApi>> {
+        Ok(match ctx.format {
+            CodecFormat::VP8 | CodecFormat::VP9 | CodecFormat::AV1 => {
+                Box::new(WebmRecorder::new(ctx.clone())?)
+            }
+            CodecFormat::H264 | CodecFormat::H265 => Box::new(Mp4Recorder::new(ctx.clone())?),
+            _ => bail!("unsupported codec type"),
+        })
+    }
+
     pub fn write_message(&mut self, msg: &Message) {
         if let Some(message::Union::VideoFrame(vf)) = &msg.union {
-            if let Some(frame) = &vf.union {
-                self.write_frame(frame).ok();
-            }
+            self.write_video_frame(vf).ok();
+        }
+    }
+
+    pub fn write_video_frame(&mut self, vf: &VideoFrame) -> ResultType<()> {
+        self.pts_offset = vf.pts_offset;
+        match &vf.union {
+            Some(frame) => self.write_frame(frame),
+            None => Ok(()),
         }
     }
 
@@ -160,8 +196,8 @@ impl Recorder {
                     })?;
                 }
                 for f in vp8s.frames.iter() {
-                    self.check_pts(f.pts)?;
-                    self.write_video(f);
+                    let pts = self.check_pts(f.pts)?;
+                    self.write_video(&EncodedVideoFrame { pts, ..f.clone() });
                 }
             }
             video_frame::Union::Vp9s(vp9s) => {
@@ -172,8 +208,8 @@ impl Recorder {
                     })?;
                 }
                 for f in vp9s.frames.iter() {
-                    self.check_pts(f.pts)?;
-                    self.write_video(f);
+                    let pts = self.check_pts(f.pts)?;
+                    self.write_video(&EncodedVideoFrame { pts, ..f.clone() });
                 }
             }
             video_frame::Union::Av1s(av1s) => {
@@ -184,11 +220,10 @@ impl Recorder {
                     })?;
                 }
                 for f in av1s.frames.iter() {
-                    self.check_pts(f.pts)?;
-                    self.write_video(f);
+                    let pts = self.check_pts(f.pts)?;
+                    self.write_video(&EncodedVideoFrame { pts, ..f.clone() });
                 }
             }
-            #[cfg(feature = "hwcodec")]
             video_frame::Union::H264s(h264s) => {
                 if self.ctx.format != CodecFormat::H264 {
                     self.change(RecorderContext {
@@ -197,11 +232,10 @@ impl Recorder {
                     })?;
                 }
                 for f in h264s.frames.iter() {
-                    self.check_pts(f.pts)?;
-                    self.write_video(f);
+                    let pts = self.check_pts(f.pts)?;
+                    self.write_video(&EncodedVideoFrame { pts, ..f.clone() });
                 }
             }
-            #[cfg(feature = "hwcodec")]
             video_frame::Union::H265s(h265s) => {
                 if self.ctx.format != CodecFormat::H265 {
                     self.change(RecorderContext {
@@ -210,8 +244,8 @@ impl Recorder {
                     })?;
                 }
                 for f in h265s.frames.iter() {
-                    self.check_pts(f.pts)?;
-                    self.write_video(f);
+                    let pts = self.check_pts(f.pts)?;
+                    self.write_video(&EncodedVideoFrame { pts, ..f.clone() });
                 }
             }
             _ => bail!("unsupported frame type"),
@@ -220,7 +254,45 @@ impl Recorder {
         Ok(())
     }
 
-    fn check_pts(&mut self, pts: i64) -> ResultType<()> {
+    /// Audio frames use their pts, moved to the clock of the video with the
+    /// offset the video frames carry, or the time of the video they arrive
+    /// with if the peer does not stamp them. They are dropped before the
+    /// first video frame.
+    pub fn write_audio_frame(&mut self, frame: &AudioFrame) {
+        let pts = match self.base {
+            Some(base) if frame.pts > 0 => Some(frame.pts - self.pts_offset - base),
+            _ => self.now_pts(),
+        };
+        if let Some(pts) = pts {
+            if pts >= 0 {
+                self.inner.write_audio(frame, pts);
+            }
+        }
+    }
+
+    /// Mark a chapter at the current video time, e.g. when the display is
+    /// switched.
+    pub fn add_chapter(&mut self, title: &str) {
+        let pts = self.now_pts().unwrap_or_default();
+        self.inner.add_chapter(pts, title);
+        self.chapter = Some(title.to_owned());
+    }
+
+    /// Log an event of the session, e.g. a click, at the current video time.
+    pub fn write_event(&mut self, event: &str, detail: &str) {
+        let pts = self.now_pts().unwrap_or_default();
+        if let Some(events) = self.events.as_mut() {
+            events.write(pts, event, detail);
+        }
+    }
+
+    fn now_pts(&self) -> Option<i64> {
+        self.clock
+            .map(|(pts, time)| pts + time.elapsed().as_millis() as i64)
+    }
+
+    // Returns the pts in the recording.
+    fn check_pts(&mut self, pts: i64) -> ResultType<i64> {
         // https://stackoverflow.com/questions/76379101/how-to-create-one-playable-webm-file-from-two-different-video-tracks-with-same-c
         let old_pts = self.pts;
         self.pts = Some(pts);
@@ -228,7 +300,9 @@ impl Recorder {
             log::info!("pts {:?} -> {}, change record filename", old_pts, pts);
             self.change(self.ctx.clone())?;
         }
-        Ok(())
+        let pts = pts - *self.base.get_or_insert(pts);
+        self.clock = Some((pts, Instant::now()));
+        Ok(pts)
     }
 
     fn send_state(&self, state: RecordState) {
@@ -236,13 +310,73 @@ impl Recorder {
     }
 }
 
+/// JSON Lines beside the recording, one `{"pts", "event", "detail"}` object
+/// per line, with the pts in milliseconds like the video frames. It is
+/// removed with the recording when that is too short to keep.
+struct EventLog {
+    file: Option<File>,
+    filename: String,
+    recording: String,
+}
+
+impl EventLog {
+    fn new(ctx: &RecorderContext) -> Option<Self> {
+        if !ctx.events {
+            return None;
+        }
+        let filename = Path::new(&ctx.filename)
+            .with_extension("events.jsonl")
+            .to_string_lossy()
+            .to_string();
+        match File::create(&filename) {
+            Ok(file) => Some(EventLog {
+                file: Some(file),
+                filename,
+                recording: ctx.filename.clone(),
+            }),
+            Err(e) => {
+                log::error!("Failed to create event log {}: {}", filename, e);
+                None
+            }
+        }
+    }
+
+    fn write(&mut self, pts: i64, event: &str, detail: &str) {
+        let line = serde_json::json!({ "pts": pts, "event": event, "detail": detail });
+        if let Some(file) = self.file.as_mut() {
+            writeln!(file, "{}", line).ok();
+        }
+    }
+}
+
+impl Drop for EventLog {
+    fn drop(&mut self) {
+        // closed before sealing
+        self.file.take();
+        if Path::new(&self.recording).exists()
+            || Path::new(&encrypted_name(&self.recording)).exists()
+        {
+            match recipient_key() {
+                Ok(recipient) => seal_in_background(self.filename.clone(), recipient, None),
+                Err(e) => log::error!("Failed to seal event log {}: {}", self.filename, e),
+            }
+        } else {
+            std::fs::remove_file(&self.filename).ok();
+        }
+    }
+}
+
 struct WebmRecorder {
     vt: VideoTrack,
+    at: Option<AudioTrack>,
+    // the last audio pts, the muxer wants them increasing
+    audio_pts: i64,
     webm: Option<Segment<Writer<File>>>,
     ctx: RecorderContext,
     key: bool,
     written: bool,
     start: Instant,
+    chapters: Vec<(i64, String)>,
 }
 
 impl RecorderApi for WebmRecorder {
@@ -280,13 +414,30 @@ impl RecorderApi for WebmRecorder {
                 bail!("Failed to set codec private");
             }
         }
+        let mut at = None;
+        if let Some(format) = ctx.audio.as_ref() {
+            let track = webm.add_audio_track(
+                format.sample_rate as _,
+                format.channels as _,
+                None,
+                mux::AudioCodecId::Opus,
+            );
+            if webm.set_codec_private(track.track_number(), &opus_head(format)) {
+                at = Some(track);
+            } else {
+                log::error!("Failed to set opus codec private, record without audio");
+            }
+        }
         Ok(WebmRecorder {
             vt,
+            at,
+            audio_pts: -1,
             webm: Some(webm),
             ctx,
             key: false,
             written: false,
             start: Instant::now(),
+            chapters: vec![],
         })
     }
 
@@ -306,74 +457,828 @@ impl RecorderApi for WebmRecorder {
             false
         }
     }
+
+    fn write_audio(&mut self, frame: &AudioFrame, pts: i64) -> bool {
+        let Some(at) = self.at.as_mut() else {
+            return false;
+        };
+        if !self.key {
+            return false;
+        }
+        let pts = pts.max(self.audio_pts + 1);
+        self.audio_pts = pts;
+        at.add_frame(&frame.data, pts as u64 * 1_000_000, true)
+    }
+
+    fn add_chapter(&mut self, pts: i64, title: &str) {
+        self.chapters.push((pts.max(0), title.to_owned()));
+    }
+}
+
+// The Opus identification header of https://www.rfc-editor.org/rfc/rfc7845#section-5.1,
+// which Matroska takes as the codec private data.
+fn opus_head(format: &AudioFormat) -> Vec<u8> {
+    let mut head = b"OpusHead".to_vec();
+    head.push(1); // version
+    head.push(format.channels as u8);
+    head.extend_from_slice(&0u16.to_le_bytes()); // pre-skip
+    head.extend_from_slice(&format.sample_rate.to_le_bytes());
+    head.extend_from_slice(&0i16.to_le_bytes()); // output gain
+    head.push(0); // channel mapping family, mono or stereo
+    head
 }
 
 impl Drop for WebmRecorder {
     fn drop(&mut self) {
-        let _ = std::mem::replace(&mut self.webm, None).map_or(false, |webm| webm.finalize(None));
-        let mut state = RecordState::WriteTail;
-        if !self.written || self.start.elapsed().as_secs() < MIN_SECS {
-            std::fs::remove_file(&self.ctx.filename).ok();
-            state = RecordState::RemoveFile;
+        let finalized =
+            std::mem::replace(&mut self.webm, None).map_or(false, |webm| webm.finalize(None));
+        if finalized && self.written && !self.chapters.is_empty() {
+            if let Err(e) = write_webm_chapters(&self.ctx.filename, &self.chapters) {
+                log::error!(
+                    "Failed to write the chapters of {}: {}",
+                    self.ctx.filename,
+                    e
+                );
+            }
         }
-        self.ctx.tx.as_ref().map(|tx| tx.send(state));
+        finish(
+            &self.ctx,
+            self.written && self.start.elapsed().as_secs() >= MIN_SECS,
+        );
     }
 }
 
-#[cfg(feature = "hwcodec")]
-struct HwRecorder {
-    muxer: Muxer,
+const EBML_SEGMENT: u32 = 0x18538067;
+const EBML_SEEK_HEAD: u32 = 0x114D9B74;
+const EBML_VOID: u32 = 0xEC;
+const EBML_CHAPTERS: u32 = 0x1043A770;
+
+// libwebm writes no chapters. They are appended to the finalized segment and
+// found through a seek entry, which takes some of the void libwebm reserves
+// after its seek head.
+fn write_webm_chapters(filename: &str, chapters: &[(i64, String)]) -> ResultType<()> {
+    let mut file = OpenOptions::new().read(true).write(true).open(filename)?;
+    let mut head = vec![0u8; 4096];
+    let n = read_full(&mut file, &mut head)?;
+    head.truncate(n);
+    let end = file.seek(SeekFrom::End(0))?;
+
+    let Some((_, header)) = ebml_element(&head, 0) else {
+        bail!("invalid webm header");
+    };
+    let Some((EBML_SEGMENT, segment)) = ebml_element(&head, header.end) else {
+        bail!("no webm segment");
+    };
+    let Some((EBML_SEEK_HEAD, seek_head)) = ebml_element(&head, segment.data) else {
+        bail!("no seek head");
+    };
+    let Some((EBML_VOID, void)) = ebml_element(&head, seek_head.end) else {
+        bail!("no room for a seek entry");
+    };
+
+    let mut entry = ebml_bytes(0x53AB, &ebml_id(EBML_CHAPTERS));
+    entry.extend(ebml_bytes(
+        0x53AC,
+        &(end - segment.data as u64).to_be_bytes(),
+    ));
+    let entry = ebml_bytes(0x4DBB, &entry);
+    let left = match (void.end - seek_head.end).checked_sub(entry.len()) {
+        Some(left) if left != 1 => left,
+        _ => bail!("no room for a seek entry"),
+    };
+    let mut patch = ebml_id(EBML_SEEK_HEAD);
+    patch.extend(ebml_size(
+        (seek_head.end - seek_head.data + entry.len()) as _,
+        seek_head.data - seek_head.size,
+    )?);
+    patch.extend_from_slice(&head[seek_head.data..seek_head.end]);
+    patch.extend(entry);
+    if left > 0 {
+        let size_len = if left - 2 < 0x7f { 1 } else { 8 };
+        patch.extend(ebml_id(EBML_VOID));
+        patch.extend(ebml_size((left - 1 - size_len) as _, size_len)?);
+        patch.resize(patch.len() + left - 1 - size_len, 0);
+    }
+
+    let mut editions = vec![];
+    for (i, (pts, title)) in chapters.iter().enumerate() {
+        let mut display = ebml_bytes(0x85, title.as_bytes());
+        display.extend(ebml_bytes(0x437C, b"eng"));
+        let mut atom = ebml_uint(0x73C4, i as u64 + 1);
+        atom.extend(ebml_uint(0x91, *pts as u64 * 1_000_000));
+        atom.extend(ebml_bytes(0x80, &display));
+        editions.extend(ebml_bytes(0xB6, &atom));
+    }
+    let element = ebml_bytes(EBML_CHAPTERS, &ebml_bytes(0x45B9, &editions));
+
+    file.write_all(&element)?;
+    file.seek(SeekFrom::Start(seek_head.start as _))?;
+    file.write_all(&patch)?;
+    // an unknown size needs no update
+    if let Some(size) = segment.known_size {
+        file.seek(SeekFrom::Start(segment.size as _))?;
+        file.write_all(&ebml_size(
+            size + element.len() as u64,
+            segment.data - segment.size,
+        )?)?;
+    }
+    file.sync_all()?;
+    Ok(())
+}
+
+// The offsets of an EBML element, from its id to the end of its data.
+struct EbmlElement {
+    start: usize,
+    size: usize,
+    data: usize,
+    end: usize,
+    known_size: Option<u64>,
+}
+
+fn ebml_element(buf: &[u8], start: usize) -> Option<(u32, EbmlElement)> {
+    let first = *buf.get(start)?;
+    let id_len = first.leading_zeros() as usize + 1;
+    if id_len > 4 {
+        return None;
+    }
+    let id = buf
+        .get(start..start + id_len)?
+        .iter()
+        .fold(0u32, |id, b| id << 8 | *b as u32);
+    let size = start + id_len;
+    let size_len = buf.get(size)?.leading_zeros() as usize + 1;
+    if size_len > 8 {
+        return None;
+    }
+    let bytes = buf.get(size..size + size_len)?;
+    let mut value = bytes[0] as u64 & (0xff >> size_len);
+    for b in &bytes[1..] {
+        value = value << 8 | *b as u64;
+    }
+    let data = size + size_len;
+    let known_size = if value == (1 << (7 * size_len)) - 1 {
+        None
+    } else {
+        Some(value)
+    };
+    // the segment may be larger than the buffer
+    let end = data + known_size.unwrap_or_default().min(buf.len() as _) as usize;
+    Some((
+        id,
+        EbmlElement {
+            start,
+            size,
+            data,
+            end,
+            known_size,
+        },
+    ))
+}
+
+fn ebml_id(id: u32) -> Vec<u8> {
+    let bytes = id.to_be_bytes();
+    let skip = bytes.iter().take_while(|b| **b == 0).count();
+    bytes[skip..].to_vec()
+}
+
+// `value` as a size of `len` bytes.
+fn ebml_size(value: u64, len: usize) -> ResultType<Vec<u8>> {
+    if len == 0 || len > 8 || value >= (1 << (7 * len)) - 1 {
+        bail!("{} does not fit in a size of {} bytes", value, len);
+    }
+    let bytes = (value | 1 << (7 * len)).to_be_bytes();
+    Ok(bytes[8 - len..].to_vec())
+}
+
+fn ebml_bytes(id: u32, data: &[u8]) -> Vec<u8> {
+    let mut len = 1;
+    while data.len() as u64 >= (1 << (7 * len)) - 1 {
+        len += 1;
+    }
+    let mut element = ebml_id(id);
+    // the length is checked above
+    element.extend(ebml_size(data.len() as _, len).unwrap_or_default());
+    element.extend_from_slice(data);
+    element
+}
+
+fn ebml_uint(id: u32, value: u64) -> Vec<u8> {
+    let bytes = value.to_be_bytes();
+    let skip = bytes.iter().take_while(|b| **b == 0).count().min(7);
+    ebml_bytes(id, &bytes[skip..])
+}
+
+struct Mp4Recorder {
+    mp4: Option<Mp4Writer>,
     ctx: RecorderContext,
     written: bool,
-    key: bool,
     start: Instant,
 }
 
-#[cfg(feature = "hwcodec")]
-impl RecorderApi for HwRecorder {
+impl RecorderApi for Mp4Recorder {
     fn new(ctx: RecorderContext) -> ResultType<Self> {
-        let muxer = Muxer::new(MuxContext {
-            filename: ctx.filename.clone(),
-            width: ctx.width,
-            height: ctx.height,
-            is265: ctx.format == CodecFormat::H265,
-            framerate: crate::hwcodec::DEFAULT_TIME_BASE[1] as _,
-        })
-        .map_err(|_| anyhow!("Failed to create hardware muxer"))?;
-        Ok(HwRecorder {
-            muxer,
+        let mp4 = Mp4Writer::new(
+            File::create(&ctx.filename)?,
+            ctx.format == CodecFormat::H265,
+            ctx.width,
+            ctx.height,
+            ctx.audio
+                .as_ref()
+                .map(|format| (format.channels as _, format.sample_rate)),
+        );
+        Ok(Mp4Recorder {
+            mp4: Some(mp4),
             ctx,
             written: false,
-            key: false,
             start: Instant::now(),
         })
     }
 
     fn write_video(&mut self, frame: &EncodedVideoFrame) -> bool {
-        if frame.key {
-            self.key = true;
-        }
-        if self.key {
-            let ok = self.muxer.write_video(&frame.data, frame.key).is_ok();
-            if ok {
-                self.written = true;
+        let Some(mp4) = self.mp4.as_mut() else {
+            return false;
+        };
+        match mp4.write_video(&frame.data, frame.pts, frame.key) {
+            Ok(ok) => {
+                self.written |= ok;
+                ok
             }
-            ok
-        } else {
-            false
+            Err(e) => {
+                log::error!("Failed to write {}: {}", self.ctx.filename, e);
+                false
+            }
+        }
+    }
+
+    fn write_audio(&mut self, frame: &AudioFrame, pts: i64) -> bool {
+        self.mp4
+            .as_mut()
+            .map_or(false, |mp4| mp4.write_audio(&frame.data, pts))
+    }
+
+    fn add_chapter(&mut self, pts: i64, title: &str) {
+        if let Some(mp4) = self.mp4.as_mut() {
+            mp4.add_chapter(pts, title);
         }
     }
 }
 
-#[cfg(feature = "hwcodec")]
-impl Drop for HwRecorder {
+impl Drop for Mp4Recorder {
     fn drop(&mut self) {
-        self.muxer.write_tail().ok();
-        let mut state = RecordState::WriteTail;
-        if !self.written || self.start.elapsed().as_secs() < MIN_SECS {
-            std::fs::remove_file(&self.ctx.filename).ok();
-            state = RecordState::RemoveFile;
+        if let Some(Err(e)) = self.mp4.take().map(|mp4| mp4.finish()) {
+            log::error!("Failed to finish {}: {}", self.ctx.filename, e);
         }
-        self.ctx.tx.as_ref().map(|tx| tx.send(state));
+        finish(
+            &self.ctx,
+            self.written && self.start.elapsed().as_secs() >= MIN_SECS,
+        );
+    }
+}
+
+// A kept recording is sealed when it is closed: signed with the key of this
+// device into `<file>.sig`, and encrypted into `<file>.enc` for the holder of
+// the secret key matching "record-public-key", a base64 curve25519 public key.
+// The encrypted file is `SEAL_MAGIC | sealed file key | nonce | (len | chunk)*`,
+// every chunk a secretbox of at most `SEAL_CHUNK` bytes with a `u32` LE length,
+// its nonce carrying the index of the chunk and whether it is the last one, so
+// that reordered or truncated files fail to open.
+const SEAL_MAGIC: &[u8] = b"RDREC001";
+const SEAL_CHUNK: usize = 1024 * 1024;
+const SEAL_LAST: u64 = 1 << 63;
+
+fn finish(ctx: &RecorderContext, keep: bool) {
+    let send = |state| {
+        ctx.tx.as_ref().map(|tx| tx.send(state));
+    };
+    if !keep {
+        std::fs::remove_file(&ctx.filename).ok();
+        send(RecordState::RemoveFile);
+        return;
+    }
+    let recipient = match recipient_key() {
+        Ok(recipient) => recipient,
+        Err(e) => {
+            log::error!("Failed to seal recording {}: {}", ctx.filename, e);
+            send(RecordState::WriteTail);
+            return;
+        }
+    };
+    // a plain recording is complete, its upload ends before the next starts
+    if recipient.is_none() {
+        send(RecordState::WriteTail);
+    }
+    seal_in_background(ctx.filename.clone(), recipient, ctx.tx.clone());
+}
+
+// Encrypting a long recording takes a while, not to be done on the thread
+// which closes it, e.g. the video thread.
+fn seal_in_background(
+    filename: String,
+    recipient: Option<box_::PublicKey>,
+    tx: Option<Sender<RecordState>>,
+) {
+    let signing = recipient.is_some() || !Config::get_option("allow-record-signing").is_empty();
+    if !signing {
+        return;
+    }
+    std::thread::spawn(move || {
+        let send = |state| {
+            tx.as_ref().map(|tx| tx.send(state));
+        };
+        match seal(&filename, recipient.as_ref(), &Config::get_key_pair()) {
+            Ok((encrypted, signature)) => {
+                if let Some(file) = encrypted {
+                    send(RecordState::Encrypted(file));
+                }
+                send(RecordState::Signed(signature));
+            }
+            Err(e) => log::error!("Failed to seal {}: {}", filename, e),
+        }
+    });
+}
+
+/// Whether the recordings are encrypted when closed, never to be uploaded in plain.
+pub fn is_encrypted() -> bool {
+    !Config::get_option("record-public-key").is_empty()
+}
+
+/// A new key pair for "record-public-key", `(public key, secret key)` in base64.
+pub fn gen_record_keys() -> (String, String) {
+    let (pk, sk) = box_::gen_keypair();
+    (
+        base64::encode(pk, base64::Variant::Original),
+        base64::encode(sk, base64::Variant::Original),
+    )
+}
+
+fn encrypted_name(filename: &str) -> String {
+    format!("{}.enc", filename)
+}
+
+fn recipient_key() -> ResultType<Option<box_::PublicKey>> {
+    let key = Config::get_option("record-public-key");
+    if key.is_empty() {
+        return Ok(None);
+    }
+    match base64::decode(key.trim(), base64::Variant::Original)
+        .ok()
+        .and_then(|key| box_::PublicKey::from_slice(&key))
+    {
+        Some(pk) => Ok(Some(pk)),
+        None => bail!("invalid record-public-key"),
+    }
+}
+
+// Sign with `key_pair`, the `(secret key, public key)` of the device, and
+// encrypt for `recipient`. Returns the encrypted file, which replaces the
+// plain one, and the signature.
+fn seal(
+    filename: &str,
+    recipient: Option<&box_::PublicKey>,
+    key_pair: &(Vec<u8>, Vec<u8>),
+) -> ResultType<(Option<String>, String)> {
+    let signature = sign_file(filename, key_pair)?;
+    let encrypted = match recipient {
+        Some(pk) => Some(encrypt_file(filename, pk)?),
+        None => None,
+    };
+    Ok((encrypted, signature))
+}
+
+fn sha256_file(filename: &str) -> ResultType<sha256::Digest> {
+    let mut file = File::open(filename)?;
+    let mut state = sha256::State::new();
+    let mut buf = vec![0u8; SEAL_CHUNK];
+    loop {
+        let n = file.read(&mut buf)?;
+        if n == 0 {
+            break;
+        }
+        state.update(&buf[..n]);
+    }
+    Ok(state.finalize())
+}
+
+fn sign_file(filename: &str, (sk, pk): &(Vec<u8>, Vec<u8>)) -> ResultType<String> {
+    let sk = match sign::SecretKey::from_slice(sk) {
+        Some(sk) => sk,
+        None => bail!("no key to sign with"),
+    };
+    let digest = sha256_file(filename)?;
+    let signature = serde_json::json!({
+        "file": Path::new(filename).file_name().map(|f| f.to_string_lossy()),
+        "sha256": digest.0.iter().map(|b| format!("{:02x}", b)).collect::<String>(),
+        "public_key": base64::encode(pk, base64::Variant::Original),
+        "signed": base64::encode(sign::sign(&digest.0, &sk), base64::Variant::Original),
+    });
+    let sig = format!("{}.sig", filename);
+    std::fs::write(&sig, signature.to_string())?;
+    Ok(sig)
+}
+
+fn chunk_nonce(base: &secretbox::Nonce, index: u64, last: bool) -> secretbox::Nonce {
+    let mut nonce = *base;
+    let tag = index | if last { SEAL_LAST } else { 0 };
+    for (n, t) in nonce.0[secretbox::NONCEBYTES - 8..]
+        .iter_mut()
+        .zip(tag.to_le_bytes())
+    {
+        *n ^= t;
+    }
+    nonce
+}
+
+fn read_full(file: &mut File, buf: &mut [u8]) -> io::Result<usize> {
+    let mut n = 0;
+    while n < buf.len() {
+        match file.read(&mut buf[n..])? {
+            0 => break,
+            m => n += m,
+        }
+    }
+    Ok(n)
+}
+
+fn encrypt_file(filename: &str, pk: &box_::PublicKey) -> ResultType<String> {
+    let key = secretbox::gen_key();
+    let base = secretbox::gen_nonce();
+    let mut input = File::open(filename)?;
+    let encrypted = encrypted_name(filename);
+    let mut out = BufWriter::new(File::create(&encrypted)?);
+    out.write_all(SEAL_MAGIC)?;
+    out.write_all(&sealedbox::seal(&key.0, pk))?;
+    out.write_all(&base.0)?;
+    let mut buf = vec![0u8; SEAL_CHUNK];
+    let mut index = 0;
+    loop {
+        let n = read_full(&mut input, &mut buf)?;
+        let last = n < SEAL_CHUNK;
+        let chunk = secretbox::seal(&buf[..n], &chunk_nonce(&base, index, last), &key);
+        out.write_all(&(chunk.len() as u32).to_le_bytes())?;
+        out.write_all(&chunk)?;
+        if last {
+            break;
+        }
+        index += 1;
+    }
+    out.into_inner()?.sync_all()?;
+    drop(input);
+    std::fs::remove_file(filename)?;
+    Ok(encrypted)
+}
+
+// Decrypt `path` chunk by chunk into `write`, which gets nothing more once a
+// chunk does not authenticate.
+fn decrypt(
+    path: &str,
+    sk: &box_::SecretKey,
+    mut write: impl FnMut(&[u8]) -> ResultType<()>,
+) -> ResultType<()> {
+    let mut input = File::open(path)?;
+    let mut magic = vec![0u8; SEAL_MAGIC.len()];
+    input.read_exact(&mut magic)?;
+    if magic != SEAL_MAGIC {
+        bail!("{} is not an encrypted recording", path);
+    }
+    let mut sealed = vec![0u8; secretbox::KEYBYTES + sealedbox::SEALBYTES];
+    input.read_exact(&mut sealed)?;
+    let key = match sealedbox::open(&sealed, &sk.public_key(), sk)
+        .ok()
+        .and_then(|key| secretbox::Key::from_slice(&key))
+    {
+        Some(key) => key,
+        None => bail!("{} is not encrypted for this key", path),
+    };
+    let mut base = secretbox::Nonce([0u8; secretbox::NONCEBYTES]);
+    input.read_exact(&mut base.0)?;
+    let mut index = 0;
+    loop {
+        let mut len = [0u8; 4];
+        input.read_exact(&mut len)?;
+        let len = u32::from_le_bytes(len) as usize;
+        if len > SEAL_CHUNK + secretbox::MACBYTES {
+            bail!("{} is corrupted at chunk {}", path, index);
+        }
+        let last = len < SEAL_CHUNK + secretbox::MACBYTES;
+        let mut chunk = vec![0u8; len];
+        input.read_exact(&mut chunk)?;
+        match secretbox::open(&chunk, &chunk_nonce(&base, index, last), &key) {
+            Ok(data) => write(&data)?,
+            Err(_) => bail!("{} is corrupted at chunk {}", path, index),
+        }
+        if last {
+            break;
+        }
+        index += 1;
+    }
+    if input.read(&mut [0u8; 1])? != 0 {
+        bail!("{} has data after its last chunk", path);
+    }
+    Ok(())
+}
+
+/// Check a recording, or its event log, against the signature beside it. An
+/// encrypted `<file>.enc` is decrypted with `secret_key`, the base64 secret
+/// key matching "record-public-key", and only hashed unless `output` is
+/// given. The plaintext is then written to `output`, which must not exist
+/// and is removed again if the check fails. Returns the public key of the
+/// device which signed it.
+pub fn verify_recording(
+    path: &str,
+    secret_key: Option<&str>,
+    output: Option<&str>,
+) -> ResultType<String> {
+    let plain = match path.strip_suffix(".enc") {
+        Some(plain) => plain,
+        None if output.is_some() => bail!("{} is not encrypted", path),
+        None => return verify_signature(path, &sha256_file(path)?),
+    };
+    let sk = match secret_key
+        .and_then(|key| base64::decode(key.trim(), base64::Variant::Original).ok())
+        .and_then(|key| box_::SecretKey::from_slice(&key))
+    {
+        Some(sk) => sk,
+        None => bail!("a valid secret key is needed to decrypt {}", path),
+    };
+    let mut out = match output {
+        Some(output) => Some(BufWriter::new(
+            OpenOptions::new()
+                .write(true)
+                .create_new(true)
+                .open(output)?,
+        )),
+        None => None,
+    };
+    let mut state = sha256::State::new();
+    let res = decrypt(path, &sk, |data| {
+        state.update(data);
+        if let Some(out) = out.as_mut() {
+            out.write_all(data)?;
+        }
+        Ok(())
+    })
+    .and_then(|_| match out.take() {
+        Some(out) => Ok(out.into_inner()?.sync_all()?),
+        None => Ok(()),
+    })
+    .and_then(|_| verify_signature(plain, &state.finalize()));
+    if let (Err(_), Some(output)) = (&res, output) {
+        std::fs::remove_file(output).ok();
+    }
+    res
+}
+
+// `digest` is the sha256 of `filename`, whose signature is `<filename>.sig`.
+fn verify_signature(filename: &str, digest: &sha256::Digest) -> ResultType<String> {
+    let signature: serde_json::Value =
+        serde_json::from_str(&std::fs::read_to_string(format!("{}.sig", filename))?)?;
+    let field = |name: &str| {
+        signature
+            .get(name)
+            .and_then(|v| v.as_str())
+            .and_then(|v| base64::decode(v, base64::Variant::Original).ok())
+    };
+    let (pk, signed) = match (
+        field("public_key").and_then(|pk| sign::PublicKey::from_slice(&pk)),
+        field("signed"),
+    ) {
+        (Some(pk), Some(signed)) => (pk, signed),
+        _ => bail!("invalid signature of {}", filename),
+    };
+    match sign::verify(&signed, &pk) {
+        Ok(signed) if signed == digest.0 => {}
+        Ok(_) => bail!("{} was modified after it was signed", filename),
+        Err(_) => bail!("invalid signature of {}", filename),
+    }
+    Ok(base64::encode(pk, base64::Variant::Original))
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn temp_file(name: &str, data: &[u8]) -> String {
+        let path =
+            std::env::temp_dir().join(format!("record_test_{}_{}", std::process::id(), name));
+        std::fs::write(&path, data).unwrap();
+        path.to_string_lossy().to_string()
+    }
+
+    fn device_key() -> (Vec<u8>, Vec<u8>) {
+        let (pk, sk) = sign::gen_keypair();
+        (sk.0.to_vec(), pk.0.to_vec())
+    }
+
+    fn remove(filename: &str) {
+        for f in [
+            filename.to_owned(),
+            encrypted_name(filename),
+            format!("{}.sig", filename),
+        ] {
+            std::fs::remove_file(f).ok();
+        }
+    }
+
+    #[test]
+    fn test_finish_short() {
+        let filename = temp_file("short.webm", b"short");
+        let (tx, rx) = std::sync::mpsc::channel();
+        let ctx = RecorderContext {
+            server: true,
+            id: "".to_owned(),
+            default_dir: "".to_owned(),
+            filename: filename.clone(),
+            width: 0,
+            height: 0,
+            format: CodecFormat::VP9,
+            audio: None,
+            events: false,
+            tx: Some(tx),
+        };
+        finish(&ctx, false);
+        assert!(!Path::new(&filename).exists());
+        assert!(matches!(rx.try_recv(), Ok(RecordState::RemoveFile)));
+    }
+
+    #[test]
+    fn test_seal_round_trip() {
+        // a few chunks, the last one partial
+        let data: Vec<u8> = (0..SEAL_CHUNK * 2 + 1000).map(|i| (i * 7) as u8).collect();
+        let filename = temp_file("round_trip.mp4", &data);
+        let (pk, sk) = box_::gen_keypair();
+        let device = device_key();
+        let (encrypted, signature) = seal(&filename, Some(&pk), &device).unwrap();
+        let encrypted = encrypted.unwrap();
+        assert_eq!(encrypted, encrypted_name(&filename));
+        assert_eq!(signature, format!("{}.sig", filename));
+        assert!(!Path::new(&filename).exists());
+
+        let sk = base64::encode(sk, base64::Variant::Original);
+        let signer = verify_recording(&encrypted, Some(&sk), None).unwrap();
+        assert_eq!(signer, base64::encode(&device.1, base64::Variant::Original));
+        // only hashed without an output
+        assert!(!Path::new(&filename).exists());
+
+        let signer = verify_recording(&encrypted, Some(&sk), Some(&filename)).unwrap();
+        assert_eq!(signer, base64::encode(&device.1, base64::Variant::Original));
+        assert_eq!(std::fs::read(&filename).unwrap(), data);
+        // an existing output is kept
+        std::fs::write(&filename, b"kept").unwrap();
+        assert!(verify_recording(&encrypted, Some(&sk), Some(&filename)).is_err());
+        assert_eq!(std::fs::read(&filename).unwrap(), b"kept");
+        remove(&filename);
+    }
+
+    #[test]
+    fn test_seal_exact_chunks() {
+        let data = vec![1u8; SEAL_CHUNK];
+        let filename = temp_file("exact.mp4", &data);
+        let (pk, sk) = box_::gen_keypair();
+        let encrypted = encrypt_file(&filename, &pk).unwrap();
+        let mut plain = Vec::new();
+        decrypt(&encrypted, &sk, |chunk| {
+            plain.extend_from_slice(chunk);
+            Ok(())
+        })
+        .unwrap();
+        assert_eq!(plain, data);
+        remove(&filename);
+    }
+
+    #[test]
+    fn test_seal_wrong_key() {
+        let filename = temp_file("wrong_key.mp4", b"recording");
+        let (pk, _) = box_::gen_keypair();
+        let (encrypted, _) = seal(&filename, Some(&pk), &device_key()).unwrap();
+        let (_, other) = box_::gen_keypair();
+        let other = base64::encode(other, base64::Variant::Original);
+        let encrypted = encrypted.unwrap();
+        assert!(verify_recording(&encrypted, Some(&other), Some(&filename)).is_err());
+        // nothing decrypted is left behind
+        assert!(!Path::new(&filename).exists());
+        assert!(verify_recording(&encrypted, None, None).is_err());
+        remove(&filename);
+    }
+
+    #[test]
+    fn test_seal_tampered() {
+        let data = vec![2u8; SEAL_CHUNK + 10];
+        let filename = temp_file("tampered.mp4", &data);
+        let (pk, sk) = box_::gen_keypair();
+        let (encrypted, _) = seal(&filename, Some(&pk), &device_key()).unwrap();
+        let encrypted = encrypted.unwrap();
+        let sk = base64::encode(sk, base64::Variant::Original);
+        let sealed = std::fs::read(&encrypted).unwrap();
+
+        // a flipped bit
+        let mut tampered = sealed.clone();
+        let n = tampered.len();
+        tampered[n - 5] ^= 1;
+        std::fs::write(&encrypted, &tampered).unwrap();
+        assert!(verify_recording(&encrypted, Some(&sk), None).is_err());
+
+        // the last chunk cut off
+        let first = SEAL_MAGIC.len()
+            + secretbox::KEYBYTES
+            + sealedbox::SEALBYTES
+            + secretbox::NONCEBYTES
+            + 4
+            + SEAL_CHUNK
+            + secretbox::MACBYTES;
+        std::fs::write(&encrypted, &sealed[..first]).unwrap();
+        assert!(verify_recording(&encrypted, Some(&sk), None).is_err());
+
+        // data appended
+        let mut appended = sealed.clone();
+        appended.push(0);
+        std::fs::write(&encrypted, &appended).unwrap();
+        assert!(verify_recording(&encrypted, Some(&sk), None).is_err());
+
+        std::fs::write(&encrypted, &sealed).unwrap();
+        assert!(verify_recording(&encrypted, Some(&sk), None).is_ok());
+        remove(&filename);
+    }
+
+    #[test]
+    fn test_signature_tampered() {
+        let filename = temp_file("signed.webm", b"recording");
+        let (encrypted, _) = seal(&filename, None, &device_key()).unwrap();
+        assert!(encrypted.is_none());
+        assert!(verify_recording(&filename, None, None).is_ok());
+
+        std::fs::write(&filename, b"Recording").unwrap();
+        assert!(verify_recording(&filename, None, None).is_err());
+
+        // signed by another device with the key of this one
+        std::fs::write(&filename, b"recording").unwrap();
+        let signature = format!("{}.sig", filename);
+        let mut json: serde_json::Value =
+            serde_json::from_str(&std::fs::read_to_string(&signature).unwrap()).unwrap();
+        let other = device_key();
+        json["public_key"] = base64::encode(&other.1, base64::Variant::Original).into();
+        std::fs::write(&signature, json.to_string()).unwrap();
+        assert!(verify_recording(&filename, None, None).is_err());
+        remove(&filename);
+    }
+
+    #[test]
+    fn test_webm_chapters() {
+        let path = std::env::temp_dir().join(format!("record_test_{}.webm", std::process::id()));
+        let filename = path.to_string_lossy().to_string();
+        // a segment of 8 bytes size, as libwebm writes it, with a seek head,
+        // the void after it and an info element
+        let mut segment = ebml_bytes(EBML_SEEK_HEAD, &ebml_bytes(0x4DBB, &[0; 18]));
+        segment.extend(ebml_bytes(EBML_VOID, &[0; 60]));
+        let info = segment.len();
+        segment.extend(ebml_bytes(0x1549A966, &[1, 2, 3]));
+        let mut data = ebml_bytes(0x1A45DFA3, &[0x42, 0x82, 0x84, b'w', b'e', b'b', b'm']);
+        let segment_data = data.len() + 4 + 8;
+        data.extend(ebml_id(EBML_SEGMENT));
+        data.extend(ebml_size(segment.len() as _, 8).unwrap());
+        data.extend(segment);
+        let len = data.len();
+        std::fs::write(&path, &data).unwrap();
+
+        let chapters = vec![(0, "Display 1".to_owned()), (1500, "Display 2".to_owned())];
+        write_webm_chapters(&filename, &chapters).unwrap();
+        let out = std::fs::read(&path).unwrap();
+        std::fs::remove_file(&path).ok();
+
+        let (_, header) = ebml_element(&out, 0).unwrap();
+        let (id, segment) = ebml_element(&out, header.end).unwrap();
+        assert_eq!(id, EBML_SEGMENT);
+        assert_eq!(segment.data, segment_data);
+        assert_eq!(segment.known_size, Some((out.len() - segment_data) as u64));
+        // the elements after the void do not move
+        assert_eq!(out[segment_data + info..len], data[segment_data + info..]);
+        let (_, seek_head) = ebml_element(&out, segment.data).unwrap();
+        let (id, void) = ebml_element(&out, seek_head.end).unwrap();
+        assert_eq!(id, EBML_VOID);
+        assert_eq!(void.end, segment_data + info);
+        let (_, seek) = ebml_element(&out, seek_head.data + 21).unwrap();
+        assert_eq!(seek.end, seek_head.end);
+        let (_, seek_id) = ebml_element(&out, seek.data).unwrap();
+        assert_eq!(out[seek_id.data..seek_id.end], ebml_id(EBML_CHAPTERS));
+        let (_, position) = ebml_element(&out, seek_id.end).unwrap();
+        let position = out[position.data..position.end]
+            .iter()
+            .fold(0, |v, b| v << 8 | *b as usize);
+        assert_eq!(position, len - segment_data);
+
+        let (id, element) = ebml_element(&out, len).unwrap();
+        assert_eq!(id, EBML_CHAPTERS);
+        assert_eq!(element.end, out.len());
+        let (_, edition) = ebml_element(&out, element.data).unwrap();
+        let (id, atom) = ebml_element(&out, edition.data).unwrap();
+        assert_eq!(id, 0xB6);
+        let (_, atom) = ebml_element(&out, atom.end).unwrap();
+        let (_, uid) = ebml_element(&out, atom.data).unwrap();
+        let (id, time) = ebml_element(&out, uid.end).unwrap();
+        assert_eq!(id, 0x91);
+        assert_eq!(
+            out[time.data..time.end],
+            1_500_000_000u64.to_be_bytes()[4..]
+        );
+        assert!(out.windows(9).any(|w| w == b"Display 2"));
     }
 }
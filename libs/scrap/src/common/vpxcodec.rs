@@ -8,6 +8,7 @@ use hbb_common::message_proto::{Chroma, EncodedVideoFrame, EncodedVideoFrames, V
 use hbb_common::ResultType;
 
 use crate::codec::{base_bitrate, codec_thread_num, EncoderApi, Quality};
+use crate::dirty::Rect;
 use crate::{EncodeYuvFormat, GoogleImage, Pixfmt, STRIDE_ALIGN};
 
 use super::vpx::{vp8e_enc_control_id::*, vpx_codec_err_t::*, *};
@@ -41,6 +42,7 @@ pub struct VpxEncoder {
     id: VpxVideoCodecId,
     i444: bool,
     yuvfmt: EncodeYuvFormat,
+    active_map: Vec<u8>,
 }
 
 pub struct VpxDecoder {
@@ -164,6 +166,14 @@ impl EncoderApi for VpxEncoder {
                         VP9E_SET_TILE_COLUMNS as _,
                         4 as c_int
                     ));
+
+                    if config.screen_content {
+                        call_vpx!(vpx_codec_control_(
+                            &mut ctx,
+                            VP9E_SET_TUNE_CONTENT as _,
+                            vp9e_tune_content::VP9E_CONTENT_SCREEN as c_int
+                        ));
+                    }
                 } else if config.codec == VpxVideoCodecId::VP8 {
                     // https://github.com/webmproject/libvpx/blob/972149cafeb71d6f08df89e91a0130d6a38c4b15/vpx/vp8cx.h#L172
                     // https://groups.google.com/a/webmproject.org/g/webm-discuss/c/DJhSrmfQ61M
@@ -177,6 +187,7 @@ impl EncoderApi for VpxEncoder {
                     id: config.codec,
                     i444,
                     yuvfmt: Self::get_yuvfmt(config.width, config.height, i444),
+                    active_map: Vec::new(),
                 })
             }
             _ => Err(anyhow!("encoder type mismatch")),
@@ -199,7 +210,7 @@ impl EncoderApi for VpxEncoder {
         if frames.len() > 0 {
             Ok(VpxEncoder::create_video_frame(self.id, frames))
         } else {
-            Err(anyhow!("no valid frame"))
+            Err(anyhow!(crate::codec::ENCODE_NO_FRAME))
         }
     }
 
@@ -226,6 +237,35 @@ impl EncoderApi for VpxEncoder {
         let c = unsafe { *self.ctx.config.enc.to_owned() };
         c.rc_target_bitrate
     }
+
+    fn set_active_region(&mut self, rects: Option<&[Rect]>) -> ResultType<()> {
+        // one entry per 16x16 macroblock
+        let cols = (self.width + 15) / 16;
+        let rows = (self.height + 15) / 16;
+        let mut map = vpx_active_map_t {
+            active_map: ptr::null_mut(),
+            rows: rows as _,
+            cols: cols as _,
+        };
+        if let Some(rects) = rects {
+            self.active_map.clear();
+            self.active_map.resize(rows * cols, 0);
+            for r in rects {
+                for row in r.y / 16..((r.y + r.h + 15) / 16).min(rows) {
+                    for col in r.x / 16..((r.x + r.w + 15) / 16).min(cols) {
+                        self.active_map[row * cols + col] = 1;
+                    }
+                }
+            }
+            map.active_map = self.active_map.as_mut_ptr();
+        }
+        call_vpx!(vpx_codec_control_(
+            &mut self.ctx,
+            VP8E_SET_ACTIVEMAP as _,
+            &mut map as *mut vpx_active_map_t,
+        ));
+        Ok(())
+    }
 }
 
 impl VpxEncoder {
@@ -399,6 +439,8 @@ pub struct VpxEncoderConfig {
     pub codec: VpxVideoCodecId,
     /// keyframe interval
     pub keyframe_interval: Option<usize>,
+    /// Tune VP9 for screen content, sharper text
+    pub screen_content: bool,
 }
 
 #[derive(Clone, Copy, Debug)]
@@ -25,6 +25,43 @@ impl TraitCapturer for Capturer {
     }
 }
 
+/// A single window instead of a display, see `x11::WindowCapturer`.
+pub struct WindowCapturer(x11::WindowCapturer);
+
+impl WindowCapturer {
+    pub fn new(target: &str) -> io::Result<WindowCapturer> {
+        x11::WindowCapturer::new(target).map(WindowCapturer)
+    }
+
+    pub fn origin(&self) -> (i32, i32) {
+        let r = self.0.rect();
+        (r.x as _, r.y as _)
+    }
+
+    pub fn width(&self) -> usize {
+        self.0.rect().w as usize
+    }
+
+    pub fn height(&self) -> usize {
+        self.0.rect().h as usize
+    }
+
+    pub fn id(&self) -> u32 {
+        self.0.window()
+    }
+}
+
+/// Raise the window and give it the input focus, see `x11::activate_window`.
+pub fn activate_window(id: u32) -> io::Result<()> {
+    x11::activate_window(id)
+}
+
+impl TraitCapturer for WindowCapturer {
+    fn frame<'a>(&'a mut self, _timeout: Duration) -> io::Result<Frame<'a>> {
+        Ok(self.0.frame()?)
+    }
+}
+
 pub struct Frame<'a> {
     pub data: &'a [u8],
     pub pixfmt: Pixfmt,
@@ -591,7 +591,8 @@ fn on_create_session_response(
         let mut args: PropMap = HashMap::new();
         if is_server_running() {
             if let Ok(version) = screencast_portal::version(&portal) {
-                if version >= 4 {
+                // the restored source may be a monitor, let the user pick the window each time
+                if version >= 4 && !is_window_capture() {
                     let restore_token = config::LocalConfig::get_option(RESTORE_TOKEN_CONF_KEY);
                     if !restore_token.is_empty() {
                         args.insert(RESTORE_TOKEN.to_string(), Variant(Box::new(restore_token)));
@@ -605,8 +606,8 @@ fn on_create_session_response(
                 Variant(Box::new("u3".to_string())),
             );
             // https://flatpak.github.io/xdg-desktop-portal/portal-docs.html#gdbus-method-org-freedesktop-portal-ScreenCast.SelectSources
-            args.insert("multiple".into(), Variant(Box::new(true)));
-            args.insert("types".into(), Variant(Box::new(1u32))); //| 2u32)));
+            args.insert("multiple".into(), Variant(Box::new(!is_window_capture())));
+            args.insert("types".into(), Variant(Box::new(source_types())));
 
             let path = portal.select_sources(ses.clone(), args)?;
             handle_response(
@@ -635,6 +636,21 @@ fn on_create_session_response(
     }
 }
 
+// A single window is shared instead of the monitors, the portal asks the
+// user which one.
+fn is_window_capture() -> bool {
+    !config::Config::get_option("capture-window").is_empty()
+}
+
+// https://flatpak.github.io/xdg-desktop-portal/portal-docs.html#gdbus-property-org-freedesktop-portal-ScreenCast.AvailableSourceTypes
+fn source_types() -> u32 {
+    if is_window_capture() {
+        2 // WINDOW
+    } else {
+        1 // MONITOR
+    }
+}
+
 fn on_select_devices_response(
     fd: Arc<Mutex<Option<OwnedFd>>>,
     streams: Arc<Mutex<Vec<PwStreamInfo>>>,
@@ -653,8 +669,8 @@ fn on_select_devices_response(
             Variant(Box::new("u3".to_string())),
         );
         // https://flatpak.github.io/xdg-desktop-portal/portal-docs.html#gdbus-method-org-freedesktop-portal-ScreenCast.SelectSources
-        args.insert("multiple".into(), Variant(Box::new(true)));
-        args.insert("types".into(), Variant(Box::new(1u32))); //| 2u32)));
+        args.insert("multiple".into(), Variant(Box::new(!is_window_capture())));
+        args.insert("types".into(), Variant(Box::new(source_types())));
 
         let session = session.clone();
         let path = portal.select_sources(session.clone(), args)?;
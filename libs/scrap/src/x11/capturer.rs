@@ -5,7 +5,7 @@ use hbb_common::libc;
 use crate::Frame;
 
 use super::ffi::*;
-use super::Display;
+use super::{Display, Server};
 
 pub struct Capturer {
     display: Display,
@@ -25,41 +25,7 @@ impl Capturer {
         let rect = display.rect();
         let size = (rect.w as usize) * (rect.h as usize) * pixel_width;
 
-        // Create a shared memory segment.
-
-        let shmid = unsafe {
-            libc::shmget(
-                libc::IPC_PRIVATE,
-                size,
-                // Everyone can do anything.
-                libc::IPC_CREAT | 0o777,
-            )
-        };
-
-        if shmid == -1 {
-            return Err(io::Error::last_os_error());
-        }
-
-        // Attach the segment to a readable address.
-
-        let buffer = unsafe { libc::shmat(shmid, ptr::null(), libc::SHM_RDONLY) } as *mut u8;
-
-        if buffer as isize == -1 {
-            return Err(io::Error::last_os_error());
-        }
-
-        // Attach the segment to XCB.
-
-        let server = display.server().raw();
-        let xcbid = unsafe { xcb_generate_id(server) };
-        unsafe {
-            xcb_shm_attach(
-                server,
-                xcbid,
-                shmid as u32,
-                0, // False, i.e. not read-only.
-            );
-        }
+        let (shmid, xcbid, buffer) = attach_shm(display.server(), size)?;
 
         let c = Capturer {
             display,
@@ -110,12 +76,56 @@ impl Capturer {
 impl Drop for Capturer {
     fn drop(&mut self) {
         unsafe {
-            // Detach segment from XCB.
-            xcb_shm_detach(self.display.server().raw(), self.xcbid);
-            // Detach segment from our space.
-            libc::shmdt(self.buffer as *mut _);
-            // Destroy the shared memory segment.
-            libc::shmctl(self.shmid, libc::IPC_RMID, ptr::null_mut());
+            detach_shm(self.display.server(), self.shmid, self.xcbid, self.buffer);
         }
     }
 }
+
+// A shared memory segment of `size` bytes attached to XCB for `xcb_shm_get_image`.
+pub(super) fn attach_shm(server: &Server, size: usize) -> io::Result<(i32, u32, *const u8)> {
+    // Create a shared memory segment.
+
+    let shmid = unsafe {
+        libc::shmget(
+            libc::IPC_PRIVATE,
+            size,
+            // Everyone can do anything.
+            libc::IPC_CREAT | 0o777,
+        )
+    };
+
+    if shmid == -1 {
+        return Err(io::Error::last_os_error());
+    }
+
+    // Attach the segment to a readable address.
+
+    let buffer = unsafe { libc::shmat(shmid, ptr::null(), libc::SHM_RDONLY) } as *mut u8;
+
+    if buffer as isize == -1 {
+        return Err(io::Error::last_os_error());
+    }
+
+    // Attach the segment to XCB.
+
+    let server = server.raw();
+    let xcbid = unsafe { xcb_generate_id(server) };
+    unsafe {
+        xcb_shm_attach(
+            server,
+            xcbid,
+            shmid as u32,
+            0, // False, i.e. not read-only.
+        );
+    }
+    Ok((shmid, xcbid, buffer as *const u8))
+}
+
+pub(super) unsafe fn detach_shm(server: &Server, shmid: i32, xcbid: u32, buffer: *const u8) {
+    // Detach segment from XCB.
+    xcb_shm_detach(server.raw(), xcbid);
+    // Detach segment from our space.
+    libc::shmdt(buffer as *mut _);
+    // Destroy the shared memory segment.
+    libc::shmctl(shmid, libc::IPC_RMID, ptr::null_mut());
+}
@@ -80,9 +80,120 @@ extern "C" {
     pub fn xcb_get_atom_name_name(reply: *const xcb_get_atom_name_request_t) -> *const u8;
 
     pub fn xcb_get_atom_name_name_length(reply: *const xcb_get_atom_name_reply_t) -> i32;
+
+    pub fn xcb_free_pixmap(c: *mut xcb_connection_t, pixmap: xcb_pixmap_t) -> xcb_void_cookie_t;
+
+    pub fn xcb_get_geometry(
+        c: *mut xcb_connection_t,
+        drawable: xcb_drawable_t,
+    ) -> xcb_get_geometry_cookie_t;
+
+    pub fn xcb_get_geometry_reply(
+        c: *mut xcb_connection_t,
+        cookie: xcb_get_geometry_cookie_t,
+        e: *mut *mut xcb_generic_error_t,
+    ) -> *mut xcb_get_geometry_reply_t;
+
+    pub fn xcb_translate_coordinates(
+        c: *mut xcb_connection_t,
+        src_window: xcb_window_t,
+        dst_window: xcb_window_t,
+        src_x: i16,
+        src_y: i16,
+    ) -> xcb_translate_coordinates_cookie_t;
+
+    pub fn xcb_translate_coordinates_reply(
+        c: *mut xcb_connection_t,
+        cookie: xcb_translate_coordinates_cookie_t,
+        e: *mut *mut xcb_generic_error_t,
+    ) -> *mut xcb_translate_coordinates_reply_t;
+
+    pub fn xcb_intern_atom(
+        c: *mut xcb_connection_t,
+        only_if_exists: u8,
+        name_len: u16,
+        name: *const i8,
+    ) -> xcb_intern_atom_cookie_t;
+
+    pub fn xcb_intern_atom_reply(
+        c: *mut xcb_connection_t,
+        cookie: xcb_intern_atom_cookie_t,
+        e: *mut *mut xcb_generic_error_t,
+    ) -> *mut xcb_intern_atom_reply_t;
+
+    pub fn xcb_get_property(
+        c: *mut xcb_connection_t,
+        delete: u8,
+        window: xcb_window_t,
+        property: xcb_atom_t,
+        type_: xcb_atom_t,
+        long_offset: u32,
+        long_length: u32,
+    ) -> xcb_get_property_cookie_t;
+
+    pub fn xcb_get_property_reply(
+        c: *mut xcb_connection_t,
+        cookie: xcb_get_property_cookie_t,
+        e: *mut *mut xcb_generic_error_t,
+    ) -> *mut xcb_get_property_reply_t;
+
+    pub fn xcb_get_property_value(r: *const xcb_get_property_reply_t) -> *const c_void;
+
+    pub fn xcb_get_property_value_length(r: *const xcb_get_property_reply_t) -> i32;
+
+    pub fn xcb_send_event(
+        c: *mut xcb_connection_t,
+        propagate: u8,
+        destination: xcb_window_t,
+        event_mask: u32,
+        event: *const i8,
+    ) -> xcb_void_cookie_t;
+
+    pub fn xcb_flush(c: *mut xcb_connection_t) -> i32;
+}
+
+#[link(name = "xcb-composite")]
+extern "C" {
+    pub fn xcb_composite_query_version(
+        c: *mut xcb_connection_t,
+        client_major_version: u32,
+        client_minor_version: u32,
+    ) -> xcb_composite_query_version_cookie_t;
+
+    pub fn xcb_composite_query_version_reply(
+        c: *mut xcb_connection_t,
+        cookie: xcb_composite_query_version_cookie_t,
+        e: *mut *mut xcb_generic_error_t,
+    ) -> *mut xcb_composite_query_version_reply_t;
+
+    pub fn xcb_composite_redirect_window(
+        c: *mut xcb_connection_t,
+        window: xcb_window_t,
+        update: u8,
+    ) -> xcb_void_cookie_t;
+
+    pub fn xcb_composite_unredirect_window(
+        c: *mut xcb_connection_t,
+        window: xcb_window_t,
+        update: u8,
+    ) -> xcb_void_cookie_t;
+
+    pub fn xcb_composite_name_window_pixmap(
+        c: *mut xcb_connection_t,
+        window: xcb_window_t,
+        pixmap: xcb_pixmap_t,
+    ) -> xcb_void_cookie_t;
 }
 
 pub const XCB_IMAGE_FORMAT_Z_PIXMAP: u8 = 2;
+pub const XCB_COMPOSITE_REDIRECT_AUTOMATIC: u8 = 0;
+pub const XCB_ATOM_NONE: xcb_atom_t = 0;
+pub const XCB_ATOM_STRING: xcb_atom_t = 31;
+pub const XCB_ATOM_WINDOW: xcb_atom_t = 33;
+pub const XCB_ATOM_WM_CLASS: xcb_atom_t = 67;
+pub const XCB_CLIENT_MESSAGE: u8 = 33;
+pub const XCB_EVENT_MASK_SUBSTRUCTURE_NOTIFY: u32 = 524288;
+pub const XCB_EVENT_MASK_SUBSTRUCTURE_REDIRECT: u32 = 1048576;
 
 pub type xcb_atom_t = u32;
 pub type xcb_connection_t = c_void;
@@ -93,6 +204,7 @@ pub type xcb_timestamp_t = u32;
 pub type xcb_colormap_t = u32;
 pub type xcb_shm_seg_t = u32;
 pub type xcb_drawable_t = u32;
+pub type xcb_pixmap_t = u32;
 pub type xcb_get_atom_name_cookie_t = u32;
 pub type xcb_get_atom_name_reply_t = u32;
 pub type xcb_get_atom_name_request_t = xcb_get_atom_name_reply_t;
@@ -221,3 +333,101 @@ pub struct xcb_randr_get_monitors_reply_t {
     pub n_outputs: u32,
     pub pad1: [u8; 12],
 }
+
+#[repr(C)]
+#[derive(Clone, Copy)]
+pub struct xcb_get_geometry_cookie_t {
+    pub sequence: u32,
+}
+
+#[repr(C)]
+#[derive(Clone, Copy)]
+pub struct xcb_translate_coordinates_cookie_t {
+    pub sequence: u32,
+}
+
+#[repr(C)]
+#[derive(Clone, Copy)]
+pub struct xcb_intern_atom_cookie_t {
+    pub sequence: u32,
+}
+
+#[repr(C)]
+#[derive(Clone, Copy)]
+pub struct xcb_get_property_cookie_t {
+    pub sequence: u32,
+}
+
+#[repr(C)]
+#[derive(Clone, Copy)]
+pub struct xcb_composite_query_version_cookie_t {
+    pub sequence: u32,
+}
+
+#[repr(C)]
+pub struct xcb_get_geometry_reply_t {
+    pub response_type: u8,
+    pub depth: u8,
+    pub sequence: u16,
+    pub length: u32,
+    pub root: xcb_window_t,
+    pub x: i16,
+    pub y: i16,
+    pub width: u16,
+    pub height: u16,
+    pub border_width: u16,
+    pub pad0: [u8; 2],
+}
+
+#[repr(C)]
+pub struct xcb_translate_coordinates_reply_t {
+    pub response_type: u8,
+    pub same_screen: u8,
+    pub sequence: u16,
+    pub length: u32,
+    pub child: xcb_window_t,
+    pub dst_x: i16,
+    pub dst_y: i16,
+}
+
+#[repr(C)]
+pub struct xcb_intern_atom_reply_t {
+    pub response_type: u8,
+    pub pad0: u8,
+    pub sequence: u16,
+    pub length: u32,
+    pub atom: xcb_atom_t,
+}
+
+#[repr(C)]
+pub struct xcb_get_property_reply_t {
+    pub response_type: u8,
+    pub format: u8,
+    pub sequence: u16,
+    pub length: u32,
+    pub type_: xcb_atom_t,
+    pub bytes_after: u32,
+    pub value_len: u32,
+    pub pad0: [u8; 12],
+}
+
+#[repr(C)]
+pub struct xcb_composite_query_version_reply_t {
+    pub response_type: u8,
+    pub pad0: u8,
+    pub sequence: u16,
+    pub length: u32,
+    pub major_version: u32,
+    pub minor_version: u32,
+    pub pad1: [u8; 16],
+}
+
+#[repr(C)]
+pub struct xcb_client_message_event_t {
+    pub response_type: u8,
+    pub format: u8,
+    pub sequence: u16,
+    pub window: xcb_window_t,
+    pub type_: xcb_atom_t,
+    pub data: [u32; 5],
+}
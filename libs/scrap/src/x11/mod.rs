@@ -2,9 +2,11 @@ pub use self::capturer::*;
 pub use self::display::*;
 pub use self::iter::*;
 pub use self::server::*;
+pub use self::window::*;
 
 mod capturer;
 mod display;
 mod ffi;
 mod iter;
 mod server;
+mod window;
@@ -0,0 +1,308 @@
+use std::{cell::RefCell, io, ptr, rc::Rc, slice, thread, time::Duration};
+
+use hbb_common::libc;
+
+use crate::Frame;
+
+use super::capturer::{attach_shm, detach_shm};
+use super::ffi::*;
+use super::{Rect, Server};
+
+/// Captures a single top level window, named by its id or by the WM_CLASS of
+/// its application. The composite extension keeps the window drawn offscreen,
+/// so it is captured as is even when covered by other windows.
+pub struct WindowCapturer {
+    server: Rc<Server>,
+    root: xcb_window_t,
+    window: xcb_window_t,
+    pixmap: xcb_pixmap_t,
+    // the content, without the border, on the root window
+    rect: Rect,
+    border: u16,
+    shmid: i32,
+    xcbid: u32,
+    buffer: *const u8,
+
+    size: usize,
+    saved_raw_data: Vec<u8>, // for faster compare and copy
+}
+
+impl WindowCapturer {
+    pub fn new(target: &str) -> io::Result<WindowCapturer> {
+        let server =
+            Server::default().map_err(|_| io::Error::from(io::ErrorKind::ConnectionRefused))?;
+        let root = unsafe { (*xcb_setup_roots_iterator(server.setup()).data).root };
+        let window = find_window(&server, root, target).ok_or_else(|| {
+            io::Error::new(
+                io::ErrorKind::NotFound,
+                format!("No window matches {}", target),
+            )
+        })?;
+        let (rect, border) = geometry(&server, root, window)?;
+        unsafe {
+            // the extension must be told the version before use
+            let reply = xcb_composite_query_version_reply(
+                server.raw(),
+                xcb_composite_query_version(server.raw(), 0, 2),
+                ptr::null_mut(),
+            );
+            if reply.is_null() {
+                return Err(io::Error::new(
+                    io::ErrorKind::Unsupported,
+                    "No composite extension",
+                ));
+            }
+            libc::free(reply as *mut _);
+        }
+
+        let size = (rect.w as usize) * (rect.h as usize) * 4;
+        let (shmid, xcbid, buffer) = attach_shm(&server, size)?;
+        let pixmap = unsafe {
+            xcb_composite_redirect_window(server.raw(), window, XCB_COMPOSITE_REDIRECT_AUTOMATIC);
+            let pixmap = xcb_generate_id(server.raw());
+            xcb_composite_name_window_pixmap(server.raw(), window, pixmap);
+            pixmap
+        };
+        Ok(WindowCapturer {
+            server,
+            root,
+            window,
+            pixmap,
+            rect,
+            border,
+            shmid,
+            xcbid,
+            buffer,
+            size,
+            saved_raw_data: Vec::new(),
+        })
+    }
+
+    pub fn rect(&self) -> Rect {
+        self.rect
+    }
+
+    pub fn window(&self) -> xcb_window_t {
+        self.window
+    }
+
+    pub fn frame<'b>(&'b mut self) -> std::io::Result<Frame> {
+        // the pixmap is only valid for the size it was named with, and the
+        // peer maps its input with the position
+        if geometry(&self.server, self.root, self.window)?.0 != self.rect {
+            return Err(io::Error::new(
+                io::ErrorKind::Other,
+                "The window is moved or resized",
+            ));
+        }
+        unsafe {
+            let request = xcb_shm_get_image_unchecked(
+                self.server.raw(),
+                self.pixmap,
+                self.border as _,
+                self.border as _,
+                self.rect.w,
+                self.rect.h,
+                !0,
+                XCB_IMAGE_FORMAT_Z_PIXMAP,
+                self.xcbid,
+                0,
+            );
+            let mut error = ptr::null_mut();
+            let response = xcb_shm_get_image_reply(self.server.raw(), request, &mut error);
+            libc::free(response as *mut _);
+            if !error.is_null() {
+                libc::free(error as *mut _);
+                return Err(io::Error::new(
+                    io::ErrorKind::Other,
+                    "Failed to get the window image",
+                ));
+            }
+        }
+        let result = unsafe { slice::from_raw_parts(self.buffer, self.size) };
+        crate::would_block_if_equal(&mut self.saved_raw_data, result)?;
+        Ok(Frame::new(
+            result,
+            crate::Pixfmt::BGRA,
+            self.rect.w as _,
+            self.rect.h as _,
+        ))
+    }
+}
+
+impl Drop for WindowCapturer {
+    fn drop(&mut self) {
+        unsafe {
+            detach_shm(&self.server, self.shmid, self.xcbid, self.buffer);
+            xcb_free_pixmap(self.server.raw(), self.pixmap);
+            xcb_composite_unredirect_window(
+                self.server.raw(),
+                self.window,
+                XCB_COMPOSITE_REDIRECT_AUTOMATIC,
+            );
+        }
+    }
+}
+
+thread_local! {
+    // the connection of the thread injecting the input
+    static FOCUS_SERVER: RefCell<Option<Rc<Server>>> = RefCell::new(None);
+}
+
+/// Ask the window manager to activate `window`, which raises it and gives it
+/// the input focus, unless it is active already. Fails if it is not active
+/// shortly after, e.g. because the window manager refuses.
+pub fn activate_window(window: xcb_window_t) -> io::Result<()> {
+    let server = FOCUS_SERVER.with(|cached| {
+        let mut cached = cached.borrow_mut();
+        match cached.as_ref() {
+            Some(server) if unsafe { xcb_connection_has_error(server.raw()) } == 0 => {
+                Ok(server.clone())
+            }
+            _ => {
+                let server = Server::default()
+                    .map_err(|_| io::Error::from(io::ErrorKind::ConnectionRefused))?;
+                *cached = Some(server.clone());
+                Ok::<_, io::Error>(server)
+            }
+        }
+    })?;
+    let root = unsafe { (*xcb_setup_roots_iterator(server.setup()).data).root };
+    let active_window = intern_atom(&server, "_NET_ACTIVE_WINDOW")
+        .ok_or_else(|| io::Error::new(io::ErrorKind::Unsupported, "No _NET_ACTIVE_WINDOW"))?;
+    let is_active = || {
+        get_property(&server, root, active_window, XCB_ATOM_WINDOW, 1).and_then(|b| {
+            b.get(..4)
+                .map(|b| u32::from_ne_bytes([b[0], b[1], b[2], b[3]]))
+        }) == Some(window)
+    };
+    if is_active() {
+        return Ok(());
+    }
+    let event = xcb_client_message_event_t {
+        response_type: XCB_CLIENT_MESSAGE,
+        format: 32,
+        sequence: 0,
+        window,
+        type_: active_window,
+        // the source is a pager, whose requests the window managers follow
+        data: [2, 0, 0, 0, 0],
+    };
+    unsafe {
+        xcb_send_event(
+            server.raw(),
+            0,
+            root,
+            XCB_EVENT_MASK_SUBSTRUCTURE_NOTIFY | XCB_EVENT_MASK_SUBSTRUCTURE_REDIRECT,
+            &event as *const _ as *const i8,
+        );
+        xcb_flush(server.raw());
+    }
+    for _ in 0..10 {
+        thread::sleep(Duration::from_millis(10));
+        if is_active() {
+            return Ok(());
+        }
+    }
+    Err(io::Error::new(
+        io::ErrorKind::Other,
+        "The window does not get the focus",
+    ))
+}
+
+// A window id, decimal or 0x hex, or the instance or class name in WM_CLASS
+// of a window the window manager lists.
+fn find_window(server: &Server, root: xcb_window_t, target: &str) -> Option<xcb_window_t> {
+    let id = match target.strip_prefix("0x") {
+        Some(hex) => u32::from_str_radix(hex, 16).ok(),
+        None => target.parse().ok(),
+    };
+    if id.is_some() {
+        return id;
+    }
+    let client_list = intern_atom(server, "_NET_CLIENT_LIST")?;
+    get_property(server, root, client_list, XCB_ATOM_WINDOW, 4096)?
+        .chunks_exact(4)
+        .map(|b| u32::from_ne_bytes([b[0], b[1], b[2], b[3]]))
+        .find(|window| {
+            get_property(server, *window, XCB_ATOM_WM_CLASS, XCB_ATOM_STRING, 64).map_or(
+                false,
+                |class| {
+                    class
+                        .split(|c| *c == 0)
+                        .any(|name| String::from_utf8_lossy(name).eq_ignore_ascii_case(target))
+                },
+            )
+        })
+}
+
+fn geometry(server: &Server, root: xcb_window_t, window: xcb_window_t) -> io::Result<(Rect, u16)> {
+    let gone = || io::Error::new(io::ErrorKind::NotFound, "The window is gone");
+    unsafe {
+        let reply = xcb_get_geometry_reply(
+            server.raw(),
+            xcb_get_geometry(server.raw(), window),
+            ptr::null_mut(),
+        );
+        if reply.is_null() {
+            return Err(gone());
+        }
+        let (w, h, border) = ((*reply).width, (*reply).height, (*reply).border_width);
+        libc::free(reply as *mut _);
+
+        let reply = xcb_translate_coordinates_reply(
+            server.raw(),
+            xcb_translate_coordinates(server.raw(), window, root, 0, 0),
+            ptr::null_mut(),
+        );
+        if reply.is_null() {
+            return Err(gone());
+        }
+        let (x, y) = ((*reply).dst_x, (*reply).dst_y);
+        libc::free(reply as *mut _);
+        Ok((Rect { x, y, w, h }, border))
+    }
+}
+
+fn intern_atom(server: &Server, name: &str) -> Option<xcb_atom_t> {
+    unsafe {
+        let reply = xcb_intern_atom_reply(
+            server.raw(),
+            xcb_intern_atom(server.raw(), 1, name.len() as _, name.as_ptr() as _),
+            ptr::null_mut(),
+        );
+        if reply.is_null() {
+            return None;
+        }
+        let atom = (*reply).atom;
+        libc::free(reply as *mut _);
+        (atom != XCB_ATOM_NONE).then_some(atom)
+    }
+}
+
+// At most `len` 32 bit units of the value.
+fn get_property(
+    server: &Server,
+    window: xcb_window_t,
+    property: xcb_atom_t,
+    type_: xcb_atom_t,
+    len: u32,
+) -> Option<Vec<u8>> {
+    unsafe {
+        let reply = xcb_get_property_reply(
+            server.raw(),
+            xcb_get_property(server.raw(), 0, window, property, type_, 0, len),
+            ptr::null_mut(),
+        );
+        if reply.is_null() {
+            return None;
+        }
+        let value = slice::from_raw_parts(
+            xcb_get_property_value(reply) as *const u8,
+            xcb_get_property_value_length(reply) as usize,
+        )
+        .to_vec();
+        libc::free(reply as *mut _);
+        Some(value)
+    }
+}
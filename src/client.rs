@@ -3,7 +3,8 @@ use std::{
     net::SocketAddr,
     ops::Deref,
     str::FromStr,
-    sync::{mpsc, Arc, Mutex, RwLock},
+    sync::{mpsc, Arc, Mutex, RwLock, Weak},
+    time::Instant,
 };
 
 use bytes::Bytes;
@@ -30,12 +31,13 @@ use hbb_common::{
     anyhow::{anyhow, Context},
     bail,
     config::{
-        Config, LocalConfig, PeerConfig, PeerInfoSerde, Resolution, CONNECT_TIMEOUT,
+        Config, KeyMapping, LocalConfig, PeerConfig, PeerInfoSerde, Resolution, CONNECT_TIMEOUT,
         PUBLIC_RS_PUB_KEY, READ_TIMEOUT, RELAY_PORT, RENDEZVOUS_PORT, RENDEZVOUS_SERVERS,
     },
-    get_version_number, log,
+    get_time, get_version_number, log,
     message_proto::{option_message::BoolOption, *},
     protobuf::Message as _,
+    punch::{self, PortPredictor, PUNCH_BATCH},
     rand,
     rendezvous_proto::*,
     socket_client,
@@ -69,11 +71,23 @@ use crate::{check_clipboard, ClipboardContext, CLIPBOARD_INTERVAL};
 pub use super::lang::*;
 
 pub mod file_trait;
+#[cfg(feature = "gamepad")]
+pub mod gamepad;
 pub mod helper;
+#[cfg(not(any(target_os = "android", target_os = "ios")))]
+pub mod input_macro;
 pub mod io_loop;
+mod jitter_buffer;
+pub mod key_remap;
+
+use jitter_buffer::JitterBuffer;
 
 pub const MILLI1: Duration = Duration::from_millis(1);
 pub const SEC30: Duration = Duration::from_secs(30);
+// how long a direct attempt runs alone before relay joins the race, in ms
+const RELAY_HEAD_START: u64 = 1_000;
+/// Global ipv6 needs no nat traversal, so it gets a short try before anything else.
+const IPV6_CONNECT_TIMEOUT: u64 = 1_000;
 pub const VIDEO_QUEUE_SIZE: usize = 120;
 
 #[cfg(all(target_os = "linux", feature = "linux_headless"))]
@@ -224,7 +238,7 @@ impl Client {
         debug_assert!(peer == interface.get_id());
         interface.update_direct(None);
         interface.update_received(false);
-        match Self::_start(peer, key, token, conn_type, interface).await {
+        match Self::_start(peer, key, token, conn_type, interface, false).await {
             Err(err) => {
                 let err_str = err.to_string();
                 if err_str.starts_with("Failed") {
@@ -237,6 +251,24 @@ impl Client {
         }
     }
 
+    /// Try to reach the peer directly for a session that currently goes through relay.
+    ///
+    /// The returned stream is secured and announced as a migrated transport,
+    /// the caller has to present its [`TransportMigration`] token on it.
+    pub async fn start_direct_transport(
+        peer: &str,
+        key: &str,
+        token: &str,
+        conn_type: ConnType,
+        interface: impl Interface,
+    ) -> ResultType<Stream> {
+        let (conn, _, _) = Self::_start(peer, key, token, conn_type, interface, true).await?;
+        if !conn.is_secured() {
+            bail!("Transport migration requires a secure connection");
+        }
+        Ok(conn)
+    }
+
     /// Start a new connection.
     async fn _start(
         peer: &str,
@@ -244,12 +276,17 @@ impl Client {
         token: &str,
         conn_type: ConnType,
         interface: impl Interface,
+        direct_only: bool,
     ) -> ResultType<(Stream, bool, Option<Vec<u8>>)> {
         // to-do: remember the port for each peer, so that we can retry easier
         if hbb_common::is_ip_str(peer) {
             return Ok((
-                socket_client::connect_tcp(check_port(peer, RELAY_PORT + 1), CONNECT_TIMEOUT)
-                    .await?,
+                socket_client::connect_tcp_local(
+                    check_port(peer, RELAY_PORT + 1),
+                    None,
+                    CONNECT_TIMEOUT,
+                )
+                .await?,
                 true,
                 None,
             ));
@@ -257,7 +294,7 @@ impl Client {
         // Allow connect to {domain}:{port}
         if hbb_common::is_domain_port_str(peer) {
             return Ok((
-                socket_client::connect_tcp(peer, CONNECT_TIMEOUT).await?,
+                socket_client::connect_tcp_local(peer, None, CONNECT_TIMEOUT).await?,
                 true,
                 None,
             ));
@@ -316,16 +353,27 @@ impl Client {
         let start = std::time::Instant::now();
         let mut peer_addr = Config::get_any_listen_addr(true);
         let mut peer_nat_type = NatType::UNKNOWN_NAT;
+        let mut peer_nat_port_delta = 0;
         let my_nat_type = crate::get_nat_type(100).await;
+        let my_nat_port_delta = crate::get_nat_port_delta(100).await;
+        let my_addr_v6 = if interface.is_force_relay() {
+            None
+        } else {
+            socket_client::new_ipv6_punch_addr()
+        };
+        let mut peer_addr_v6 = None;
         let mut is_local = false;
         for i in 1..=3 {
             log::info!("#{} punch attempt with {}, id: {}", i, my_addr, peer);
             let mut msg_out = RendezvousMessage::new();
             use hbb_common::protobuf::Enum;
-            let nat_type = if interface.is_force_relay() {
-                NatType::SYMMETRIC
+            let (nat_type, nat_port_delta) = if interface.is_force_relay() {
+                (NatType::SYMMETRIC, 0)
             } else {
-                NatType::from_i32(my_nat_type).unwrap_or(NatType::UNKNOWN_NAT)
+                (
+                    NatType::from_i32(my_nat_type).unwrap_or(NatType::UNKNOWN_NAT),
+                    my_nat_port_delta,
+                )
             };
             msg_out.set_punch_hole_request(PunchHoleRequest {
                 id: peer.to_owned(),
@@ -333,6 +381,12 @@ impl Client {
                 nat_type: nat_type.into(),
                 licence_key: key.to_owned(),
                 conn_type: conn_type.into(),
+                nat_port_delta,
+                socket_addr_v6: my_addr_v6
+                    .map(AddrMangle::encode)
+                    .unwrap_or_default()
+                    .into(),
+                direct_only,
                 ..Default::default()
             });
             socket.send(&msg_out).await?;
@@ -362,15 +416,23 @@ impl Client {
                             }
                         } else {
                             peer_nat_type = ph.nat_type();
+                            peer_nat_port_delta = ph.nat_port_delta;
                             is_local = ph.is_local();
                             signed_id_pk = ph.pk.into();
                             relay_server = ph.relay_server;
                             peer_addr = AddrMangle::decode(&ph.socket_addr);
+                            if !ph.socket_addr_v6.is_empty() {
+                                peer_addr_v6 = Some(AddrMangle::decode(&ph.socket_addr_v6))
+                                    .filter(|x| x.is_ipv6());
+                            }
                             log::info!("Hole Punched {} = {}", peer, peer_addr);
                             break;
                         }
                     }
                     Some(rendezvous_message::Union::RelayResponse(rr)) => {
+                        if direct_only {
+                            bail!("Peer requested relay, no direct path");
+                        }
                         log::info!(
                             "relay requested from peer, time used: {:?}, relay_server: {}",
                             start.elapsed(),
@@ -386,8 +448,8 @@ impl Client {
                             my_addr.is_ipv4(),
                         )
                         .await?;
-                        let pk =
-                            Self::secure_connection(peer, signed_id_pk, key, &mut conn).await?;
+                        let pk = Self::secure_connection(peer, signed_id_pk, key, false, &mut conn)
+                            .await?;
                         return Ok((conn, false, pk));
                     }
                     _ => {
@@ -414,38 +476,46 @@ impl Client {
         Self::connect(
             my_addr,
             peer_addr,
+            my_addr_v6.zip(peer_addr_v6),
             peer,
             signed_id_pk,
             &relay_server,
             &rendezvous_server,
             time_used,
             peer_nat_type,
+            peer_nat_port_delta,
             my_nat_type,
             is_local,
             key,
             token,
             conn_type,
             interface,
+            direct_only,
         )
         .await
     }
 
     /// Connect to the peer.
+    ///
+    /// `ipv6` is the local and peer global ipv6 address, tried before `peer`.
     async fn connect(
         local_addr: SocketAddr,
         peer: SocketAddr,
+        ipv6: Option<(SocketAddr, SocketAddr)>,
         peer_id: &str,
         signed_id_pk: Vec<u8>,
         relay_server: &str,
         rendezvous_server: &str,
         punch_time_used: u64,
         peer_nat_type: NatType,
+        peer_nat_port_delta: i32,
         my_nat_type: i32,
         is_local: bool,
         key: &str,
         token: &str,
         conn_type: ConnType,
         interface: impl Interface,
+        direct_only: bool,
     ) -> ResultType<(Stream, bool, Option<Vec<u8>>)> {
         let direct_failures = interface.get_lch().read().unwrap().direct_failures;
         let mut connect_timeout = 0;
@@ -482,47 +552,97 @@ impl Client {
         log::info!("peer address: {}, timeout: {}", peer, connect_timeout);
         let start = std::time::Instant::now();
         // NOTICE: Socks5 is be used event in intranet. Which may be not a good way.
-        let mut conn =
-            socket_client::connect_tcp_local(peer, Some(local_addr), connect_timeout).await;
-        let mut direct = !conn.is_err();
-        interface.update_direct(Some(direct));
-        if interface.is_force_relay() || conn.is_err() {
-            if !relay_server.is_empty() {
-                conn = Self::request_relay(
-                    peer_id,
-                    relay_server.to_owned(),
-                    rendezvous_server,
-                    !signed_id_pk.is_empty(),
-                    key,
-                    token,
-                    conn_type,
-                )
-                .await;
-                interface.update_direct(Some(false));
-                if let Err(e) = conn {
-                    bail!("Failed to connect via relay server: {}", e);
+        let predictor = PortPredictor::new(peer, peer_nat_port_delta);
+        let connect_direct = async {
+            if let Some((local_v6, peer_v6)) = ipv6 {
+                let ms_timeout = connect_timeout.min(IPV6_CONNECT_TIMEOUT);
+                match socket_client::connect_tcp_local(peer_v6, Some(local_v6), ms_timeout).await {
+                    Ok(conn) => {
+                        log::info!("direct ipv6 connection to {}", peer_v6);
+                        return Ok(conn);
+                    }
+                    Err(err) => log::info!("ipv6 connection to {} failed: {}", peer_v6, err),
                 }
-                direct = false;
+            }
+            if !is_local
+                && peer_nat_type == NatType::SYMMETRIC
+                && my_nat_type != NatType::SYMMETRIC as i32
+                && predictor.is_predictable()
+            {
+                // the peer opened its gateway from a port we can only guess, race the batch
+                let candidates = predictor.candidates(PUNCH_BATCH);
+                log::info!("predicted peer addresses: {:?}", candidates);
+                punch::connect_predicted(&candidates, Some(local_addr), connect_timeout).await
             } else {
+                socket_client::connect_tcp_local(peer, Some(local_addr), connect_timeout).await
+            }
+        };
+        let secure = !signed_id_pk.is_empty();
+        // owned, a relay that loses the race is closed in the background
+        let connect_relay = {
+            let (peer_id, relay_server) = (peer_id.to_owned(), relay_server.to_owned());
+            let rendezvous_server = rendezvous_server.to_owned();
+            let (key, token) = (key.to_owned(), token.to_owned());
+            move || async move {
+                Self::request_relay(
+                    &peer_id,
+                    relay_server,
+                    &rendezvous_server,
+                    secure,
+                    &key,
+                    &token,
+                    conn_type,
+                )
+                .await
+            }
+        };
+        let (conn, direct) = if direct_only || relay_server.is_empty() {
+            let conn = connect_direct.await;
+            if conn.is_err() || (!direct_only && interface.is_force_relay()) {
                 bail!("Failed to make direct connection to remote desktop");
             }
+            (conn, true)
+        } else if interface.is_force_relay() {
+            (connect_relay().await, false)
+        } else {
+            // do not wait for the whole direct timeout before trying relay
+            race_with_head_start(
+                connect_direct,
+                connect_relay,
+                Duration::from_millis(connect_timeout.min(RELAY_HEAD_START)),
+            )
+            .await
+        };
+        if !direct_only {
+            interface.update_direct(Some(direct && conn.is_ok()));
         }
-        if !relay_server.is_empty() && (direct_failures == 0) != direct {
+        let mut conn = match conn {
+            Ok(conn) => conn,
+            Err(e) => bail!("Failed to connect via relay server: {}", e),
+        };
+        if !direct_only && !relay_server.is_empty() && (direct_failures == 0) != direct {
             let n = if direct { 0 } else { 1 };
             log::info!("direct_failures updated to {}", n);
             interface.get_lch().write().unwrap().set_direct_failure(n);
         }
-        let mut conn = conn?;
-        log::info!("{:?} used to establish connection", start.elapsed());
-        let pk = Self::secure_connection(peer_id, signed_id_pk, key, &mut conn).await?;
+        log::info!(
+            "{:?} used to establish {} connection",
+            start.elapsed(),
+            if direct { "direct" } else { "relay" }
+        );
+        let pk =
+            Self::secure_connection(peer_id, signed_id_pk, key, direct_only, &mut conn).await?;
         Ok((conn, direct, pk))
     }
 
     /// Establish secure connection with the server.
+    ///
+    /// `migrate` marks the connection as a new transport for a running session.
     async fn secure_connection(
         peer_id: &str,
         signed_id_pk: Vec<u8>,
         key: &str,
+        migrate: bool,
         conn: &mut Stream,
     ) -> ResultType<Option<Vec<u8>>> {
         let rs_pk = get_rs_pk(if key.is_empty() {
@@ -566,6 +686,7 @@ impl Client {
                                 msg_out.set_public_key(PublicKey {
                                     asymmetric_value,
                                     symmetric_value,
+                                    migrate,
                                     ..Default::default()
                                 });
                                 timeout(CONNECT_TIMEOUT, conn.send(&msg_out)).await??;
@@ -786,6 +907,9 @@ pub struct AudioHandler {
     oboe: Option<OboePlayer>,
     #[cfg(target_os = "linux")]
     simple: Option<psimple::Simple>,
+    // the PulseAudio sink to play into, the default one if none
+    #[cfg(target_os = "linux")]
+    device: Option<String>,
     #[cfg(not(any(target_os = "android", target_os = "linux")))]
     audio_buffer: AudioBuffer,
     sample_rate: (u32, u32),
@@ -796,6 +920,33 @@ pub struct AudioHandler {
     device_channel: u16,
     #[cfg(not(any(target_os = "android", target_os = "linux")))]
     ready: Arc<std::sync::Mutex<bool>>,
+    // the decoded audio held back until it is due
+    jitter: JitterBuffer<Vec<f32>>,
+    // the video to play in sync with, none for the voice calls
+    sync: Option<Arc<Mutex<MediaSync>>>,
+}
+
+/// The video shown and the audio held of a session, for playing them in sync.
+#[derive(Default)]
+pub struct MediaSync {
+    // the pts of the last video frame shown, and when
+    video: Option<(i64, Instant)>,
+    // the audio held in ms
+    audio_buffer: Option<i32>,
+}
+
+impl MediaSync {
+    // The pts of the video shown now, none if it is not playing.
+    fn video_now(&self) -> Option<i64> {
+        let (pts, time) = self.video?;
+        let elapsed = time.elapsed().as_millis() as i64;
+        (elapsed < 1000).then_some(pts + elapsed)
+    }
+
+    #[inline]
+    pub fn audio_buffer(&self) -> Option<i32> {
+        self.audio_buffer
+    }
 }
 
 #[cfg(not(any(target_os = "android", target_os = "linux")))]
@@ -831,7 +982,7 @@ impl AudioHandler {
             None,                   // Use the default server
             &crate::get_app_name(), // Our application’s name
             Direction::Playback,    // We want a playback stream
-            None,                   // Use the default device
+            self.device.as_deref(), // Use the default device if none
             "playback",             // Description of our stream
             &spec,                  // Our sample format
             None,                   // Use default channel map
@@ -911,7 +1062,7 @@ impl AudioHandler {
         }
     }
 
-    /// Handle audio frame and play it.
+    /// Handle audio frame and play it when it is due.
     #[inline]
     pub fn handle_frame(&mut self, frame: AudioFrame) {
         #[cfg(not(any(target_os = "android", target_os = "linux")))]
@@ -927,47 +1078,87 @@ impl AudioHandler {
         if self.oboe.is_none() {
             return;
         }
-        self.audio_decoder.as_mut().map(|(d, buffer)| {
-            if let Ok(n) = d.decode_float(&frame.data, buffer, false) {
-                let channels = self.channels;
-                let n = n * (channels as usize);
-                #[cfg(not(any(target_os = "android", target_os = "linux")))]
-                {
-                    let sample_rate0 = self.sample_rate.0;
-                    let sample_rate = self.sample_rate.1;
-                    let audio_buffer = self.audio_buffer.0.clone();
-                    let mut buffer = buffer[0..n].to_owned();
-                    if sample_rate != sample_rate0 {
-                        buffer = crate::audio_resample(
-                            &buffer[0..n],
-                            sample_rate0,
-                            sample_rate,
-                            channels,
-                        );
-                    }
-                    if self.channels != self.device_channel {
-                        buffer = crate::audio_rechannel(
-                            buffer,
-                            sample_rate,
-                            sample_rate,
-                            self.channels,
-                            self.device_channel,
-                        );
-                    }
-                    audio_buffer.lock().unwrap().push_slice_overwrite(&buffer);
-                }
-                #[cfg(target_os = "android")]
-                {
-                    self.oboe.as_mut().map(|x| x.push(&buffer[0..n]));
-                }
-                #[cfg(target_os = "linux")]
-                {
-                    let data_u8 =
-                        unsafe { std::slice::from_raw_parts::<u8>(buffer.as_ptr() as _, n * 4) };
-                    self.simple.as_mut().map(|x| x.write(data_u8));
-                }
+        let Some((d, buffer)) = self.audio_decoder.as_mut() else {
+            return;
+        };
+        let Ok(n) = d.decode_float(&frame.data, buffer, false) else {
+            return;
+        };
+        let duration = (n * 1000 / self.sample_rate.0.max(1) as usize) as i64;
+        let data = buffer[0..n * self.channels as usize].to_owned();
+        self.jitter.push(get_time(), frame.pts, duration, data);
+        self.play_due();
+    }
+
+    /// Play the audio held which is due.
+    pub fn play_due(&mut self) {
+        let video = self
+            .sync
+            .as_ref()
+            .and_then(|s| s.lock().unwrap().video_now())
+            .map(|pts| pts + self.output_latency());
+        while let Some(data) = self.jitter.pop(get_time(), video) {
+            self.play(data);
+        }
+        if let Some(sync) = self.sync.as_ref() {
+            sync.lock().unwrap().audio_buffer = Some(self.jitter.buffered() as _);
+        }
+    }
+
+    // How long the audio played now takes to be heard, in ms.
+    fn output_latency(&self) -> i64 {
+        #[cfg(not(any(target_os = "android", target_os = "linux")))]
+        let latency = {
+            let samples = self.audio_buffer.0.lock().unwrap().occupied_len();
+            let rate = self.sample_rate.1 as usize * self.device_channel.max(1) as usize;
+            (samples * 1000 / rate.max(1)) as i64
+        };
+        #[cfg(target_os = "linux")]
+        let latency = self
+            .simple
+            .as_ref()
+            .and_then(|s| s.get_latency().ok())
+            .map_or(0, |l| (l.0 / 1000) as i64);
+        #[cfg(target_os = "android")]
+        let latency = 0;
+        latency
+    }
+
+    // `buffer` is decoded, in the format of the peer.
+    fn play(&mut self, buffer: Vec<f32>) {
+        #[cfg(not(any(target_os = "android", target_os = "linux")))]
+        {
+            let sample_rate0 = self.sample_rate.0;
+            let sample_rate = self.sample_rate.1;
+            let mut buffer = buffer;
+            if sample_rate != sample_rate0 {
+                buffer = crate::audio_resample(&buffer, sample_rate0, sample_rate, self.channels);
             }
-        });
+            if self.channels != self.device_channel {
+                buffer = crate::audio_rechannel(
+                    buffer,
+                    sample_rate,
+                    sample_rate,
+                    self.channels,
+                    self.device_channel,
+                );
+            }
+            self.audio_buffer
+                .0
+                .lock()
+                .unwrap()
+                .push_slice_overwrite(&buffer);
+        }
+        #[cfg(target_os = "android")]
+        {
+            self.oboe.as_mut().map(|x| x.push(&buffer));
+        }
+        #[cfg(target_os = "linux")]
+        {
+            let data_u8 =
+                unsafe { std::slice::from_raw_parts::<u8>(buffer.as_ptr() as _, buffer.len() * 4) };
+            self.simple.as_mut().map(|x| x.write(data_u8));
+        }
     }
 
     /// Build audio output stream for current device.
@@ -1016,6 +1207,28 @@ impl AudioHandler {
     }
 }
 
+/// The audio of the session for the screen recorders, which are made in the
+/// video thread and written from the audio thread.
+#[derive(Default)]
+pub struct RecordAudio {
+    format: Option<AudioFormat>,
+    recorders: Vec<Weak<Mutex<Option<Recorder>>>>,
+}
+
+impl RecordAudio {
+    fn write(&mut self, frame: &AudioFrame) {
+        self.recorders.retain(|r| {
+            let Some(r) = r.upgrade() else {
+                return false;
+            };
+            if let Some(r) = r.lock().unwrap().as_mut() {
+                r.write_audio_frame(frame);
+            }
+            true
+        });
+    }
+}
+
 /// Video handler for the [`Client`].
 pub struct VideoHandler {
     decoder: Decoder,
@@ -1055,7 +1268,7 @@ impl VideoHandler {
                         .lock()
                         .unwrap()
                         .as_mut()
-                        .map(|r| r.write_frame(frame));
+                        .map(|r| r.write_video_frame(&vf));
                 }
                 res
             }
@@ -1068,10 +1281,27 @@ impl VideoHandler {
         self.decoder = Decoder::new();
     }
 
-    /// Start or stop screen record.
-    pub fn record_screen(&mut self, start: bool, w: i32, h: i32, id: String) {
+    /// Mark the switch to `display` in the recording.
+    pub fn record_chapter(&mut self, display: usize) {
+        if self.record {
+            if let Some(r) = self.recorder.lock().unwrap().as_mut() {
+                r.add_chapter(&format!("Display {}", display + 1));
+            }
+        }
+    }
+
+    /// Start or stop screen record, with the audio of the session if it has.
+    pub fn record_screen(
+        &mut self,
+        start: bool,
+        w: i32,
+        h: i32,
+        id: String,
+        audio: &Arc<Mutex<RecordAudio>>,
+    ) {
         self.record = false;
         if start {
+            let mut audio = audio.lock().unwrap();
             self.recorder = Recorder::new(RecorderContext {
                 server: false,
                 id,
@@ -1080,9 +1310,12 @@ impl VideoHandler {
                 width: w as _,
                 height: h as _,
                 format: scrap::CodecFormat::VP9,
+                audio: audio.format.clone(),
+                events: false,
                 tx: None,
             })
             .map_or(Default::default(), |r| Arc::new(Mutex::new(Some(r))));
+            audio.recorders.push(Arc::downgrade(&self.recorder));
         } else {
             self.recorder = Default::default();
         }
@@ -1400,6 +1633,14 @@ impl LoginConfigHandler {
                 BoolOption::No
             })
             .into();
+        } else if name == "text-mode" {
+            let v = !self.get_toggle_option("text-mode");
+            if v {
+                config.options.insert(name.clone(), "Y".to_owned());
+            } else {
+                config.options.remove(&name);
+            }
+            option.text_mode = (if v { BoolOption::Yes } else { BoolOption::No }).into();
         } else if name == "block-input" {
             option.block_input = BoolOption::Yes.into();
         } else if name == "unblock-input" {
@@ -1535,6 +1776,25 @@ impl LoginConfigHandler {
             msg.disable_clipboard = BoolOption::Yes.into();
             n += 1;
         }
+        if self.get_toggle_option("text-mode") {
+            msg.text_mode = BoolOption::Yes.into();
+            n += 1;
+        }
+        if let Some((width, height)) =
+            parse_stream_resolution(&self.get_option("stream-resolution"))
+        {
+            msg.stream_resolution =
+                hbb_common::protobuf::MessageField::some(hbb_common::message_proto::Resolution {
+                    width,
+                    height,
+                    ..Default::default()
+                });
+            n += 1;
+        }
+        if let Some(q) = self.get_audio_quality() {
+            msg.audio_quality = hbb_common::protobuf::MessageField::some(q);
+            n += 1;
+        }
         msg.supported_decoding =
             hbb_common::protobuf::MessageField::some(Decoder::supported_decodings(Some(&self.id)));
         n += 1;
@@ -1715,6 +1975,77 @@ impl LoginConfigHandler {
         msg_out
     }
 
+    /// Create a [`Message`] for the size the peer encodes its displays at.
+    ///
+    /// # Arguments
+    ///
+    /// * `value` - "WxH", or empty for the resolution of the displays.
+    pub fn set_stream_resolution(&mut self, value: String) -> Message {
+        let (width, height) = parse_stream_resolution(&value).unwrap_or_default();
+        let mut misc = Misc::new();
+        misc.set_option(OptionMessage {
+            stream_resolution: hbb_common::protobuf::MessageField::some(
+                hbb_common::message_proto::Resolution {
+                    width,
+                    height,
+                    ..Default::default()
+                },
+            ),
+            ..Default::default()
+        });
+        let mut msg_out = Message::new();
+        msg_out.set_misc(misc);
+        let mut config = self.load_config();
+        config.options.insert("stream-resolution".to_owned(), value);
+        self.save_config(config);
+        msg_out
+    }
+
+    /// Create a [`Message`] for the audio quality, from one of its options.
+    ///
+    /// # Arguments
+    ///
+    /// * `option` - "audio-mode", "audio-bitrate", "audio-channels" or "audio-frame-ms".
+    /// * `value` - The value of the option, empty for the default.
+    pub fn set_audio_quality(&mut self, option: String, value: String) -> Message {
+        let mut config = self.load_config();
+        config.options.insert(option, value);
+        self.save_config(config);
+        let mut misc = Misc::new();
+        misc.set_option(OptionMessage {
+            audio_quality: hbb_common::protobuf::MessageField::some(
+                self.get_audio_quality().unwrap_or_default(),
+            ),
+            ..Default::default()
+        });
+        let mut msg_out = Message::new();
+        msg_out.set_misc(misc);
+        msg_out
+    }
+
+    // The audio quality asked for, none if left to the peer.
+    // "audio-mode" is "low-delay", "voice" or "music", "audio-bitrate" in kbps.
+    fn get_audio_quality(&self) -> Option<AudioQuality> {
+        let mode = match self.get_option("audio-mode").as_str() {
+            "low-delay" => audio_quality::Mode::LowDelay,
+            "voice" => audio_quality::Mode::Voice,
+            "music" => audio_quality::Mode::Music,
+            _ => audio_quality::Mode::NotSet,
+        };
+        let q = AudioQuality {
+            mode: mode.into(),
+            bitrate: self
+                .get_option("audio-bitrate")
+                .parse::<i32>()
+                .unwrap_or(0)
+                .saturating_mul(1000),
+            channels: self.get_option("audio-channels").parse().unwrap_or(0),
+            frame_ms: self.get_option("audio-frame-ms").parse().unwrap_or(0),
+            ..Default::default()
+        };
+        (q != AudioQuality::default()).then_some(q)
+    }
+
     pub fn get_option(&self, k: &str) -> String {
         if let Some(v) = self.config.options.get(k) {
             v.clone()
@@ -1748,6 +2079,18 @@ impl LoginConfigHandler {
         self.save_config(config);
     }
 
+    #[inline]
+    pub fn get_key_mappings(&self) -> Vec<KeyMapping> {
+        self.config.key_mappings.clone()
+    }
+
+    #[inline]
+    pub fn set_key_mappings(&mut self, mappings: Vec<KeyMapping>) {
+        let mut config = self.load_config();
+        config.key_mappings = mappings;
+        self.save_config(config);
+    }
+
     /// Get user name.
     /// Return the name of the given peer. If the peer has no name, return the name in the config.
     ///
@@ -1970,6 +2313,7 @@ pub fn start_video_audio_threads<F, T>(
     Arc<RwLock<HashMap<usize, ArrayQueue<VideoFrame>>>>,
     Arc<RwLock<HashMap<usize, usize>>>,
     Arc<RwLock<Option<Chroma>>>,
+    Arc<Mutex<MediaSync>>,
 )
 where
     F: 'static + FnMut(usize, &mut scrap::ImageRgb) + Send,
@@ -1984,6 +2328,10 @@ where
     let chroma = Arc::new(RwLock::new(None));
     let chroma_cloned = chroma.clone();
     let mut last_chroma = None;
+    let record_audio: Arc<Mutex<RecordAudio>> = Default::default();
+    let record_audio_cloned = record_audio.clone();
+    let media_sync: Arc<Mutex<MediaSync>> = Default::default();
+    let media_sync_cloned = media_sync.clone();
 
     std::thread::spawn(move || {
         #[cfg(windows)]
@@ -2030,9 +2378,14 @@ where
                         }
                         if let Some(handler_controller) = handler_controller_map.get_mut(display) {
                             let mut tmp_chroma = None;
+                            let pts = video_frame_pts(&vf);
                             match handler_controller.handler.handle_frame(vf, &mut tmp_chroma) {
                                 Ok(true) => {
                                     video_callback(display, &mut handler_controller.handler.rgb);
+                                    if let Some(pts) = pts {
+                                        media_sync.lock().unwrap().video =
+                                            Some((pts, Instant::now()));
+                                    }
 
                                     // chroma
                                     if tmp_chroma.is_some() && last_chroma != tmp_chroma {
@@ -2086,6 +2439,15 @@ where
                         if let Some(handler_controler) = handler_controller_map.get_mut(display) {
                             handler_controler.handler.reset();
                         }
+                        // the single handler of the sciter version shows every display
+                        let handler_controler = if handler_controller_map.len() == 1 {
+                            handler_controller_map.get_mut(0)
+                        } else {
+                            handler_controller_map.get_mut(display)
+                        };
+                        if let Some(handler_controler) = handler_controler {
+                            handler_controler.handler.record_chapter(display);
+                        }
                     }
                     MediaData::RecordScreen(start, display, w, h, id) => {
                         log::info!("record screen command: start: {start}, display: {display}");
@@ -2093,13 +2455,23 @@ where
                             // Compatible with the sciter version(single ui session).
                             // For the sciter version, there're no multi-ui-sessions for one connection.
                             // The display is always 0, video_handler_controllers.len() is always 1. So we use the first video handler.
-                            handler_controller_map[0]
-                                .handler
-                                .record_screen(start, w, h, id);
+                            handler_controller_map[0].handler.record_screen(
+                                start,
+                                w,
+                                h,
+                                id,
+                                &record_audio,
+                            );
                         } else {
                             if let Some(handler_controler) = handler_controller_map.get_mut(display)
                             {
-                                handler_controler.handler.record_screen(start, w, h, id);
+                                handler_controler.handler.record_screen(
+                                    start,
+                                    w,
+                                    h,
+                                    id,
+                                    &record_audio,
+                                );
                             }
                         }
                     }
@@ -2111,36 +2483,75 @@ where
         }
         log::info!("Video decoder loop exits");
     });
-    let audio_sender = start_audio_thread();
+    let audio_handler = AudioHandler {
+        sync: Some(media_sync.clone()),
+        ..Default::default()
+    };
+    let audio_sender = start_audio_thread_with_record(record_audio_cloned, audio_handler);
     return (
         video_sender,
         audio_sender,
         video_queue_map_cloned,
         decode_fps_map,
         chroma_cloned,
+        media_sync_cloned,
     );
 }
 
+// The pts of the last frame in `vf`, none if it is not encoded.
+fn video_frame_pts(vf: &VideoFrame) -> Option<i64> {
+    let frames = match vf.union.as_ref()? {
+        video_frame::Union::Vp8s(f)
+        | video_frame::Union::Vp9s(f)
+        | video_frame::Union::Av1s(f)
+        | video_frame::Union::H264s(f)
+        | video_frame::Union::H265s(f) => f,
+        _ => return None,
+    };
+    // on the clock of the audio pts
+    frames.frames.last().map(|f| f.pts + vf.pts_offset)
+}
+
 /// Start an audio thread
 /// Return a audio [`MediaSender`]
 pub fn start_audio_thread() -> MediaSender {
+    start_audio_thread_with_record(Default::default(), Default::default())
+}
+
+/// Play the audio into the PulseAudio sink `device` rather than the default one.
+#[cfg(target_os = "linux")]
+pub fn start_audio_thread_to_device(device: String) -> MediaSender {
+    let audio_handler = AudioHandler {
+        device: Some(device),
+        ..Default::default()
+    };
+    start_audio_thread_with_record(Default::default(), audio_handler)
+}
+
+// The audio is also written to the screen recorders in `record`.
+fn start_audio_thread_with_record(
+    record: Arc<Mutex<RecordAudio>>,
+    mut audio_handler: AudioHandler,
+) -> MediaSender {
     let (audio_sender, audio_receiver) = mpsc::channel::<MediaData>();
     std::thread::spawn(move || {
-        let mut audio_handler = AudioHandler::default();
         loop {
-            if let Ok(data) = audio_receiver.recv() {
-                match data {
+            // wake up to play the audio held in the jitter buffer
+            match audio_receiver.recv_timeout(Duration::from_millis(10)) {
+                Ok(data) => match data {
                     MediaData::AudioFrame(af) => {
+                        record.lock().unwrap().write(&af);
                         audio_handler.handle_frame(*af);
                     }
                     MediaData::AudioFormat(f) => {
                         log::debug!("recved audio format, sample rate={}", f.sample_rate);
+                        record.lock().unwrap().format = Some(f.clone());
                         audio_handler.handle_format(f);
                     }
                     _ => {}
-                }
-            } else {
-                break;
+                },
+                Err(mpsc::RecvTimeoutError::Timeout) => audio_handler.play_due(),
+                Err(_) => break,
             }
         }
         log::info!("Audio decoder loop exits");
@@ -2901,6 +3312,13 @@ pub fn check_if_retry(msgtype: &str, title: &str, text: &str, retry_for_relay: b
                 && !text.to_lowercase().contains("not allowed")))
 }
 
+// "WxH" of the stream resolution option, none for the display resolution.
+fn parse_stream_resolution(value: &str) -> Option<(i32, i32)> {
+    let (w, h) = value.split_once('x')?;
+    let (w, h) = (w.trim().parse().ok()?, h.trim().parse().ok()?);
+    (w > 0 && h > 0).then_some((w, h))
+}
+
 #[inline]
 fn get_pk(pk: &[u8]) -> Option<[u8; 32]> {
     if pk.len() == 32 {
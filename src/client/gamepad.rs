@@ -0,0 +1,184 @@
+//! Forwards the local gamepads to the peer, which replays them on virtual
+//! gamepads, and plays the rumble of them sent back.
+
+use crate::common::input::*;
+use gilrs::{
+    ff::{BaseEffect, BaseEffectType, Effect, EffectBuilder, Replay, Ticks},
+    Axis, Button, EventType, Gamepad, GamepadId, Gilrs,
+};
+use hbb_common::{
+    log,
+    message_proto::{gamepad_event, GamepadEvent, GamepadRumble, GamepadState},
+};
+use std::{
+    collections::HashMap,
+    sync::mpsc::{channel, Receiver, Sender, TryRecvError},
+    thread,
+    time::Duration,
+};
+
+const POLL_INTERVAL: Duration = Duration::from_millis(4);
+
+const BUTTONS: &[(Button, u32)] = &[
+    (Button::South, GAMEPAD_BUTTON_A),
+    (Button::East, GAMEPAD_BUTTON_B),
+    (Button::West, GAMEPAD_BUTTON_X),
+    (Button::North, GAMEPAD_BUTTON_Y),
+    (Button::LeftTrigger, GAMEPAD_BUTTON_LEFT_SHOULDER),
+    (Button::RightTrigger, GAMEPAD_BUTTON_RIGHT_SHOULDER),
+    (Button::Select, GAMEPAD_BUTTON_BACK),
+    (Button::Start, GAMEPAD_BUTTON_START),
+    (Button::Mode, GAMEPAD_BUTTON_GUIDE),
+    (Button::LeftThumb, GAMEPAD_BUTTON_LEFT_THUMB),
+    (Button::RightThumb, GAMEPAD_BUTTON_RIGHT_THUMB),
+    (Button::DPadUp, GAMEPAD_BUTTON_DPAD_UP),
+    (Button::DPadDown, GAMEPAD_BUTTON_DPAD_DOWN),
+    (Button::DPadLeft, GAMEPAD_BUTTON_DPAD_LEFT),
+    (Button::DPadRight, GAMEPAD_BUTTON_DPAD_RIGHT),
+];
+
+/// Polls the gamepads in a thread, which ends when this is dropped.
+pub struct GamepadForwarder {
+    tx: Sender<(u32, GamepadRumble)>,
+}
+
+impl GamepadForwarder {
+    pub fn start(send: impl Fn(GamepadEvent) + Send + 'static) -> Self {
+        let (tx, rx) = channel();
+        thread::spawn(move || match Gilrs::new() {
+            Ok(gilrs) => run(gilrs, rx, send),
+            Err(e) => log::error!("Failed to open gamepads: {}", e),
+        });
+        Self { tx }
+    }
+
+    pub fn rumble(&self, evt: &GamepadEvent) {
+        if let Some(gamepad_event::Union::Rumble(rumble)) = &evt.union {
+            self.tx.send((evt.index, rumble.clone())).ok();
+        }
+    }
+}
+
+fn run(mut gilrs: Gilrs, rx: Receiver<(u32, GamepadRumble)>, send: impl Fn(GamepadEvent)) {
+    let mut states: HashMap<u32, GamepadState> = HashMap::new();
+    let mut effects: HashMap<u32, Effect> = HashMap::new();
+    loop {
+        loop {
+            match rx.try_recv() {
+                Ok((index, rumble)) => {
+                    effects.remove(&index);
+                    if let Some(effect) = new_effect(&mut gilrs, index, &rumble) {
+                        effects.insert(index, effect);
+                    }
+                }
+                Err(TryRecvError::Empty) => break,
+                Err(TryRecvError::Disconnected) => {
+                    for index in states.keys() {
+                        send(disconnected(*index));
+                    }
+                    return;
+                }
+            }
+        }
+        while let Some(event) = gilrs.next_event() {
+            if event.event == EventType::Disconnected {
+                let index = usize::from(event.id) as u32;
+                effects.remove(&index);
+                if states.remove(&index).is_some() {
+                    send(disconnected(index));
+                }
+            }
+        }
+        for (id, gamepad) in gilrs.gamepads() {
+            let index = usize::from(id) as u32;
+            let state = read_state(&gamepad);
+            if states.get(&index) != Some(&state) {
+                states.insert(index, state.clone());
+                let mut evt = GamepadEvent::new();
+                evt.index = index;
+                evt.set_state(state);
+                send(evt);
+            }
+        }
+        thread::sleep(POLL_INTERVAL);
+    }
+}
+
+fn read_state(gamepad: &Gamepad) -> GamepadState {
+    let mut buttons = 0;
+    for (button, bit) in BUTTONS {
+        if gamepad.is_pressed(*button) {
+            buttons |= bit;
+        }
+    }
+    // y of gilrs is up
+    let axis = |axis, sign: f32| (gamepad.value(axis) * sign * 32767.0).round() as i32;
+    let trigger = |button| {
+        gamepad
+            .button_data(button)
+            .map(|data| (data.value() * 255.0).round() as u32)
+            .unwrap_or_default()
+    };
+    GamepadState {
+        buttons,
+        left_x: axis(Axis::LeftStickX, 1.0),
+        left_y: axis(Axis::LeftStickY, -1.0),
+        right_x: axis(Axis::RightStickX, 1.0),
+        right_y: axis(Axis::RightStickY, -1.0),
+        left_trigger: trigger(Button::LeftTrigger2),
+        right_trigger: trigger(Button::RightTrigger2),
+        ..Default::default()
+    }
+}
+
+fn disconnected(index: u32) -> GamepadEvent {
+    let mut evt = GamepadEvent::new();
+    evt.index = index;
+    evt.set_disconnected(true);
+    evt
+}
+
+fn new_effect(gilrs: &mut Gilrs, index: u32, rumble: &GamepadRumble) -> Option<Effect> {
+    if rumble.strong == 0 && rumble.weak == 0 {
+        return None;
+    }
+    let id: GamepadId = gilrs
+        .gamepads()
+        .map(|(id, _)| id)
+        .find(|id| usize::from(*id) as u32 == index)?;
+    if !gilrs.gamepad(id).is_ff_supported() {
+        return None;
+    }
+    let play_for = match rumble.duration {
+        0 => Ticks::from_ms(u32::MAX),
+        duration => Ticks::from_ms(duration),
+    };
+    let mut builder = EffectBuilder::new();
+    for kind in [
+        BaseEffectType::Strong {
+            magnitude: rumble.strong.min(u16::MAX as _) as _,
+        },
+        BaseEffectType::Weak {
+            magnitude: rumble.weak.min(u16::MAX as _) as _,
+        },
+    ] {
+        builder.add_effect(BaseEffect {
+            kind,
+            scheduling: Replay {
+                play_for,
+                ..Default::default()
+            },
+            ..Default::default()
+        });
+    }
+    match builder.gamepads(&[id]).finish(gilrs) {
+        Ok(effect) => {
+            effect.play().ok();
+            Some(effect)
+        }
+        Err(e) => {
+            log::debug!("Failed to rumble gamepad {}: {}", index, e);
+            None
+        }
+    }
+}
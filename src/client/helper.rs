@@ -1,9 +1,11 @@
 use hbb_common::{
     get_time,
     message_proto::{Message, VoiceCallRequest, VoiceCallResponse},
+    tokio::{self, time::Duration},
+    ResultType,
 };
 use scrap::CodecFormat;
-use std::collections::HashMap;
+use std::{collections::HashMap, future::Future};
 
 #[derive(Debug, Default)]
 pub struct QualityStatus {
@@ -13,6 +15,8 @@ pub struct QualityStatus {
     pub target_bitrate: Option<i32>,
     pub codec_format: Option<CodecFormat>,
     pub chroma: Option<String>,
+    // the audio held back against the jitter, in ms
+    pub audio_buffer: Option<i32>,
 }
 
 #[inline]
@@ -35,3 +39,104 @@ pub fn new_voice_call_response(request_timestamp: i64, accepted: bool) -> Messag
     msg.set_voice_call_response(resp);
     msg
 }
+
+/// Race two ways of connecting like happy eyeballs: `primary` starts at once,
+/// `fallback` after `head_start` or as soon as `primary` fails.
+/// The first success wins, the flag tells whether it is from `primary`.
+///
+/// A started `fallback` that loses is not cancelled halfway, it runs to its
+/// end in the background and what it set up is dropped, e.g. a relay closes
+/// instead of keeping the peer waiting.
+pub async fn race_with_head_start<T, P, F, Fut>(
+    primary: P,
+    fallback: F,
+    head_start: Duration,
+) -> (ResultType<T>, bool)
+where
+    T: Send + 'static,
+    P: Future<Output = ResultType<T>>,
+    F: FnOnce() -> Fut,
+    Fut: Future<Output = ResultType<T>> + Send + 'static,
+{
+    tokio::pin!(primary);
+    tokio::select! {
+        res = &mut primary => match res {
+            Ok(v) => return (Ok(v), true),
+            Err(_) => return (fallback().await, false),
+        },
+        _ = tokio::time::sleep(head_start) => {}
+    }
+    let mut fallback = Box::pin(fallback());
+    tokio::select! {
+        res = &mut primary => match res {
+            Ok(v) => {
+                tokio::spawn(fallback);
+                (Ok(v), true)
+            }
+            Err(_) => (fallback.await, false),
+        },
+        res = &mut fallback => match res {
+            Ok(v) => (Ok(v), false),
+            Err(e) => match primary.await {
+                Ok(v) => (Ok(v), true),
+                Err(_) => (Err(e), false),
+            },
+        },
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use hbb_common::bail;
+    use std::sync::{
+        atomic::{AtomicBool, Ordering},
+        Arc,
+    };
+
+    async fn after(ms: u64, ok: bool) -> ResultType<u64> {
+        tokio::time::sleep(Duration::from_millis(ms)).await;
+        if !ok {
+            bail!("failed after {}ms", ms);
+        }
+        Ok(ms)
+    }
+
+    #[tokio::test]
+    async fn test_race_with_head_start() {
+        let head_start = Duration::from_millis(50);
+        // primary wins before the fallback is started
+        let (res, primary) =
+            race_with_head_start(after(10, true), || after(0, true), head_start).await;
+        assert_eq!((res.unwrap(), primary), (10, true));
+        // slow primary loses against the fallback
+        let (res, primary) =
+            race_with_head_start(after(500, true), || after(10, true), head_start).await;
+        assert_eq!((res.unwrap(), primary), (10, false));
+        // failed primary starts the fallback right away
+        let (res, primary) =
+            race_with_head_start(after(0, false), || after(10, true), head_start).await;
+        assert_eq!((res.unwrap(), primary), (10, false));
+        // the lost fallback still runs to its end
+        let done = Arc::new(AtomicBool::new(false));
+        let done2 = done.clone();
+        let fallback = || async move {
+            let res = after(100, true).await;
+            done2.store(true, Ordering::SeqCst);
+            res
+        };
+        let (res, primary) = race_with_head_start(after(80, true), fallback, head_start).await;
+        assert_eq!((res.unwrap(), primary), (80, true));
+        tokio::time::sleep(Duration::from_millis(200)).await;
+        assert!(done.load(Ordering::SeqCst));
+        // failed fallback leaves the primary running
+        let (res, primary) =
+            race_with_head_start(after(100, true), || after(0, false), head_start).await;
+        assert_eq!((res.unwrap(), primary), (100, true));
+        // both failed, the fallback error is reported
+        let (res, primary) =
+            race_with_head_start(after(100, false), || after(0, false), head_start).await;
+        assert!(res.is_err());
+        assert!(!primary);
+    }
+}
@@ -0,0 +1,615 @@
+//! Recording and playback of the input of a session, to repeat the same steps
+//! on many machines.
+//!
+//! A macro is a text file in the `macros` directory of the config, a step a
+//! line, so it can be edited by hand:
+//!
+//! ```text
+//! # a comment
+//! delay <ms>
+//! mouse <mask> <x> <y> [<modifier>,...]
+//! key <down|up|press> <mode> <modifier>,...|- <control|chr|unicode|seq> <value>
+//! type <keys in the DSL of enigo, e.g. {+CTRL}a{-CTRL}>
+//! ```
+
+use crate::input::{MOUSE_TYPE_DOWN, MOUSE_TYPE_UP};
+use enigo::{Key, KeyboardControllable};
+use hbb_common::{
+    anyhow::anyhow,
+    bail,
+    config::Config,
+    message_proto::*,
+    protobuf::{EnumFull, EnumOrUnknown},
+    ResultType,
+};
+use std::{
+    path::PathBuf,
+    sync::{
+        atomic::{AtomicBool, Ordering},
+        Arc,
+    },
+    time::{Duration, Instant},
+};
+
+const EXTENSION: &str = "macro";
+const MIN_SPEED: f64 = 0.1;
+const MAX_SPEED: f64 = 10.;
+
+#[derive(Debug, Clone, PartialEq)]
+pub enum Step {
+    Delay(u64),
+    Mouse(MouseEvent),
+    Key(KeyEvent),
+}
+
+/// The macro of a session being recorded or played.
+#[derive(Default)]
+pub struct InputMacro {
+    // when the last step was recorded, and the steps
+    recording: Option<(Instant, Vec<Step>)>,
+    // set to stop the playback, or by it when it ends
+    stopped: Option<Arc<AtomicBool>>,
+}
+
+impl InputMacro {
+    pub fn start_recording(&mut self) {
+        self.recording = Some((Instant::now(), Vec::new()));
+    }
+
+    pub fn stop_recording(&mut self) -> Option<Vec<Step>> {
+        self.recording.take().map(|(_, steps)| steps)
+    }
+
+    #[inline]
+    pub fn is_recording(&self) -> bool {
+        self.recording.is_some()
+    }
+
+    /// Record `msg` if it is input.
+    pub fn record(&mut self, msg: &Message) {
+        let Some((last, steps)) = self.recording.as_mut() else {
+            return;
+        };
+        let step = match &msg.union {
+            Some(message::Union::MouseEvent(evt)) => Step::Mouse(evt.clone()),
+            Some(message::Union::KeyEvent(evt)) => Step::Key(evt.clone()),
+            _ => return,
+        };
+        if format_step(&step).is_none() {
+            return;
+        }
+        let ms = last.elapsed().as_millis() as u64;
+        if ms > 0 && !steps.is_empty() {
+            steps.push(Step::Delay(ms));
+        }
+        *last = Instant::now();
+        steps.push(step);
+    }
+
+    /// Play `steps` in a thread, the delays divided by `speed`.
+    pub fn play(&mut self, steps: Vec<Step>, speed: f64, send: impl Fn(Message) + Send + 'static) {
+        self.stop();
+        let stopped = Arc::new(AtomicBool::new(false));
+        self.stopped = Some(stopped.clone());
+        std::thread::spawn(move || {
+            play(&steps, speed, &stopped, send);
+            stopped.store(true, Ordering::SeqCst);
+        });
+    }
+
+    pub fn stop(&mut self) {
+        if let Some(stopped) = self.stopped.take() {
+            stopped.store(true, Ordering::SeqCst);
+        }
+    }
+
+    #[inline]
+    pub fn is_playing(&self) -> bool {
+        self.stopped
+            .as_ref()
+            .map_or(false, |stopped| !stopped.load(Ordering::SeqCst))
+    }
+}
+
+fn play(steps: &[Step], speed: f64, stopped: &AtomicBool, send: impl Fn(Message)) {
+    let speed = speed.clamp(MIN_SPEED, MAX_SPEED);
+    // the steps releasing what is held down, in case the playback stops midway
+    let mut held: Vec<Step> = Vec::new();
+    for step in steps {
+        if stopped.load(Ordering::SeqCst) {
+            break;
+        }
+        let mut msg = Message::new();
+        match step {
+            Step::Delay(ms) => {
+                let end = Instant::now() + Duration::from_secs_f64(*ms as f64 / 1000. / speed);
+                while !stopped.load(Ordering::SeqCst) {
+                    let left = end.saturating_duration_since(Instant::now());
+                    if left.is_zero() {
+                        break;
+                    }
+                    std::thread::sleep(left.min(Duration::from_millis(50)));
+                }
+                continue;
+            }
+            Step::Mouse(evt) => msg.set_mouse_event(evt.clone()),
+            Step::Key(evt) => msg.set_key_event(evt.clone()),
+        }
+        match release_of(step) {
+            Some(release) => held.push(release),
+            None => held.retain(|h| !releases(step, h)),
+        }
+        send(msg);
+    }
+    for step in held.into_iter().rev() {
+        let mut msg = Message::new();
+        match step {
+            Step::Mouse(evt) => msg.set_mouse_event(evt),
+            Step::Key(evt) => msg.set_key_event(evt),
+            Step::Delay(_) => continue,
+        }
+        send(msg);
+    }
+}
+
+// The step releasing what `step` presses, none if it presses nothing.
+fn release_of(step: &Step) -> Option<Step> {
+    match step {
+        Step::Mouse(evt) if evt.mask & 0x7 == MOUSE_TYPE_DOWN => Some(Step::Mouse(MouseEvent {
+            mask: evt.mask & !0x7 | MOUSE_TYPE_UP,
+            x: evt.x,
+            y: evt.y,
+            ..Default::default()
+        })),
+        Step::Key(evt) if evt.down && !evt.press => Some(Step::Key(KeyEvent {
+            down: false,
+            ..evt.clone()
+        })),
+        _ => None,
+    }
+}
+
+// Whether `step` does what the release step `held` does.
+fn releases(step: &Step, held: &Step) -> bool {
+    match (step, held) {
+        (Step::Mouse(a), Step::Mouse(b)) => a.mask == b.mask,
+        (Step::Key(a), Step::Key(b)) => !a.down && !a.press && a.union == b.union,
+        _ => false,
+    }
+}
+
+pub fn format(steps: &[Step]) -> String {
+    let mut text = String::new();
+    for line in steps.iter().filter_map(format_step) {
+        text.push_str(&line);
+        text.push('\n');
+    }
+    text
+}
+
+fn format_step(step: &Step) -> Option<String> {
+    match step {
+        Step::Delay(ms) => Some(format!("delay {ms}")),
+        Step::Mouse(evt) => {
+            let mut line = format!("mouse {} {} {}", evt.mask, evt.x, evt.y);
+            if !evt.modifiers.is_empty() {
+                line.push(' ');
+                line.push_str(&format_modifiers(&evt.modifiers));
+            }
+            Some(line)
+        }
+        Step::Key(evt) => {
+            let (kind, value) = match evt.union.as_ref()? {
+                key_event::Union::ControlKey(key) => ("control", enum_name(key)),
+                key_event::Union::Chr(chr) => ("chr", chr.to_string()),
+                key_event::Union::Unicode(chr) => ("unicode", chr.to_string()),
+                key_event::Union::Seq(seq) if !seq.contains('\n') => ("seq", seq.clone()),
+                _ => return None,
+            };
+            let action = if evt.press {
+                "press"
+            } else if evt.down {
+                "down"
+            } else {
+                "up"
+            };
+            let modifiers = if evt.modifiers.is_empty() {
+                "-".to_owned()
+            } else {
+                format_modifiers(&evt.modifiers)
+            };
+            Some(format!(
+                "key {action} {} {modifiers} {kind} {value}",
+                enum_name(&evt.mode)
+            ))
+        }
+    }
+}
+
+pub fn parse(text: &str) -> ResultType<Vec<Step>> {
+    let mut steps = Vec::new();
+    for (i, line) in text.lines().enumerate() {
+        let line = line.trim_start();
+        if line.is_empty() || line.starts_with('#') {
+            continue;
+        }
+        parse_line(line, &mut steps).map_err(|e| anyhow!("line {}: {}", i + 1, e))?;
+    }
+    Ok(steps)
+}
+
+fn parse_line(line: &str, steps: &mut Vec<Step>) -> ResultType<()> {
+    let (cmd, args) = line.split_once(' ').unwrap_or((line, ""));
+    match cmd {
+        "delay" => steps.push(Step::Delay(args.trim().parse()?)),
+        "mouse" => {
+            let v: Vec<&str> = args.split_whitespace().collect();
+            if v.len() < 3 || v.len() > 4 {
+                bail!("expected <mask> <x> <y> [<modifiers>]");
+            }
+            steps.push(Step::Mouse(MouseEvent {
+                mask: v[0].parse()?,
+                x: v[1].parse()?,
+                y: v[2].parse()?,
+                modifiers: parse_modifiers(v.get(3).copied().unwrap_or("-"))?,
+                ..Default::default()
+            }));
+        }
+        "key" => {
+            let v: Vec<&str> = args.splitn(5, ' ').collect();
+            if v.len() != 5 {
+                bail!("expected <action> <mode> <modifiers> <kind> <value>");
+            }
+            let mut evt = KeyEvent {
+                mode: enum_value::<KeyboardMode>(v[1])?.into(),
+                modifiers: parse_modifiers(v[2])?,
+                ..Default::default()
+            };
+            match v[0] {
+                "down" => evt.down = true,
+                "up" => {}
+                "press" => evt.press = true,
+                a => bail!("unknown action {a}"),
+            }
+            evt.union = Some(match v[3] {
+                "control" => key_event::Union::ControlKey(enum_value::<ControlKey>(v[4])?.into()),
+                "chr" => key_event::Union::Chr(v[4].parse()?),
+                "unicode" => key_event::Union::Unicode(v[4].parse()?),
+                "seq" => key_event::Union::Seq(v[4].to_owned()),
+                k => bail!("unknown kind {k}"),
+            });
+            steps.push(Step::Key(evt));
+        }
+        "type" => {
+            let mut keys = KeyCollector::default();
+            enigo::dsl::eval(&mut keys, args).map_err(|e| anyhow!("{:?}", e))?;
+            steps.extend(keys.events.into_iter().map(Step::Key));
+        }
+        _ => bail!("unknown step {cmd}"),
+    }
+    Ok(())
+}
+
+fn format_modifiers(modifiers: &[EnumOrUnknown<ControlKey>]) -> String {
+    modifiers
+        .iter()
+        .map(enum_name)
+        .collect::<Vec<_>>()
+        .join(",")
+}
+
+fn parse_modifiers(s: &str) -> ResultType<Vec<EnumOrUnknown<ControlKey>>> {
+    if s == "-" {
+        return Ok(Vec::new());
+    }
+    s.split(',')
+        .map(|m| enum_value::<ControlKey>(m).map(Into::into))
+        .collect()
+}
+
+fn enum_name<E: EnumFull>(e: &EnumOrUnknown<E>) -> String {
+    match e.enum_value() {
+        Ok(e) => e.descriptor().name().to_owned(),
+        Err(v) => v.to_string(),
+    }
+}
+
+fn enum_value<E: EnumFull>(name: &str) -> ResultType<E> {
+    E::enum_descriptor()
+        .value_by_name(name)
+        .and_then(|v| E::from_i32(v.value()))
+        .ok_or_else(|| anyhow!("unknown name {name}"))
+}
+
+// Turns the DSL of enigo into key events of the legacy keyboard mode.
+#[derive(Default)]
+struct KeyCollector {
+    events: Vec<KeyEvent>,
+    modifiers: Vec<ControlKey>,
+}
+
+impl KeyCollector {
+    fn push(&mut self, union: key_event::Union, down: bool, press: bool) {
+        self.events.push(KeyEvent {
+            down,
+            press,
+            mode: KeyboardMode::Legacy.into(),
+            modifiers: self.modifiers.iter().map(|m| (*m).into()).collect(),
+            union: Some(union),
+            ..Default::default()
+        });
+    }
+}
+
+// The DSL holds down no other keys.
+fn modifier_key(key: Key) -> Option<ControlKey> {
+    match key {
+        Key::Shift => Some(ControlKey::Shift),
+        Key::Control => Some(ControlKey::Control),
+        Key::Alt => Some(ControlKey::Alt),
+        Key::Meta => Some(ControlKey::Meta),
+        _ => None,
+    }
+}
+
+impl KeyboardControllable for KeyCollector {
+    fn as_any(&self) -> &dyn std::any::Any {
+        self
+    }
+
+    fn as_mut_any(&mut self) -> &mut dyn std::any::Any {
+        self
+    }
+
+    fn key_sequence(&mut self, sequence: &str) {
+        self.push(key_event::Union::Seq(sequence.to_owned()), true, true);
+    }
+
+    fn key_down(&mut self, key: Key) -> enigo::ResultType {
+        let Some(key) = modifier_key(key) else {
+            return Err(format!("unsupported key {:?}", key).into());
+        };
+        self.push(key_event::Union::ControlKey(key.into()), true, false);
+        self.modifiers.push(key);
+        Ok(())
+    }
+
+    fn key_up(&mut self, key: Key) {
+        if let Some(key) = modifier_key(key) {
+            self.modifiers.retain(|m| *m != key);
+            self.push(key_event::Union::ControlKey(key.into()), false, false);
+        }
+    }
+
+    fn key_click(&mut self, key: Key) {
+        match key {
+            // with a modifier held, as a key, or it would be typed as text
+            Key::Layout(chr) if !self.modifiers.is_empty() => {
+                self.push(key_event::Union::Chr(chr as _), true, true)
+            }
+            Key::Layout(chr) => self.push(key_event::Union::Seq(chr.to_string()), true, true),
+            key => {
+                if let Some(key) = modifier_key(key) {
+                    self.push(key_event::Union::ControlKey(key.into()), true, true);
+                }
+            }
+        }
+    }
+
+    fn get_key_state(&mut self, _key: Key) -> bool {
+        false
+    }
+}
+
+/// The steps typing `text` a character each `delay` ms, for the peers which
+/// take no clipboard. The characters are sent as text, so the layout of the
+/// peer maps them to keys. New lines and tabs are pressed as keys, in the
+/// legacy mode, as the peer takes no control keys in the others.
+pub fn text_steps(text: &str, delay: u64, mode: KeyboardMode) -> Vec<Step> {
+    let mut steps = Vec::new();
+    for chr in text.chars() {
+        let (union, mode) = match chr {
+            '\n' => (
+                key_event::Union::ControlKey(ControlKey::Return.into()),
+                KeyboardMode::Legacy,
+            ),
+            '\t' => (
+                key_event::Union::ControlKey(ControlKey::Tab.into()),
+                KeyboardMode::Legacy,
+            ),
+            chr if chr.is_control() => continue,
+            chr => (key_event::Union::Seq(chr.to_string()), mode),
+        };
+        if delay > 0 && !steps.is_empty() {
+            steps.push(Step::Delay(delay));
+        }
+        steps.push(Step::Key(KeyEvent {
+            press: true,
+            mode: mode.into(),
+            union: Some(union),
+            ..Default::default()
+        }));
+    }
+    steps
+}
+
+/// Whether `evt` is Esc, which stops the playback.
+pub fn is_abort_key(evt: &KeyEvent) -> bool {
+    match &evt.union {
+        Some(key_event::Union::ControlKey(key)) => key.enum_value() == Ok(ControlKey::Escape),
+        Some(key_event::Union::Chr(code)) if evt.mode.enum_value() != Ok(KeyboardMode::Legacy) => {
+            crate::keyboard::keycode_to_rdev_key(code & 0x0000FFFF) == rdev::Key::Escape
+        }
+        _ => false,
+    }
+}
+
+fn path(name: &str) -> ResultType<PathBuf> {
+    if name.is_empty()
+        || !name
+            .chars()
+            .all(|c| c.is_alphanumeric() || c == '-' || c == '_' || c == ' ')
+    {
+        bail!("invalid macro name {name}");
+    }
+    Ok(Config::path("macros").join(format!("{name}.{EXTENSION}")))
+}
+
+/// The names of the saved macros.
+pub fn list() -> Vec<String> {
+    let Ok(dir) = std::fs::read_dir(Config::path("macros")) else {
+        return Vec::new();
+    };
+    let mut names: Vec<String> = dir
+        .filter_map(|e| e.ok())
+        .map(|e| e.path())
+        .filter(|p| p.extension().map_or(false, |e| e == EXTENSION))
+        .filter_map(|p| Some(p.file_stem()?.to_string_lossy().to_string()))
+        .collect();
+    names.sort();
+    names
+}
+
+pub fn load(name: &str) -> ResultType<Vec<Step>> {
+    parse(&get(name)?)
+}
+
+/// The text of the macro `name`.
+pub fn get(name: &str) -> ResultType<String> {
+    Ok(std::fs::read_to_string(path(name)?)?)
+}
+
+/// Save `text` as the macro `name` if it parses, or remove it if `text` is empty.
+pub fn set(name: &str, text: &str) -> ResultType<()> {
+    let path = path(name)?;
+    if text.is_empty() {
+        std::fs::remove_file(path).ok();
+        return Ok(());
+    }
+    parse(text)?;
+    if let Some(dir) = path.parent() {
+        std::fs::create_dir_all(dir)?;
+    }
+    std::fs::write(path, text)?;
+    Ok(())
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_format_parse() {
+        let steps = vec![
+            Step::Mouse(MouseEvent {
+                mask: 9,
+                x: 100,
+                y: -20,
+                modifiers: vec![ControlKey::Shift.into()],
+                ..Default::default()
+            }),
+            Step::Delay(120),
+            Step::Key(KeyEvent {
+                down: true,
+                mode: KeyboardMode::Map.into(),
+                union: Some(key_event::Union::Chr(30)),
+                ..Default::default()
+            }),
+            Step::Key(KeyEvent {
+                press: true,
+                mode: KeyboardMode::Translate.into(),
+                modifiers: vec![ControlKey::Control.into(), ControlKey::Alt.into()],
+                union: Some(key_event::Union::Seq("a b".to_owned())),
+                ..Default::default()
+            }),
+            Step::Key(KeyEvent {
+                union: Some(key_event::Union::ControlKey(ControlKey::Return.into())),
+                ..Default::default()
+            }),
+        ];
+        let text = format(&steps);
+        assert_eq!(
+            text.lines().nth(3),
+            Some("key press Translate Control,Alt seq a b")
+        );
+        assert_eq!(parse(&text).unwrap(), steps);
+        assert!(parse("# comment\n\ndelay 5").is_ok());
+        assert!(parse("key down Map - chr").is_err());
+        assert!(parse("mouse 1 2").is_err());
+        assert!(parse("click 1 2").is_err());
+    }
+
+    #[test]
+    fn test_type() {
+        let steps = parse("type {+CTRL}a{-CTRL}b").unwrap();
+        let keys: Vec<_> = steps
+            .iter()
+            .map(|s| match s {
+                Step::Key(k) => (k.down, k.press, k.union.clone(), k.modifiers.len()),
+                _ => panic!(),
+            })
+            .collect();
+        let control = key_event::Union::ControlKey(ControlKey::Control.into());
+        assert_eq!(
+            keys,
+            vec![
+                (true, false, Some(control.clone()), 0),
+                (true, true, Some(key_event::Union::Chr('a' as _)), 1),
+                (false, false, Some(control), 0),
+                (true, true, Some(key_event::Union::Seq("b".to_owned())), 0),
+            ]
+        );
+        assert!(parse("type {+FOO}").is_err());
+    }
+
+    #[test]
+    fn test_text_steps() {
+        let steps = text_steps("a\r\nb", 30, KeyboardMode::Translate);
+        assert_eq!(steps.len(), 5);
+        assert_eq!(steps[1], Step::Delay(30));
+        let Step::Key(evt) = &steps[2] else { panic!() };
+        assert!(evt.press);
+        assert_eq!(evt.control_key(), ControlKey::Return);
+        assert!(is_abort_key(&KeyEvent {
+            union: Some(key_event::Union::ControlKey(ControlKey::Escape.into())),
+            ..Default::default()
+        }));
+        assert_eq!(text_steps("ab", 0, KeyboardMode::Legacy).len(), 2);
+
+        let keys: Vec<_> = text_steps("a\nb\tc", 0, KeyboardMode::Translate)
+            .into_iter()
+            .map(|s| match s {
+                Step::Key(k) => (k.mode.enum_value().unwrap(), k.union.unwrap()),
+                _ => panic!(),
+            })
+            .collect();
+        let seq = |s: &str| key_event::Union::Seq(s.to_owned());
+        let control = |k: ControlKey| key_event::Union::ControlKey(k.into());
+        assert_eq!(
+            keys,
+            vec![
+                (KeyboardMode::Translate, seq("a")),
+                (KeyboardMode::Legacy, control(ControlKey::Return)),
+                (KeyboardMode::Translate, seq("b")),
+                (KeyboardMode::Legacy, control(ControlKey::Tab)),
+                (KeyboardMode::Translate, seq("c")),
+            ]
+        );
+    }
+
+    #[test]
+    fn test_release() {
+        let steps =
+            parse("mouse 9 1 1\nkey down Map - chr 30\nkey down Map - chr 31\nkey up Map - chr 30")
+                .unwrap();
+        let sent = std::sync::Mutex::new(Vec::new());
+        play(&steps, 1., &AtomicBool::new(false), |msg| {
+            sent.lock().unwrap().push(msg)
+        });
+        let sent = sent.into_inner().unwrap();
+        assert_eq!(sent.len(), 6);
+        assert!(!sent[4].key_event().down);
+        assert_eq!(sent[4].key_event().chr(), 31);
+        assert_eq!(sent[5].mouse_event().mask, 1 << 3 | MOUSE_TYPE_UP);
+    }
+}
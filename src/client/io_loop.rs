@@ -3,7 +3,7 @@ use std::{
     num::NonZeroI64,
     sync::{
         atomic::{AtomicUsize, Ordering},
-        Arc, RwLock,
+        Arc, Mutex, RwLock,
     },
 };
 
@@ -39,16 +39,35 @@ use hbb_common::{tokio::sync::Mutex as TokioMutex, ResultType};
 use scrap::CodecFormat;
 
 use crate::client::{
-    new_voice_call_request, Client, MediaData, MediaSender, QualityStatus, MILLI1, SEC30,
+    new_voice_call_request, Client, MediaData, MediaSender, MediaSync, QualityStatus, MILLI1, SEC30,
 };
 #[cfg(not(any(target_os = "android", target_os = "ios")))]
 use crate::common::{self, update_clipboard};
 use crate::common::{get_default_sound_input, set_sound_input};
+use crate::metrics::{self, Side, Transport};
 use crate::ui_session_interface::{InvokeUiSession, Session};
 #[cfg(not(any(target_os = "ios")))]
 use crate::{audio_service, ConnInner, CLIENT_SERVER};
 use crate::{client::Data, client::Interface};
 
+// First look for a direct path this long after a relayed session is up, then back off.
+const MIGRATION_DELAY: Duration = Duration::from_secs(5);
+const MIGRATION_MAX_ATTEMPTS: u32 = 5;
+// how long the peer may take to mark the end of the old transport
+const MIGRATION_SWITCH_TIMEOUT: Duration = Duration::from_secs(10);
+
+/// Moving a relayed session to a direct transport, see [`TransportMigration`].
+#[derive(Default)]
+struct TransportMigrationState {
+    token: Option<Vec<u8>>,
+    // the direct transport, used once the peer marks the end of the old one in time
+    pending: Option<(Stream, Instant)>,
+    probing: bool,
+    attempts: u32,
+    next_attempt: Option<Instant>,
+    migrated: bool,
+}
+
 pub struct Remote<T: InvokeUiSession> {
     handler: Session<T>,
     video_queue_map: Arc<RwLock<HashMap<usize, ArrayQueue<VideoFrame>>>>,
@@ -75,6 +94,11 @@ pub struct Remote<T: InvokeUiSession> {
     fps_control_map: HashMap<usize, FpsControl>,
     decode_fps_map: Arc<RwLock<HashMap<usize, usize>>>,
     chroma: Arc<RwLock<Option<Chroma>>>,
+    media_sync: Arc<Mutex<MediaSync>>,
+    migration: TransportMigrationState,
+    tx_transport: mpsc::UnboundedSender<Option<Stream>>,
+    rx_transport: mpsc::UnboundedReceiver<Option<Stream>>,
+    metrics_id: i32,
 }
 
 impl<T: InvokeUiSession> Remote<T> {
@@ -88,7 +112,9 @@ impl<T: InvokeUiSession> Remote<T> {
         frame_count_map: Arc<RwLock<HashMap<usize, usize>>>,
         decode_fps: Arc<RwLock<HashMap<usize, usize>>>,
         chroma: Arc<RwLock<Option<Chroma>>>,
+        media_sync: Arc<Mutex<MediaSync>>,
     ) -> Self {
+        let (tx_transport, rx_transport) = mpsc::unbounded_channel();
         Self {
             handler,
             video_queue_map: video_queue,
@@ -114,6 +140,11 @@ impl<T: InvokeUiSession> Remote<T> {
             fps_control_map: Default::default(),
             decode_fps_map: decode_fps,
             chroma,
+            media_sync,
+            migration: Default::default(),
+            tx_transport,
+            rx_transport,
+            metrics_id: metrics::new_conn_id(),
         }
     }
 
@@ -147,6 +178,16 @@ impl<T: InvokeUiSession> Remote<T> {
                     self.handler
                         .set_fingerprint(crate::common::pk_to_fingerprint(pk.unwrap_or_default()));
                 }
+                let id = self.handler.get_id();
+                let transport = if !direct {
+                    Transport::Relay
+                } else if hbb_common::is_ip_str(&id) || hbb_common::is_domain_port_str(&id) {
+                    Transport::Direct
+                } else {
+                    Transport::Punched
+                };
+                metrics::start(Side::Controller);
+                metrics::add(Side::Controller, self.metrics_id, id, transport);
 
                 // just build for now
                 #[cfg(not(any(target_os = "windows", target_os = "linux", target_os = "macos")))]
@@ -212,6 +253,14 @@ impl<T: InvokeUiSession> Remote<T> {
                                 }
                             }
                         }
+                        Some(res) = self.rx_transport.recv() => {
+                            self.migration.probing = false;
+                            if let Some(stream) = res {
+                                log::info!("Direct transport ready, waiting for the peer to switch");
+                                self.migration.pending =
+                                    Some((stream, Instant::now() + MIGRATION_SWITCH_TIMEOUT));
+                            }
+                        }
                         _msg = rx_clip_client.recv() => {
                             #[cfg(any(target_os="windows", target_os="linux", target_os = "macos"))]
                            self.handle_local_clipboard_msg(&mut peer, _msg).await;
@@ -222,17 +271,21 @@ impl<T: InvokeUiSession> Remote<T> {
                                 break;
                             }
                             if !self.read_jobs.is_empty() {
+                                let progress = metrics::job_progress(&self.read_jobs);
                                 if let Err(err) = fs::handle_read_jobs(&mut self.read_jobs, &mut peer).await {
                                     self.handler.msgbox("error", "Connection Error", &err.to_string(), "");
                                     break;
                                 }
+                                let sent = metrics::progress_since(&progress, &self.read_jobs);
+                                metrics::update(Side::Controller, self.metrics_id, |m| m.file_bytes_sent += sent);
                                 self.update_jobs_status();
                             } else {
                                 self.timer = time::interval_at(Instant::now() + SEC30, SEC30);
                             }
                         }
                         _ = status_timer.tick() => {
-                            self.fps_control(direct);
+                            self.fps_control(direct || self.migration.migrated);
+                            self.try_migrate_transport(direct, key, token, conn_type);
                             let elapsed = fps_instant.elapsed().as_millis();
                             if elapsed < 1000 {
                                 continue;
@@ -250,6 +303,8 @@ impl<T: InvokeUiSession> Remote<T> {
                                 // Correcting the inaccuracy of status_timer
                                 (k.clone(), (*v as i32) * 1000 / elapsed as i32)
                             }).collect::<HashMap<usize, i32>>();
+                            let total_fps = fps.values().sum::<i32>().max(0) as u32;
+                            metrics::update(Side::Controller, self.metrics_id, |m| m.fps = Some(total_fps));
                             let chroma = self.chroma.read().unwrap().clone();
                             let chroma = match chroma {
                                 Some(Chroma::I444) => "4:4:4",
@@ -257,16 +312,19 @@ impl<T: InvokeUiSession> Remote<T> {
                                 None => "-",
                             };
                             let chroma = Some(chroma.to_string());
+                            let audio_buffer = self.media_sync.lock().unwrap().audio_buffer();
                             self.handler.update_quality_status(QualityStatus {
                                 speed: Some(speed),
                                 fps,
                                 chroma,
+                                audio_buffer,
                                 ..Default::default()
                             });
                         }
                     }
                 }
                 log::debug!("Exit io_loop of id={}", self.handler.get_id());
+                metrics::remove(Side::Controller, self.metrics_id);
                 // Stop client audio server.
                 if let Some(s) = self.stop_voice_call_sender.take() {
                     s.send(()).ok();
@@ -960,6 +1018,68 @@ impl<T: InvokeUiSession> Remote<T> {
         }
     }
 
+    // Look for a direct path in the background while the session goes through relay.
+    fn try_migrate_transport(&mut self, direct: bool, key: &str, token: &str, conn_type: ConnType) {
+        let m = &mut self.migration;
+        if let Some((_, deadline)) = m.pending.as_ref() {
+            if *deadline > Instant::now() {
+                return;
+            }
+            log::debug!("The peer did not switch to the direct transport");
+            m.pending = None;
+        }
+        if direct || m.migrated || m.probing || self.handler.is_force_relay() {
+            return;
+        }
+        if m.attempts >= MIGRATION_MAX_ATTEMPTS {
+            if m.token.take().is_some() {
+                log::info!("No transport migration after {} attempts", m.attempts);
+            }
+            return;
+        }
+        let Some(migration_token) = m.token.clone() else {
+            return;
+        };
+        if m.next_attempt.map(|t| t > Instant::now()).unwrap_or(true) {
+            return;
+        }
+        m.probing = true;
+        m.attempts += 1;
+        m.next_attempt = Some(Instant::now() + MIGRATION_DELAY * 2u32.pow(m.attempts));
+        let id = self.handler.get_id();
+        let interface = self.handler.clone();
+        let key = key.to_owned();
+        let token = token.to_owned();
+        let tx = self.tx_transport.clone();
+        tokio::spawn(async move {
+            let res = match Client::start_direct_transport(&id, &key, &token, conn_type, interface)
+                .await
+            {
+                Ok(mut stream) => {
+                    let mut misc = Misc::new();
+                    misc.set_transport_migration(TransportMigration {
+                        token: migration_token.into(),
+                        ..Default::default()
+                    });
+                    let mut msg = Message::new();
+                    msg.set_misc(misc);
+                    match stream.send(&msg).await {
+                        Ok(_) => Some(stream),
+                        Err(err) => {
+                            log::debug!("Failed to request transport migration: {}", err);
+                            None
+                        }
+                    }
+                }
+                Err(err) => {
+                    log::debug!("No direct transport to migrate to: {}", err);
+                    None
+                }
+            };
+            tx.send(res).ok();
+        });
+    }
+
     #[inline]
     fn fps_control(&mut self, direct: bool) {
         let custom_fps = self.handler.lc.read().unwrap().custom_fps.clone();
@@ -1052,8 +1172,14 @@ impl<T: InvokeUiSession> Remote<T> {
                         self.send_toggle_privacy_mode_msg(peer).await;
                     }
                     let incoming_format = CodecFormat::from(&vf);
-                    if self.video_format != incoming_format {
+                    // tiles carry no codec
+                    if self.video_format != incoming_format
+                        && incoming_format != CodecFormat::Unknown
+                    {
                         self.video_format = incoming_format.clone();
+                        metrics::update(Side::Controller, self.metrics_id, |m| {
+                            m.codec = format!("{:?}", incoming_format)
+                        });
                         self.handler.update_quality_status(QualityStatus {
                             codec_format: Some(incoming_format),
                             ..Default::default()
@@ -1070,7 +1196,13 @@ impl<T: InvokeUiSession> Remote<T> {
                     }
                     if Self::contains_key_frame(&vf) {
                         if let Some(video_queue) = video_queue_write.get_mut(&display) {
-                            while let Some(_) = video_queue.pop() {}
+                            let mut dropped = 0;
+                            while let Some(_) = video_queue.pop() {
+                                dropped += 1;
+                            }
+                            metrics::update(Side::Controller, self.metrics_id, |m| {
+                                m.frames_dropped += dropped
+                            });
                         }
                         self.video_sender
                             .send(MediaData::VideoFrame(Box::new(vf)))
@@ -1288,6 +1420,10 @@ impl<T: InvokeUiSession> Remote<T> {
                             }
                         }
                         Some(file_response::Union::Block(block)) => {
+                            let len = block.data.len() as u64;
+                            metrics::update(Side::Controller, self.metrics_id, |m| {
+                                m.file_bytes_received += len
+                            });
                             if let Some(job) = fs::get_job(block.id, &mut self.write_jobs) {
                                 if let Err(_err) = job.write(block).await {
                                     // to-do: add "skip" for writing job
@@ -1376,6 +1512,12 @@ impl<T: InvokeUiSession> Remote<T> {
                             );
                         }
                     }
+                    Some(misc::Union::CanvasLayout(layout)) => {
+                        self.handler.set_canvas_layout(&layout);
+                    }
+                    Some(misc::Union::InputControl(control)) => {
+                        self.handler.set_input_control(&control);
+                    }
                     Some(misc::Union::CloseReason(c)) => {
                         self.handler.msgbox("error", "Connection Error", &c, "");
                         return false;
@@ -1485,6 +1627,24 @@ impl<T: InvokeUiSession> Remote<T> {
                         ));
                         // to-do: show message box on UI when error occurs?
                     }
+                    Some(misc::Union::TransportMigration(tm)) => {
+                        if tm.done {
+                            if let Some((stream, _)) = self.migration.pending.take() {
+                                // nothing follows on the old transport, dropping it lets the peer close it too
+                                *peer = stream;
+                                self.migration.migrated = true;
+                                metrics::update(Side::Controller, self.metrics_id, |m| {
+                                    m.transport = Transport::Punched
+                                });
+                                self.handler.update_direct(Some(true));
+                                self.handler.set_connection_type(peer.is_secured(), true);
+                                log::info!("Session migrated to the direct transport");
+                            }
+                        } else if !tm.token.is_empty() {
+                            self.migration.token = Some(tm.token.to_vec());
+                            self.migration.next_attempt = Some(Instant::now() + MIGRATION_DELAY);
+                        }
+                    }
                     #[cfg(all(feature = "flutter", feature = "plugin_framework"))]
                     #[cfg(not(any(target_os = "android", target_os = "ios")))]
                     Some(misc::Union::PluginFailure(p)) => {
@@ -1498,6 +1658,12 @@ impl<T: InvokeUiSession> Remote<T> {
                     _ => {}
                 },
                 Some(message::Union::TestDelay(t)) => {
+                    if !t.from_client {
+                        let rtt = t.last_delay;
+                        metrics::update(Side::Controller, self.metrics_id, |m| {
+                            m.rtt_ms = Some(rtt)
+                        });
+                    }
                     self.handler.handle_test_delay(t, peer).await;
                 }
                 Some(message::Union::AudioFrame(frame)) => {
@@ -1561,6 +1727,10 @@ impl<T: InvokeUiSession> Remote<T> {
                     self.handler.set_displays(&pi.displays);
                     self.handler.set_platform_additions(&pi.platform_additions);
                 }
+                #[cfg(feature = "gamepad")]
+                Some(message::Union::GamepadEvent(evt)) => {
+                    self.handler.handle_gamepad_event(&evt);
+                }
                 _ => {}
             }
         }
@@ -0,0 +1,166 @@
+//! Holds the audio back against the network jitter, or until the video it
+//! belongs to is shown, when the peer stamps the audio with the clock of the
+//! video pts.
+//!
+//! The times are in milliseconds, the pts on the clock of the peer and `now`
+//! on the local one.
+
+use std::collections::VecDeque;
+
+const MIN_DEPTH_MS: i64 = 20;
+const MAX_DEPTH_MS: i64 = 400;
+// Chunks later than this are dropped rather than delaying the rest.
+const MAX_LATE_MS: i64 = 120;
+// The number of chunks the lowest transit time is taken from, 5s of 10ms chunks.
+const TRANSIT_WINDOW: usize = 500;
+// A change of the transit time this big is a new clock of the peer, not jitter.
+const CLOCK_JUMP_MS: i64 = 1000;
+
+struct Chunk<T> {
+    // 0 if the peer does not stamp the audio
+    pts: i64,
+    duration: i64,
+    data: T,
+}
+
+pub struct JitterBuffer<T> {
+    chunks: VecDeque<Chunk<T>>,
+    // the interarrival jitter of https://www.rfc-editor.org/rfc/rfc3550#appendix-A.8
+    jitter: f64,
+    transits: VecDeque<i64>,
+}
+
+impl<T> Default for JitterBuffer<T> {
+    fn default() -> Self {
+        Self {
+            chunks: Default::default(),
+            jitter: 0.,
+            transits: Default::default(),
+        }
+    }
+}
+
+impl<T> JitterBuffer<T> {
+    pub fn push(&mut self, now: i64, pts: i64, duration: i64, data: T) {
+        if pts > 0 {
+            let transit = now - pts;
+            if let Some(&last) = self.transits.back() {
+                let d = (transit - last).abs();
+                if d > CLOCK_JUMP_MS {
+                    self.transits.clear();
+                    self.jitter = 0.;
+                } else {
+                    self.jitter += (d as f64 - self.jitter) / 16.;
+                }
+            }
+            if self.transits.len() >= TRANSIT_WINDOW {
+                self.transits.pop_front();
+            }
+            self.transits.push_back(transit);
+        }
+        self.chunks.push_back(Chunk {
+            pts,
+            duration,
+            data,
+        });
+    }
+
+    /// The next chunk to play at `now`. `video` is the pts of the video shown
+    /// by the time the chunk is heard, if the audio is synced to it.
+    pub fn pop(&mut self, now: i64, video: Option<i64>) -> Option<T> {
+        loop {
+            let chunk = self.chunks.front()?;
+            if chunk.pts <= 0 || self.buffered() > MAX_DEPTH_MS {
+                return self.chunks.pop_front().map(|c| c.data);
+            }
+            let position = match video {
+                Some(video) => video,
+                None => now - self.base_transit() - self.depth(),
+            };
+            if chunk.pts + chunk.duration + MAX_LATE_MS < position {
+                self.chunks.pop_front();
+                continue;
+            }
+            if chunk.pts <= position {
+                return self.chunks.pop_front().map(|c| c.data);
+            }
+            return None;
+        }
+    }
+
+    /// The depth the audio is held back against the jitter.
+    pub fn depth(&self) -> i64 {
+        ((self.jitter * 3.) as i64 + MIN_DEPTH_MS).clamp(MIN_DEPTH_MS, MAX_DEPTH_MS)
+    }
+
+    /// The duration of the audio held.
+    pub fn buffered(&self) -> i64 {
+        self.chunks.iter().map(|c| c.duration).sum()
+    }
+
+    fn base_transit(&self) -> i64 {
+        self.transits.iter().min().copied().unwrap_or_default()
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_unstamped() {
+        let mut b = JitterBuffer::default();
+        b.push(1000, 0, 10, 1);
+        assert_eq!(b.pop(1000, Some(0)), Some(1));
+        assert_eq!(b.pop(1000, None), None);
+    }
+
+    #[test]
+    fn test_depth() {
+        let mut b = JitterBuffer::default();
+        b.push(1000, 100, 10, 1);
+        assert_eq!(b.pop(1000, None), None);
+        assert_eq!(b.pop(1000 + MIN_DEPTH_MS, None), Some(1));
+        // arriving later than the others is held less
+        for i in 0..100 {
+            let pts = 200 + i * 10;
+            b.push(1100 + i * 10 + (i % 2) * 30, pts, 10, pts);
+        }
+        assert!(b.depth() > MIN_DEPTH_MS);
+        assert!(b.depth() < MAX_DEPTH_MS);
+        assert_eq!(b.base_transit(), 900);
+    }
+
+    #[test]
+    fn test_video() {
+        let mut b = JitterBuffer::default();
+        b.push(1000, 100, 10, 1);
+        b.push(1000, 110, 10, 2);
+        b.push(1000, 300, 10, 3);
+        assert_eq!(b.pop(1000, Some(50)), None);
+        // too late
+        assert_eq!(b.pop(1000, Some(250)), None);
+        assert_eq!(b.buffered(), 10);
+        assert_eq!(b.pop(1000, Some(300)), Some(3));
+    }
+
+    #[test]
+    fn test_max_depth() {
+        let mut b = JitterBuffer::default();
+        for i in 0..50 {
+            b.push(1000, 100 + i * 10, 10, i);
+        }
+        assert_eq!(b.pop(1000, Some(0)), Some(0));
+        while b.pop(1000, Some(0)).is_some() {}
+        assert_eq!(b.buffered(), MAX_DEPTH_MS);
+    }
+
+    #[test]
+    fn test_clock_jump() {
+        let mut b = JitterBuffer::default();
+        b.push(1000, 900, 10, 1);
+        b.push(5000, 100, 10, 2);
+        assert_eq!(b.base_transit(), 4900);
+        assert_eq!(b.jitter, 0.);
+    }
+}
@@ -0,0 +1,476 @@
+//! Remapping of the keys sent to a peer, e.g. Cmd↔Ctrl when a Mac controls
+//! Linux, CapsLock→Escape, or a key for Ctrl+Alt+Del.
+//!
+//! The mappings of a peer are kept in its `PeerConfig` and edited as text, a
+//! mapping a line:
+//!
+//! ```text
+//! # a comment
+//! CapsLock = Escape
+//! Meta = Control
+//! Control = Meta
+//! Pause = Control+Alt+Delete
+//! ```
+//!
+//! A key is the name of a `ControlKey` or a letter or digit, the keys before
+//! the last one of a chord must be modifiers. The mappings are applied at once
+//! rather than one after another, so the two in the middle swap Meta and
+//! Control. The legacy keyboard mode does not send the lock keys, so they can
+//! only be remapped in the other modes.
+
+use hbb_common::{
+    anyhow::anyhow,
+    bail,
+    config::KeyMapping,
+    message_proto::*,
+    protobuf::{Enum, EnumFull},
+    ResultType,
+};
+use rdev::Key;
+
+#[derive(Debug, Clone, Copy, PartialEq)]
+enum RemapKey {
+    Control(ControlKey),
+    Chr(char),
+}
+
+#[derive(Debug, Clone, PartialEq)]
+pub struct Rule {
+    from: RemapKey,
+    // the modifiers of the chord, then the key
+    to: Vec<RemapKey>,
+}
+
+const KEYS: &[(ControlKey, Key)] = &[
+    (ControlKey::Alt, Key::Alt),
+    (ControlKey::RAlt, Key::AltGr),
+    (ControlKey::Backspace, Key::Backspace),
+    (ControlKey::CapsLock, Key::CapsLock),
+    (ControlKey::Control, Key::ControlLeft),
+    (ControlKey::RControl, Key::ControlRight),
+    (ControlKey::Delete, Key::Delete),
+    (ControlKey::DownArrow, Key::DownArrow),
+    (ControlKey::End, Key::End),
+    (ControlKey::Escape, Key::Escape),
+    (ControlKey::F1, Key::F1),
+    (ControlKey::F2, Key::F2),
+    (ControlKey::F3, Key::F3),
+    (ControlKey::F4, Key::F4),
+    (ControlKey::F5, Key::F5),
+    (ControlKey::F6, Key::F6),
+    (ControlKey::F7, Key::F7),
+    (ControlKey::F8, Key::F8),
+    (ControlKey::F9, Key::F9),
+    (ControlKey::F10, Key::F10),
+    (ControlKey::F11, Key::F11),
+    (ControlKey::F12, Key::F12),
+    (ControlKey::Home, Key::Home),
+    (ControlKey::LeftArrow, Key::LeftArrow),
+    (ControlKey::Meta, Key::MetaLeft),
+    (ControlKey::RWin, Key::MetaRight),
+    (ControlKey::PageDown, Key::PageDown),
+    (ControlKey::PageUp, Key::PageUp),
+    (ControlKey::Return, Key::Return),
+    (ControlKey::RightArrow, Key::RightArrow),
+    (ControlKey::Shift, Key::ShiftLeft),
+    (ControlKey::RShift, Key::ShiftRight),
+    (ControlKey::Space, Key::Space),
+    (ControlKey::Tab, Key::Tab),
+    (ControlKey::UpArrow, Key::UpArrow),
+    (ControlKey::Insert, Key::Insert),
+    (ControlKey::Pause, Key::Pause),
+    (ControlKey::Snapshot, Key::PrintScreen),
+    (ControlKey::Scroll, Key::ScrollLock),
+    (ControlKey::NumLock, Key::NumLock),
+    (ControlKey::Apps, Key::Apps),
+    (ControlKey::Numpad0, Key::Kp0),
+    (ControlKey::Numpad1, Key::Kp1),
+    (ControlKey::Numpad2, Key::Kp2),
+    (ControlKey::Numpad3, Key::Kp3),
+    (ControlKey::Numpad4, Key::Kp4),
+    (ControlKey::Numpad5, Key::Kp5),
+    (ControlKey::Numpad6, Key::Kp6),
+    (ControlKey::Numpad7, Key::Kp7),
+    (ControlKey::Numpad8, Key::Kp8),
+    (ControlKey::Numpad9, Key::Kp9),
+    (ControlKey::NumpadEnter, Key::KpReturn),
+    (ControlKey::Multiply, Key::KpMultiply),
+    (ControlKey::Add, Key::KpPlus),
+    (ControlKey::Subtract, Key::KpMinus),
+    (ControlKey::Divide, Key::KpDivide),
+    (ControlKey::Decimal, Key::KpDecimal),
+];
+
+const CHARS: &[(char, Key)] = &[
+    ('a', Key::KeyA),
+    ('b', Key::KeyB),
+    ('c', Key::KeyC),
+    ('d', Key::KeyD),
+    ('e', Key::KeyE),
+    ('f', Key::KeyF),
+    ('g', Key::KeyG),
+    ('h', Key::KeyH),
+    ('i', Key::KeyI),
+    ('j', Key::KeyJ),
+    ('k', Key::KeyK),
+    ('l', Key::KeyL),
+    ('m', Key::KeyM),
+    ('n', Key::KeyN),
+    ('o', Key::KeyO),
+    ('p', Key::KeyP),
+    ('q', Key::KeyQ),
+    ('r', Key::KeyR),
+    ('s', Key::KeyS),
+    ('t', Key::KeyT),
+    ('u', Key::KeyU),
+    ('v', Key::KeyV),
+    ('w', Key::KeyW),
+    ('x', Key::KeyX),
+    ('y', Key::KeyY),
+    ('z', Key::KeyZ),
+    ('0', Key::Num0),
+    ('1', Key::Num1),
+    ('2', Key::Num2),
+    ('3', Key::Num3),
+    ('4', Key::Num4),
+    ('5', Key::Num5),
+    ('6', Key::Num6),
+    ('7', Key::Num7),
+    ('8', Key::Num8),
+    ('9', Key::Num9),
+];
+
+/// The rules of `mappings`, skipping the invalid ones.
+pub fn rules(mappings: &[KeyMapping]) -> Vec<Rule> {
+    mappings.iter().filter_map(|m| rule(m).ok()).collect()
+}
+
+/// The events to send for `evt` after applying `rules`. `peer` is the lower
+/// case platform of the peer without spaces, which the key codes of the map
+/// and translate modes belong to.
+pub fn remap(rules: &[Rule], peer: &str, evt: &KeyEvent) -> Vec<KeyEvent> {
+    let mut evt = evt.clone();
+    remap_modifiers(rules, &mut evt);
+    let Some((key, modifiers)) = key_of(peer, &evt)
+        .and_then(|k| rules.iter().find(|r| r.from == k))
+        .and_then(|r| r.to.split_last())
+    else {
+        return vec![evt];
+    };
+
+    if let RemapKey::Control(k @ (ControlKey::CtrlAltDel | ControlKey::LockScreen)) = key {
+        // handled by the peer on the key down in the legacy mode only
+        if !evt.down && !evt.press {
+            return Vec::new();
+        }
+        let mut evt = KeyEvent::new();
+        evt.set_control_key(*k);
+        evt.down = true;
+        evt.mode = KeyboardMode::Legacy.into();
+        return vec![evt];
+    }
+
+    let mut main = key_event(&evt, peer, *key);
+    if main.mode.enum_value() == Ok(KeyboardMode::Legacy) {
+        // the peer presses and releases the modifiers of a legacy event itself
+        for m in modifiers {
+            if let RemapKey::Control(k) = m {
+                let k = (*k).into();
+                if !main.modifiers.contains(&k) {
+                    main.modifiers.push(k);
+                }
+            }
+        }
+        return vec![main];
+    }
+
+    let modifier = |key: &RemapKey, down: bool| {
+        let template = KeyEvent {
+            down,
+            mode: evt.mode,
+            ..Default::default()
+        };
+        key_event(&template, peer, *key)
+    };
+    let mut events = Vec::new();
+    if evt.down || evt.press {
+        events.extend(modifiers.iter().map(|m| modifier(m, true)));
+    }
+    events.push(main);
+    if !evt.down {
+        events.extend(modifiers.iter().rev().map(|m| modifier(m, false)));
+    }
+    events
+}
+
+/// The text of `mappings`, a mapping a line.
+pub fn format(mappings: &[KeyMapping]) -> String {
+    mappings
+        .iter()
+        .map(|m| format!("{} = {}\n", m.from, m.to))
+        .collect()
+}
+
+pub fn parse(text: &str) -> ResultType<Vec<KeyMapping>> {
+    let mut mappings = Vec::new();
+    for line in text.lines() {
+        let line = line.trim();
+        if line.is_empty() || line.starts_with('#') {
+            continue;
+        }
+        let Some((from, to)) = line.split_once('=') else {
+            bail!("invalid line {line}");
+        };
+        let mapping = KeyMapping {
+            from: from.trim().to_owned(),
+            to: to.split('+').map(str::trim).collect::<Vec<_>>().join("+"),
+        };
+        rule(&mapping)?;
+        mappings.push(mapping);
+    }
+    Ok(mappings)
+}
+
+fn rule(mapping: &KeyMapping) -> ResultType<Rule> {
+    let from = parse_key(&mapping.from)?;
+    let to = mapping
+        .to
+        .split('+')
+        .map(|k| parse_key(k.trim()))
+        .collect::<ResultType<Vec<_>>>()?;
+    for (i, k) in to.iter().enumerate() {
+        let last = i + 1 == to.len();
+        match k {
+            RemapKey::Control(ControlKey::CtrlAltDel | ControlKey::LockScreen) if to.len() > 1 => {
+                bail!("{} can not be in a chord", mapping.to);
+            }
+            RemapKey::Control(k) if !last && !is_modifier(*k) => {
+                bail!("{k:?} is not a modifier");
+            }
+            RemapKey::Chr(c) if !last => {
+                bail!("{c} is not a modifier");
+            }
+            _ => {}
+        }
+    }
+    Ok(Rule { from, to })
+}
+
+fn parse_key(name: &str) -> ResultType<RemapKey> {
+    let mut chars = name.chars();
+    if let (Some(c), None) = (chars.next(), chars.next()) {
+        if c.is_ascii_alphanumeric() {
+            return Ok(RemapKey::Chr(c.to_ascii_lowercase()));
+        }
+    }
+    ControlKey::enum_descriptor()
+        .value_by_name(name)
+        .and_then(|v| ControlKey::from_i32(v.value()))
+        .filter(|k| *k != ControlKey::Unknown)
+        .map(RemapKey::Control)
+        .ok_or_else(|| anyhow!("unknown key {name}"))
+}
+
+fn is_modifier(key: ControlKey) -> bool {
+    matches!(
+        key,
+        ControlKey::Alt
+            | ControlKey::RAlt
+            | ControlKey::Control
+            | ControlKey::RControl
+            | ControlKey::Shift
+            | ControlKey::RShift
+            | ControlKey::Meta
+            | ControlKey::RWin
+    )
+}
+
+// The modifiers held with a key of the legacy mode.
+fn remap_modifiers(rules: &[Rule], evt: &mut KeyEvent) {
+    for m in evt.modifiers.iter_mut() {
+        let Ok(k) = m.enum_value() else {
+            continue;
+        };
+        let to = rules
+            .iter()
+            .find(|r| r.from == RemapKey::Control(k))
+            .and_then(|r| match r.to[..] {
+                [RemapKey::Control(to)] if is_modifier(to) => Some(to),
+                _ => None,
+            });
+        if let Some(to) = to {
+            *m = to.into();
+        }
+    }
+}
+
+fn key_of(peer: &str, evt: &KeyEvent) -> Option<RemapKey> {
+    match &evt.union {
+        Some(key_event::Union::ControlKey(k)) => k.enum_value().ok().map(RemapKey::Control),
+        Some(key_event::Union::Chr(code)) => {
+            if evt.mode.enum_value() == Ok(KeyboardMode::Legacy) {
+                let c = char::from_u32(*code)?.to_ascii_lowercase();
+                c.is_ascii_alphanumeric().then_some(RemapKey::Chr(c))
+            } else {
+                from_rdev(peer_key(peer, *code)?)
+            }
+        }
+        _ => None,
+    }
+}
+
+// `template` with `key`, in the legacy mode if the peer has no code for it.
+fn key_event(template: &KeyEvent, peer: &str, key: RemapKey) -> KeyEvent {
+    let mut evt = template.clone();
+    if evt.mode.enum_value() != Ok(KeyboardMode::Legacy) {
+        if let Some(code) = to_rdev(key).and_then(|k| peer_code(peer, k)) {
+            evt.set_chr(code);
+            return evt;
+        }
+        evt.mode = KeyboardMode::Legacy.into();
+    }
+    match key {
+        RemapKey::Control(k) => evt.set_control_key(k),
+        RemapKey::Chr(c) => evt.set_chr(c as _),
+    }
+    evt
+}
+
+fn to_rdev(key: RemapKey) -> Option<Key> {
+    match key {
+        RemapKey::Control(k) => KEYS.iter().find(|(c, _)| *c == k).map(|(_, r)| *r),
+        RemapKey::Chr(c) => CHARS.iter().find(|(ch, _)| *ch == c).map(|(_, r)| *r),
+    }
+}
+
+fn from_rdev(key: Key) -> Option<RemapKey> {
+    KEYS.iter()
+        .find(|(_, r)| *r == key)
+        .map(|(c, _)| RemapKey::Control(*c))
+        .or_else(|| {
+            CHARS
+                .iter()
+                .find(|(_, r)| *r == key)
+                .map(|(c, _)| RemapKey::Chr(*c))
+        })
+}
+
+fn peer_key(peer: &str, code: u32) -> Option<Key> {
+    match peer {
+        "windows" => Some(rdev::win_key_from_scancode(code)),
+        "macos" => Some(rdev::macos_key_from_code(code as _)),
+        "linux" => Some(rdev::linux_key_from_code(code)),
+        _ => None,
+    }
+}
+
+fn peer_code(peer: &str, key: Key) -> Option<u32> {
+    match peer {
+        "windows" => rdev::win_scancode_from_key(key),
+        "macos" => rdev::macos_keycode_from_key(key).map(|c| c as _),
+        "linux" => rdev::linux_keycode_from_key(key),
+        _ => None,
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn mappings(text: &str) -> Vec<Rule> {
+        rules(&parse(text).unwrap())
+    }
+
+    fn control(key: ControlKey, down: bool) -> KeyEvent {
+        let mut evt = KeyEvent::new();
+        evt.set_control_key(key);
+        evt.down = down;
+        evt
+    }
+
+    fn map(key: Key, down: bool) -> KeyEvent {
+        let mut evt = KeyEvent::new();
+        evt.set_chr(rdev::linux_keycode_from_key(key).unwrap());
+        evt.down = down;
+        evt.mode = KeyboardMode::Map.into();
+        evt
+    }
+
+    #[test]
+    fn test_parse() {
+        let text = "# swap\nMeta = Control\nControl=Meta\n\nPause = Control + Alt + Delete\n";
+        let parsed = parse(text).unwrap();
+        assert_eq!(parsed.len(), 3);
+        assert_eq!(parsed[2].to, "Control+Alt+Delete");
+        assert_eq!(parse(&format(&parsed)).unwrap(), parsed);
+        assert!(parse("Foo = Escape").is_err());
+        assert!(parse("CapsLock").is_err());
+        assert!(parse("a = Escape+Control").is_err());
+        assert!(parse("a = Control+CtrlAltDel").is_err());
+    }
+
+    #[test]
+    fn test_swap() {
+        let rules = mappings("Meta = Control\nControl = Meta");
+        assert_eq!(
+            remap(&rules, "linux", &control(ControlKey::Meta, true)),
+            vec![control(ControlKey::Control, true)]
+        );
+        assert_eq!(
+            remap(&rules, "linux", &map(Key::ControlLeft, false)),
+            vec![map(Key::MetaLeft, false)]
+        );
+        let mut evt = KeyEvent::new();
+        evt.set_chr('c' as _);
+        evt.down = true;
+        evt.modifiers = vec![ControlKey::Meta.into()];
+        assert_eq!(
+            remap(&rules, "linux", &evt)[0].modifiers,
+            vec![ControlKey::Control.into()]
+        );
+    }
+
+    #[test]
+    fn test_chord() {
+        let rules = mappings("CapsLock = Escape\nF11 = Control+Alt+Delete\nF12 = CtrlAltDel");
+        assert_eq!(
+            remap(&rules, "linux", &map(Key::CapsLock, true)),
+            vec![map(Key::Escape, true)]
+        );
+        assert_eq!(
+            remap(&rules, "linux", &map(Key::F11, true)),
+            vec![
+                map(Key::ControlLeft, true),
+                map(Key::Alt, true),
+                map(Key::Delete, true)
+            ]
+        );
+        assert_eq!(
+            remap(&rules, "linux", &map(Key::F11, false)),
+            vec![
+                map(Key::Delete, false),
+                map(Key::Alt, false),
+                map(Key::ControlLeft, false)
+            ]
+        );
+        let mut evt = control(ControlKey::F11, false);
+        evt.press = true;
+        let events = remap(&rules, "linux", &evt);
+        assert_eq!(events.len(), 1);
+        assert_eq!(events[0].control_key(), ControlKey::Delete);
+        assert_eq!(
+            events[0].modifiers,
+            vec![ControlKey::Control.into(), ControlKey::Alt.into()]
+        );
+        assert_eq!(
+            remap(&rules, "linux", &map(Key::F12, true))[0].control_key(),
+            ControlKey::CtrlAltDel
+        );
+        assert!(remap(&rules, "linux", &map(Key::F12, false)).is_empty());
+        // no mapping
+        assert_eq!(
+            remap(&rules, "linux", &map(Key::KeyA, true)),
+            vec![map(Key::KeyA, true)]
+        );
+    }
+}
@@ -177,6 +177,25 @@ pub mod input {
     pub const MOUSE_BUTTON_WHEEL: i32 = 0x04;
     pub const MOUSE_BUTTON_BACK: i32 = 0x08;
     pub const MOUSE_BUTTON_FORWARD: i32 = 0x10;
+
+    pub const GAMEPAD_BUTTON_A: u32 = 0x0001;
+    pub const GAMEPAD_BUTTON_B: u32 = 0x0002;
+    pub const GAMEPAD_BUTTON_X: u32 = 0x0004;
+    pub const GAMEPAD_BUTTON_Y: u32 = 0x0008;
+    pub const GAMEPAD_BUTTON_LEFT_SHOULDER: u32 = 0x0010;
+    pub const GAMEPAD_BUTTON_RIGHT_SHOULDER: u32 = 0x0020;
+    pub const GAMEPAD_BUTTON_BACK: u32 = 0x0040;
+    pub const GAMEPAD_BUTTON_START: u32 = 0x0080;
+    pub const GAMEPAD_BUTTON_GUIDE: u32 = 0x0100;
+    pub const GAMEPAD_BUTTON_LEFT_THUMB: u32 = 0x0200;
+    pub const GAMEPAD_BUTTON_RIGHT_THUMB: u32 = 0x0400;
+    pub const GAMEPAD_BUTTON_DPAD_UP: u32 = 0x0800;
+    pub const GAMEPAD_BUTTON_DPAD_DOWN: u32 = 0x1000;
+    pub const GAMEPAD_BUTTON_DPAD_LEFT: u32 = 0x2000;
+    pub const GAMEPAD_BUTTON_DPAD_RIGHT: u32 = 0x4000;
+
+    pub const PEN_BUTTON_PRIMARY: u32 = 0x01;
+    pub const PEN_BUTTON_SECONDARY: u32 = 0x02;
 }
 
 lazy_static::lazy_static! {
@@ -302,6 +321,16 @@ pub fn check_clipboard(
     None
 }
 
+/// The text of the local clipboard, empty if none.
+#[cfg(not(any(target_os = "android", target_os = "ios")))]
+pub fn get_clipboard_text() -> String {
+    let Ok(mut ctx) = ClipboardContext::new() else {
+        return "".to_owned();
+    };
+    let _lock = ARBOARD_MTX.lock().unwrap();
+    ctx.get_text().unwrap_or_default()
+}
+
 /// Set sound input device.
 pub fn set_sound_input(device: String) {
     let prior_device = get_option("audio-input".to_owned());
@@ -666,10 +695,15 @@ pub fn test_nat_type() {
 #[tokio::main(flavor = "current_thread")]
 async fn test_nat_type_() -> ResultType<bool> {
     log::info!("Testing nat ...");
+    // only a delta measured by this test may be used to predict ports
+    Config::set_nat_port_delta(0);
     #[cfg(not(any(target_os = "android", target_os = "ios")))]
     let is_direct = crate::ipc::get_socks_async(1_000).await.is_none(); // sync socks BTW
     #[cfg(any(target_os = "android", target_os = "ios"))]
     let is_direct = Config::get_socks().is_none(); // sync socks BTW
+    let is_direct = is_direct
+        && Config::get_network_type() == hbb_common::config::NetworkType::Direct
+        && !socket_client::is_udp_disabled();
     if !is_direct {
         Config::set_nat_type(NatType::SYMMETRIC as _);
         return Ok(true);
@@ -728,7 +762,15 @@ async fn test_nat_type_() -> ResultType<bool> {
             NatType::SYMMETRIC
         };
         Config::set_nat_type(t as _);
-        log::info!("Tested nat type: {:?} in {:?}", t, start.elapsed());
+        // sequential symmetric nats allocate the next mapping this far away,
+        // which is what port prediction in hole punching relies on
+        Config::set_nat_port_delta(port2 - port1);
+        log::info!(
+            "Tested nat type: {:?}, port delta: {} in {:?}",
+            t,
+            port2 - port1,
+            start.elapsed()
+        );
     }
     Ok(ok)
 }
@@ -785,6 +827,18 @@ pub async fn get_nat_type(ms_timeout: u64) -> i32 {
     crate::ipc::get_nat_type(ms_timeout).await
 }
 
+#[inline]
+#[cfg(any(target_os = "android", target_os = "ios"))]
+pub async fn get_nat_port_delta(_ms_timeout: u64) -> i32 {
+    Config::get_nat_port_delta()
+}
+
+#[inline]
+#[cfg(not(any(target_os = "android", target_os = "ios")))]
+pub async fn get_nat_port_delta(ms_timeout: u64) -> i32 {
+    crate::ipc::get_nat_port_delta(ms_timeout).await
+}
+
 // used for client to test which server is faster in case stop-servic=Y
 #[tokio::main(flavor = "current_thread")]
 async fn test_rendezvous_server_() {
@@ -422,6 +422,37 @@ pub fn core_main() -> Option<Vec<String>> {
             #[cfg(feature = "hwcodec")]
             scrap::hwcodec::check_config();
             return None;
+        } else if args[0] == "--gen-record-keys" {
+            let (pk, sk) = scrap::record::gen_record_keys();
+            println!("public key: {}\nsecret key: {}", pk, sk);
+            return None;
+        } else if args[0] == "--verify-recording" {
+            if args.len() < 2 {
+                println!("Usage: --verify-recording <file> [<output>]");
+                println!("The secret key of an encrypted <file>.enc is read from stdin.");
+                println!("The plaintext is only written to <output>, if given.");
+                std::process::exit(2);
+            }
+            // never on the command line, where other users see it
+            let mut secret_key = String::new();
+            if args[1].ends_with(".enc") {
+                if let Err(e) = std::io::stdin().read_line(&mut secret_key) {
+                    println!("Failed to read the secret key: {}", e);
+                    std::process::exit(2);
+                }
+            }
+            match scrap::record::verify_recording(
+                &args[1],
+                Some(secret_key.as_str()).filter(|k| !k.is_empty()),
+                args.get(2).map(|o| o.as_str()),
+            ) {
+                Ok(pk) => println!("Valid recording, signed by {}", pk),
+                Err(e) => {
+                    println!("Invalid recording: {}", e);
+                    std::process::exit(1);
+                }
+            }
+            return None;
         } else if args[0] == "--cm" {
             // call connection manager to establish connections
             // meanwhile, return true to call flutter window to show control panel
@@ -449,6 +449,19 @@ impl InvokeUiSession for FlutterHandler {
         self.push_event("permission", vec![(name, &value.to_string())]);
     }
 
+    fn set_input_control(&self, control: &InputControl) {
+        self.push_event(
+            "input_control",
+            vec![
+                ("holder", &control.holder),
+                ("holding", &control.holding.to_string()),
+                ("requesting", &control.requesting.to_string()),
+                ("requester_id", &control.requester_id.to_string()),
+                ("requester", &control.requester),
+            ],
+        );
+    }
+
     // unused in flutter
     fn close_success(&self) {}
 
@@ -472,6 +485,10 @@ impl InvokeUiSession for FlutterHandler {
                     &status.codec_format.map_or(NULL, |it| it.to_string()),
                 ),
                 ("chroma", &status.chroma.map_or(NULL, |it| it.to_string())),
+                (
+                    "audio_buffer",
+                    &status.audio_buffer.map_or(NULL, |it| it.to_string()),
+                ),
             ],
         );
     }
@@ -692,6 +709,32 @@ impl InvokeUiSession for FlutterHandler {
         );
     }
 
+    fn set_canvas_layout(&self, layout: &CanvasLayout) {
+        let regions = layout
+            .regions
+            .iter()
+            .map(|r| {
+                let mut h: HashMap<&str, i32> = Default::default();
+                h.insert("display", r.display);
+                h.insert("x", r.x);
+                h.insert("y", r.y);
+                h.insert("width", r.width);
+                h.insert("height", r.height);
+                h
+            })
+            .collect::<Vec<_>>();
+        self.push_event(
+            "canvas_layout",
+            vec![
+                ("display", &layout.display.to_string()),
+                (
+                    "regions",
+                    &serde_json::ser::to_string(&regions).unwrap_or("".to_owned()),
+                ),
+            ],
+        );
+    }
+
     fn set_platform_additions(&self, data: &str) {
         self.push_event(
             "sync_platform_additions",
@@ -1030,6 +1073,11 @@ pub mod connection_manager {
             self.push_event("update_voice_call_state", vec![("client", &client_json)]);
         }
 
+        fn update_input_control_state(&self, client: &crate::ui_cm_interface::Client) {
+            let client_json = serde_json::to_string(&client).unwrap_or("".into());
+            self.push_event("update_input_control_state", vec![("client", &client_json)]);
+        }
+
         fn file_transfer_log(&self, action: &str, log: &str) {
             self.push_event("cm_file_transfer_log", vec![(action, log)]);
         }
@@ -1346,6 +1394,37 @@ fn session_send_touch_event(
     }
 }
 
+#[inline]
+fn session_send_pen_event(
+    session_id: SessionID,
+    v: &serde_json::Value,
+    alt: bool,
+    ctrl: bool,
+    shift: bool,
+    command: bool,
+) {
+    let Some(v) = v.get("v") else {
+        return;
+    };
+    let int = |k| v.get(k).and_then(|v| v.as_i64()).unwrap_or_default();
+    let bool = |k| v.get(k).and_then(|v| v.as_bool()).unwrap_or_default();
+    let evt = PenEvent {
+        x: int("x") as _,
+        y: int("y") as _,
+        down: bool("down"),
+        pressure: int("pressure") as _,
+        tilt_x: int("tilt_x") as _,
+        tilt_y: int("tilt_y") as _,
+        eraser: bool("eraser"),
+        buttons: int("buttons") as _,
+        leave: bool("leave"),
+        ..Default::default()
+    };
+    if let Some(session) = sessions::get_session_by_session_id(&session_id) {
+        session.send_pen_event(evt, alt, ctrl, shift, command);
+    }
+}
+
 pub fn session_send_pointer(session_id: SessionID, msg: String) {
     if let Ok(m) = serde_json::from_str::<HashMap<String, serde_json::Value>>(&msg) {
         let alt = m.get("alt").is_some();
@@ -1355,6 +1434,7 @@ pub fn session_send_pointer(session_id: SessionID, msg: String) {
         match (m.get("k"), m.get("v")) {
             (Some(k), Some(v)) => match k.as_str() {
                 Some("touch") => session_send_touch_event(session_id, v, alt, ctrl, shift, command),
+                Some("pen") => session_send_pen_event(session_id, v, alt, ctrl, shift, command),
                 _ => {}
             },
             _ => {}
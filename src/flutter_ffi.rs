@@ -104,6 +104,16 @@ pub fn session_try_add_display(session_id: SessionID, displays: Vec<i32>) -> Syn
     SyncReturn(())
 }
 
+pub fn session_capture_displays_canvas(
+    session_id: SessionID,
+    displays: Vec<i32>,
+) -> SyncReturn<()> {
+    if let Some(session) = sessions::get_session_by_session_id(&session_id) {
+        session.capture_displays_canvas(displays);
+    }
+    SyncReturn(())
+}
+
 pub fn session_add_sync(
     session_id: SessionID,
     id: String,
@@ -428,6 +438,114 @@ pub fn session_set_custom_fps(session_id: SessionID, fps: i32) {
     }
 }
 
+pub fn session_set_stream_resolution(session_id: SessionID, value: String) {
+    if let Some(session) = sessions::get_session_by_session_id(&session_id) {
+        session.set_stream_resolution(value);
+    }
+}
+
+pub fn session_set_audio_quality(session_id: SessionID, option: String, value: String) {
+    if let Some(session) = sessions::get_session_by_session_id(&session_id) {
+        session.set_audio_quality(option, value);
+    }
+}
+
+pub fn session_start_macro_recording(_session_id: SessionID) {
+    #[cfg(not(any(target_os = "android", target_os = "ios")))]
+    if let Some(session) = sessions::get_session_by_session_id(&_session_id) {
+        session.start_macro_recording();
+    }
+}
+
+/// Returns the error, empty if none.
+pub fn session_stop_macro_recording(_session_id: SessionID, _name: String) -> String {
+    #[cfg(not(any(target_os = "android", target_os = "ios")))]
+    if let Some(session) = sessions::get_session_by_session_id(&_session_id) {
+        if let Err(e) = session.stop_macro_recording(_name) {
+            return e.to_string();
+        }
+    }
+    "".to_owned()
+}
+
+/// Returns the error, empty if none.
+pub fn session_play_macro(_session_id: SessionID, _name: String, _speed: f64) -> String {
+    #[cfg(not(any(target_os = "android", target_os = "ios")))]
+    if let Some(session) = sessions::get_session_by_session_id(&_session_id) {
+        if let Err(e) = session.play_macro(_name, _speed) {
+            return e.to_string();
+        }
+    }
+    "".to_owned()
+}
+
+/// Returns false if the local clipboard has no text.
+pub fn session_type_clipboard(_session_id: SessionID) -> bool {
+    #[cfg(not(any(target_os = "android", target_os = "ios")))]
+    if let Some(session) = sessions::get_session_by_session_id(&_session_id) {
+        return session.type_clipboard();
+    }
+    false
+}
+
+pub fn session_stop_macro(_session_id: SessionID) {
+    #[cfg(not(any(target_os = "android", target_os = "ios")))]
+    if let Some(session) = sessions::get_session_by_session_id(&_session_id) {
+        session.stop_macro();
+    }
+}
+
+pub fn session_get_macro_state(_session_id: SessionID) -> SyncReturn<String> {
+    #[cfg(not(any(target_os = "android", target_os = "ios")))]
+    if let Some(session) = sessions::get_session_by_session_id(&_session_id) {
+        return SyncReturn(session.get_macro_state());
+    }
+    SyncReturn("".to_owned())
+}
+
+pub fn session_get_key_mappings(session_id: SessionID) -> String {
+    if let Some(session) = sessions::get_session_by_session_id(&session_id) {
+        session.get_key_mappings()
+    } else {
+        "".to_owned()
+    }
+}
+
+/// Returns the error, empty if none.
+pub fn session_set_key_mappings(session_id: SessionID, value: String) -> String {
+    if let Some(session) = sessions::get_session_by_session_id(&session_id) {
+        if let Err(e) = session.set_key_mappings(&value) {
+            return e.to_string();
+        }
+    }
+    "".to_owned()
+}
+
+pub fn main_get_macros() -> SyncReturn<String> {
+    #[cfg(not(any(target_os = "android", target_os = "ios")))]
+    return SyncReturn(
+        serde_json::to_string(&crate::client::input_macro::list()).unwrap_or_default(),
+    );
+    #[cfg(any(target_os = "android", target_os = "ios"))]
+    SyncReturn("[]".to_owned())
+}
+
+pub fn main_get_macro(_name: String) -> String {
+    #[cfg(not(any(target_os = "android", target_os = "ios")))]
+    return crate::client::input_macro::get(&_name).unwrap_or_default();
+    #[cfg(any(target_os = "android", target_os = "ios"))]
+    "".to_owned()
+}
+
+/// Returns the error, empty if none. An empty `text` removes the macro.
+pub fn main_set_macro(_name: String, _text: String) -> String {
+    #[cfg(not(any(target_os = "android", target_os = "ios")))]
+    if let Err(e) = crate::client::input_macro::set(&_name, &_text) {
+        return e.to_string();
+    }
+    "".to_owned()
+}
+
 pub fn session_lock_screen(session_id: SessionID) {
     if let Some(session) = sessions::get_session_by_session_id(&session_id) {
         session.lock_screen();
@@ -1193,6 +1311,18 @@ pub fn session_close_voice_call(session_id: SessionID) {
     }
 }
 
+pub fn session_request_input_control(session_id: SessionID, request: bool) {
+    if let Some(session) = sessions::get_session_by_session_id(&session_id) {
+        session.request_input_control(request);
+    }
+}
+
+pub fn session_grant_input_control(session_id: SessionID, conn_id: i32) {
+    if let Some(session) = sessions::get_session_by_session_id(&session_id) {
+        session.grant_input_control(conn_id);
+    }
+}
+
 pub fn cm_handle_incoming_voice_call(id: i32, accept: bool) {
     crate::ui_cm_interface::handle_incoming_voice_call(id, accept);
 }
@@ -1539,6 +1669,11 @@ pub fn cm_switch_permission(conn_id: i32, name: String, enabled: bool) {
     crate::ui_cm_interface::switch_permission(conn_id, name, enabled)
 }
 
+pub fn cm_grant_input_control(conn_id: i32) {
+    #[cfg(not(any(target_os = "ios")))]
+    crate::ui_cm_interface::grant_input_control(conn_id)
+}
+
 pub fn cm_can_elevate() -> SyncReturn<bool> {
     SyncReturn(crate::ui_cm_interface::can_elevate())
 }
@@ -1751,6 +1886,10 @@ pub fn main_has_file_clipboard() -> SyncReturn<bool> {
     SyncReturn(ret)
 }
 
+pub fn main_has_gamepad() -> SyncReturn<bool> {
+    SyncReturn(cfg!(feature = "gamepad"))
+}
+
 pub fn cm_init() {
     #[cfg(not(any(target_os = "android", target_os = "ios")))]
     crate::flutter::connection_manager::cm_init();
@@ -34,12 +34,37 @@ pub fn run(rx: Receiver<RecordState>) {
         filename: Default::default(),
         upload_size: Default::default(),
         running: Default::default(),
+        encrypted: Default::default(),
         last_send: Instant::now(),
     };
     std::thread::spawn(move || loop {
         if let Err(e) = match rx.recv() {
             Ok(state) => match state {
-                RecordState::NewFile(filepath) => uploader.handle_new_file(filepath),
+                RecordState::NewFile(filepath) => {
+                    // encrypted recordings are uploaded once sealed
+                    uploader.encrypted = scrap::record::is_encrypted();
+                    if uploader.encrypted {
+                        uploader.running = false;
+                        Ok(())
+                    } else {
+                        uploader.handle_new_file(filepath)
+                    }
+                }
+                // sealed in the background, maybe after the next recording started
+                RecordState::Encrypted(filepath) => {
+                    let res = uploader
+                        .handle_new_file(filepath)
+                        .and_then(|_| uploader.handle_tail());
+                    uploader.running = false;
+                    res
+                }
+                RecordState::Signed(filepath) => {
+                    if uploader.running || uploader.encrypted {
+                        uploader.handle_signature(filepath)
+                    } else {
+                        Ok(())
+                    }
+                }
                 RecordState::NewFrame => {
                     if uploader.running {
                         uploader.handle_frame(false)
@@ -80,6 +105,7 @@ struct RecordUploader {
     filename: String,
     upload_size: u64,
     running: bool,
+    encrypted: bool,
     last_send: Instant,
 }
 impl RecordUploader {
@@ -194,6 +220,26 @@ impl RecordUploader {
         }
     }
 
+    // The signature is named after the recording, which may not be the
+    // current one.
+    fn handle_signature(&mut self, filepath: String) -> ResultType<()> {
+        let signature = std::fs::read(&filepath)?;
+        let Some(file) = std::path::Path::new(&filepath)
+            .file_name()
+            .and_then(|f| f.to_str())
+            .and_then(|f| f.strip_suffix(".sig"))
+        else {
+            bail!("can't parse signature path:{}", filepath);
+        };
+        let file = if self.encrypted {
+            format!("{}.enc", file)
+        } else {
+            file.to_owned()
+        };
+        self.send(&[("type", "sig"), ("file", &file)], signature)?;
+        Ok(())
+    }
+
     fn handle_remove(&mut self) -> ResultType<()> {
         self.send(
             &[("type", "remove"), ("file", &self.filename)],
@@ -128,6 +128,15 @@ pub enum DataMouse {
     Refresh,
 }
 
+// The protobuf bytes of GamepadState and GamepadRumble.
+#[cfg(target_os = "linux")]
+#[derive(Debug, Serialize, Deserialize, Clone)]
+#[serde(tag = "t", content = "c")]
+pub enum DataGamepad {
+    State(Vec<u8>),
+    Rumble(Vec<u8>),
+}
+
 #[derive(Debug, Serialize, Deserialize, Clone)]
 #[serde(tag = "t", content = "c")]
 pub enum DataControl {
@@ -208,6 +217,11 @@ pub enum Data {
     KeyboardResponse(DataKeyboardResponse),
     #[cfg(not(any(target_os = "android", target_os = "ios")))]
     Mouse(DataMouse),
+    #[cfg(target_os = "linux")]
+    Gamepad(DataGamepad),
+    // The protobuf bytes of PenEvent.
+    #[cfg(target_os = "linux")]
+    Pen(Vec<u8>),
     Control(DataControl),
     Theme(String),
     Language(String),
@@ -221,6 +235,11 @@ pub enum Data {
     StartVoiceCall,
     VoiceCallResponse(bool),
     CloseVoiceCall(String),
+    InputControl {
+        holding: bool,
+        requesting: bool,
+    },
+    GrantInputControl,
     #[cfg(all(feature = "flutter", feature = "plugin_framework"))]
     #[cfg(not(any(target_os = "android", target_os = "ios")))]
     Plugin(Plugin),
@@ -296,7 +315,7 @@ pub async fn new_listener(postfix: &str) -> ResultType<Incoming> {
     }
 }
 
-pub struct CheckIfRestart(String, Vec<String>, String);
+pub struct CheckIfRestart(String, Vec<String>, String, String);
 
 impl CheckIfRestart {
     pub fn new() -> CheckIfRestart {
@@ -304,6 +323,7 @@ impl CheckIfRestart {
             Config::get_option("stop-service"),
             Config::get_rendezvous_servers(),
             Config::get_option("audio-input"),
+            Config::get_option("capture-apps"),
         )
     }
 }
@@ -314,7 +334,9 @@ impl Drop for CheckIfRestart {
         {
             RendezvousMediator::restart();
         }
-        if self.2 != Config::get_option("audio-input") {
+        if self.2 != Config::get_option("audio-input")
+            || self.3 != Config::get_option("capture-apps")
+        {
             crate::audio_service::restart();
         }
     }
@@ -398,6 +420,8 @@ async fn handle(data: Data, stream: &mut Connection) {
                     ));
                 } else if name == "rendezvous_servers" {
                     value = Some(Config::get_rendezvous_servers().join(","));
+                } else if name == "nat_port_delta" {
+                    value = Some(Config::get_nat_port_delta().to_string());
                 } else if name == "fingerprint" {
                     value = if Config::get_key_confirmed() {
                         Some(crate::common::pk_to_fingerprint(Config::get_key_pair().1))
@@ -523,13 +547,32 @@ pub async fn start_pa() {
                         Ok(stream) => {
                             let mut stream = Connection::new(stream);
                             let mut device: String = "".to_owned();
-                            if let Some(Ok(Some(Data::Config((_, Some(x)))))) =
-                                stream.next_timeout2(1000).await
-                            {
-                                device = x;
+                            let mut apps: String = "".to_owned();
+                            // "audio-input", then "capture-apps"
+                            for _ in 0..2 {
+                                if let Some(Ok(Some(Data::Config((name, Some(x)))))) =
+                                    stream.next_timeout2(1000).await
+                                {
+                                    if name == "capture-apps" {
+                                        apps = x;
+                                    } else {
+                                        device = x;
+                                    }
+                                }
                             }
+                            let mut capture = None;
                             if !device.is_empty() {
                                 device = crate::platform::linux::get_pa_source_name(&device);
+                            } else if !apps.is_empty() {
+                                match crate::platform::linux::AppAudioCapture::new(&apps) {
+                                    Ok(c) => {
+                                        device = c.source();
+                                        capture = Some(c);
+                                    }
+                                    Err(err) => {
+                                        log::error!("Failed to capture {}: {}", apps, err);
+                                    }
+                                }
                             }
                             if device.is_empty() {
                                 device = crate::platform::linux::get_pa_monitor();
@@ -556,6 +599,9 @@ pub async fn start_pa() {
                                 None, // Use default buffering attributes
                             ) {
                                 Ok(s) => loop {
+                                    if let Some(c) = capture.as_mut() {
+                                        c.update();
+                                    }
                                     if let Ok(_) = s.read(&mut buf) {
                                         let out =
                                             if buf.iter().filter(|x| **x != 0).next().is_none() {
@@ -851,6 +897,16 @@ pub async fn get_nat_type(ms_timeout: u64) -> i32 {
         .unwrap_or(Config::get_nat_type())
 }
 
+pub async fn get_nat_port_delta(ms_timeout: u64) -> i32 {
+    if let Ok(Some(v)) = get_config_async("nat_port_delta", ms_timeout).await {
+        if let Ok(delta) = v.parse::<i32>() {
+            Config::set_nat_port_delta(delta);
+            return delta;
+        }
+    }
+    Config::get_nat_port_delta()
+}
+
 pub async fn get_rendezvous_servers(ms_timeout: u64) -> Vec<String> {
     if let Ok(Some(v)) = get_config_async("rendezvous_servers", ms_timeout).await {
         return v.split(',').map(|x| x.to_owned()).collect();
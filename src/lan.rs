@@ -16,7 +16,7 @@ use hbb_common::{
 
 use std::{
     collections::{HashMap, HashSet},
-    net::{IpAddr, Ipv4Addr, SocketAddr, ToSocketAddrs, UdpSocket},
+    net::{IpAddr, Ipv4Addr, Ipv6Addr, SocketAddr, SocketAddrV6, UdpSocket},
     time::Instant,
 };
 
@@ -24,10 +24,31 @@ type Message = RendezvousMessage;
 
 #[cfg(not(any(target_os = "android", target_os = "ios")))]
 pub(super) fn start_listening() -> ResultType<()> {
-    let addr = SocketAddr::from(([0, 0, 0, 0], get_broadcast_port()));
-    let socket = std::net::UdpSocket::bind(addr)?;
-    socket.set_read_timeout(Some(std::time::Duration::from_millis(1000)))?;
+    let port = get_broadcast_port();
+    match hbb_common::udp::bind_ipv6_only(port) {
+        Ok(socket) => {
+            for interface in default_net::get_interfaces() {
+                if !interface.ipv6.is_empty() {
+                    socket
+                        .join_multicast_v6(&MULTICAST_V6, interface.index)
+                        .ok();
+                }
+            }
+            std::thread::spawn(move || {
+                log::info!("lan discovery ipv6 listener started");
+                allow_err!(handle_discovery(socket));
+            });
+        }
+        Err(err) => log::warn!("Failed to listen lan discovery on ipv6: {}", err),
+    }
+    let socket = UdpSocket::bind(SocketAddr::from(([0, 0, 0, 0], port)))?;
     log::info!("lan discovery listener started");
+    handle_discovery(socket)
+}
+
+#[cfg(not(any(target_os = "android", target_os = "ios")))]
+fn handle_discovery(socket: UdpSocket) -> ResultType<()> {
+    socket.set_read_timeout(Some(std::time::Duration::from_millis(1000)))?;
     loop {
         let mut buf = [0; 2048];
         if let Ok((len, addr)) = socket.recv_from(&mut buf) {
@@ -90,6 +111,9 @@ pub fn send_wol(id: String) {
     }
 }
 
+// link-local scope, the ipv6 counterpart of the ipv4 broadcast
+const MULTICAST_V6: Ipv6Addr = Ipv6Addr::new(0xff02, 0, 0, 0, 0, 0, 0x7275, 0x7374);
+
 #[inline]
 fn get_broadcast_port() -> u16 {
     (RENDEZVOUS_PORT + 3) as _
@@ -130,8 +154,13 @@ fn get_mac_by_ip(ip: &IpAddr) -> ResultType<String> {
 }
 
 // Mainly from https://github.com/shellrow/default-net/blob/cf7ca24e7e6e8e566ed32346c9cfddab3f47e2d6/src/interface/shared.rs#L4
-fn get_ipaddr_by_peer<A: ToSocketAddrs>(peer: A) -> Option<IpAddr> {
-    let socket = match UdpSocket::bind("0.0.0.0:0") {
+fn get_ipaddr_by_peer(peer: &SocketAddr) -> Option<IpAddr> {
+    let any = if peer.is_ipv4() {
+        "0.0.0.0:0"
+    } else {
+        "[::]:0"
+    };
+    let socket = match UdpSocket::bind(any) {
         Ok(s) => s,
         Err(_) => return None,
     };
@@ -167,12 +196,22 @@ fn create_broadcast_sockets() -> Vec<UdpSocket> {
     sockets
 }
 
-fn send_query() -> ResultType<Vec<UdpSocket>> {
-    let sockets = create_broadcast_sockets();
-    if sockets.is_empty() {
-        bail!("Found no bindable ipv4 addresses");
+// One socket per ipv6 interface, the scope id of the target selects the link.
+fn create_multicast_sockets() -> Vec<(UdpSocket, SocketAddr)> {
+    let mut sockets = Vec::new();
+    for interface in default_net::get_interfaces() {
+        if interface.ipv6.is_empty() {
+            continue;
+        }
+        if let Ok(s) = UdpSocket::bind("[::]:0") {
+            let maddr = SocketAddrV6::new(MULTICAST_V6, get_broadcast_port(), 0, interface.index);
+            sockets.push((s, maddr.into()));
+        }
     }
+    sockets
+}
 
+fn send_query() -> ResultType<Vec<UdpSocket>> {
     let mut msg_out = Message::new();
     let peer = PeerDiscovery {
         cmd: "ping".to_owned(),
@@ -180,9 +219,19 @@ fn send_query() -> ResultType<Vec<UdpSocket>> {
     };
     msg_out.set_peer_discovery(peer);
     let out = msg_out.write_to_bytes()?;
+
+    let mut sockets = Vec::new();
     let maddr = SocketAddr::from(([255, 255, 255, 255], get_broadcast_port()));
-    for socket in &sockets {
+    for socket in create_broadcast_sockets() {
+        allow_err!(socket.send_to(&out, maddr));
+        sockets.push(socket);
+    }
+    for (socket, maddr) in create_multicast_sockets() {
         allow_err!(socket.send_to(&out, maddr));
+        sockets.push(socket);
+    }
+    if sockets.is_empty() {
+        bail!("Found no bindable addresses");
     }
     log::info!("discover ping sent");
     Ok(sockets)
@@ -77,5 +77,11 @@ pub static ref T: std::collections::HashMap<&'static str, &'static str> =
         ("pull_ab_failed_tip", "Failed to refresh address book"),
         ("push_ab_failed_tip", "Failed to sync address book to server"),
         ("synced_peer_readded_tip", "The devices that were present in the recent sessions will be synchronized back to the address book."),
+        ("capture_apps_tip", "The names of the applications whose audio is shared, separated by commas, e.g. firefox,vlc. Leave it empty to share all the audio."),
+        ("type_clipboard_tip", "Type the text of the clipboard as key strokes, for the screens that do not take the clipboard. Press Esc to stop."),
+        ("key_mappings_tip", "A mapping a line, e.g. \"CapsLock = Escape\", \"Meta = Control\" and \"Control = Meta\" to swap the two, or \"Pause = Control+Alt+Delete\". A key is a letter, a digit or a name like Escape, Return, F1 or NumpadEnter."),
+        ("input_control_request_tip", "asks for the control of the mouse and the keyboard. Yours stops when granted."),
+        ("capture_window_tip", "The id of a window or the name of an application, e.g. firefox. Leave it empty to share the whole screen. On Wayland, the window is picked when a session starts."),
+        ("text_mode_fallback_tip", "Sharp text needs lossless tiles, which a recording or a peer of this session does not support. Full color encoding is used instead where possible."),
         ].iter().cloned().collect();
 }
@@ -541,5 +541,40 @@ pub static ref T: std::collections::HashMap<&'static str, &'static str> =
         ("Change Color", ""),
         ("Primary Color", ""),
         ("HSV Color", ""),
+        ("Share a window only", ""),
+        ("capture_window_tip", ""),
+        ("text_mode_fallback_tip", ""),
+        ("Stream resolution", ""),
+        ("Native resolution", ""),
+        ("Record the session events", ""),
+        ("Sign the recordings", ""),
+        ("Use voice calls as a microphone", ""),
+        ("Audio quality", ""),
+        ("Low latency audio", ""),
+        ("Voice audio", ""),
+        ("Music audio", ""),
+        ("Share the audio of some applications only", ""),
+        ("capture_apps_tip", ""),
+        ("Record macro", ""),
+        ("Stop macro recording", ""),
+        ("Play macro", ""),
+        ("Stop macro", ""),
+        ("Macros", ""),
+        ("No macros", ""),
+        ("Edit", ""),
+        ("Type clipboard", ""),
+        ("type_clipboard_tip", ""),
+        ("Delay between characters (ms)", ""),
+        ("Key mappings", ""),
+        ("key_mappings_tip", ""),
+        ("Forward gamepads", ""),
+        ("Only one controller at a time", ""),
+        ("Controlling", ""),
+        ("Request control", ""),
+        ("Release control", ""),
+        ("Grant control", ""),
+        ("Control requested", ""),
+        ("input_control_request_tip", ""),
+        ("Stream all displays as one", ""),
     ].iter().cloned().collect();
 }
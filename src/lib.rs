@@ -12,6 +12,7 @@ pub use self::server::*;
 mod client;
 #[cfg(not(any(target_os = "ios")))]
 mod lan;
+mod metrics;
 #[cfg(not(any(target_os = "ios")))]
 mod rendezvous_mediator;
 #[cfg(not(any(target_os = "ios")))]
@@ -0,0 +1,433 @@
+//! Connection quality metrics in Prometheus text format.
+//!
+//! Off unless the `metrics-server` option holds an address to listen on,
+//! e.g. `127.0.0.1:9464`. The controlled side (the service) listens on it and
+//! the controlling side (the ui process) one port above, as both may run on
+//! the same host. The option is read once, changes need a restart.
+
+use hbb_common::{
+    config::Config,
+    fs::TransferJob,
+    log,
+    tokio::{
+        self,
+        io::{AsyncReadExt, AsyncWriteExt},
+        net::TcpListener,
+    },
+    ResultType,
+};
+use std::{
+    collections::HashMap,
+    fmt::Write as _,
+    sync::{
+        atomic::{AtomicBool, AtomicI32, Ordering},
+        Mutex,
+    },
+};
+
+lazy_static::lazy_static! {
+    static ref REGISTRY: Mutex<Registry> = Default::default();
+}
+static STARTED: [AtomicBool; 2] = [AtomicBool::new(false), AtomicBool::new(false)];
+static NEXT_CONN_ID: AtomicI32 = AtomicI32::new(1);
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Hash)]
+pub enum Side {
+    Controlled = 0,
+    Controller = 1,
+}
+
+impl Side {
+    fn as_str(&self) -> &'static str {
+        match self {
+            Side::Controlled => "controlled",
+            Side::Controller => "controller",
+        }
+    }
+}
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Hash, Default)]
+pub enum Transport {
+    /// Connected to the address directly, no rendezvous involved.
+    #[default]
+    Direct,
+    /// Hole punched through rendezvous.
+    Punched,
+    Relay,
+}
+
+impl Transport {
+    fn as_str(&self) -> &'static str {
+        match self {
+            Transport::Direct => "direct",
+            Transport::Punched => "punched",
+            Transport::Relay => "relay",
+        }
+    }
+}
+
+#[derive(Debug, Default, Clone)]
+pub struct ConnMetrics {
+    pub peer_id: String,
+    pub transport: Transport,
+    pub codec: String,
+    pub rtt_ms: Option<u32>,
+    pub fps: Option<u32>,
+    pub frames_dropped: u64,
+    pub file_bytes_sent: u64,
+    pub file_bytes_received: u64,
+}
+
+#[derive(Debug, Default, Clone)]
+pub struct EncoderMetrics {
+    pub codec: String,
+    pub bitrate_kbps: u32,
+    pub frames_dropped: u64,
+}
+
+#[derive(Default)]
+struct Registry {
+    conns: HashMap<(Side, i32), ConnMetrics>,
+    connections_total: [u64; 2],
+    encoders: HashMap<usize, EncoderMetrics>,
+}
+
+/// Id for a controlling session, the controlled side uses its connection id.
+pub fn new_conn_id() -> i32 {
+    NEXT_CONN_ID.fetch_add(1, Ordering::Relaxed)
+}
+
+pub fn add(side: Side, id: i32, peer_id: String, transport: Transport) {
+    let mut registry = REGISTRY.lock().unwrap();
+    registry.connections_total[side as usize] += 1;
+    registry.conns.insert(
+        (side, id),
+        ConnMetrics {
+            peer_id,
+            transport,
+            ..Default::default()
+        },
+    );
+}
+
+/// Update a connection registered with `add`, removed ones stay removed.
+pub fn update(side: Side, id: i32, f: impl FnOnce(&mut ConnMetrics)) {
+    if let Some(m) = REGISTRY.lock().unwrap().conns.get_mut(&(side, id)) {
+        f(m);
+    }
+}
+
+pub fn remove(side: Side, id: i32) {
+    REGISTRY.lock().unwrap().conns.remove(&(side, id));
+}
+
+pub fn update_encoder(display: usize, f: impl FnOnce(&mut EncoderMetrics)) {
+    f(REGISTRY
+        .lock()
+        .unwrap()
+        .encoders
+        .entry(display)
+        .or_default());
+}
+
+/// Drop the series of a display whose encoder is gone.
+pub fn remove_encoder(display: usize) {
+    REGISTRY.lock().unwrap().encoders.remove(&display);
+}
+
+/// Bytes done per file job, to count what a round of `fs::handle_read_jobs` sent.
+pub fn job_progress(jobs: &[TransferJob]) -> HashMap<i32, u64> {
+    jobs.iter().map(|j| (j.id(), j.transferred())).collect()
+}
+
+/// Bytes done since `before`, a job that finished in between sent nothing more.
+pub fn progress_since(before: &HashMap<i32, u64>, jobs: &[TransferJob]) -> u64 {
+    jobs.iter()
+        .map(|j| {
+            j.transferred()
+                .saturating_sub(*before.get(&j.id()).unwrap_or(&0))
+        })
+        .sum()
+}
+
+pub fn start(side: Side) {
+    let addr = Config::get_option("metrics-server");
+    if addr.is_empty() || STARTED[side as usize].swap(true, Ordering::SeqCst) {
+        return;
+    }
+    let addr = match side {
+        Side::Controlled => addr,
+        Side::Controller => crate::increase_port(&addr, 1),
+    };
+    std::thread::spawn(move || {
+        if let Err(err) = serve(&addr) {
+            log::error!("Metrics server on {} stopped: {}", addr, err);
+        }
+    });
+}
+
+#[tokio::main(flavor = "current_thread")]
+async fn serve(addr: &str) -> ResultType<()> {
+    let listener = TcpListener::bind(addr).await?;
+    log::info!("Metrics server listening on {}", addr);
+    loop {
+        let (mut stream, _) = listener.accept().await?;
+        tokio::spawn(async move {
+            let mut buf = [0u8; 1024];
+            let Ok(Ok(n)) = hbb_common::timeout(1_000, stream.read(&mut buf)).await else {
+                return;
+            };
+            let request = String::from_utf8_lossy(&buf[..n]);
+            let (status, body) =
+                if request.starts_with("GET /metrics ") || request.starts_with("GET / ") {
+                    ("200 OK", render())
+                } else {
+                    ("404 Not Found", String::new())
+                };
+            let response = format!(
+                "HTTP/1.1 {status}\r\nContent-Type: text/plain; version=0.0.4\r\nContent-Length: {}\r\nConnection: close\r\n\r\n{body}",
+                body.len()
+            );
+            stream.write_all(response.as_bytes()).await.ok();
+        });
+    }
+}
+
+fn escape(v: &str) -> String {
+    v.replace('\\', "\\\\")
+        .replace('"', "\\\"")
+        .replace('\n', "\\n")
+}
+
+fn header(out: &mut String, name: &str, typ: &str, help: &str) {
+    writeln!(out, "# HELP {name} {help}").ok();
+    writeln!(out, "# TYPE {name} {typ}").ok();
+}
+
+fn render() -> String {
+    render_registry(&REGISTRY.lock().unwrap())
+}
+
+fn render_registry(registry: &Registry) -> String {
+    let mut out = String::new();
+    let mut conns: Vec<_> = registry.conns.iter().collect();
+    conns.sort_by_key(|((side, id), _)| (*side as usize, *id));
+    let labels = |side: &Side, id: &i32, m: &ConnMetrics| {
+        format!(
+            "side=\"{}\",conn=\"{}\",peer=\"{}\"",
+            side.as_str(),
+            id,
+            escape(&m.peer_id)
+        )
+    };
+
+    header(
+        &mut out,
+        "rustdesk_connections",
+        "gauge",
+        "Open connections.",
+    );
+    let mut open: HashMap<(Side, Transport), u64> = HashMap::new();
+    for ((side, _), m) in conns.iter() {
+        *open.entry((*side, m.transport)).or_default() += 1;
+    }
+    for side in [Side::Controlled, Side::Controller] {
+        for transport in [Transport::Direct, Transport::Punched, Transport::Relay] {
+            writeln!(
+                out,
+                "rustdesk_connections{{side=\"{}\",transport=\"{}\"}} {}",
+                side.as_str(),
+                transport.as_str(),
+                open.get(&(side, transport)).unwrap_or(&0)
+            )
+            .ok();
+        }
+    }
+    header(
+        &mut out,
+        "rustdesk_connections_total",
+        "counter",
+        "Connections since start.",
+    );
+    for side in [Side::Controlled, Side::Controller] {
+        writeln!(
+            out,
+            "rustdesk_connections_total{{side=\"{}\"}} {}",
+            side.as_str(),
+            registry.connections_total[side as usize]
+        )
+        .ok();
+    }
+
+    header(
+        &mut out,
+        "rustdesk_connection_info",
+        "gauge",
+        "Transport and codec of a connection.",
+    );
+    for ((side, id), m) in conns.iter() {
+        writeln!(
+            out,
+            "rustdesk_connection_info{{{},transport=\"{}\",codec=\"{}\"}} 1",
+            labels(side, id, m),
+            m.transport.as_str(),
+            escape(&m.codec)
+        )
+        .ok();
+    }
+    header(
+        &mut out,
+        "rustdesk_rtt_milliseconds",
+        "gauge",
+        "Round trip time to the peer.",
+    );
+    for ((side, id), m) in conns.iter() {
+        if let Some(rtt) = m.rtt_ms {
+            writeln!(
+                out,
+                "rustdesk_rtt_milliseconds{{{}}} {rtt}",
+                labels(side, id, m)
+            )
+            .ok();
+        }
+    }
+    header(
+        &mut out,
+        "rustdesk_fps",
+        "gauge",
+        "Received frames per second.",
+    );
+    for ((side, id), m) in conns.iter() {
+        if let Some(fps) = m.fps {
+            writeln!(out, "rustdesk_fps{{{}}} {fps}", labels(side, id, m)).ok();
+        }
+    }
+    header(
+        &mut out,
+        "rustdesk_frames_dropped_total",
+        "counter",
+        "Received frames dropped before decoding.",
+    );
+    for ((side, id), m) in conns.iter() {
+        if *side == Side::Controller {
+            writeln!(
+                out,
+                "rustdesk_frames_dropped_total{{{}}} {}",
+                labels(side, id, m),
+                m.frames_dropped
+            )
+            .ok();
+        }
+    }
+    header(
+        &mut out,
+        "rustdesk_file_transfer_bytes_total",
+        "counter",
+        "File data transferred.",
+    );
+    for ((side, id), m) in conns.iter() {
+        for (direction, v) in [
+            ("sent", m.file_bytes_sent),
+            ("received", m.file_bytes_received),
+        ] {
+            writeln!(
+                out,
+                "rustdesk_file_transfer_bytes_total{{{},direction=\"{direction}\"}} {v}",
+                labels(side, id, m)
+            )
+            .ok();
+        }
+    }
+
+    let mut encoders: Vec<_> = registry.encoders.iter().collect();
+    encoders.sort_by_key(|(display, _)| **display);
+    header(
+        &mut out,
+        "rustdesk_encoder_bitrate_kbps",
+        "gauge",
+        "Target bitrate of the video encoder.",
+    );
+    for (display, e) in encoders.iter() {
+        writeln!(
+            out,
+            "rustdesk_encoder_bitrate_kbps{{display=\"{display}\",codec=\"{}\"}} {}",
+            escape(&e.codec),
+            e.bitrate_kbps
+        )
+        .ok();
+    }
+    header(
+        &mut out,
+        "rustdesk_encoder_frames_dropped_total",
+        "counter",
+        "Captured frames that failed to encode.",
+    );
+    for (display, e) in encoders.iter() {
+        writeln!(
+            out,
+            "rustdesk_encoder_frames_dropped_total{{display=\"{display}\"}} {}",
+            e.frames_dropped
+        )
+        .ok();
+    }
+    out
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_render() {
+        let mut registry = Registry::default();
+        registry.connections_total = [3, 1];
+        registry.conns.insert(
+            (Side::Controlled, 7),
+            ConnMetrics {
+                peer_id: "123\"456".to_owned(),
+                transport: Transport::Relay,
+                rtt_ms: Some(42),
+                file_bytes_received: 1024,
+                ..Default::default()
+            },
+        );
+        registry.conns.insert(
+            (Side::Controller, 1),
+            ConnMetrics {
+                peer_id: "987".to_owned(),
+                transport: Transport::Punched,
+                codec: "VP9".to_owned(),
+                fps: Some(30),
+                frames_dropped: 2,
+                ..Default::default()
+            },
+        );
+        registry.encoders.insert(
+            0,
+            EncoderMetrics {
+                codec: "VP9".to_owned(),
+                bitrate_kbps: 1500,
+                frames_dropped: 0,
+            },
+        );
+        let out = render_registry(&registry);
+        for line in [
+            "rustdesk_connections{side=\"controlled\",transport=\"relay\"} 1",
+            "rustdesk_connections{side=\"controlled\",transport=\"punched\"} 0",
+            "rustdesk_connections_total{side=\"controlled\"} 3",
+            "rustdesk_rtt_milliseconds{side=\"controlled\",conn=\"7\",peer=\"123\\\"456\"} 42",
+            "rustdesk_fps{side=\"controller\",conn=\"1\",peer=\"987\"} 30",
+            "rustdesk_connection_info{side=\"controller\",conn=\"1\",peer=\"987\",transport=\"punched\",codec=\"VP9\"} 1",
+            "rustdesk_frames_dropped_total{side=\"controller\",conn=\"1\",peer=\"987\"} 2",
+            "rustdesk_file_transfer_bytes_total{side=\"controlled\",conn=\"7\",peer=\"123\\\"456\",direction=\"received\"} 1024",
+            "rustdesk_encoder_bitrate_kbps{display=\"0\",codec=\"VP9\"} 1500",
+        ] {
+            assert!(out.lines().any(|x| x == line), "missing {line} in\n{out}");
+        }
+        // every sample belongs to a declared metric
+        for line in out.lines().filter(|x| !x.starts_with('#')) {
+            let name = line.split(|c| c == '{' || c == ' ').next().unwrap();
+            assert!(out.contains(&format!("# TYPE {name} ")), "{line}");
+        }
+    }
+}
@@ -16,6 +16,7 @@ use hbb_common::{
 };
 use std::{
     cell::RefCell,
+    collections::HashMap,
     ffi::OsStr,
     io::Write,
     path::{Path, PathBuf},
@@ -200,6 +201,12 @@ fn start_uinput_service() {
     std::thread::spawn(|| {
         service::start_service_mouse();
     });
+    std::thread::spawn(|| {
+        service::start_service_gamepad();
+    });
+    std::thread::spawn(|| {
+        service::start_service_pen();
+    });
 }
 
 #[inline]
@@ -655,6 +662,201 @@ pub fn get_default_pa_source() -> Option<(String, String)> {
     None
 }
 
+/// A microphone fed with the audio of a voice call: a null sink to play the
+/// audio into, remapped as a source which is made the default one.
+/// Works with PulseAudio and PipeWire, both loading the modules with pactl.
+pub struct VirtualMic {
+    sink: String,
+    modules: Vec<String>,
+    default_source: Option<String>,
+}
+
+impl VirtualMic {
+    pub fn new() -> ResultType<Self> {
+        let app_name = crate::get_app_name();
+        let sink = pa_name("mic");
+        let source = format!("{}_source", sink);
+        let mut mic = VirtualMic {
+            sink: sink.clone(),
+            modules: Vec::new(),
+            default_source: get_default_pa_source().map(|x| x.0),
+        };
+        // left over by a previous session which did not exit cleanly
+        if get_pa_sources().iter().all(|x| x.0 != source) {
+            mic.load_module(&[
+                "module-null-sink",
+                &format!("sink_name={}", sink),
+                &format!(
+                    "sink_properties=device.description='{} Voice Call'",
+                    app_name
+                ),
+            ])?;
+            mic.load_module(&[
+                "module-remap-source",
+                &format!("master={}.monitor", sink),
+                &format!("source_name={}", source),
+                &format!(
+                    "source_properties=device.description='{} Microphone'",
+                    app_name
+                ),
+            ])?;
+        }
+        pactl(&["set-default-source", &source])?;
+        Ok(mic)
+    }
+
+    /// The sink to play the audio of the voice call into.
+    pub fn sink(&self) -> &str {
+        &self.sink
+    }
+
+    fn load_module(&mut self, args: &[&str]) -> ResultType<()> {
+        let mut pactl_args = vec!["load-module"];
+        pactl_args.extend_from_slice(args);
+        self.modules.push(pactl(&pactl_args)?);
+        Ok(())
+    }
+}
+
+impl Drop for VirtualMic {
+    fn drop(&mut self) {
+        if let Some(source) = self.default_source.as_ref() {
+            pactl(&["set-default-source", source]).ok();
+        }
+        for module in self.modules.drain(..).rev() {
+            pactl(&["unload-module", &module]).ok();
+        }
+    }
+}
+
+/// The audio of some applications only: their streams are moved into a null
+/// sink whose monitor is recorded, and which is looped back to the default
+/// sink for them to be still heard.
+pub struct AppAudioCapture {
+    apps: Vec<String>,
+    sink: String,
+    modules: Vec<String>,
+    // the streams moved, and the sinks they played into
+    moved: HashMap<u32, u32>,
+    updated: Option<Instant>,
+}
+
+impl AppAudioCapture {
+    /// `apps` are names or binaries of applications, separated by commas.
+    pub fn new(apps: &str) -> ResultType<Self> {
+        let sink = pa_name("capture");
+        let mut capture = AppAudioCapture {
+            apps: apps
+                .split(',')
+                .map(|x| x.trim().to_lowercase())
+                .filter(|x| !x.is_empty())
+                .collect(),
+            sink: sink.clone(),
+            modules: Vec::new(),
+            moved: HashMap::new(),
+            updated: None,
+        };
+        capture.modules.push(pactl(&[
+            "load-module",
+            "module-null-sink",
+            &format!("sink_name={}", sink),
+            &format!(
+                "sink_properties=device.description='{} Capture'",
+                crate::get_app_name()
+            ),
+        ])?);
+        capture.modules.push(pactl(&[
+            "load-module",
+            "module-loopback",
+            &format!("source={}.monitor", sink),
+            "source_dont_move=true",
+            "latency_msec=30",
+        ])?);
+        capture.update();
+        Ok(capture)
+    }
+
+    /// The source to record.
+    pub fn source(&self) -> String {
+        format!("{}.monitor", self.sink)
+    }
+
+    /// Move the streams of the applications into the sink, the new ones too,
+    /// at most every other second.
+    pub fn update(&mut self) {
+        use pulsectl::controllers::*;
+        if self
+            .updated
+            .map_or(false, |t| t.elapsed() < Duration::from_secs(2))
+        {
+            return;
+        }
+        self.updated = Some(Instant::now());
+        let mut handler = match SinkController::create() {
+            Ok(handler) => handler,
+            Err(err) => {
+                log::error!("Failed to capture the application audio: {:?}", err);
+                return;
+            }
+        };
+        let sink = match handler.get_device_by_name(&self.sink) {
+            Ok(dev) => dev.index,
+            Err(_) => return,
+        };
+        if let Ok(streams) = handler.list_applications() {
+            for stream in streams {
+                if stream.sink == sink {
+                    continue;
+                }
+                let captured = ["application.name", "application.process.binary"]
+                    .iter()
+                    .filter_map(|k| stream.proplist.get_str(k))
+                    .any(|x| self.apps.contains(&x.to_lowercase()));
+                if captured && handler.move_app_by_index(stream.index, sink).is_ok() {
+                    self.moved.insert(stream.index, stream.sink);
+                }
+            }
+        }
+    }
+}
+
+impl Drop for AppAudioCapture {
+    fn drop(&mut self) {
+        use pulsectl::controllers::*;
+        if let Ok(mut handler) = SinkController::create() {
+            for (stream, sink) in self.moved.drain() {
+                handler.move_app_by_index(stream, sink).ok();
+            }
+        }
+        for module in self.modules.drain(..).rev() {
+            pactl(&["unload-module", &module]).ok();
+        }
+    }
+}
+
+// The name of a sink or source of ours, told apart by `suffix`.
+fn pa_name(suffix: &str) -> String {
+    format!(
+        "{}_{}",
+        crate::get_app_name()
+            .to_lowercase()
+            .replace(|c: char| !c.is_ascii_alphanumeric(), "_"),
+        suffix
+    )
+}
+
+fn pactl(args: &[&str]) -> ResultType<String> {
+    let output = Command::new("pactl").args(args).output()?;
+    if !output.status.success() {
+        bail!(
+            "pactl {} failed: {}",
+            args.join(" "),
+            String::from_utf8_lossy(&output.stderr).trim()
+        );
+    }
+    Ok(String::from_utf8_lossy(&output.stdout).trim().to_owned())
+}
+
 pub fn lock_screen() {
     Command::new("xdg-screensaver").arg("lock").spawn().ok();
 }
@@ -17,6 +17,7 @@ use hbb_common::{
     futures::future::join_all,
     log,
     protobuf::Message as _,
+    punch::{self, PortPredictor, PUNCH_BATCH},
     rendezvous_proto::*,
     sleep,
     socket_client::{self, is_ipv4},
@@ -25,7 +26,7 @@ use hbb_common::{
         time::{interval, Duration},
     },
     udp::FramedSocket,
-    AddrMangle, ResultType,
+    AddrMangle, IntoTargetAddr, ResultType, TargetAddr,
 };
 
 use crate::server::{check_zombie, new as new_server, ServerPtr};
@@ -67,6 +68,7 @@ impl RendezvousMediator {
         tokio::spawn(async move {
             direct_server(server_cloned).await;
         });
+        crate::metrics::start(crate::metrics::Side::Controlled);
         #[cfg(not(any(target_os = "android", target_os = "ios")))]
         if crate::platform::is_installed() {
             std::thread::spawn(move || {
@@ -126,6 +128,9 @@ impl RendezvousMediator {
             })
             .unwrap_or(host.to_owned());
         let host = crate::check_port(&host, RENDEZVOUS_PORT);
+        if socket_client::is_udp_disabled() {
+            return Self::start_tcp(server, host, host_prefix).await;
+        }
         let (mut socket, addr) = socket_client::new_udp_for(&host, CONNECT_TIMEOUT).await?;
         let mut rz = Self {
             addr: addr,
@@ -177,66 +182,7 @@ impl RendezvousMediator {
                 n = socket.next() => {
                     match n {
                         Some(Ok((bytes, _))) => {
-                            if let Ok(msg_in) = Message::parse_from_bytes(&bytes) {
-                                match msg_in.union {
-                                    Some(rendezvous_message::Union::RegisterPeerResponse(rpr)) => {
-                                        update_latency();
-                                        if rpr.request_pk {
-                                            log::info!("request_pk received from {}", host);
-                                            allow_err!(rz.register_pk(&mut socket).await);
-                                            continue;
-                                        }
-                                    }
-                                    Some(rendezvous_message::Union::RegisterPkResponse(rpr)) => {
-                                        update_latency();
-                                        match rpr.result.enum_value() {
-                                            Ok(register_pk_response::Result::OK) => {
-                                                Config::set_key_confirmed(true);
-                                                Config::set_host_key_confirmed(&rz.host_prefix, true);
-                                                *SOLVING_PK_MISMATCH.lock().unwrap() = "".to_owned();
-                                            }
-                                            Ok(register_pk_response::Result::UUID_MISMATCH) => {
-                                                allow_err!(rz.handle_uuid_mismatch(&mut socket).await);
-                                            }
-                                            _ => {
-                                                log::error!("unknown RegisterPkResponse");
-                                            }
-                                        }
-                                    }
-                                    Some(rendezvous_message::Union::PunchHole(ph)) => {
-                                        let rz = rz.clone();
-                                        let server = server.clone();
-                                        tokio::spawn(async move {
-                                            allow_err!(rz.handle_punch_hole(ph, server).await);
-                                        });
-                                    }
-                                    Some(rendezvous_message::Union::RequestRelay(rr)) => {
-                                        let rz = rz.clone();
-                                        let server = server.clone();
-                                        tokio::spawn(async move {
-                                            allow_err!(rz.handle_request_relay(rr, server).await);
-                                        });
-                                    }
-                                    Some(rendezvous_message::Union::FetchLocalAddr(fla)) => {
-                                        let rz = rz.clone();
-                                        let server = server.clone();
-                                        tokio::spawn(async move {
-                                            allow_err!(rz.handle_intranet(fla, server).await);
-                                        });
-                                    }
-                                    Some(rendezvous_message::Union::ConfigureUpdate(cu)) => {
-                                        let v0 = Config::get_rendezvous_servers();
-                                        Config::set_option("rendezvous-servers".to_owned(), cu.rendezvous_servers.join(","));
-                                        Config::set_serial(cu.serial);
-                                        if v0 != Config::get_rendezvous_servers() {
-                                            Self::restart();
-                                        }
-                                    }
-                                    _ => {}
-                                }
-                            } else {
-                                log::debug!("Non-protobuf message bytes received: {:?}", bytes);
-                            }
+                            rz.handle_resp(&bytes, Sink::Framed(&mut socket), &server, &mut update_latency).await?;
                         },
                         Some(Err(e)) => bail!("Failed to receive next {}", e),  // maybe socks5 tcp disconnected
                         None => {
@@ -257,7 +203,7 @@ impl RendezvousMediator {
                     let elapsed_resp = last_register_resp.map(|x| x.elapsed().as_millis() as i64).unwrap_or(REG_INTERVAL);
                     let timeout = (elapsed_resp - last_register_sent.map(|x| x.elapsed().as_millis() as i64).unwrap_or(REG_INTERVAL)) > REG_TIMEOUT;
                     if timeout || elapsed_resp >= REG_INTERVAL {
-                        allow_err!(rz.register_peer(&mut socket).await);
+                        allow_err!(rz.register_peer(Sink::Framed(&mut socket)).await);
                         last_register_sent = now;
                         if timeout {
                             fails += 1;
@@ -285,6 +231,126 @@ impl RendezvousMediator {
         Ok(())
     }
 
+    async fn handle_resp(
+        &mut self,
+        bytes: &[u8],
+        sink: Sink<'_>,
+        server: &ServerPtr,
+        update_latency: &mut impl FnMut(),
+    ) -> ResultType<()> {
+        let Ok(msg_in) = Message::parse_from_bytes(bytes) else {
+            log::debug!("Non-protobuf message bytes received: {:?}", bytes);
+            return Ok(());
+        };
+        match msg_in.union {
+            Some(rendezvous_message::Union::RegisterPeerResponse(rpr)) => {
+                update_latency();
+                if rpr.request_pk {
+                    log::info!("request_pk received from {}", self.host);
+                    allow_err!(self.register_pk(sink).await);
+                }
+            }
+            Some(rendezvous_message::Union::RegisterPkResponse(rpr)) => {
+                update_latency();
+                match rpr.result.enum_value() {
+                    Ok(register_pk_response::Result::OK) => {
+                        Config::set_key_confirmed(true);
+                        Config::set_host_key_confirmed(&self.host_prefix, true);
+                        *SOLVING_PK_MISMATCH.lock().unwrap() = "".to_owned();
+                    }
+                    Ok(register_pk_response::Result::UUID_MISMATCH) => {
+                        allow_err!(self.handle_uuid_mismatch(sink).await);
+                    }
+                    _ => {
+                        log::error!("unknown RegisterPkResponse");
+                    }
+                }
+            }
+            Some(rendezvous_message::Union::PunchHole(ph)) => {
+                let rz = self.clone();
+                let server = server.clone();
+                tokio::spawn(async move {
+                    allow_err!(rz.handle_punch_hole(ph, server).await);
+                });
+            }
+            Some(rendezvous_message::Union::RequestRelay(rr)) => {
+                let rz = self.clone();
+                let server = server.clone();
+                tokio::spawn(async move {
+                    allow_err!(rz.handle_request_relay(rr, server).await);
+                });
+            }
+            Some(rendezvous_message::Union::FetchLocalAddr(fla)) => {
+                let rz = self.clone();
+                let server = server.clone();
+                tokio::spawn(async move {
+                    allow_err!(rz.handle_intranet(fla, server).await);
+                });
+            }
+            Some(rendezvous_message::Union::ConfigureUpdate(cu)) => {
+                let v0 = Config::get_rendezvous_servers();
+                Config::set_option(
+                    "rendezvous-servers".to_owned(),
+                    cu.rendezvous_servers.join(","),
+                );
+                Config::set_serial(cu.serial);
+                if v0 != Config::get_rendezvous_servers() {
+                    Self::restart();
+                }
+            }
+            _ => {}
+        }
+        Ok(())
+    }
+
+    /// Register over a tcp connection, for networks where only tcp gets out,
+    /// i.e. behind an http proxy or in a websocket tunnel.
+    async fn start_tcp(server: ServerPtr, host: String, host_prefix: String) -> ResultType<()> {
+        let mut conn = socket_client::connect_tcp(&*host, CONNECT_TIMEOUT).await?;
+        let mut rz = Self {
+            addr: host.clone().into_target_addr()?.to_owned(),
+            host: host.clone(),
+            host_prefix,
+            last_id_pk_registry: "".to_owned(),
+        };
+        let mut timer = interval(Duration::from_secs(1));
+        let mut last_register_sent: Option<Instant> = None;
+        let mut last_register_resp = Instant::now();
+        loop {
+            let mut update_latency = || {
+                last_register_resp = Instant::now();
+                if let Some(sent) = last_register_sent {
+                    Config::update_latency(&host, sent.elapsed().as_micros() as i64);
+                }
+            };
+            select! {
+                res = conn.next() => {
+                    let bytes = match res {
+                        Some(Ok(bytes)) => bytes,
+                        Some(Err(e)) => bail!("Failed to receive next {}", e),
+                        None => bail!("Rendezvous connection closed"),
+                    };
+                    rz.handle_resp(&bytes, Sink::Stream(&mut conn), &server, &mut update_latency).await?;
+                }
+                _ = timer.tick() => {
+                    if SHOULD_EXIT.load(Ordering::SeqCst) {
+                        break;
+                    }
+                    let elapsed = last_register_sent.map(|x| x.elapsed().as_millis() as i64);
+                    if elapsed.map(|x| x >= REG_INTERVAL).unwrap_or(true) {
+                        if last_register_resp.elapsed().as_millis() as i64 > REG_INTERVAL * 3 {
+                            Config::update_latency(&host, -1);
+                            bail!("No response from rendezvous server {}", host);
+                        }
+                        rz.register_peer(Sink::Stream(&mut conn)).await?;
+                        last_register_sent = Some(Instant::now());
+                    }
+                }
+            }
+        }
+        Ok(())
+    }
+
     async fn handle_request_relay(&self, rr: RequestRelay, server: ServerPtr) -> ResultType<()> {
         self.create_relay(
             rr.socket_addr.into(),
@@ -342,10 +408,43 @@ impl RendezvousMediator {
         Ok(())
     }
 
+    /// Tells a direct-only requester there is no direct path, without setting up a relay.
+    async fn refuse_relay(&self, socket_addr: Vec<u8>) -> ResultType<()> {
+        let mut socket = socket_client::connect_tcp(&*self.host, CONNECT_TIMEOUT).await?;
+        let mut msg_out = Message::new();
+        msg_out.set_relay_response(RelayResponse {
+            socket_addr: socket_addr.into(),
+            version: crate::VERSION.to_owned(),
+            ..Default::default()
+        });
+        socket.send(&msg_out).await?;
+        Ok(())
+    }
+
     async fn handle_intranet(&self, fla: FetchLocalAddr, server: ServerPtr) -> ResultType<()> {
         let relay_server = self.get_relay_server(fla.relay_server);
+        let socket_addr_v6 = punch_ipv6(&fla.socket_addr_v6, server.clone()).await;
+        if !is_ipv4(&self.addr) && !socket_addr_v6.is_empty() {
+            // ipv6-only wan, nothing to demangle, just echo the peer address
+            let mut socket = socket_client::connect_tcp(&*self.host, CONNECT_TIMEOUT).await?;
+            let mut msg_out = Message::new();
+            msg_out.set_local_addr(LocalAddr {
+                id: Config::get_id(),
+                socket_addr: fla.socket_addr,
+                local_addr: socket_addr_v6.clone().into(),
+                relay_server,
+                version: crate::VERSION.to_owned(),
+                socket_addr_v6: socket_addr_v6.into(),
+                ..Default::default()
+            });
+            socket.send_raw(msg_out.write_to_bytes()?).await?;
+            return Ok(());
+        }
         if !is_ipv4(&self.addr) {
             // nat64, go relay directly, because current hbbs will crash if demangle ipv6 address
+            if fla.direct_only {
+                return self.refuse_relay(fla.socket_addr.into()).await;
+            }
             let uuid = Uuid::new_v4().to_string();
             return self
                 .create_relay(
@@ -371,6 +470,7 @@ impl RendezvousMediator {
             local_addr: AddrMangle::encode(local_addr).into(),
             relay_server,
             version: crate::VERSION.to_owned(),
+            socket_addr_v6: socket_addr_v6.into(),
             ..Default::default()
         });
         let bytes = msg_out.write_to_bytes()?;
@@ -381,9 +481,16 @@ impl RendezvousMediator {
 
     async fn handle_punch_hole(&self, ph: PunchHole, server: ServerPtr) -> ResultType<()> {
         let relay_server = self.get_relay_server(ph.relay_server);
-        if ph.nat_type.enum_value() == Ok(NatType::SYMMETRIC)
-            || Config::get_nat_type() == NatType::SYMMETRIC as i32
+        let peer_symmetric = ph.nat_type.enum_value() == Ok(NatType::SYMMETRIC);
+        let my_symmetric = Config::get_nat_type() == NatType::SYMMETRIC as i32;
+        let socket_addr_v6 = punch_ipv6(&ph.socket_addr_v6, server.clone()).await;
+        if socket_addr_v6.is_empty()
+            && (peer_symmetric || my_symmetric)
+            && !can_predict_ports(peer_symmetric, ph.nat_port_delta, my_symmetric)
         {
+            if ph.direct_only {
+                return self.refuse_relay(ph.socket_addr.into()).await;
+            }
             let uuid = Uuid::new_v4().to_string();
             return self
                 .create_relay(
@@ -403,7 +510,16 @@ impl RendezvousMediator {
             let local_addr = socket.local_addr();
             // key important here for punch hole to tell my gateway incoming peer is safe.
             // it can not be async here, because local_addr can not be reused, we must close the connection before use it again.
-            allow_err!(socket_client::connect_tcp_local(peer_addr, Some(local_addr), 30).await);
+            if peer_symmetric {
+                // the peer will come from a port its nat has not allocated yet,
+                // open our gateway to the whole predicted batch
+                let candidates =
+                    PortPredictor::new(peer_addr, ph.nat_port_delta).candidates(PUNCH_BATCH);
+                log::debug!("Punch hole to predicted ports {:?}", candidates);
+                punch::open_gateway(&candidates, local_addr, 30).await;
+            } else {
+                allow_err!(socket_client::connect_tcp_local(peer_addr, Some(local_addr), 30).await);
+            }
             socket
         };
         let mut msg_out = Message::new();
@@ -415,6 +531,8 @@ impl RendezvousMediator {
             relay_server,
             nat_type: nat_type.into(),
             version: crate::VERSION.to_owned(),
+            nat_port_delta: Config::get_nat_port_delta(),
+            socket_addr_v6: socket_addr_v6.into(),
             ..Default::default()
         });
         let bytes = msg_out.write_to_bytes()?;
@@ -423,7 +541,7 @@ impl RendezvousMediator {
         Ok(())
     }
 
-    async fn register_pk(&mut self, socket: &mut FramedSocket) -> ResultType<()> {
+    async fn register_pk(&mut self, socket: Sink<'_>) -> ResultType<()> {
         let mut msg_out = Message::new();
         let pk = Config::get_key_pair().1;
         let uuid = hbb_common::get_uuid();
@@ -435,11 +553,11 @@ impl RendezvousMediator {
             pk: pk.into(),
             ..Default::default()
         });
-        socket.send(&msg_out, self.addr.to_owned()).await?;
+        socket.send(&msg_out, &self.addr).await?;
         Ok(())
     }
 
-    async fn handle_uuid_mismatch(&mut self, socket: &mut FramedSocket) -> ResultType<()> {
+    async fn handle_uuid_mismatch(&mut self, socket: Sink<'_>) -> ResultType<()> {
         if self.last_id_pk_registry != Config::get_id() {
             return Ok(());
         }
@@ -457,7 +575,7 @@ impl RendezvousMediator {
         self.register_pk(socket).await
     }
 
-    async fn register_peer(&mut self, socket: &mut FramedSocket) -> ResultType<()> {
+    async fn register_peer(&mut self, socket: Sink<'_>) -> ResultType<()> {
         if !SOLVING_PK_MISMATCH.lock().unwrap().is_empty() {
             return Ok(());
         }
@@ -481,7 +599,7 @@ impl RendezvousMediator {
             serial,
             ..Default::default()
         });
-        socket.send(&msg_out, self.addr.to_owned()).await?;
+        socket.send(&msg_out, &self.addr).await?;
         Ok(())
     }
 
@@ -497,6 +615,64 @@ impl RendezvousMediator {
     }
 }
 
+enum Sink<'a> {
+    Framed(&'a mut FramedSocket),
+    Stream(&'a mut FramedStream),
+}
+
+impl Sink<'_> {
+    async fn send(self, msg: &Message, addr: &TargetAddr<'static>) -> ResultType<()> {
+        match self {
+            Sink::Framed(socket) => socket.send(msg, addr.to_owned()).await,
+            Sink::Stream(stream) => stream.send(msg).await,
+        }
+    }
+}
+
+/// Open our firewall towards the peer's global ipv6 address and wait for it there.
+///
+/// There is no nat to traverse on ipv6, but stateful firewalls still drop
+/// unsolicited connections. Returns the address to advertise, empty if either
+/// side has no global ipv6.
+async fn punch_ipv6(peer_addr_v6: &[u8], server: ServerPtr) -> Vec<u8> {
+    if peer_addr_v6.is_empty() {
+        return Vec::new();
+    }
+    let peer_addr = AddrMangle::decode(peer_addr_v6);
+    if !peer_addr.is_ipv6() {
+        return Vec::new();
+    }
+    let Some(local_addr) = socket_client::new_ipv6_punch_addr() else {
+        return Vec::new();
+    };
+    log::debug!("Punch ipv6 hole to {:?} from {:?}", peer_addr, local_addr);
+    allow_err!(socket_client::connect_tcp_local(peer_addr, Some(local_addr), 30).await);
+    let listener = match hbb_common::tcp::new_listener(local_addr, true).await {
+        Ok(listener) => listener,
+        Err(err) => {
+            log::error!("Failed to listen on {}: {}", local_addr, err);
+            return Vec::new();
+        }
+    };
+    tokio::spawn(async move {
+        crate::server::accept_punched_connection(server, listener, peer_addr, true).await;
+    });
+    AddrMangle::encode(local_addr)
+}
+
+/// Port prediction only helps if exactly one side is symmetric and its nat
+/// allocates ports in a measurable step, otherwise go relay directly.
+fn can_predict_ports(peer_symmetric: bool, peer_delta: i32, my_symmetric: bool) -> bool {
+    if Config::get_option("enable-port-prediction") == "N" {
+        return false;
+    }
+    match (peer_symmetric, my_symmetric) {
+        (true, false) => peer_delta != 0,
+        (false, true) => Config::get_nat_port_delta() != 0,
+        _ => false,
+    }
+}
+
 fn get_direct_port() -> i32 {
     let mut port = Config::get_option("direct-access-port")
         .parse::<i32>()
@@ -559,6 +735,7 @@ async fn direct_server(server: ServerPtr) {
                             hbb_common::Stream::from(stream, local_addr),
                             addr,
                             false,
+                            crate::metrics::Transport::Direct,
                         )
                         .await
                     );
@@ -29,6 +29,7 @@ use service::ServiceTmpl;
 use service::{EmptyExtraFieldService, GenericService, Service, Subscriber};
 
 use crate::ipc::Data;
+use crate::metrics::Transport;
 
 pub mod audio_service;
 cfg_if::cfg_if! {
@@ -56,6 +57,8 @@ pub const NAME_POS: &'static str = "";
 
 mod connection;
 pub mod display_service;
+mod input_control;
+mod motion_qos;
 #[cfg(windows)]
 pub mod portable_service;
 mod service;
@@ -117,11 +120,38 @@ async fn accept_connection_(server: ServerPtr, socket: Stream, secure: bool) ->
     // there is TIME_WAIT before socket really released, so sometimes we
     // see “Only one usage of each socket address is normally permitted” on windows sometimes,
     let listener = new_listener(local_addr, true).await?;
+    accept_on_listener(server, listener, secure).await
+}
+
+/// Accept the punched connection on a listener the caller already set up.
+pub async fn accept_punched_connection(
+    server: ServerPtr,
+    listener: tokio::net::TcpListener,
+    peer_addr: SocketAddr,
+    secure: bool,
+) {
+    if let Err(err) = accept_on_listener(server, listener, secure).await {
+        log::error!("Failed to accept connection from {}: {}", peer_addr, err);
+    }
+}
+
+async fn accept_on_listener(
+    server: ServerPtr,
+    listener: tokio::net::TcpListener,
+    secure: bool,
+) -> ResultType<()> {
     log::info!("Server listening on: {}", &listener.local_addr()?);
     if let Ok((stream, addr)) = timeout(CONNECT_TIMEOUT, listener.accept()).await? {
         stream.set_nodelay(true).ok();
         let stream_addr = stream.local_addr()?;
-        create_tcp_connection(server, Stream::from(stream, stream_addr), addr, secure).await?;
+        create_tcp_connection(
+            server,
+            Stream::from(stream, stream_addr),
+            addr,
+            secure,
+            Transport::Punched,
+        )
+        .await?;
     }
     Ok(())
 }
@@ -131,8 +161,10 @@ pub async fn create_tcp_connection(
     stream: Stream,
     addr: SocketAddr,
     secure: bool,
+    transport: Transport,
 ) -> ResultType<()> {
     let mut stream = stream;
+    let mut migrate = false;
     let id = server.write().unwrap().get_new_id();
     let (sk, pk) = Config::get_key_pair();
     if secure && pk.len() == sign::PUBLICKEYBYTES && sk.len() == sign::SECRETKEYBYTES {
@@ -167,6 +199,7 @@ pub async fn create_tcp_connection(
                                 &pk.asymmetric_value,
                                 &our_sk_b,
                             )?);
+                            migrate = pk.migrate;
                         } else if pk.asymmetric_value.is_empty() {
                             Config::set_key_confirmed(false);
                             log::info!("Force to update pk");
@@ -186,6 +219,10 @@ pub async fn create_tcp_connection(
         }
     }
 
+    if migrate {
+        return connection::accept_transport_migration(stream).await;
+    }
+
     #[cfg(target_os = "macos")]
     {
         use std::process::Command;
@@ -196,7 +233,7 @@ pub async fn create_tcp_connection(
             .ok();
         log::info!("wake up macos");
     }
-    Connection::start(addr, stream, id, Arc::downgrade(&server)).await;
+    Connection::start(addr, stream, id, Arc::downgrade(&server), transport).await;
     Ok(())
 }
 
@@ -252,7 +289,7 @@ async fn create_relay_connection_(
         ..Default::default()
     });
     stream.send(&msg_out).await?;
-    create_tcp_connection(server, stream, peer_addr, secure).await?;
+    create_tcp_connection(server, stream, peer_addr, secure, Transport::Relay).await?;
     Ok(())
 }
 
@@ -337,7 +374,10 @@ impl Server {
     pub fn set_video_service_opt(&self, display: Option<usize>, opt: &str, value: &str) {
         for (k, v) in self.services.iter() {
             if let Some(display) = display {
-                if k != &video_service::get_service_name(display) {
+                if k != &video_service::get_service_name(display)
+                    && !(k == video_service::CANVAS_NAME
+                        && video_service::is_canvas_display(display))
+                {
                     continue;
                 }
             }
@@ -374,6 +414,16 @@ impl Server {
             }
         }
     }
+
+    // Subscribe to the canvas only, it replaces the streams of its displays.
+    fn capture_canvas(&mut self, conn: ConnInner) {
+        let keys = self.services.keys().cloned().collect::<Vec<_>>();
+        for name in keys.iter() {
+            if Self::is_video_service_name(&name) {
+                self.subscribe(&name, conn.clone(), name == video_service::CANVAS_NAME);
+            }
+        }
+    }
 }
 
 impl Drop for Server {
@@ -15,12 +15,21 @@
 use super::*;
 #[cfg(not(any(target_os = "linux", target_os = "android")))]
 use hbb_common::anyhow::anyhow;
-use magnum_opus::{Application::*, Channels::*, Encoder};
-use std::sync::atomic::{AtomicBool, Ordering};
+use magnum_opus::{Application, Application::*, Bitrate, Channels, Channels::*, Encoder};
+use std::{
+    sync::atomic::{AtomicBool, Ordering},
+    time::Instant,
+};
 
 pub const NAME: &'static str = "audio";
 pub const AUDIO_DATA_SIZE_U8: usize = 960 * 4; // 10ms in 48000 stereo
 static RESTARTING: AtomicBool = AtomicBool::new(false);
+const QUALITY_CHECK_INTERVAL: Duration = Duration::from_secs(1);
+
+lazy_static::lazy_static! {
+    // the audio quality each connection asks for
+    static ref QUALITIES: Mutex<HashMap<i32, AudioQuality>> = Default::default();
+}
 
 #[cfg(not(any(target_os = "linux", target_os = "android")))]
 pub fn new() -> GenericService {
@@ -44,6 +53,14 @@ pub fn restart() {
     RESTARTING.store(true, Ordering::SeqCst);
 }
 
+pub fn set_quality(id: i32, quality: Option<AudioQuality>) {
+    let mut qualities = QUALITIES.lock().unwrap();
+    match quality {
+        Some(quality) => qualities.insert(id, quality),
+        None => qualities.remove(&id),
+    };
+}
+
 #[cfg(any(target_os = "linux", target_os = "android"))]
 mod pa_impl {
     use super::*;
@@ -56,27 +73,29 @@ mod pa_impl {
         unsafe {
             AUDIO_ZERO_COUNT = 0;
         }
-        let mut encoder = Encoder::new(crate::platform::PA_SAMPLE_RATE, Stereo, LowDelay)?;
+        let mut encoder = AudioEncoder::new(crate::platform::PA_SAMPLE_RATE, Stereo)?;
         #[cfg(target_os = "linux")]
-        allow_err!(
-            stream
-                .send(&crate::ipc::Data::Config((
-                    "audio-input".to_owned(),
-                    Some(Config::get_option("audio-input"))
-                )))
-                .await
-        );
+        for name in ["audio-input", "capture-apps"] {
+            allow_err!(
+                stream
+                    .send(&crate::ipc::Data::Config((
+                        name.to_owned(),
+                        Some(Config::get_option(name))
+                    )))
+                    .await
+            );
+        }
         #[cfg(target_os = "linux")]
         let zero_audio_frame: Vec<f32> = vec![0.; AUDIO_DATA_SIZE_U8 / 4];
         while sp.ok() && !RESTARTING.load(Ordering::SeqCst) {
             sp.snapshot(|sps| {
-                sps.send(create_format_msg(crate::platform::PA_SAMPLE_RATE, 2));
+                sps.send(encoder.format_msg());
                 Ok(())
             })?;
             #[cfg(target_os = "linux")]
             if let Ok(data) = stream.next_raw().await {
                 if data.len() == 0 {
-                    send_f32(&zero_audio_frame, &mut encoder, &sp);
+                    encoder.send(&zero_audio_frame, &sp);
                     continue;
                 }
                 if data.len() != AUDIO_DATA_SIZE_U8 {
@@ -85,14 +104,14 @@ mod pa_impl {
                 let data = unsafe {
                     std::slice::from_raw_parts::<f32>(data.as_ptr() as _, data.len() / 4)
                 };
-                send_f32(data, &mut encoder, &sp);
+                encoder.send(data, &sp);
             }
             #[cfg(target_os = "android")]
             if let Some(data) = scrap::android::ffi::get_audio_raw() {
                 let data = unsafe {
                     std::slice::from_raw_parts::<f32>(data.as_ptr() as _, data.len() / 4)
                 };
-                send_f32(data, &mut encoder, &sp);
+                encoder.send(data, &sp);
             } else {
                 hbb_common::sleep(0.1).await;
             }
@@ -116,7 +135,7 @@ mod cpal_impl {
 
     #[derive(Default)]
     pub struct State {
-        stream: Option<(Box<dyn StreamTrait>, Arc<Message>)>,
+        stream: Option<(Box<dyn StreamTrait>, Arc<Mutex<AudioEncoder>>)>,
     }
 
     impl super::service::Reset for State {
@@ -133,8 +152,8 @@ mod cpal_impl {
                 }
                 _ => {}
             }
-            if let Some((_, format)) = &state.stream {
-                sps.send_shared(format.clone());
+            if let Some((_, encoder)) = &state.stream {
+                sps.send(encoder.lock().unwrap().format_msg());
             }
             Ok(())
         })?;
@@ -147,7 +166,7 @@ mod cpal_impl {
         sample_rate: u32,
         device_channel: u16,
         encode_channel: u16,
-        encoder: &mut Encoder,
+        encoder: &Mutex<AudioEncoder>,
         sp: &GenericService,
     ) {
         let mut data = data;
@@ -163,7 +182,7 @@ mod cpal_impl {
                 encode_channel,
             )
         }
-        send_f32(&data, encoder, sp);
+        encoder.lock().unwrap().send(&data, sp);
     }
 
     #[cfg(windows)]
@@ -219,7 +238,7 @@ mod cpal_impl {
         Ok((device, format))
     }
 
-    fn play(sp: &GenericService) -> ResultType<(Box<dyn StreamTrait>, Arc<Message>)> {
+    fn play(sp: &GenericService) -> ResultType<(Box<dyn StreamTrait>, Arc<Mutex<AudioEncoder>>)> {
         use cpal::SampleFormat::*;
         let (device, config) = get_device()?;
         let sp = sp.clone();
@@ -237,24 +256,22 @@ mod cpal_impl {
             48000
         };
         let ch = if config.channels() > 1 { Stereo } else { Mono };
+        let encoder = Arc::new(Mutex::new(AudioEncoder::new(sample_rate, ch)?));
         let stream = match config.sample_format() {
-            I8 => build_input_stream::<i8>(device, &config, sp, sample_rate, ch)?,
-            I16 => build_input_stream::<i16>(device, &config, sp, sample_rate, ch)?,
-            I32 => build_input_stream::<i32>(device, &config, sp, sample_rate, ch)?,
-            I64 => build_input_stream::<i64>(device, &config, sp, sample_rate, ch)?,
-            U8 => build_input_stream::<u8>(device, &config, sp, sample_rate, ch)?,
-            U16 => build_input_stream::<u16>(device, &config, sp, sample_rate, ch)?,
-            U32 => build_input_stream::<u32>(device, &config, sp, sample_rate, ch)?,
-            U64 => build_input_stream::<u64>(device, &config, sp, sample_rate, ch)?,
-            F32 => build_input_stream::<f32>(device, &config, sp, sample_rate, ch)?,
-            F64 => build_input_stream::<f64>(device, &config, sp, sample_rate, ch)?,
+            I8 => build_input_stream::<i8>(device, &config, sp, sample_rate, encoder.clone())?,
+            I16 => build_input_stream::<i16>(device, &config, sp, sample_rate, encoder.clone())?,
+            I32 => build_input_stream::<i32>(device, &config, sp, sample_rate, encoder.clone())?,
+            I64 => build_input_stream::<i64>(device, &config, sp, sample_rate, encoder.clone())?,
+            U8 => build_input_stream::<u8>(device, &config, sp, sample_rate, encoder.clone())?,
+            U16 => build_input_stream::<u16>(device, &config, sp, sample_rate, encoder.clone())?,
+            U32 => build_input_stream::<u32>(device, &config, sp, sample_rate, encoder.clone())?,
+            U64 => build_input_stream::<u64>(device, &config, sp, sample_rate, encoder.clone())?,
+            F32 => build_input_stream::<f32>(device, &config, sp, sample_rate, encoder.clone())?,
+            F64 => build_input_stream::<f64>(device, &config, sp, sample_rate, encoder.clone())?,
             f => bail!("unsupported audio format: {:?}", f),
         };
         stream.play()?;
-        Ok((
-            Box::new(stream),
-            Arc::new(create_format_msg(sample_rate, ch as _)),
-        ))
+        Ok((Box::new(stream), encoder))
     }
 
     fn build_input_stream<T>(
@@ -262,7 +279,7 @@ mod cpal_impl {
         config: &cpal::SupportedStreamConfig,
         sp: GenericService,
         sample_rate: u32,
-        encode_channel: magnum_opus::Channels,
+        encoder: Arc<Mutex<AudioEncoder>>,
     ) -> ResultType<cpal::Stream>
     where
         T: cpal::SizedSample + dasp::sample::ToSample<f32>,
@@ -277,10 +294,10 @@ mod cpal_impl {
             AUDIO_ZERO_COUNT = 0;
         }
         let device_channel = config.channels();
-        let mut encoder = Encoder::new(sample_rate, encode_channel, LowDelay)?;
+        let encode_channel = encoder.lock().unwrap().source_channels;
         // https://www.opus-codec.org/docs/html_api/group__opusencoder.html#gace941e4ef26ed844879fde342ffbe546
         // https://chromium.googlesource.com/chromium/deps/opus/+/1.1.1/include/opus.h
-        let frame_size = sample_rate as usize / 100; // 10 ms
+        let frame_size = sample_rate as usize / 100; // 10 ms, framed again by `AudioEncoder`
         let encode_len = frame_size * encode_channel as usize;
         let rechannel_len = encode_len * device_channel as usize / encode_channel as usize;
         INPUT_BUFFER.lock().unwrap().clear();
@@ -304,7 +321,7 @@ mod cpal_impl {
                         sample_rate,
                         device_channel,
                         encode_channel as _,
-                        &mut encoder,
+                        &encoder,
                         &sp,
                     );
                 }
@@ -329,13 +346,180 @@ fn create_format_msg(sample_rate: u32, channels: u16) -> Message {
     msg
 }
 
+// The Opus settings of the audio service, from the quality the peers ask for.
+#[derive(Debug, Clone, Copy, PartialEq)]
+struct Settings {
+    application: Application,
+    channels: Channels,
+    bitrate: Option<i32>,
+    frame_ms: usize,
+}
+
+impl Settings {
+    // Between peers asking differently, low delay wins over music and music over
+    // voice, and so do the highest bitrate, the most channels and the shortest frames.
+    fn new(source_channels: Channels) -> Self {
+        use audio_quality::Mode;
+        let qualities: Vec<AudioQuality> = QUALITIES.lock().unwrap().values().cloned().collect();
+        let mode = qualities
+            .iter()
+            .map(|q| q.mode.enum_value_or_default())
+            .filter(|m| *m != Mode::NotSet)
+            .max_by_key(|m| match m {
+                Mode::LowDelay => 3,
+                Mode::Music => 2,
+                _ => 1,
+            })
+            .unwrap_or(Mode::LowDelay);
+        let mut settings = match mode {
+            Mode::Voice => Settings {
+                application: Voip,
+                channels: Mono,
+                bitrate: Some(32_000),
+                frame_ms: 20,
+            },
+            Mode::Music => Settings {
+                application: Audio,
+                channels: source_channels,
+                bitrate: Some(128_000),
+                frame_ms: 20,
+            },
+            _ => Settings {
+                application: LowDelay,
+                channels: source_channels,
+                bitrate: None,
+                frame_ms: 10,
+            },
+        };
+        if let Some(bitrate) = qualities.iter().map(|q| q.bitrate).filter(|b| *b > 0).max() {
+            settings.bitrate = Some(bitrate.clamp(6_000, 510_000));
+        }
+        if let Some(channels) = qualities
+            .iter()
+            .map(|q| q.channels)
+            .filter(|c| *c > 0)
+            .max()
+        {
+            settings.channels = if channels == 1 { Mono } else { source_channels };
+        }
+        if let Some(frame_ms) = qualities
+            .iter()
+            .map(|q| q.frame_ms as usize)
+            .filter(|f| [10, 20, 40, 60].contains(f))
+            .min()
+        {
+            settings.frame_ms = frame_ms;
+        }
+        // leave the bandwidth to the video on a delayed network
+        if let Some(limit) = super::video_service::VIDEO_QOS
+            .lock()
+            .unwrap()
+            .audio_bitrate_limit()
+        {
+            settings.bitrate = Some(settings.bitrate.map_or(limit, |b| b.min(limit)));
+        }
+        settings
+    }
+
+    fn encoder(&self, sample_rate: u32) -> ResultType<Encoder> {
+        let mut encoder = Encoder::new(sample_rate, self.channels, self.application)?;
+        if let Some(bitrate) = self.bitrate {
+            encoder.set_bitrate(Bitrate::Bits(bitrate))?;
+        }
+        Ok(encoder)
+    }
+}
+
+// The Opus encoder of the audio service, following `Settings` and cutting the
+// audio into the frames they ask for.
+struct AudioEncoder {
+    encoder: Encoder,
+    sample_rate: u32,
+    source_channels: Channels,
+    settings: Settings,
+    buffer: Vec<f32>,
+    checked: Instant,
+}
+
+impl AudioEncoder {
+    fn new(sample_rate: u32, source_channels: Channels) -> ResultType<Self> {
+        let settings = Settings::new(source_channels);
+        Ok(Self {
+            encoder: settings.encoder(sample_rate)?,
+            sample_rate,
+            source_channels,
+            settings,
+            buffer: Vec::new(),
+            checked: Instant::now(),
+        })
+    }
+
+    fn format_msg(&self) -> Message {
+        create_format_msg(self.sample_rate, self.settings.channels as _)
+    }
+
+    // Follow the quality asked for, sending the new format to the peers if it changes.
+    fn update(&mut self, sp: &GenericService) {
+        if self.checked.elapsed() < QUALITY_CHECK_INTERVAL {
+            return;
+        }
+        self.checked = Instant::now();
+        let settings = Settings::new(self.source_channels);
+        if settings == self.settings {
+            return;
+        }
+        log::info!("audio settings: {:?}", settings);
+        if settings.application != self.settings.application
+            || settings.channels != self.settings.channels
+        {
+            match settings.encoder(self.sample_rate) {
+                Ok(encoder) => self.encoder = encoder,
+                Err(e) => {
+                    log::error!("Failed to create audio encoder: {}", e);
+                    return;
+                }
+            }
+            self.buffer.clear();
+        } else if settings.bitrate != self.settings.bitrate {
+            let bitrate = settings.bitrate.map_or(Bitrate::Auto, Bitrate::Bits);
+            allow_err!(self.encoder.set_bitrate(bitrate));
+        }
+        let format_changed = settings.channels != self.settings.channels;
+        self.settings = settings;
+        if format_changed {
+            sp.send(self.format_msg());
+        }
+    }
+
+    // `data` is interleaved in the channels of the source.
+    fn send(&mut self, data: &[f32], sp: &GenericService) {
+        self.update(sp);
+        if self.settings.channels == Mono && self.source_channels == Stereo {
+            self.buffer
+                .extend(data.chunks_exact(2).map(|s| (s[0] + s[1]) / 2.));
+        } else {
+            self.buffer.extend_from_slice(data);
+        }
+        let channels = self.settings.channels as usize;
+        let frame_len = self.sample_rate as usize * self.settings.frame_ms / 1000 * channels;
+        // the end of the buffer is captured now, stamp the frames by their start
+        let now = super::video_service::media_pts();
+        while self.buffer.len() >= frame_len {
+            let pts =
+                now - (self.buffer.len() / channels * 1000 / self.sample_rate as usize) as i64;
+            let frame: Vec<f32> = self.buffer.drain(..frame_len).collect();
+            send_f32(&frame, pts, &mut self.encoder, sp);
+        }
+    }
+}
+
 // use AUDIO_ZERO_COUNT for the Noise(Zero) Gate Attack Time
 // every audio data length is set to 480
 // MAX_AUDIO_ZERO_COUNT=800 is similar as Gate Attack Time 3~5s(Linux) || 6~8s(Windows)
 const MAX_AUDIO_ZERO_COUNT: u16 = 800;
 static mut AUDIO_ZERO_COUNT: u16 = 0;
 
-fn send_f32(data: &[f32], encoder: &mut Encoder, sp: &GenericService) {
+fn send_f32(data: &[f32], pts: i64, encoder: &mut Encoder, sp: &GenericService) {
     if data.iter().filter(|x| **x != 0.).next().is_some() {
         unsafe {
             AUDIO_ZERO_COUNT = 0;
@@ -352,42 +536,12 @@ fn send_f32(data: &[f32], encoder: &mut Encoder, sp: &GenericService) {
             AUDIO_ZERO_COUNT += 1;
         }
     }
-    #[cfg(target_os = "android")]
-    {
-        // the permitted opus data size are 120, 240, 480, 960, 1920, and 2880
-        // if data size is bigger than BATCH_SIZE, AND is an integer multiple of BATCH_SIZE
-        // then upload in batches
-        const BATCH_SIZE: usize = 960;
-        let input_size = data.len();
-        if input_size > BATCH_SIZE && input_size % BATCH_SIZE == 0 {
-            let n = input_size / BATCH_SIZE;
-            for i in 0..n {
-                match encoder
-                    .encode_vec_float(&data[i * BATCH_SIZE..(i + 1) * BATCH_SIZE], BATCH_SIZE)
-                {
-                    Ok(data) => {
-                        let mut msg_out = Message::new();
-                        msg_out.set_audio_frame(AudioFrame {
-                            data: data.into(),
-                            ..Default::default()
-                        });
-                        sp.send(msg_out);
-                    }
-                    Err(_) => {}
-                }
-            }
-        } else {
-            log::debug!("invalid audio data size:{} ", input_size);
-            return;
-        }
-    }
-
-    #[cfg(not(target_os = "android"))]
     match encoder.encode_vec_float(data, data.len() * 6) {
         Ok(data) => {
             let mut msg_out = Message::new();
             msg_out.set_audio_frame(AudioFrame {
                 data: data.into(),
+                pts,
                 ..Default::default()
             });
             sp.send(msg_out);
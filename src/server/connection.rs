@@ -1,4 +1,6 @@
 use super::{input_service::*, *};
+#[cfg(target_os = "linux")]
+use crate::client::start_audio_thread_to_device;
 #[cfg(any(target_os = "windows", target_os = "linux", target_os = "macos"))]
 use crate::clipboard_file::*;
 #[cfg(not(any(target_os = "android", target_os = "ios")))]
@@ -19,7 +21,9 @@ use crate::{
         new_voice_call_request, new_voice_call_response, start_audio_thread, MediaData, MediaSender,
     },
     common::{get_default_sound_input, set_sound_input},
-    display_service, ipc, privacy_mode, video_service, VERSION,
+    display_service, ipc,
+    metrics::{self, Side, Transport},
+    privacy_mode, video_service, VERSION,
 };
 #[cfg(any(target_os = "android", target_os = "ios"))]
 use crate::{common::DEVICE_NAME, flutter::connection_manager::start_channel};
@@ -73,6 +77,7 @@ lazy_static::lazy_static! {
     static ref AUTHED_CONNS: Arc::<Mutex<Vec<(i32, AuthConnType)>>> = Default::default();
     static ref SWITCH_SIDES_UUID: Arc::<Mutex<HashMap<String, (Instant, uuid::Uuid)>>> = Default::default();
     static ref WAKELOCK_SENDER: Arc::<Mutex<std::sync::mpsc::Sender<(usize, usize)>>> = Arc::new(Mutex::new(start_wakelock_thread()));
+    static ref MIGRATION_TARGETS: Arc::<Mutex<HashMap<Vec<u8>, mpsc::UnboundedSender<super::Stream>>>> = Default::default();
 }
 
 #[cfg(any(target_os = "windows", target_os = "linux"))]
@@ -134,6 +139,8 @@ enum MessageInput {
     Key((KeyEvent, bool)),
     #[cfg(not(any(target_os = "android", target_os = "ios")))]
     Pointer((PointerDeviceEvent, i32)),
+    #[cfg(target_os = "linux")]
+    Gamepad(GamepadEvent),
     BlockOn,
     BlockOff,
     #[cfg(all(feature = "flutter", feature = "plugin_framework"))]
@@ -220,6 +227,9 @@ pub struct Connection {
     from_switch: bool,
     voice_call_request_timestamp: Option<NonZeroI64>,
     audio_input_device_before_voice_call: Option<String>,
+    // the audio of the voice call is played into it, see "allow-virtual-microphone"
+    #[cfg(target_os = "linux")]
+    virtual_mic: Option<crate::platform::linux::VirtualMic>,
     options_in_login: Option<OptionMessage>,
     #[cfg(not(any(target_os = "ios")))]
     pressed_modifiers: HashSet<rdev::Key>,
@@ -233,6 +243,16 @@ pub struct Connection {
     auto_disconnect_timer: Option<(Instant, u64)>,
     authed_conn_id: Option<self::raii::AuthedConnID>,
     file_remove_log_control: FileRemoveLogControl,
+    migration_token: Vec<u8>,
+    tx_migration: mpsc::UnboundedSender<super::Stream>,
+    // the transport before migration, read until the peer closes it
+    old_stream: Option<super::Stream>,
+    transport: Transport,
+    // the input control token as last told to the peer and the cm
+    input_control: Option<input_control::View>,
+    // the last pen event passed to the input pressed the tip or a button
+    #[cfg(not(any(target_os = "android", target_os = "ios")))]
+    pen_pressed: bool,
 }
 
 impl ConnInner {
@@ -283,6 +303,7 @@ impl Connection {
         stream: super::Stream,
         id: i32,
         server: super::ServerPtrWeak,
+        transport: Transport,
     ) {
         let _raii_id = raii::ConnectionID::new(id);
         let hash = Hash {
@@ -297,7 +318,9 @@ impl Connection {
         let (tx, mut rx) = mpsc::unbounded_channel::<(Instant, Arc<Message>)>();
         let (tx_video, mut rx_video) = mpsc::unbounded_channel::<(Instant, Arc<Message>)>();
         let (tx_input, _rx_input) = std_mpsc::channel();
+        let (tx_migration, mut rx_migration) = mpsc::unbounded_channel::<super::Stream>();
         let mut hbbs_rx = crate::hbbs_http::sync::signal_receiver();
+        let mut input_control_rx = input_control::subscribe();
         #[cfg(not(any(target_os = "android", target_os = "ios")))]
         let (tx_cm_stream_ready, _rx_cm_stream_ready) = mpsc::channel(1);
         #[cfg(not(any(target_os = "android", target_os = "ios")))]
@@ -359,6 +382,8 @@ impl Connection {
             audio_sender: None,
             voice_call_request_timestamp: None,
             audio_input_device_before_voice_call: None,
+            #[cfg(target_os = "linux")]
+            virtual_mic: None,
             options_in_login: None,
             #[cfg(not(any(target_os = "ios")))]
             pressed_modifiers: Default::default(),
@@ -377,6 +402,13 @@ impl Connection {
             auto_disconnect_timer: None,
             authed_conn_id: None,
             file_remove_log_control: FileRemoveLogControl::new(id),
+            migration_token: Vec::new(),
+            tx_migration,
+            old_stream: None,
+            transport,
+            input_control: None,
+            #[cfg(not(any(target_os = "android", target_os = "ios")))]
+            pen_pressed: false,
         };
         let addr = hbb_common::try_into_v4(addr);
         if !conn.on_open(addr).await {
@@ -412,13 +444,7 @@ impl Connection {
             time::interval_at(Instant::now() + TEST_DELAY_TIMEOUT, TEST_DELAY_TIMEOUT);
         let mut last_recv_time = Instant::now();
 
-        conn.stream.set_send_timeout(
-            if conn.file_transfer.is_some() || conn.port_forward_socket.is_some() {
-                SEND_TIMEOUT_OTHER
-            } else {
-                SEND_TIMEOUT_VIDEO
-            },
-        );
+        conn.stream.set_send_timeout(conn.send_timeout());
 
         #[cfg(not(any(target_os = "android", target_os = "ios")))]
         std::thread::spawn(move || Self::handle_input(_rx_input, tx_cloned));
@@ -451,6 +477,7 @@ impl Connection {
                             }
                         }
                         ipc::Data::ChatMessage{text} => {
+                            video_service::record_event("chat", &format!("local: {}", text));
                             let mut misc = Misc::new();
                             misc.set_chat_message(ChatMessage {
                                 text,
@@ -463,6 +490,7 @@ impl Connection {
                         }
                         ipc::Data::SwitchPermission{name, enabled} => {
                             log::info!("Change permission {} -> {}", name, enabled);
+                            video_service::record_event("permission", &format!("{} {}", name, if enabled { "on" } else { "off" }));
                             if &name == "keyboard" {
                                 conn.keyboard = enabled;
                                 conn.send_permission(Permission::Keyboard, enabled).await;
@@ -554,6 +582,10 @@ impl Connection {
                             let msg = new_voice_call_request(false);
                             conn.send(msg).await;
                         }
+                        ipc::Data::GrantInputControl => {
+                            log::info!("Input control granted to #{} by the local user", id);
+                            input_control::grant(id);
+                        }
                         _ => {}
                     }
                 },
@@ -579,10 +611,34 @@ impl Connection {
                         break;
                     }
                 },
+                res = next_of_old_stream(&mut conn.old_stream) => {
+                    match res {
+                        Some(Ok(bytes)) => {
+                            last_recv_time = Instant::now();
+                            *conn.last_recv_time.lock().unwrap() = Instant::now();
+                            if let Ok(msg_in) = Message::parse_from_bytes(&bytes) {
+                                if !conn.on_message(msg_in).await {
+                                    break;
+                                }
+                            }
+                        }
+                        _ => {
+                            log::info!("#{} old transport closed", id);
+                            conn.old_stream = None;
+                        }
+                    }
+                },
+                Some(stream) = rx_migration.recv() => {
+                    conn.on_transport_migrated(stream).await;
+                }
                 _ = conn.file_timer.tick() => {
                     if !conn.read_jobs.is_empty() {
                         conn.send_to_cm(ipc::Data::FileTransferLog(("transfer".to_string(), fs::serialize_transfer_jobs(&conn.read_jobs))));
-                        match fs::handle_read_jobs(&mut conn.read_jobs, &mut conn.stream).await {
+                        let progress = metrics::job_progress(&conn.read_jobs);
+                        let res = fs::handle_read_jobs(&mut conn.read_jobs, &mut conn.stream).await;
+                        let sent = metrics::progress_since(&progress, &conn.read_jobs);
+                        metrics::update(Side::Controlled, id, |m| m.file_bytes_sent += sent);
+                        match res {
                             Ok(log) => {
                                 if !log.is_empty() {
                                     conn.send_to_cm(ipc::Data::FileTransferLog(("transfer".to_string(), log)));
@@ -604,6 +660,9 @@ impl Connection {
                         break;
                     }
                 }
+                Ok(_) = input_control_rx.recv() => {
+                    conn.update_input_control().await;
+                }
                 Some((instant, value)) = rx_video.recv() => {
                     if !conn.video_ack_required {
                         video_service::notify_video_frame_fetched(id, Some(instant.into()));
@@ -724,6 +783,8 @@ impl Connection {
         }
         #[cfg(target_os = "macos")]
         reset_input_ondisconn();
+        #[cfg(target_os = "linux")]
+        let mut gamepads = HashMap::new();
         loop {
             match receiver.recv_timeout(std::time::Duration::from_millis(500)) {
                 Ok(v) => match v {
@@ -744,6 +805,19 @@ impl Connection {
                     MessageInput::Pointer((msg, id)) => {
                         handle_pointer(&msg, id);
                     }
+                    #[cfg(target_os = "linux")]
+                    MessageInput::Gamepad(evt) => {
+                        let tx = tx.clone();
+                        let index = evt.index;
+                        handle_gamepad(&mut gamepads, &evt, move |rumble| {
+                            let mut evt = GamepadEvent::new();
+                            evt.index = index;
+                            evt.set_rumble(rumble);
+                            let mut msg_out = Message::new();
+                            msg_out.set_gamepad_event(evt);
+                            tx.send((Instant::now(), Arc::new(msg_out))).ok();
+                        });
+                    }
                     MessageInput::BlockOn => {
                         let (ok, msg) = crate::platform::block_input(true);
                         if ok {
@@ -1022,6 +1096,71 @@ impl Connection {
         crate::post_request(url, v.to_string(), "").await
     }
 
+    #[inline]
+    fn send_timeout(&self) -> u64 {
+        if self.file_transfer.is_some() || self.port_forward_socket.is_some() {
+            SEND_TIMEOUT_OTHER
+        } else {
+            SEND_TIMEOUT_VIDEO
+        }
+    }
+
+    // Let the peer move the session to another transport later, e.g. from relay to direct.
+    async fn issue_migration_token(&mut self) {
+        if !self.stream.is_secured() || !self.migration_token.is_empty() {
+            return;
+        }
+        let token = hbb_common::rand::random::<[u8; 16]>().to_vec();
+        MIGRATION_TARGETS
+            .lock()
+            .unwrap()
+            .insert(token.clone(), self.tx_migration.clone());
+        self.migration_token = token.clone();
+        let mut misc = Misc::new();
+        misc.set_transport_migration(TransportMigration {
+            token: token.into(),
+            ..Default::default()
+        });
+        let mut msg_out = Message::new();
+        msg_out.set_misc(misc);
+        self.send(msg_out).await;
+    }
+
+    async fn on_transport_migrated(&mut self, mut stream: super::Stream) {
+        if self.old_stream.is_some() {
+            // still draining the previous transport, dropping the new one closes it
+            log::info!(
+                "#{} transport migration refused, the previous one is pending",
+                self.inner.id()
+            );
+            return;
+        }
+        // one migration per session, the token is spent
+        MIGRATION_TARGETS
+            .lock()
+            .unwrap()
+            .remove(&self.migration_token);
+        log::info!(
+            "#{} session migrated to the transport via {}",
+            self.inner.id(),
+            stream.local_addr()
+        );
+        stream.set_send_timeout(self.send_timeout());
+        let mut misc = Misc::new();
+        misc.set_transport_migration(TransportMigration {
+            done: true,
+            ..Default::default()
+        });
+        let mut msg_out = Message::new();
+        msg_out.set_misc(misc);
+        allow_err!(self.stream.send(&msg_out).await);
+        self.old_stream = Some(std::mem::replace(&mut self.stream, stream));
+        self.transport = Transport::Punched;
+        metrics::update(Side::Controlled, self.inner.id(), |m| {
+            m.transport = Transport::Punched
+        });
+    }
+
     async fn send_logon_response(&mut self) {
         if self.authorized {
             return;
@@ -1158,6 +1297,12 @@ impl Connection {
             }
         }
         self.authorized = true;
+        metrics::add(
+            Side::Controlled,
+            self.inner.id(),
+            self.lr.my_id.clone(),
+            self.transport,
+        );
         #[cfg(all(feature = "flutter", feature = "plugin_framework"))]
         #[cfg(not(any(target_os = "android", target_os = "ios")))]
         PLUGIN_BLOCK_INPUT_TXS
@@ -1224,6 +1369,7 @@ impl Connection {
         let mut msg_out = Message::new();
         msg_out.set_login_response(res);
         self.send(msg_out).await;
+        self.issue_migration_token().await;
         if let Some(o) = self.options_in_login.take() {
             self.update_options(&o).await;
         }
@@ -1256,6 +1402,7 @@ impl Connection {
                 s.try_add_primay_video_service();
                 s.add_connection(self.inner.clone(), &noperms);
             }
+            self.update_input_control().await;
         }
     }
 
@@ -1282,6 +1429,41 @@ impl Connection {
         self.keyboard && !self.disable_keyboard
     }
 
+    #[inline]
+    #[cfg(not(any(target_os = "android", target_os = "ios")))]
+    fn acquire_input_control(&self) -> bool {
+        input_control::acquire(self.inner.id, &self.lr.my_name)
+    }
+
+    // Tells the peer and the cm who holds the input control token, if changed.
+    async fn update_input_control(&mut self) {
+        if !input_control::is_enabled() || self.file_transfer.is_some() {
+            return;
+        }
+        let view = input_control::view(self.inner.id);
+        if self.input_control.as_ref() == Some(&view) {
+            return;
+        }
+        self.send_to_cm(ipc::Data::InputControl {
+            holding: view.holding,
+            requesting: view.requesting,
+        });
+        let (requester_id, requester) = view.requester.clone().unwrap_or_default();
+        let mut misc = Misc::new();
+        misc.set_input_control(InputControl {
+            holder: view.holder.clone(),
+            holding: view.holding,
+            requesting: view.requesting,
+            requester_id,
+            requester,
+            ..Default::default()
+        });
+        let mut msg_out = Message::new();
+        msg_out.set_misc(misc);
+        self.send(msg_out).await;
+        self.input_control = Some(view);
+    }
+
     fn clipboard_enabled(&self) -> bool {
         self.clipboard && !self.disable_clipboard
     }
@@ -1365,6 +1547,12 @@ impl Connection {
             .ok();
     }
 
+    #[inline]
+    #[cfg(target_os = "linux")]
+    fn input_gamepad(&self, evt: GamepadEvent) {
+        self.tx_input.send(MessageInput::Gamepad(evt)).ok();
+    }
+
     #[inline]
     #[cfg(not(any(target_os = "android", target_os = "ios")))]
     fn input_key(&self, msg: KeyEvent, press: bool) {
@@ -1721,6 +1909,9 @@ impl Connection {
                     .lock()
                     .unwrap()
                     .user_network_delay(self.inner.id(), new_delay);
+                metrics::update(Side::Controlled, self.inner.id(), |m| {
+                    m.rtt_ms = Some(new_delay)
+                });
                 self.network_delay = Some(new_delay);
                 self.delay_response_instant = Instant::now();
             }
@@ -1752,13 +1943,20 @@ impl Connection {
                     if let Err(e) = call_main_service_pointer_input("mouse", me.mask, me.x, me.y) {
                         log::debug!("call_main_service_pointer_input fail:{}", e);
                     }
+                    // releases pass, not to leave the buttons of a former holder down
                     #[cfg(not(any(target_os = "android", target_os = "ios")))]
-                    if self.peer_keyboard_enabled() {
+                    if self.peer_keyboard_enabled()
+                        && (me.mask & 0x7 == crate::input::MOUSE_TYPE_UP
+                            || self.acquire_input_control())
+                    {
                         if is_left_up(&me) {
                             CLICK_TIME.store(get_time(), Ordering::SeqCst);
                         } else {
                             MOUSE_MOVE_TIME.store(get_time(), Ordering::SeqCst);
                         }
+                        if let Some(detail) = mouse_event_detail(&me) {
+                            video_service::record_event("click", &detail);
+                        }
                         self.input_mouse(me, self.inner.id());
                     }
                     self.update_auto_disconnect_timer();
@@ -1794,8 +1992,31 @@ impl Connection {
                     }
                     #[cfg(not(any(target_os = "android", target_os = "ios")))]
                     if self.peer_keyboard_enabled() {
-                        MOUSE_MOVE_TIME.store(get_time(), Ordering::SeqCst);
-                        self.input_pointer(pde, self.inner.id());
+                        let pde = if self.acquire_input_control() {
+                            MOUSE_MOVE_TIME.store(get_time(), Ordering::SeqCst);
+                            Some(pde)
+                        } else {
+                            // a release passes, not to leave the pen of a former holder down
+                            pen_release(&pde).filter(|_| self.pen_pressed)
+                        };
+                        if let Some(pde) = pde {
+                            if let Some(pointer_device_event::Union::PenEvent(pen)) = &pde.union {
+                                self.pen_pressed = pen.down || pen.buttons != 0;
+                            }
+                            self.input_pointer(pde, self.inner.id());
+                        }
+                    }
+                    self.update_auto_disconnect_timer();
+                }
+                Some(message::Union::GamepadEvent(_evt)) => {
+                    #[cfg(target_os = "linux")]
+                    if self.peer_keyboard_enabled() {
+                        if self.acquire_input_control() {
+                            self.input_gamepad(_evt);
+                        } else if let Some(evt) = gamepad_release(&_evt) {
+                            // not to leave the controller of a former holder pressed
+                            self.input_gamepad(evt);
+                        }
                     }
                     self.update_auto_disconnect_timer();
                 }
@@ -1854,7 +2075,10 @@ impl Connection {
                 }
                 #[cfg(not(any(target_os = "android", target_os = "ios")))]
                 Some(message::Union::KeyEvent(me)) => {
-                    if self.peer_keyboard_enabled() {
+                    // releases pass, not to leave the keys of a former holder down
+                    if self.peer_keyboard_enabled()
+                        && (!(me.down || me.press) || self.acquire_input_control())
+                    {
                         if is_enter(&me) {
                             CLICK_TIME.store(get_time(), Ordering::SeqCst);
                         }
@@ -1891,6 +2115,7 @@ impl Connection {
                         }
 
                         if is_press {
+                            video_service::record_event("key", &key_event_detail(&me));
                             match me.union {
                                 Some(key_event::Union::Unicode(_))
                                 | Some(key_event::Union::Seq(_)) => {
@@ -1910,6 +2135,10 @@ impl Connection {
                 {
                     #[cfg(not(any(target_os = "android", target_os = "ios")))]
                     if self.clipboard {
+                        video_service::record_event(
+                            "clipboard",
+                            &format!("{:?}, {} bytes", _cb.format, _cb.content.len()),
+                        );
                         update_clipboard(_cb, None);
                     }
                 }
@@ -1918,11 +2147,15 @@ impl Connection {
                     #[cfg(any(target_os = "windows", target_os = "linux", target_os = "macos"))]
                     if let Some(clip) = msg_2_clip(_clip) {
                         log::debug!("got clipfile from client peer");
+                        video_service::record_event("clipboard", "files");
                         self.send_to_cm(ipc::Data::ClipboardFile(clip))
                     }
                 }
                 Some(message::Union::FileAction(fa)) => {
                     if self.file_transfer.is_some() {
+                        if let Some(detail) = file_action_detail(&fa) {
+                            video_service::record_event("file", &detail);
+                        }
                         match fa.union {
                             Some(file_action::Union::ReadDir(rd)) => {
                                 self.read_dir(&rd.path, rd.include_hidden);
@@ -2063,6 +2296,10 @@ impl Connection {
                 }
                 Some(message::Union::FileResponse(fr)) => match fr.union {
                     Some(file_response::Union::Block(block)) => {
+                        let len = block.data.len() as u64;
+                        metrics::update(Side::Controlled, self.inner.id(), |m| {
+                            m.file_bytes_received += len
+                        });
                         self.send_fs(ipc::FS::WriteBlock {
                             id: block.id,
                             file_num: block.file_num,
@@ -2100,7 +2337,8 @@ impl Connection {
                         let add = displays.add.iter().map(|d| *d as usize).collect::<Vec<_>>();
                         let sub = displays.sub.iter().map(|d| *d as usize).collect::<Vec<_>>();
                         let set = displays.set.iter().map(|d| *d as usize).collect::<Vec<_>>();
-                        self.capture_displays(&add, &sub, &set).await;
+                        self.capture_displays(&add, &sub, &set, displays.canvas)
+                            .await;
                     }
                     #[cfg(all(windows, feature = "virtual_display_driver"))]
                     Some(misc::Union::ToggleVirtualDisplay(t)) => {
@@ -2110,6 +2348,7 @@ impl Connection {
                         self.toggle_privacy_mode(t).await;
                     }
                     Some(misc::Union::ChatMessage(c)) => {
+                        video_service::record_event("chat", &format!("peer: {}", c.text));
                         self.send_to_cm(ipc::Data::ChatMessage { text: c.text });
                         self.chat_unanswered = true;
                         self.update_auto_disconnect_timer();
@@ -2129,6 +2368,23 @@ impl Connection {
                         self.refresh_video_display(Some(display as usize));
                         self.update_auto_disconnect_timer();
                     }
+                    Some(misc::Union::RequestInputControl(request)) => {
+                        if request {
+                            input_control::request(self.inner.id, &self.lr.my_name);
+                        } else {
+                            input_control::release(self.inner.id);
+                        }
+                    }
+                    Some(misc::Union::GrantInputControl(conn_id)) => {
+                        if input_control::is_holder(self.inner.id) {
+                            log::info!(
+                                "Input control granted to #{} by #{}",
+                                conn_id,
+                                self.inner.id
+                            );
+                            input_control::grant(conn_id);
+                        }
+                    }
                     Some(misc::Union::VideoReceived(_)) => {
                         video_service::notify_video_frame_fetched(
                             self.inner.id,
@@ -2175,7 +2431,14 @@ impl Connection {
                         if !self.disable_audio {
                             // Drop the audio sender previously.
                             drop(std::mem::replace(&mut self.audio_sender, None));
-                            self.audio_sender = Some(start_audio_thread());
+                            #[cfg(target_os = "linux")]
+                            if let Some(mic) = self.virtual_mic.as_ref() {
+                                self.audio_sender =
+                                    Some(start_audio_thread_to_device(mic.sink().to_owned()));
+                            }
+                            if self.audio_sender.is_none() {
+                                self.audio_sender = Some(start_audio_thread());
+                            }
                             self.audio_sender
                                 .as_ref()
                                 .map(|a| allow_err!(a.send(MediaData::AudioFormat(format))));
@@ -2342,7 +2605,13 @@ impl Connection {
         self.update_auto_disconnect_timer();
     }
 
-    async fn capture_displays(&mut self, add: &[usize], sub: &[usize], set: &[usize]) {
+    async fn capture_displays(
+        &mut self,
+        add: &[usize],
+        sub: &[usize],
+        set: &[usize],
+        canvas: bool,
+    ) {
         #[cfg(windows)]
         if portable_client::running() && (add.len() > 0 || set.len() > 1) {
             log::info!("Capturing multiple displays is not supported in the elevated mode.");
@@ -2359,6 +2628,18 @@ impl Connection {
             return;
         }
 
+        if canvas && set.len() > 1 && video_service::is_canvas_supported() {
+            if let Some(sever) = self.server.upgrade() {
+                video_service::set_canvas_displays(set);
+                let mut lock = sever.write().unwrap();
+                if !lock.contains(video_service::CANVAS_NAME) {
+                    lock.add_service(Box::new(video_service::new_canvas()));
+                }
+                lock.capture_canvas(self.inner.clone());
+            }
+            return;
+        }
+
         if let Some(sever) = self.server.upgrade() {
             let mut lock = sever.write().unwrap();
             for display in add.iter() {
@@ -2490,6 +2771,13 @@ impl Connection {
                 if let Some(device) = default_sound_device {
                     set_sound_input(device);
                 }
+                #[cfg(target_os = "linux")]
+                if !Config::get_option("allow-virtual-microphone").is_empty() {
+                    match crate::platform::linux::VirtualMic::new() {
+                        Ok(mic) => self.virtual_mic = Some(mic),
+                        Err(e) => log::error!("Failed to create the virtual microphone: {}", e),
+                    }
+                }
                 self.send_to_cm(Data::StartVoiceCall);
             } else {
                 self.send_to_cm(Data::CloseVoiceCall("".to_owned()));
@@ -2507,6 +2795,8 @@ impl Connection {
         {
             set_sound_input(sound_input);
         }
+        #[cfg(target_os = "linux")]
+        self.virtual_mic.take();
         // Notify the connection manager that the voice call has been closed.
         self.send_to_cm(Data::CloseVoiceCall("".to_owned()));
     }
@@ -2537,6 +2827,24 @@ impl Connection {
                 .unwrap()
                 .user_custom_fps(self.inner.id(), o.custom_fps as _);
         }
+        if let Ok(q) = o.text_mode.enum_value() {
+            if q != BoolOption::NotSet {
+                video_service::VIDEO_QOS
+                    .lock()
+                    .unwrap()
+                    .user_text_mode(self.inner.id(), q == BoolOption::Yes);
+            }
+        }
+        if let Some(r) = o.stream_resolution.as_ref() {
+            let r = (r.width > 0 && r.height > 0).then(|| (r.width as usize, r.height as usize));
+            video_service::VIDEO_QOS
+                .lock()
+                .unwrap()
+                .user_stream_resolution(self.inner.id(), r);
+        }
+        if let Some(q) = o.audio_quality.as_ref() {
+            super::audio_service::set_quality(self.inner.id(), Some(q.clone()));
+        }
         if let Some(q) = o.supported_decoding.clone().take() {
             scrap::codec::Encoder::update(self.inner.id(), scrap::codec::EncodingUpdate::New(q));
         }
@@ -2907,6 +3215,140 @@ impl Connection {
     }
 }
 
+// A click for the recording events.
+#[cfg(not(any(target_os = "android", target_os = "ios")))]
+fn mouse_event_detail(me: &MouseEvent) -> Option<String> {
+    use crate::input::*;
+    if me.mask & 0x7 != MOUSE_TYPE_DOWN {
+        return None;
+    }
+    let button = match me.mask >> 3 {
+        MOUSE_BUTTON_LEFT => "left",
+        MOUSE_BUTTON_RIGHT => "right",
+        MOUSE_BUTTON_WHEEL => "middle",
+        MOUSE_BUTTON_BACK => "back",
+        MOUSE_BUTTON_FORWARD => "forward",
+        _ => "other",
+    };
+    Some(format!("{} ({}, {})", button, me.x, me.y))
+}
+
+// A pressed key for the recording events. What is typed without a modifier
+// but shift, the locks and AltGr is text and is not kept, control keys and
+// shortcuts are.
+#[cfg(not(any(target_os = "android", target_os = "ios")))]
+fn key_event_detail(me: &KeyEvent) -> String {
+    let mut keys: Vec<String> = me
+        .modifiers
+        .iter()
+        .filter_map(|m| m.enum_value().ok())
+        .filter(|m| {
+            !matches!(
+                m,
+                ControlKey::Shift
+                    | ControlKey::RShift
+                    | ControlKey::CapsLock
+                    | ControlKey::NumLock
+                    | ControlKey::RAlt
+            )
+        })
+        .map(|m| format!("{:?}", m))
+        .collect();
+    let key = match &me.union {
+        Some(key_event::Union::ControlKey(ck)) => match ck.enum_value() {
+            Ok(k) if keys.is_empty() && is_text_control_key(k) => return "*".to_owned(),
+            Ok(k) => format!("{:?}", k),
+            Err(_) => Default::default(),
+        },
+        _ if keys.is_empty() => return "*".to_owned(),
+        Some(key_event::Union::Chr(c)) if me.mode.enum_value() == Ok(KeyboardMode::Legacy) => {
+            char::from_u32(*c).map_or(format!("{:#x}", c), |c| c.to_string())
+        }
+        Some(key_event::Union::Chr(c)) => format!("{:#x}", c),
+        Some(key_event::Union::Unicode(c)) => {
+            char::from_u32(*c).map_or(format!("{:#x}", c), |c| c.to_string())
+        }
+        Some(key_event::Union::Seq(seq)) => seq.clone(),
+        _ => return "*".to_owned(),
+    };
+    keys.push(key);
+    keys.join("+")
+}
+
+// The control keys that type text, the numpad and space.
+#[cfg(not(any(target_os = "android", target_os = "ios")))]
+fn is_text_control_key(key: ControlKey) -> bool {
+    matches!(
+        key,
+        ControlKey::Numpad0
+            | ControlKey::Numpad1
+            | ControlKey::Numpad2
+            | ControlKey::Numpad3
+            | ControlKey::Numpad4
+            | ControlKey::Numpad5
+            | ControlKey::Numpad6
+            | ControlKey::Numpad7
+            | ControlKey::Numpad8
+            | ControlKey::Numpad9
+            | ControlKey::Multiply
+            | ControlKey::Add
+            | ControlKey::Subtract
+            | ControlKey::Decimal
+            | ControlKey::Divide
+            | ControlKey::Separator
+            | ControlKey::Space
+    )
+}
+
+// The pen lifted out of the proximity, for a pen event of a peer without the
+// input control which presses nothing.
+#[cfg(not(any(target_os = "android", target_os = "ios")))]
+fn pen_release(pde: &PointerDeviceEvent) -> Option<PointerDeviceEvent> {
+    match &pde.union {
+        Some(pointer_device_event::Union::PenEvent(pen)) if !pen.down && pen.buttons == 0 => {
+            let mut pde = pde.clone();
+            pde.set_pen_event(PenEvent {
+                pressure: 0,
+                leave: true,
+                ..pen.clone()
+            });
+            Some(pde)
+        }
+        _ => None,
+    }
+}
+
+// The controller released with the sticks centered, for a gamepad event of a
+// peer without the input control which presses no button or trigger.
+#[cfg(target_os = "linux")]
+fn gamepad_release(evt: &GamepadEvent) -> Option<GamepadEvent> {
+    match &evt.union {
+        Some(gamepad_event::Union::Disconnected(_)) => Some(evt.clone()),
+        Some(gamepad_event::Union::State(state))
+            if state.buttons == 0 && state.left_trigger == 0 && state.right_trigger == 0 =>
+        {
+            Some(GamepadEvent {
+                union: Some(gamepad_event::Union::State(Default::default())),
+                ..evt.clone()
+            })
+        }
+        _ => None,
+    }
+}
+
+// A change to the files for the recording events, the peer downloads what is
+// sent and uploads what is received.
+fn file_action_detail(fa: &FileAction) -> Option<String> {
+    match &fa.union {
+        Some(file_action::Union::Send(s)) => Some(format!("download {}", s.path)),
+        Some(file_action::Union::Receive(r)) => Some(format!("upload {}", r.path)),
+        Some(file_action::Union::RemoveDir(d)) => Some(format!("remove dir {}", d.path)),
+        Some(file_action::Union::RemoveFile(f)) => Some(format!("remove file {}", f.path)),
+        Some(file_action::Union::Create(c)) => Some(format!("create dir {}", c.path)),
+        _ => None,
+    }
+}
+
 pub fn insert_switch_sides_uuid(id: String, uuid: uuid::Uuid) {
     SWITCH_SIDES_UUID
         .lock()
@@ -3256,6 +3698,50 @@ impl Drop for Connection {
     fn drop(&mut self) {
         #[cfg(not(any(target_os = "android", target_os = "ios")))]
         self.release_pressed_modifiers();
+        if !self.migration_token.is_empty() {
+            MIGRATION_TARGETS
+                .lock()
+                .unwrap()
+                .remove(&self.migration_token);
+        }
+        metrics::remove(Side::Controlled, self.inner.id());
+    }
+}
+
+/// Hand a new transport over to the running session it was issued for.
+pub async fn accept_transport_migration(mut stream: super::Stream) -> ResultType<()> {
+    if !stream.is_secured() {
+        bail!("Transport migration requires a secure connection");
+    }
+    let Some(res) = timeout(CONNECT_TIMEOUT, stream.next()).await? else {
+        bail!("Failed to receive transport migration request");
+    };
+    let msg_in = Message::parse_from_bytes(&res?)?;
+    let token = match msg_in.union {
+        Some(message::Union::Misc(Misc {
+            union: Some(misc::Union::TransportMigration(tm)),
+            ..
+        })) => tm.token,
+        _ => bail!("Invalid transport migration request"),
+    };
+    let tx = MIGRATION_TARGETS.lock().unwrap().get(&token[..]).cloned();
+    match tx {
+        Some(tx) => {
+            if tx.send(stream).is_err() {
+                bail!("Session of the transport migration is closed");
+            }
+            Ok(())
+        }
+        None => bail!("Unknown transport migration token"),
+    }
+}
+
+async fn next_of_old_stream(
+    old_stream: &mut Option<super::Stream>,
+) -> Option<std::io::Result<hbb_common::bytes::BytesMut>> {
+    match old_stream.as_mut() {
+        Some(stream) => stream.next().await,
+        None => std::future::pending().await,
     }
 }
 
@@ -3333,6 +3819,8 @@ mod raii {
                 .lock()
                 .unwrap()
                 .on_connection_close(self.0);
+            audio_service::set_quality(self.0, None);
+            input_control::remove(self.0);
         }
     }
 
@@ -3391,3 +3879,56 @@ mod raii {
         }
     }
 }
+
+#[cfg(test)]
+#[cfg(not(any(target_os = "android", target_os = "ios")))]
+mod tests {
+    use super::*;
+
+    fn key(union: key_event::Union, modifiers: &[ControlKey]) -> KeyEvent {
+        KeyEvent {
+            union: Some(union),
+            modifiers: modifiers.iter().map(|m| (*m).into()).collect(),
+            mode: KeyboardMode::Legacy.into(),
+            ..Default::default()
+        }
+    }
+
+    #[test]
+    fn test_key_event_detail() {
+        let chr = |c: char| key_event::Union::Chr(c as u32);
+        let ck = |k: ControlKey| key_event::Union::ControlKey(k.into());
+        // text, whatever shift, the locks and AltGr
+        for modifiers in [
+            &[][..],
+            &[ControlKey::Shift],
+            &[ControlKey::CapsLock],
+            &[ControlKey::NumLock],
+            &[ControlKey::RAlt],
+            &[ControlKey::RShift, ControlKey::CapsLock, ControlKey::RAlt],
+        ] {
+            assert_eq!(key_event_detail(&key(chr('a'), modifiers)), "*");
+            assert_eq!(
+                key_event_detail(&key(ck(ControlKey::Numpad7), modifiers)),
+                "*"
+            );
+            assert_eq!(
+                key_event_detail(&key(ck(ControlKey::Decimal), modifiers)),
+                "*"
+            );
+        }
+        // control keys and shortcuts
+        assert_eq!(
+            key_event_detail(&key(ck(ControlKey::Return), &[ControlKey::NumLock])),
+            "Return"
+        );
+        assert_eq!(
+            key_event_detail(&key(chr('c'), &[ControlKey::Control, ControlKey::CapsLock])),
+            "Control+c"
+        );
+        assert_eq!(
+            key_event_detail(&key(ck(ControlKey::Numpad1), &[ControlKey::Alt])),
+            "Alt+Numpad1"
+        );
+    }
+}
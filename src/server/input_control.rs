@@ -0,0 +1,178 @@
+//! The input control token of "allow-exclusive-input-control": only the
+//! connection holding it sends input, the others are view-only until the
+//! holder or the local user grants it to them.
+
+use hbb_common::{config::Config, tokio::sync::broadcast};
+use std::sync::Mutex;
+
+const OPTION: &str = "allow-exclusive-input-control";
+
+lazy_static::lazy_static! {
+    static ref STATE: Mutex<State> = Default::default();
+    static ref SENDER: broadcast::Sender<()> = broadcast::channel(16).0;
+}
+
+#[derive(Debug, Default, Clone, PartialEq)]
+struct State {
+    // (conn id, peer name)
+    holder: Option<(i32, String)>,
+    requests: Vec<(i32, String)>,
+}
+
+impl State {
+    // The first one sending input takes a free token.
+    fn acquire(&mut self, id: i32, name: &str) -> bool {
+        if self.holder.is_none() {
+            self.requests.retain(|r| r.0 != id);
+            self.holder = Some((id, name.to_owned()));
+        }
+        self.is_holder(id)
+    }
+
+    fn request(&mut self, id: i32, name: &str) {
+        if !self.acquire(id, name) && !self.requests.iter().any(|r| r.0 == id) {
+            self.requests.push((id, name.to_owned()));
+        }
+    }
+
+    fn grant(&mut self, id: i32) {
+        if let Some(pos) = self.requests.iter().position(|r| r.0 == id) {
+            self.holder = Some(self.requests.remove(pos));
+        }
+    }
+
+    // The token goes to the oldest request, if any.
+    fn release(&mut self, id: i32) {
+        if self.is_holder(id) {
+            self.holder = if self.requests.is_empty() {
+                None
+            } else {
+                Some(self.requests.remove(0))
+            };
+        }
+    }
+
+    fn remove(&mut self, id: i32) {
+        self.release(id);
+        self.requests.retain(|r| r.0 != id);
+    }
+
+    fn is_holder(&self, id: i32) -> bool {
+        self.holder.as_ref().map(|h| h.0) == Some(id)
+    }
+}
+
+/// What a connection tells its peer and the connection manager.
+#[derive(Debug, Default, Clone, PartialEq)]
+pub struct View {
+    pub holder: String,
+    pub holding: bool,
+    pub requesting: bool,
+    // the oldest request, only for the holder
+    pub requester: Option<(i32, String)>,
+}
+
+#[inline]
+pub fn is_enabled() -> bool {
+    Config::get_option(OPTION) == "Y"
+}
+
+pub fn subscribe() -> broadcast::Receiver<()> {
+    SENDER.subscribe()
+}
+
+fn update(f: impl FnOnce(&mut State)) {
+    let mut state = STATE.lock().unwrap();
+    let old = state.clone();
+    f(&mut state);
+    if *state != old {
+        SENDER.send(()).ok();
+    }
+}
+
+/// Whether the input of the connection is allowed, taking the token if free.
+pub fn acquire(id: i32, name: &str) -> bool {
+    if !is_enabled() {
+        return true;
+    }
+    let mut allowed = false;
+    update(|s| allowed = s.acquire(id, name));
+    allowed
+}
+
+pub fn request(id: i32, name: &str) {
+    update(|s| s.request(id, name));
+}
+
+/// Hands the token to a connection which asked for it.
+pub fn grant(id: i32) {
+    update(|s| s.grant(id));
+}
+
+pub fn release(id: i32) {
+    update(|s| s.release(id));
+}
+
+pub fn remove(id: i32) {
+    update(|s| s.remove(id));
+}
+
+pub fn is_holder(id: i32) -> bool {
+    STATE.lock().unwrap().is_holder(id)
+}
+
+pub fn view(id: i32) -> View {
+    let state = STATE.lock().unwrap();
+    let holding = state.is_holder(id);
+    View {
+        holder: state
+            .holder
+            .as_ref()
+            .map(|h| h.1.clone())
+            .unwrap_or_default(),
+        holding,
+        requesting: state.requests.iter().any(|r| r.0 == id),
+        requester: if holding {
+            state.requests.first().cloned()
+        } else {
+            None
+        },
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_arbitration() {
+        let mut s = State::default();
+        assert!(s.acquire(1, "a"));
+        assert!(!s.acquire(2, "b"));
+        s.request(2, "b");
+        s.request(3, "c");
+        s.request(2, "b");
+        assert_eq!(s.requests.len(), 2);
+
+        s.grant(3);
+        assert!(s.is_holder(3));
+        assert_eq!(s.requests, vec![(2, "b".to_owned())]);
+        // granting twice or to one not asking changes nothing
+        s.grant(3);
+        s.grant(1);
+        assert!(s.is_holder(3));
+
+        s.release(2);
+        assert!(s.is_holder(3));
+        s.release(3);
+        assert!(s.is_holder(2));
+        assert!(s.requests.is_empty());
+
+        s.request(1, "a");
+        s.remove(1);
+        assert!(s.requests.is_empty());
+        s.remove(2);
+        assert_eq!(s.holder, None);
+        assert!(s.acquire(1, "a"));
+    }
+}
@@ -292,6 +292,7 @@ fn run_pos(sp: EmptyExtraFieldService, state: &mut StatePos) -> ResultType<()> {
     }
 
     if state.is_moved(x, y) {
+        let (x, y) = super::video_service::map_cursor_position(x, y);
         let mut msg_out = Message::new();
         msg_out.set_cursor_position(CursorPosition {
             x,
@@ -312,10 +313,12 @@ fn run_pos(sp: EmptyExtraFieldService, state: &mut StatePos) -> ResultType<()> {
     state.cursor_pos = (x, y);
 
     sp.snapshot(|sps| {
+        let (x, y) =
+            super::video_service::map_cursor_position(state.cursor_pos.0, state.cursor_pos.1);
         let mut msg_out = Message::new();
         msg_out.set_cursor_position(CursorPosition {
-            x: state.cursor_pos.0,
-            y: state.cursor_pos.1,
+            x,
+            y,
             ..Default::default()
         });
         sps.send(msg_out);
@@ -588,6 +591,37 @@ pub fn handle_pointer(evt: &PointerDeviceEvent, conn: i32) {
     handle_pointer_(evt, conn);
 }
 
+#[cfg(target_os = "linux")]
+const MAX_GAMEPADS: usize = 4;
+
+/// Replays `evt` on a uinput gamepad of its index, created by its first state,
+/// and passes the rumble of it to `on_rumble`.
+#[cfg(target_os = "linux")]
+pub fn handle_gamepad(
+    gamepads: &mut HashMap<u32, super::uinput::client::UInputGamepad>,
+    evt: &GamepadEvent,
+    on_rumble: impl FnMut(GamepadRumble) + Send + 'static,
+) {
+    match &evt.union {
+        Some(gamepad_event::Union::State(state)) => {
+            if !gamepads.contains_key(&evt.index) {
+                if gamepads.len() >= MAX_GAMEPADS {
+                    return;
+                }
+                let gamepad = super::uinput::client::UInputGamepad::new(Box::new(on_rumble));
+                gamepads.insert(evt.index, gamepad);
+            }
+            if let Some(gamepad) = gamepads.get(&evt.index) {
+                allow_err!(gamepad.send_state(state));
+            }
+        }
+        Some(gamepad_event::Union::Disconnected(_)) => {
+            gamepads.remove(&evt.index);
+        }
+        _ => {}
+    }
+}
+
 pub fn fix_key_down_timeout_loop() {
     std::thread::spawn(move || loop {
         std::thread::sleep(std::time::Duration::from_millis(10_000));
@@ -829,10 +863,112 @@ pub fn handle_pointer_(evt: &PointerDeviceEvent, conn: i32) {
             }
             _ => {}
         },
+        Some(pointer_device_event::Union::PenEvent(_pen_evt)) => {
+            #[cfg(target_os = "linux")]
+            handle_pen(_pen_evt);
+        }
         _ => {}
     }
 }
 
+#[cfg(target_os = "linux")]
+const PEN_RETRY_INTERVAL: Duration = Duration::from_secs(3);
+
+// The uinput pen, when it failed to be created, and the area of the screens
+// it covers on X11.
+#[cfg(target_os = "linux")]
+#[derive(Default)]
+struct UInputPenState {
+    pen: Option<super::uinput::client::UInputPen>,
+    failed: Option<Instant>,
+    area: Option<(i32, i32, i32, i32)>,
+}
+
+#[cfg(target_os = "linux")]
+lazy_static::lazy_static! {
+    static ref UINPUT_PEN: Mutex<UInputPenState> = Default::default();
+}
+
+#[cfg(target_os = "linux")]
+fn handle_pen(evt: &PenEvent) {
+    let mut state = UINPUT_PEN.lock().unwrap();
+    // Wayland has set the resolution for the uinput mouse, which the pen
+    // follows in the uinput service.
+    let area = if crate::platform::linux::is_x11() {
+        match screens_area() {
+            Some(area) => Some(area),
+            None => return,
+        }
+    } else {
+        None
+    };
+    // the tablet is made again for the new screens
+    if state.pen.is_some() && area != state.area {
+        log::info!("Screens changed, recreate uinput pen");
+        state.pen = None;
+        state.failed = None;
+    }
+    if state.pen.is_none() {
+        if state
+            .failed
+            .map_or(false, |t| t.elapsed() < PEN_RETRY_INTERVAL)
+        {
+            return;
+        }
+        match super::uinput::client::UInputPen::new(area) {
+            Ok(p) => {
+                state.pen = Some(p);
+                state.area = area;
+            }
+            Err(e) => {
+                log::error!("Failed to create uinput pen: {}", e);
+                state.failed = Some(Instant::now());
+                return;
+            }
+        }
+    }
+    // the peer sees the scaled displays, as for the mouse
+    let (x, y) = super::video_service::map_input_position(evt.x, evt.y);
+    let evt = PenEvent {
+        x,
+        y,
+        ..evt.clone()
+    };
+    if let Some(p) = state.pen.as_mut() {
+        if let Err(e) = p.send(&evt) {
+            log::error!("Failed to send pen event: {}", e);
+            state.pen = None;
+        }
+    }
+}
+
+// The area covering the screens on X11, `(minx, maxx, miny, maxy)`.
+#[cfg(target_os = "linux")]
+fn screens_area() -> Option<(i32, i32, i32, i32)> {
+    let mut rects: Vec<_> = super::display_service::get_sync_displays()
+        .iter()
+        .map(|d| (d.x, d.y, d.width, d.height))
+        .collect();
+    if rects.is_empty() {
+        rects = super::display_service::try_get_displays()
+            .ok()?
+            .iter()
+            .map(|d| (d.origin().0, d.origin().1, d.width() as _, d.height() as _))
+            .collect();
+    }
+    let (mut minx, mut maxx, mut miny, mut maxy) = (i32::MAX, i32::MIN, i32::MAX, i32::MIN);
+    for (x, y, w, h) in rects {
+        minx = minx.min(x);
+        maxx = maxx.max(x + w);
+        miny = miny.min(y);
+        maxy = maxy.max(y + h);
+    }
+    if minx >= maxx || miny >= maxy {
+        return None;
+    }
+    Some((minx, maxx, miny, maxy))
+}
+
 pub fn handle_mouse_(evt: &MouseEvent, conn: i32) {
     if !active_mouse_(conn) {
         return;
@@ -846,6 +982,14 @@ pub fn handle_mouse_(evt: &MouseEvent, conn: i32) {
     crate::platform::windows::try_change_desktop();
     let buttons = evt.mask >> 3;
     let evt_type = evt.mask & 0x7;
+    // the pointer is kept in the shared window, the clicks must land there too
+    if matches!(
+        evt_type,
+        MOUSE_TYPE_DOWN | MOUSE_TYPE_WHEEL | MOUSE_TYPE_TRACKPAD
+    ) && !super::video_service::focus_capture_window()
+    {
+        return;
+    }
     let mut en = ENIGO.lock().unwrap();
     #[cfg(not(target_os = "macos"))]
     let mut to_release = Vec::new();
@@ -871,12 +1015,13 @@ pub fn handle_mouse_(evt: &MouseEvent, conn: i32) {
     }
     match evt_type {
         MOUSE_TYPE_MOVE => {
-            en.mouse_move_to(evt.x, evt.y);
+            let (x, y) = super::video_service::map_input_position(evt.x, evt.y);
+            en.mouse_move_to(x, y);
             *LATEST_PEER_INPUT_CURSOR.lock().unwrap() = Input {
                 conn,
                 time: get_time(),
-                x: evt.x,
-                y: evt.y,
+                x,
+                y,
             };
         }
         MOUSE_TYPE_DOWN => match buttons {
@@ -1511,6 +1656,10 @@ pub fn handle_key_(evt: &KeyEvent) {
     if EXITING.load(Ordering::SeqCst) {
         return;
     }
+    // a release always passes, so that no key is left down
+    if (evt.down || evt.press) && !super::video_service::focus_capture_window() {
+        return;
+    }
 
     #[cfg(not(any(target_os = "android", target_os = "ios")))]
     let mut _lock_mode_handler = None;
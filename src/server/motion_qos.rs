@@ -0,0 +1,178 @@
+// Content motion for the video QoS.
+//
+// The part of the screen each captured frame changes tells static documents,
+// typing, scrolling and video apart. Each of them gets its own trade between
+// fps and quantizer, on top of what the network delay allows.
+
+use scrap::codec::Quality;
+use std::{
+    collections::VecDeque,
+    time::{Duration, Instant},
+};
+
+// A frame changing less than this part of the screen is an edit, e.g. typing.
+const SMALL_CHANGE: f32 = 0.05;
+// The frames the classification is made over.
+const WINDOW: Duration = Duration::from_secs(1);
+const MIN_SAMPLES: usize = 5;
+// Part of the frames in the window with large changes to enter and to leave
+// the busy state, apart to avoid flapping.
+const BUSY_ENTER: f32 = 0.6;
+const BUSY_LEAVE: f32 = 0.3;
+// No change for this long is static content.
+const STATIC_AFTER: Duration = Duration::from_secs(1);
+// Busy for this long is video, shorter is scrolling.
+const VIDEO_AFTER: Duration = Duration::from_secs(3);
+
+#[derive(Debug, Default, Clone, Copy, PartialEq, Eq, PartialOrd, Ord)]
+pub enum Motion {
+    Static,
+    #[default]
+    Edit,
+    Scroll,
+    Video,
+}
+
+#[derive(Default)]
+pub struct MotionDetector {
+    state: Motion,
+    // (time, changed part) of the captured frames in the window
+    samples: VecDeque<(Instant, f32)>,
+    last_change: Option<Instant>,
+    busy_since: Option<Instant>,
+}
+
+impl MotionDetector {
+    pub fn new() -> Self {
+        Default::default()
+    }
+
+    pub fn state(&self) -> Motion {
+        self.state
+    }
+
+    /// Feed a captured frame, `changed` is the part of the screen it changed,
+    /// 0 for an unchanged frame. Returns the new state when it changes.
+    pub fn on_frame(&mut self, now: Instant, changed: f32) -> Option<Motion> {
+        self.samples.push_back((now, changed));
+        while let Some((t, _)) = self.samples.front() {
+            if now.duration_since(*t) <= WINDOW {
+                break;
+            }
+            self.samples.pop_front();
+        }
+        if changed > 0. {
+            self.last_change = Some(now);
+        }
+
+        let large = self.samples.iter().filter(|s| s.1 >= SMALL_CHANGE).count();
+        let part = large as f32 / self.samples.len() as f32;
+        let busy = self.samples.len() >= MIN_SAMPLES
+            && if self.busy_since.is_some() {
+                part >= BUSY_LEAVE
+            } else {
+                part >= BUSY_ENTER
+            };
+        if !busy {
+            self.busy_since = None;
+        } else if self.busy_since.is_none() {
+            self.busy_since = Some(now);
+        }
+
+        let state = match (self.last_change, self.busy_since) {
+            (None, _) => Motion::Static,
+            (Some(t), _) if now.duration_since(t) >= STATIC_AFTER => Motion::Static,
+            (_, Some(t)) if now.duration_since(t) >= VIDEO_AFTER => Motion::Video,
+            (_, Some(_)) => Motion::Scroll,
+            (_, None) => Motion::Edit,
+        };
+        if state == self.state {
+            return None;
+        }
+        self.state = state;
+        Some(state)
+    }
+}
+
+/// The fps and quality for `motion`, from those the network allows.
+///
+/// Static content is polled slower and refined with more bits. Scrolling
+/// keeps the fps and takes a coarser quantizer, its frames are gone at once.
+/// Video is capped at the fps it is usually made with and keeps most bits.
+pub fn adjust(motion: Motion, fps: u32, quality: Quality) -> (u32, Quality) {
+    let (fps, ratio) = match motion {
+        Motion::Static => ((fps / 2).max(super::video_qos::MIN_FPS), 150),
+        Motion::Edit => return (fps, quality),
+        Motion::Scroll => (fps, 50),
+        Motion::Video => (fps.min(30), 75),
+    };
+    // as percentages of the base bitrate, see `VpxEncoder::convert_quality`
+    let b = match quality {
+        Quality::Best => 150,
+        Quality::Balanced => 66,
+        Quality::Low => 50,
+        Quality::Custom(b) => b,
+    };
+    (fps, Quality::Custom((b * ratio / 100).clamp(10, 200)))
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_motion() {
+        let spf = Duration::from_millis(33);
+        let mut d = MotionDetector::new();
+        let mut t = Instant::now();
+        let mut feed = |d: &mut MotionDetector, n: usize, changed: f32| {
+            for _ in 0..n {
+                t += spf;
+                d.on_frame(t, changed);
+            }
+        };
+
+        // typing
+        feed(&mut d, 30, 0.01);
+        assert_eq!(d.state(), Motion::Edit);
+        // idle
+        feed(&mut d, 40, 0.);
+        assert_eq!(d.state(), Motion::Static);
+        // a scroll
+        feed(&mut d, 20, 0.4);
+        assert_eq!(d.state(), Motion::Scroll);
+        // it goes on, a video
+        feed(&mut d, 100, 0.3);
+        assert_eq!(d.state(), Motion::Video);
+        // with some still frames in between
+        for _ in 0..10 {
+            feed(&mut d, 2, 0.3);
+            feed(&mut d, 1, 0.);
+        }
+        assert_eq!(d.state(), Motion::Video);
+        // paused
+        feed(&mut d, 40, 0.);
+        assert_eq!(d.state(), Motion::Static);
+    }
+
+    #[test]
+    fn test_adjust() {
+        assert_eq!(
+            adjust(Motion::Edit, 30, Quality::Balanced),
+            (30, Quality::Balanced)
+        );
+        assert_eq!(
+            adjust(Motion::Static, 30, Quality::Balanced),
+            (15, Quality::Custom(99))
+        );
+        assert_eq!(
+            adjust(Motion::Scroll, 60, Quality::Custom(100)),
+            (60, Quality::Custom(50))
+        );
+        assert_eq!(
+            adjust(Motion::Video, 60, Quality::Low),
+            (30, Quality::Custom(37))
+        );
+        assert_eq!(adjust(Motion::Static, 1, Quality::Best).0, 1);
+    }
+}
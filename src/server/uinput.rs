@@ -4,13 +4,27 @@ use evdev::{
     uinput::{VirtualDevice, VirtualDeviceBuilder},
     AttributeSet, EventType, InputEvent,
 };
-use hbb_common::{allow_err, bail, log, tokio::{self, runtime::Runtime}, ResultType};
+use hbb_common::{
+    allow_err,
+    anyhow::anyhow,
+    bail, log,
+    message_proto::{GamepadRumble, GamepadState, PenEvent},
+    protobuf::Message as _,
+    tokio::{self, runtime::Runtime},
+    ResultType,
+};
 
 static IPC_CONN_TIMEOUT: u64 = 1000;
 static IPC_REQUEST_TIMEOUT: u64 = 1000;
 static IPC_POSTFIX_KEYBOARD: &str = "_uinput_keyboard";
 static IPC_POSTFIX_MOUSE: &str = "_uinput_mouse";
 static IPC_POSTFIX_CONTROL: &str = "_uinput_control";
+static IPC_POSTFIX_GAMEPAD: &str = "_uinput_gamepad";
+static IPC_POSTFIX_PEN: &str = "_uinput_pen";
+
+mod gamepad;
+mod pen;
+mod raw;
 
 pub mod client {
     use super::*;
@@ -166,6 +180,83 @@ pub mod client {
         let _ = conn.next().await?;
         Ok(())
     }
+
+    pub struct UInputPen {
+        conn: Connection,
+        rt: Runtime,
+    }
+
+    impl UInputPen {
+        /// `resolution` is the area of the screens to set first if any.
+        pub fn new(resolution: Option<(i32, i32, i32, i32)>) -> ResultType<Self> {
+            let rt = Runtime::new()?;
+            if let Some((minx, maxx, miny, maxy)) = resolution {
+                rt.block_on(set_resolution(minx, maxx, miny, maxy))?;
+            }
+            let conn = rt.block_on(ipc::connect(IPC_CONN_TIMEOUT, IPC_POSTFIX_PEN))?;
+            Ok(Self { conn, rt })
+        }
+
+        pub fn send(&mut self, evt: &PenEvent) -> ResultType<()> {
+            let evt = evt.write_to_bytes()?;
+            self.rt.block_on(self.conn.send(&Data::Pen(evt)))
+        }
+    }
+
+    /// A gamepad of the uinput service. The connection runs in a thread to
+    /// pass the rumble back, which ends when this is dropped.
+    pub struct UInputGamepad {
+        tx: tokio::sync::mpsc::UnboundedSender<Data>,
+    }
+
+    impl UInputGamepad {
+        pub fn new(on_rumble: Box<dyn FnMut(GamepadRumble) + Send>) -> Self {
+            let (tx, rx) = tokio::sync::mpsc::unbounded_channel();
+            std::thread::spawn(move || run_gamepad(rx, on_rumble));
+            Self { tx }
+        }
+
+        pub fn send_state(&self, state: &GamepadState) -> ResultType<()> {
+            let state = state.write_to_bytes()?;
+            self.tx
+                .send(Data::Gamepad(ipc::DataGamepad::State(state)))
+                .map_err(|e| anyhow!("{}", e))
+        }
+    }
+
+    #[tokio::main(flavor = "current_thread")]
+    async fn run_gamepad(
+        mut rx: tokio::sync::mpsc::UnboundedReceiver<Data>,
+        mut on_rumble: Box<dyn FnMut(GamepadRumble) + Send>,
+    ) {
+        let mut conn = match ipc::connect(IPC_CONN_TIMEOUT, IPC_POSTFIX_GAMEPAD).await {
+            Ok(conn) => conn,
+            Err(e) => {
+                log::error!("Failed to connect uinput gamepad service: {}", e);
+                return;
+            }
+        };
+        loop {
+            tokio::select! {
+                data = rx.recv() => match data {
+                    Some(data) => allow_err!(conn.send(&data).await),
+                    None => break,
+                },
+                res = conn.next() => match res {
+                    Err(err) => {
+                        log::info!("UInput gamepad ipc connection closed: {}", err);
+                        break;
+                    }
+                    Ok(Some(Data::Gamepad(ipc::DataGamepad::Rumble(rumble)))) => {
+                        if let Ok(rumble) = GamepadRumble::parse_from_bytes(&rumble) {
+                            on_rumble(rumble);
+                        }
+                    }
+                    _ => {}
+                },
+            }
+        }
+    }
 }
 
 pub mod service {
@@ -708,6 +799,129 @@ pub mod service {
         });
     }
 
+    fn spawn_gamepad_handler(mut stream: ipc::Connection) {
+        tokio::spawn(async move {
+            let gamepad = match gamepad::VirtualGamepad::new() {
+                Ok(gamepad) => gamepad,
+                Err(e) => {
+                    log::error!("Failed to create gamepad, {}", e);
+                    return;
+                }
+            };
+            let gamepad = match tokio::io::unix::AsyncFd::new(gamepad) {
+                Ok(gamepad) => gamepad,
+                Err(e) => {
+                    log::error!("Failed to poll gamepad, {}", e);
+                    return;
+                }
+            };
+            let mut effects = gamepad::Effects::default();
+            loop {
+                tokio::select! {
+                    res = stream.next() => {
+                        match res {
+                            Err(err) => {
+                                log::info!("UInput gamepad ipc connection closed: {}", err);
+                                break;
+                            }
+                            Ok(Some(Data::Gamepad(ipc::DataGamepad::State(state)))) => {
+                                if let Ok(state) = GamepadState::parse_from_bytes(&state) {
+                                    allow_err!(gamepad.get_ref().set_state(&state));
+                                }
+                            }
+                            _ => {}
+                        }
+                    }
+                    res = gamepad.readable() => {
+                        let mut guard = match res {
+                            Ok(guard) => guard,
+                            Err(_) => break,
+                        };
+                        let rumbles = match guard.get_inner().process(&mut effects) {
+                            Ok(rumbles) => rumbles,
+                            Err(e) => {
+                                log::error!("Failed to read gamepad, {}", e);
+                                break;
+                            }
+                        };
+                        guard.clear_ready();
+                        for rumble in rumbles {
+                            if let Ok(rumble) = rumble.write_to_bytes() {
+                                let data = Data::Gamepad(ipc::DataGamepad::Rumble(rumble));
+                                allow_err!(stream.send(&data).await);
+                            }
+                        }
+                    }
+                }
+            }
+        });
+    }
+
+    fn spawn_pen_handler(mut stream: ipc::Connection) {
+        let resolution = RESOLUTION.lock().unwrap();
+        if resolution.0 .0 == resolution.0 .1 || resolution.1 .0 == resolution.1 .1 {
+            return;
+        }
+        let mut rng_x = resolution.0.clone();
+        let mut rng_y = resolution.1.clone();
+        tokio::spawn(async move {
+            log::info!(
+                "Create uinput pen with rng_x: ({}, {}), rng_y: ({}, {})",
+                rng_x.0,
+                rng_x.1,
+                rng_y.0,
+                rng_y.1
+            );
+            let mut pen = match pen::VirtualPen::new(rng_x, rng_y) {
+                Ok(pen) => pen,
+                Err(e) => {
+                    log::error!("Failed to create pen, {}", e);
+                    return;
+                }
+            };
+            loop {
+                tokio::select! {
+                    res = stream.next() => {
+                        match res {
+                            Err(err) => {
+                                log::info!("UInput pen ipc connection closed: {}", err);
+                                break;
+                            }
+                            Ok(Some(Data::Pen(evt))) => {
+                                // the screens changed, the tablet covers the new ones
+                                let resolution = RESOLUTION.lock().unwrap().clone();
+                                if resolution != (rng_x, rng_y)
+                                    && resolution.0 .0 != resolution.0 .1
+                                    && resolution.1 .0 != resolution.1 .1
+                                {
+                                    (rng_x, rng_y) = resolution;
+                                    log::info!(
+                                        "Refresh uinput pen with rng_x: ({}, {}), rng_y: ({}, {})",
+                                        rng_x.0,
+                                        rng_x.1,
+                                        rng_y.0,
+                                        rng_y.1
+                                    );
+                                    pen = match pen::VirtualPen::new(rng_x, rng_y) {
+                                        Ok(pen) => pen,
+                                        Err(e) => {
+                                            log::error!("Failed to create pen, {}", e);
+                                            break;
+                                        }
+                                    };
+                                }
+                                if let Ok(evt) = PenEvent::parse_from_bytes(&evt) {
+                                    allow_err!(pen.send(&evt));
+                                }
+                            }
+                            _ => {}
+                        }
+                    }
+                }
+            }
+        });
+    }
+
     /// Start uinput service.
     async fn start_service<F: FnOnce(ipc::Connection) + Copy>(postfix: &str, handler: F) {
         match new_listener(postfix).await {
@@ -751,6 +965,20 @@ pub mod service {
         start_service(IPC_POSTFIX_CONTROL, spawn_controller_handler).await;
     }
 
+    /// Start uinput gamepad service.
+    #[tokio::main(flavor = "current_thread")]
+    pub async fn start_service_gamepad() {
+        log::info!("start uinput gamepad service");
+        start_service(IPC_POSTFIX_GAMEPAD, spawn_gamepad_handler).await;
+    }
+
+    /// Start uinput pen service.
+    #[tokio::main(flavor = "current_thread")]
+    pub async fn start_service_pen() {
+        log::info!("start uinput pen service");
+        start_service(IPC_POSTFIX_PEN, spawn_pen_handler).await;
+    }
+
     pub fn stop_service_keyboard() {
         log::info!("stop uinput keyboard service");
     }
@@ -760,4 +988,10 @@ pub mod service {
     pub fn stop_service_control() {
         log::info!("stop uinput control service");
     }
+    pub fn stop_service_gamepad() {
+        log::info!("stop uinput gamepad service");
+    }
+    pub fn stop_service_pen() {
+        log::info!("stop uinput pen service");
+    }
 }
@@ -0,0 +1,293 @@
+//! A virtual gamepad in the layout of an Xbox 360 controller, so that games
+//! and SDL take it without a mapping, with the rumble of force feedback.
+
+use super::raw::{
+    self, Device, InputAbsinfo, EV_ABS, EV_FF, EV_KEY, EV_UINPUT, IOC_READ, IOC_WRITE,
+};
+use crate::common::input::*;
+use evdev::{AbsoluteAxisType, Key};
+use hbb_common::{
+    libc,
+    message_proto::{GamepadRumble, GamepadState},
+    ResultType,
+};
+use std::{
+    collections::HashMap,
+    io, mem,
+    os::unix::io::{AsRawFd, RawFd},
+};
+
+const UI_FF_UPLOAD: u16 = 1;
+const UI_FF_ERASE: u16 = 2;
+const FF_RUMBLE: u16 = 0x50;
+const FF_GAIN: u16 = 0x60;
+const FF_GAIN_MAX: u32 = 0xFFFF;
+const FF_EFFECTS_MAX: u32 = 16;
+
+// Microsoft X-Box 360 pad
+const VENDOR: u16 = 0x045e;
+const PRODUCT: u16 = 0x028e;
+
+const BUTTONS: &[(u32, Key)] = &[
+    (GAMEPAD_BUTTON_A, Key::BTN_SOUTH),
+    (GAMEPAD_BUTTON_B, Key::BTN_EAST),
+    (GAMEPAD_BUTTON_X, Key::BTN_NORTH),
+    (GAMEPAD_BUTTON_Y, Key::BTN_WEST),
+    (GAMEPAD_BUTTON_LEFT_SHOULDER, Key::BTN_TL),
+    (GAMEPAD_BUTTON_RIGHT_SHOULDER, Key::BTN_TR),
+    (GAMEPAD_BUTTON_BACK, Key::BTN_SELECT),
+    (GAMEPAD_BUTTON_START, Key::BTN_START),
+    (GAMEPAD_BUTTON_GUIDE, Key::BTN_MODE),
+    (GAMEPAD_BUTTON_LEFT_THUMB, Key::BTN_THUMBL),
+    (GAMEPAD_BUTTON_RIGHT_THUMB, Key::BTN_THUMBR),
+];
+
+// (axis, min, max, fuzz, flat) as xpad reports them
+const AXES: &[(AbsoluteAxisType, i32, i32, i32, i32)] = &[
+    (AbsoluteAxisType::ABS_X, -32768, 32767, 16, 128),
+    (AbsoluteAxisType::ABS_Y, -32768, 32767, 16, 128),
+    (AbsoluteAxisType::ABS_RX, -32768, 32767, 16, 128),
+    (AbsoluteAxisType::ABS_RY, -32768, 32767, 16, 128),
+    (AbsoluteAxisType::ABS_Z, 0, 255, 0, 0),
+    (AbsoluteAxisType::ABS_RZ, 0, 255, 0, 0),
+    (AbsoluteAxisType::ABS_HAT0X, -1, 1, 0, 0),
+    (AbsoluteAxisType::ABS_HAT0Y, -1, 1, 0, 0),
+];
+
+#[repr(C)]
+#[derive(Clone, Copy)]
+struct FfRumbleEffect {
+    strong_magnitude: u16,
+    weak_magnitude: u16,
+}
+
+// The largest member of the union of the effects, for its size.
+#[repr(C)]
+#[derive(Clone, Copy)]
+struct FfPeriodicEffect {
+    waveform: u16,
+    period: u16,
+    magnitude: i16,
+    offset: i16,
+    phase: u16,
+    envelope: [u16; 4],
+    custom_len: u32,
+    custom_data: *mut i16,
+}
+
+#[repr(C)]
+#[derive(Clone, Copy)]
+union FfEffectUnion {
+    rumble: FfRumbleEffect,
+    periodic: FfPeriodicEffect,
+}
+
+#[repr(C)]
+#[derive(Clone, Copy)]
+struct FfEffect {
+    type_: u16,
+    id: i16,
+    direction: u16,
+    trigger: [u16; 2],
+    // length, delay
+    replay: [u16; 2],
+    u: FfEffectUnion,
+}
+
+#[repr(C)]
+struct UinputFfUpload {
+    request_id: u32,
+    retval: i32,
+    effect: FfEffect,
+    old: FfEffect,
+}
+
+#[repr(C)]
+struct UinputFfErase {
+    request_id: u32,
+    retval: i32,
+    effect_id: u32,
+}
+
+const UI_BEGIN_FF_UPLOAD: u32 =
+    raw::ioc(IOC_READ | IOC_WRITE, 200, mem::size_of::<UinputFfUpload>());
+const UI_END_FF_UPLOAD: u32 = raw::ioc(IOC_WRITE, 201, mem::size_of::<UinputFfUpload>());
+const UI_BEGIN_FF_ERASE: u32 = raw::ioc(IOC_READ | IOC_WRITE, 202, mem::size_of::<UinputFfErase>());
+const UI_END_FF_ERASE: u32 = raw::ioc(IOC_WRITE, 203, mem::size_of::<UinputFfErase>());
+
+/// The rumble effects uploaded by the programs on the gamepad.
+#[derive(Default)]
+pub struct Effects {
+    rumbles: HashMap<i16, GamepadRumble>,
+    gain: Option<u32>,
+}
+
+impl Effects {
+    fn upload(&mut self, id: i16, rumble: GamepadRumble) {
+        self.rumbles.insert(id, rumble);
+    }
+
+    fn erase(&mut self, id: i16) {
+        self.rumbles.remove(&id);
+    }
+
+    // `count` is the times to repeat, 0 to stop.
+    fn play(&self, id: i16, count: i32) -> Option<GamepadRumble> {
+        let rumble = self.rumbles.get(&id)?;
+        if count <= 0 {
+            return Some(GamepadRumble::new());
+        }
+        let gain = self.gain.unwrap_or(FF_GAIN_MAX);
+        Some(GamepadRumble {
+            strong: rumble.strong * gain / FF_GAIN_MAX,
+            weak: rumble.weak * gain / FF_GAIN_MAX,
+            duration: rumble.duration * count as u32,
+            ..Default::default()
+        })
+    }
+}
+
+pub struct VirtualGamepad {
+    device: Device,
+}
+
+impl VirtualGamepad {
+    pub fn new() -> ResultType<Self> {
+        let device = Device::open()?;
+        device.set_keys(BUTTONS.iter().map(|(_, key)| key.code()))?;
+        device.set_axes(AXES.iter().map(|(axis, minimum, maximum, fuzz, flat)| {
+            (
+                axis.0,
+                InputAbsinfo {
+                    value: 0,
+                    minimum: *minimum,
+                    maximum: *maximum,
+                    fuzz: *fuzz,
+                    flat: *flat,
+                    resolution: 0,
+                },
+            )
+        }))?;
+        device.set_effects([FF_RUMBLE, FF_GAIN])?;
+        device.create("RustDesk UInput Gamepad", VENDOR, PRODUCT, FF_EFFECTS_MAX)?;
+        Ok(Self { device })
+    }
+
+    pub fn set_state(&self, state: &GamepadState) -> io::Result<()> {
+        let mut events = Vec::new();
+        for (bit, key) in BUTTONS {
+            events.push((EV_KEY, key.code(), (state.buttons & bit != 0) as i32));
+        }
+        let pressed = |bit| (state.buttons & bit != 0) as i32;
+        let hat_x = pressed(GAMEPAD_BUTTON_DPAD_RIGHT) - pressed(GAMEPAD_BUTTON_DPAD_LEFT);
+        let hat_y = pressed(GAMEPAD_BUTTON_DPAD_DOWN) - pressed(GAMEPAD_BUTTON_DPAD_UP);
+        for (axis, value) in [
+            (AbsoluteAxisType::ABS_X, state.left_x),
+            (AbsoluteAxisType::ABS_Y, state.left_y),
+            (AbsoluteAxisType::ABS_RX, state.right_x),
+            (AbsoluteAxisType::ABS_RY, state.right_y),
+            (AbsoluteAxisType::ABS_Z, state.left_trigger.min(255) as i32),
+            (
+                AbsoluteAxisType::ABS_RZ,
+                state.right_trigger.min(255) as i32,
+            ),
+            (AbsoluteAxisType::ABS_HAT0X, hat_x),
+            (AbsoluteAxisType::ABS_HAT0Y, hat_y),
+        ] {
+            events.push((EV_ABS, axis.0, value));
+        }
+        // the kernel drops the values not changed
+        self.device.emit(&events)
+    }
+
+    /// Handles the requests of the programs on the gamepad, returns the
+    /// rumble to play.
+    pub fn process(&self, effects: &mut Effects) -> io::Result<Vec<GamepadRumble>> {
+        let mut rumbles = Vec::new();
+        while let Some(event) = self.device.read_event()? {
+            match (event.type_, event.code) {
+                (EV_UINPUT, UI_FF_UPLOAD) => self.upload(effects, event.value as _)?,
+                (EV_UINPUT, UI_FF_ERASE) => self.erase(effects, event.value as _)?,
+                (EV_FF, FF_GAIN) => effects.gain = Some((event.value as u32).min(FF_GAIN_MAX)),
+                (EV_FF, id) => rumbles.extend(effects.play(id as _, event.value)),
+                _ => {}
+            }
+        }
+        Ok(rumbles)
+    }
+
+    fn upload(&self, effects: &mut Effects, request_id: u32) -> io::Result<()> {
+        let fd = self.as_raw_fd();
+        let mut upload: UinputFfUpload = unsafe { mem::zeroed() };
+        upload.request_id = request_id;
+        raw::ioctl_ptr(fd, UI_BEGIN_FF_UPLOAD, &mut upload)?;
+        if upload.effect.type_ == FF_RUMBLE {
+            let rumble = unsafe { upload.effect.u.rumble };
+            effects.upload(
+                upload.effect.id,
+                GamepadRumble {
+                    strong: rumble.strong_magnitude as _,
+                    weak: rumble.weak_magnitude as _,
+                    duration: upload.effect.replay[0] as _,
+                    ..Default::default()
+                },
+            );
+            upload.retval = 0;
+        } else {
+            upload.retval = -libc::EINVAL;
+        }
+        raw::ioctl_ptr(fd, UI_END_FF_UPLOAD, &mut upload)
+    }
+
+    fn erase(&self, effects: &mut Effects, request_id: u32) -> io::Result<()> {
+        let fd = self.as_raw_fd();
+        let mut erase: UinputFfErase = unsafe { mem::zeroed() };
+        erase.request_id = request_id;
+        raw::ioctl_ptr(fd, UI_BEGIN_FF_ERASE, &mut erase)?;
+        effects.erase(erase.effect_id as _);
+        erase.retval = 0;
+        raw::ioctl_ptr(fd, UI_END_FF_ERASE, &mut erase)
+    }
+}
+
+impl AsRawFd for VirtualGamepad {
+    fn as_raw_fd(&self) -> RawFd {
+        self.device.as_raw_fd()
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_effects() {
+        let mut effects = Effects::default();
+        assert_eq!(effects.play(0, 1), None);
+        effects.upload(
+            0,
+            GamepadRumble {
+                strong: 0x8000,
+                weak: 0x4000,
+                duration: 200,
+                ..Default::default()
+            },
+        );
+        let rumble = effects.play(0, 2).unwrap();
+        assert_eq!(
+            (rumble.strong, rumble.weak, rumble.duration),
+            (0x8000, 0x4000, 400)
+        );
+        effects.gain = Some(FF_GAIN_MAX / 2);
+        assert_eq!(effects.play(0, 1).unwrap().strong, 0x3FFF);
+        assert_eq!(effects.play(0, 0), Some(GamepadRumble::new()));
+        effects.erase(0);
+        assert_eq!(effects.play(0, 1), None);
+    }
+
+    #[test]
+    #[cfg(target_pointer_width = "64")]
+    fn test_layout() {
+        assert_eq!(mem::size_of::<FfEffect>(), 48);
+    }
+}
@@ -0,0 +1,175 @@
+//! A virtual pen tablet covering the screens, with the pressure, the tilt,
+//! the eraser and the barrel buttons of the pen.
+
+use super::raw::{Device, InputAbsinfo, EV_ABS, EV_KEY};
+use crate::common::input::*;
+use evdev::{AbsoluteAxisType, Key};
+use hbb_common::{message_proto::PenEvent, ResultType};
+use std::io;
+
+const INPUT_PROP_POINTER: u16 = 0x00;
+const PRESSURE_MAX: i32 = 65535;
+const TILT_MAX: i32 = 90;
+// units per mm, about 100 dpi
+const RESOLUTION: i32 = 4;
+// units per radian
+const TILT_RESOLUTION: i32 = 57;
+
+const KEYS: &[Key] = &[
+    Key::BTN_TOOL_PEN,
+    Key::BTN_TOOL_RUBBER,
+    Key::BTN_TOUCH,
+    Key::BTN_STYLUS,
+    Key::BTN_STYLUS2,
+];
+
+type Report = Vec<(u16, u16, i32)>;
+
+pub struct VirtualPen {
+    device: Device,
+    // BTN_TOOL_PEN or BTN_TOOL_RUBBER in range
+    tool: Option<Key>,
+}
+
+impl VirtualPen {
+    pub fn new(rng_x: (i32, i32), rng_y: (i32, i32)) -> ResultType<Self> {
+        let device = Device::open()?;
+        device.set_property(INPUT_PROP_POINTER)?;
+        device.set_keys(KEYS.iter().map(|key| key.code()))?;
+        let absinfo = |minimum, maximum, resolution| InputAbsinfo {
+            value: 0,
+            minimum,
+            maximum,
+            fuzz: 0,
+            flat: 0,
+            resolution,
+        };
+        device.set_axes([
+            (
+                AbsoluteAxisType::ABS_X.0,
+                absinfo(rng_x.0, rng_x.1, RESOLUTION),
+            ),
+            (
+                AbsoluteAxisType::ABS_Y.0,
+                absinfo(rng_y.0, rng_y.1, RESOLUTION),
+            ),
+            (
+                AbsoluteAxisType::ABS_PRESSURE.0,
+                absinfo(0, PRESSURE_MAX, 0),
+            ),
+            (
+                AbsoluteAxisType::ABS_TILT_X.0,
+                absinfo(-TILT_MAX, TILT_MAX, TILT_RESOLUTION),
+            ),
+            (
+                AbsoluteAxisType::ABS_TILT_Y.0,
+                absinfo(-TILT_MAX, TILT_MAX, TILT_RESOLUTION),
+            ),
+        ])?;
+        device.create("RustDesk UInput Pen", 0, 0, 0)?;
+        Ok(Self { device, tool: None })
+    }
+
+    pub fn send(&mut self, evt: &PenEvent) -> io::Result<()> {
+        for report in reports(&mut self.tool, evt) {
+            self.device.emit(&report)?;
+        }
+        Ok(())
+    }
+}
+
+// A tool leaves the proximity before the other one enters it.
+fn reports(tool: &mut Option<Key>, evt: &PenEvent) -> Vec<Report> {
+    let mut reports = Vec::new();
+    let next = if evt.leave {
+        None
+    } else if evt.eraser {
+        Some(Key::BTN_TOOL_RUBBER)
+    } else {
+        Some(Key::BTN_TOOL_PEN)
+    };
+    if let Some(last) = *tool {
+        if next != Some(last) {
+            reports.push(vec![
+                (EV_KEY, Key::BTN_TOUCH.code(), 0),
+                (EV_KEY, Key::BTN_STYLUS.code(), 0),
+                (EV_KEY, Key::BTN_STYLUS2.code(), 0),
+                (EV_ABS, AbsoluteAxisType::ABS_PRESSURE.0, 0),
+                (EV_KEY, last.code(), 0),
+            ]);
+        }
+    }
+    *tool = next;
+    let Some(next) = next else {
+        return reports;
+    };
+    let down = evt.down as i32;
+    let pressure = if evt.down {
+        (evt.pressure as i32).min(PRESSURE_MAX)
+    } else {
+        0
+    };
+    reports.push(vec![
+        (EV_KEY, next.code(), 1),
+        (EV_ABS, AbsoluteAxisType::ABS_X.0, evt.x),
+        (EV_ABS, AbsoluteAxisType::ABS_Y.0, evt.y),
+        (EV_ABS, AbsoluteAxisType::ABS_PRESSURE.0, pressure),
+        (
+            EV_ABS,
+            AbsoluteAxisType::ABS_TILT_X.0,
+            evt.tilt_x.clamp(-TILT_MAX, TILT_MAX),
+        ),
+        (
+            EV_ABS,
+            AbsoluteAxisType::ABS_TILT_Y.0,
+            evt.tilt_y.clamp(-TILT_MAX, TILT_MAX),
+        ),
+        (EV_KEY, Key::BTN_TOUCH.code(), down),
+        (
+            EV_KEY,
+            Key::BTN_STYLUS.code(),
+            (evt.buttons & PEN_BUTTON_PRIMARY != 0) as i32,
+        ),
+        (
+            EV_KEY,
+            Key::BTN_STYLUS2.code(),
+            (evt.buttons & PEN_BUTTON_SECONDARY != 0) as i32,
+        ),
+    ]);
+    reports
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_reports() {
+        let mut tool = None;
+        let mut evt = PenEvent {
+            x: 10,
+            y: 20,
+            down: true,
+            pressure: 1000,
+            ..Default::default()
+        };
+        let reports = super::reports(&mut tool, &evt);
+        assert_eq!(reports.len(), 1);
+        assert!(reports[0].contains(&(EV_KEY, Key::BTN_TOOL_PEN.code(), 1)));
+        assert!(reports[0].contains(&(EV_ABS, AbsoluteAxisType::ABS_PRESSURE.0, 1000)));
+        assert_eq!(tool, Some(Key::BTN_TOOL_PEN));
+
+        evt.eraser = true;
+        let reports = super::reports(&mut tool, &evt);
+        assert_eq!(reports.len(), 2);
+        assert!(reports[0].contains(&(EV_KEY, Key::BTN_TOOL_PEN.code(), 0)));
+        assert!(reports[1].contains(&(EV_KEY, Key::BTN_TOOL_RUBBER.code(), 1)));
+
+        evt.leave = true;
+        let reports = super::reports(&mut tool, &evt);
+        assert_eq!(reports.len(), 1);
+        assert!(reports[0].contains(&(EV_KEY, Key::BTN_TOOL_RUBBER.code(), 0)));
+        assert_eq!(tool, None);
+        assert!(super::reports(&mut tool, &evt).is_empty());
+    }
+}
@@ -0,0 +1,218 @@
+//! The ioctls of `linux/uinput.h`, for the devices the evdev crate can't set
+//! up: the force feedback of the gamepad and the axes of the pen.
+
+use hbb_common::libc;
+use std::{
+    fs::{File, OpenOptions},
+    io::{self, Read, Write},
+    mem,
+    os::unix::{
+        fs::OpenOptionsExt,
+        io::{AsRawFd, RawFd},
+    },
+};
+
+pub const EV_SYN: u16 = 0x00;
+pub const EV_KEY: u16 = 0x01;
+pub const EV_ABS: u16 = 0x03;
+pub const EV_FF: u16 = 0x15;
+pub const EV_UINPUT: u16 = 0x0101;
+pub const SYN_REPORT: u16 = 0;
+pub const BUS_USB: u16 = 0x03;
+
+#[repr(C)]
+struct InputId {
+    bustype: u16,
+    vendor: u16,
+    product: u16,
+    version: u16,
+}
+
+#[repr(C)]
+struct UinputSetup {
+    id: InputId,
+    name: [u8; 80],
+    ff_effects_max: u32,
+}
+
+#[repr(C)]
+pub struct InputAbsinfo {
+    pub value: i32,
+    pub minimum: i32,
+    pub maximum: i32,
+    pub fuzz: i32,
+    pub flat: i32,
+    pub resolution: i32,
+}
+
+#[repr(C)]
+struct UinputAbsSetup {
+    code: u16,
+    absinfo: InputAbsinfo,
+}
+
+pub const IOC_WRITE: u32 = 1;
+pub const IOC_READ: u32 = 2;
+
+pub const fn ioc(dir: u32, nr: u32, size: usize) -> u32 {
+    (dir << 30) | ((size as u32) << 16) | ((b'U' as u32) << 8) | nr
+}
+
+const UI_DEV_CREATE: u32 = ioc(0, 1, 0);
+const UI_DEV_DESTROY: u32 = ioc(0, 2, 0);
+const UI_DEV_SETUP: u32 = ioc(IOC_WRITE, 3, mem::size_of::<UinputSetup>());
+const UI_ABS_SETUP: u32 = ioc(IOC_WRITE, 4, mem::size_of::<UinputAbsSetup>());
+const UI_SET_EVBIT: u32 = ioc(IOC_WRITE, 100, mem::size_of::<libc::c_int>());
+const UI_SET_KEYBIT: u32 = ioc(IOC_WRITE, 101, mem::size_of::<libc::c_int>());
+const UI_SET_ABSBIT: u32 = ioc(IOC_WRITE, 103, mem::size_of::<libc::c_int>());
+const UI_SET_FFBIT: u32 = ioc(IOC_WRITE, 107, mem::size_of::<libc::c_int>());
+const UI_SET_PROPBIT: u32 = ioc(IOC_WRITE, 110, mem::size_of::<libc::c_int>());
+
+fn ioctl_int(fd: RawFd, request: u32, value: u16) -> io::Result<()> {
+    if unsafe { libc::ioctl(fd, request as _, value as libc::c_int) } < 0 {
+        return Err(io::Error::last_os_error());
+    }
+    Ok(())
+}
+
+pub fn ioctl_ptr<T>(fd: RawFd, request: u32, arg: &mut T) -> io::Result<()> {
+    if unsafe { libc::ioctl(fd, request as _, arg as *mut T as *mut libc::c_void) } < 0 {
+        return Err(io::Error::last_os_error());
+    }
+    Ok(())
+}
+
+/// A uinput device, set up by `set_*` before `create`, destroyed when
+/// dropped.
+pub struct Device {
+    file: File,
+}
+
+impl Device {
+    pub fn open() -> io::Result<Self> {
+        let file = OpenOptions::new()
+            .read(true)
+            .write(true)
+            .custom_flags(libc::O_NONBLOCK)
+            .open("/dev/uinput")?;
+        Ok(Self { file })
+    }
+
+    pub fn set_keys(&self, keys: impl IntoIterator<Item = u16>) -> io::Result<()> {
+        ioctl_int(self.as_raw_fd(), UI_SET_EVBIT, EV_KEY)?;
+        for key in keys {
+            ioctl_int(self.as_raw_fd(), UI_SET_KEYBIT, key)?;
+        }
+        Ok(())
+    }
+
+    pub fn set_axes(&self, axes: impl IntoIterator<Item = (u16, InputAbsinfo)>) -> io::Result<()> {
+        ioctl_int(self.as_raw_fd(), UI_SET_EVBIT, EV_ABS)?;
+        for (code, absinfo) in axes {
+            ioctl_int(self.as_raw_fd(), UI_SET_ABSBIT, code)?;
+            let mut setup = UinputAbsSetup { code, absinfo };
+            ioctl_ptr(self.as_raw_fd(), UI_ABS_SETUP, &mut setup)?;
+        }
+        Ok(())
+    }
+
+    pub fn set_effects(&self, effects: impl IntoIterator<Item = u16>) -> io::Result<()> {
+        ioctl_int(self.as_raw_fd(), UI_SET_EVBIT, EV_FF)?;
+        for effect in effects {
+            ioctl_int(self.as_raw_fd(), UI_SET_FFBIT, effect)?;
+        }
+        Ok(())
+    }
+
+    pub fn set_property(&self, property: u16) -> io::Result<()> {
+        ioctl_int(self.as_raw_fd(), UI_SET_PROPBIT, property)
+    }
+
+    pub fn create(
+        &self,
+        name: &str,
+        vendor: u16,
+        product: u16,
+        ff_effects_max: u32,
+    ) -> io::Result<()> {
+        let mut setup = UinputSetup {
+            id: InputId {
+                bustype: BUS_USB,
+                vendor,
+                product,
+                version: 0x0110,
+            },
+            name: [0; 80],
+            ff_effects_max,
+        };
+        let len = name.len().min(setup.name.len() - 1);
+        setup.name[..len].copy_from_slice(&name.as_bytes()[..len]);
+        ioctl_ptr(self.as_raw_fd(), UI_DEV_SETUP, &mut setup)?;
+        if unsafe { libc::ioctl(self.as_raw_fd(), UI_DEV_CREATE as _) } < 0 {
+            return Err(io::Error::last_os_error());
+        }
+        Ok(())
+    }
+
+    /// Writes the events followed by a report.
+    pub fn emit(&self, events: &[(u16, u16, i32)]) -> io::Result<()> {
+        for (type_, code, value) in events.iter().chain([(EV_SYN, SYN_REPORT, 0)].iter()) {
+            let mut event: libc::input_event = unsafe { mem::zeroed() };
+            event.type_ = *type_;
+            event.code = *code;
+            event.value = *value;
+            let buf = unsafe {
+                std::slice::from_raw_parts(
+                    &event as *const _ as *const u8,
+                    mem::size_of::<libc::input_event>(),
+                )
+            };
+            (&self.file).write_all(buf)?;
+        }
+        Ok(())
+    }
+
+    /// Reads an event sent to the device, `None` if there is none.
+    pub fn read_event(&self) -> io::Result<Option<libc::input_event>> {
+        let mut event: libc::input_event = unsafe { mem::zeroed() };
+        let buf = unsafe {
+            std::slice::from_raw_parts_mut(
+                &mut event as *mut _ as *mut u8,
+                mem::size_of::<libc::input_event>(),
+            )
+        };
+        match (&self.file).read(buf) {
+            Ok(n) if n == buf.len() => Ok(Some(event)),
+            Ok(_) => Ok(None),
+            Err(e) if e.kind() == io::ErrorKind::WouldBlock => Ok(None),
+            Err(e) => Err(e),
+        }
+    }
+}
+
+impl AsRawFd for Device {
+    fn as_raw_fd(&self) -> RawFd {
+        self.file.as_raw_fd()
+    }
+}
+
+impl Drop for Device {
+    fn drop(&mut self) {
+        unsafe {
+            libc::ioctl(self.file.as_raw_fd(), UI_DEV_DESTROY as _);
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_layout() {
+        assert_eq!(mem::size_of::<UinputAbsSetup>(), 28);
+        assert_eq!(mem::size_of::<UinputSetup>(), 92);
+        assert_eq!(UI_DEV_SETUP, 0x405c5503);
+        assert_eq!(UI_ABS_SETUP, 0x401c5504);
+    }
+}
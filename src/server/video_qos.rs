@@ -1,4 +1,7 @@
-use super::*;
+use super::{
+    motion_qos::{self, Motion},
+    *,
+};
 use scrap::codec::Quality;
 use std::time::Duration;
 pub const FPS: u32 = 30;
@@ -36,6 +39,9 @@ struct UserData {
     delay: Option<Delay>,
     response_delayed: bool,
     record: bool,
+    text_mode: bool,
+    // the largest size the user wants the displays encoded at
+    stream_resolution: Option<(usize, usize)>,
 }
 
 pub struct VideoQoS {
@@ -43,6 +49,8 @@ pub struct VideoQoS {
     quality: Quality,
     users: HashMap<i32, UserData>,
     bitrate_store: u32,
+    // content motion of each captured display
+    motions: HashMap<usize, Motion>,
 }
 
 #[derive(PartialEq, Debug, Clone, Copy)]
@@ -80,6 +88,7 @@ impl Default for VideoQoS {
             quality: Default::default(),
             users: Default::default(),
             bitrate_store: 0,
+            motions: Default::default(),
         }
     }
 }
@@ -95,11 +104,12 @@ impl VideoQoS {
     }
 
     pub fn fps(&self) -> u32 {
-        if self.fps >= MIN_FPS && self.fps <= MAX_FPS {
+        let fps = if self.fps >= MIN_FPS && self.fps <= MAX_FPS {
             self.fps
         } else {
             FPS
-        }
+        };
+        motion_qos::adjust(self.motion(), fps, self.quality).0
     }
 
     pub fn store_bitrate(&mut self, bitrate: u32) {
@@ -111,17 +121,80 @@ impl VideoQoS {
     }
 
     pub fn quality(&self) -> Quality {
-        self.quality
+        motion_qos::adjust(self.motion(), self.fps, self.quality).1
+    }
+
+    // The busiest content of the displays, it only matters with adaptive bitrate.
+    fn motion(&self) -> Motion {
+        if !Self::abr_enabled() {
+            return Motion::default();
+        }
+        self.motions.values().max().cloned().unwrap_or_default()
+    }
+
+    pub fn set_motion(&mut self, display: usize, motion: Option<Motion>) {
+        match motion {
+            Some(motion) => {
+                self.motions.insert(display, motion);
+            }
+            None => {
+                self.motions.remove(&display);
+            }
+        }
     }
 
     pub fn record(&self) -> bool {
         self.users.iter().any(|u| u.1.record)
     }
 
+    pub fn text_mode(&self) -> bool {
+        self.users.iter().any(|u| u.1.text_mode)
+    }
+
+    /// The size to encode a `width` x `height` display at. It fits in what the
+    /// users ask for, the largest of them, and with `motion` it is also cut to
+    /// 3/4 while the content is video, which holds up better at a lower size
+    /// than at a lower quantizer.
+    pub fn stream_size(&self, width: usize, height: usize, motion: bool) -> (usize, usize) {
+        let mut max = (width, height);
+        if !self.users.is_empty() {
+            let requested = self.users.values().map(|u| u.stream_resolution);
+            // any user at the resolution gets it
+            if let Some(r) = requested.collect::<Option<Vec<_>>>() {
+                max = r.into_iter().max_by_key(|r| r.0 * r.1).unwrap_or(max);
+            }
+        }
+        let (w, h) = fit_size(width, height, max);
+        if motion && self.motion() == Motion::Video {
+            return fit_size(width, height, (w * 3 / 4, h * 3 / 4));
+        }
+        (w, h)
+    }
+
     pub fn abr_enabled() -> bool {
         "N" != Config::get_option("enable-abr")
     }
 
+    /// The most bits per second the audio may take while the network is
+    /// delayed, none if it is not.
+    pub fn audio_bitrate_limit(&self) -> Option<i32> {
+        if !Self::abr_enabled() {
+            return None;
+        }
+        let state = self
+            .users
+            .values()
+            .filter_map(|u| u.delay)
+            .map(|d| d.state)
+            .max_by_key(|s| *s as u32)?;
+        match state {
+            DelayState::Normal => None,
+            DelayState::LowDelay => Some(48_000),
+            DelayState::HighDelay => Some(24_000),
+            DelayState::Broken => Some(12_000),
+        }
+    }
+
     pub fn refresh(&mut self, typ: Option<RefreshType>) {
         // fps
         let user_fps = |u: &UserData| {
@@ -377,8 +450,66 @@ impl VideoQoS {
         }
     }
 
+    pub fn user_text_mode(&mut self, id: i32, v: bool) {
+        if let Some(user) = self.users.get_mut(&id) {
+            user.text_mode = v;
+        } else {
+            self.users.insert(
+                id,
+                UserData {
+                    text_mode: v,
+                    ..Default::default()
+                },
+            );
+        }
+    }
+
+    pub fn user_stream_resolution(&mut self, id: i32, v: Option<(usize, usize)>) {
+        if let Some(user) = self.users.get_mut(&id) {
+            user.stream_resolution = v;
+        } else {
+            self.users.insert(
+                id,
+                UserData {
+                    stream_resolution: v,
+                    ..Default::default()
+                },
+            );
+        }
+    }
+
     pub fn on_connection_close(&mut self, id: i32) {
         self.users.remove(&id);
         self.refresh(None);
     }
 }
+
+/// The largest size in `max` with the aspect ratio of `width` x `height`, never
+/// larger than it, and even for the encoders.
+pub fn fit_size(width: usize, height: usize, max: (usize, usize)) -> (usize, usize) {
+    if width == 0 || height == 0 || (max.0 >= width && max.1 >= height) {
+        return (width, height);
+    }
+    // the side that limits the size
+    let (w, h) = if max.0 * height <= max.1 * width {
+        (max.0, max.0 * height / width)
+    } else {
+        (max.1 * width / height, max.1)
+    };
+    ((w & !1).max(2), (h & !1).max(2))
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_fit_size() {
+        assert_eq!(fit_size(1920, 1080, (3840, 2160)), (1920, 1080));
+        assert_eq!(fit_size(1920, 1080, (1280, 720)), (1280, 720));
+        assert_eq!(fit_size(1920, 1080, (1280, 1024)), (1280, 720));
+        assert_eq!(fit_size(2560, 1600, (1920, 1080)), (1728, 1080));
+        assert_eq!(fit_size(1366, 768, (683, 4000)), (682, 384));
+        assert_eq!(fit_size(1920, 1080, (1, 1)), (2, 2));
+    }
+}
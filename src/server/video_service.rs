@@ -20,11 +20,11 @@
 
 use super::{
     display_service::{check_display_changed, get_display_info},
+    motion_qos::MotionDetector,
     service::ServiceTmpl,
     video_qos::VideoQoS,
     *,
 };
-#[cfg(target_os = "linux")]
 use crate::common::SimpleCallOnReturn;
 #[cfg(target_os = "linux")]
 use crate::platform::linux::is_x11;
@@ -48,9 +48,10 @@ use scrap::{
     aom::AomEncoderConfig,
     codec::{Encoder, EncoderCfg, HwEncoderConfig, Quality},
     convert_to_yuv,
+    dirty::{self, DirtyTracker, Rect},
     record::{Recorder, RecorderContext},
     vpxcodec::{VpxEncoderConfig, VpxVideoCodecId},
-    CodecName, Display, Frame, TraitCapturer, TraitFrame,
+    CodecName, Display, Frame, Pixfmt, ScaledFrame, TraitCapturer, TraitFrame,
 };
 #[cfg(windows)]
 use std::sync::Once;
@@ -63,6 +64,8 @@ use std::{
 
 pub const NAME: &'static str = "video";
 pub const OPTION_REFRESH: &'static str = "refresh";
+// Starts with `NAME` so that it is handled as a video service.
+pub const CANVAS_NAME: &'static str = "video_canvas";
 
 lazy_static::lazy_static! {
     static ref FRAME_FETCHED_NOTIFIER: (UnboundedSender<(i32, Option<Instant>)>, Arc<TokioMutex<UnboundedReceiver<(i32, Option<Instant>)>>>) = {
@@ -72,6 +75,36 @@ lazy_static::lazy_static! {
     pub static ref VIDEO_QOS: Arc<Mutex<VideoQoS>> = Default::default();
     pub static ref IS_UAC_RUNNING: Arc<Mutex<bool>> = Default::default();
     pub static ref IS_FOREGROUND_WINDOW_ELEVATED: Arc<Mutex<bool>> = Default::default();
+    // The displays in the canvas, the last connection asking for a canvas decides.
+    static ref CANVAS_DISPLAYS: Arc<Mutex<Vec<usize>>> = Default::default();
+    // (x, y, width, height) of the window shared instead of the display
+    static ref CAPTURE_WINDOW_RECT: Arc<Mutex<Option<(i32, i32, i32, i32)>>> = Default::default();
+    // the id of that window
+    static ref CAPTURE_WINDOW_ID: Arc<Mutex<Option<u32>>> = Default::default();
+    // The services encoding smaller than they capture, by name.
+    static ref STREAM_SCALES: Arc<Mutex<HashMap<String, StreamScale>>> = Default::default();
+    // The recorders of the displays, for the events of the sessions.
+    static ref RECORDERS: Mutex<Vec<Weak<Mutex<Option<Recorder>>>>> = Default::default();
+    // The clock of the audio pts. The video pts start over with each run of a
+    // video service, its frames carry their offset to this clock.
+    static ref MEDIA_START: Instant = Instant::now();
+    // The services whose frames cannot be scaled, they are sent at the native size.
+    static ref NATIVE_SIZE_ONLY: Mutex<HashSet<String>> = Default::default();
+    // Whether the peers were told that text mode falls back to the encoder.
+    static ref TEXT_MODE_FALLBACK: Arc<Mutex<bool>> = Default::default();
+}
+
+/// The current time in milliseconds on the clock of the audio pts.
+#[inline]
+pub fn media_pts() -> i64 {
+    MEDIA_START.elapsed().as_millis() as i64
+}
+
+// The pts of the frames encoded since `start` on the clock of the audio pts.
+// Older peers take the pts as they are, e.g. their recordings start at 0 and
+// are split when a service restarts.
+fn media_pts_offset(start: Instant) -> i64 {
+    start.saturating_duration_since(*MEDIA_START).as_millis() as i64
 }
 
 #[inline]
@@ -162,6 +195,107 @@ pub fn new(idx: usize) -> GenericService {
     vs.sp
 }
 
+pub fn new_canvas() -> GenericService {
+    let vs = VideoService {
+        sp: GenericService::new(CANVAS_NAME, true),
+        // the canvas is streamed as its first display, see `run_canvas`
+        idx: 0,
+    };
+    GenericService::run(&vs, run_canvas);
+    vs.sp
+}
+
+#[inline]
+pub fn is_canvas_supported() -> bool {
+    // one capturer per display, wayland only has one
+    #[cfg(target_os = "linux")]
+    if !is_x11() {
+        return false;
+    }
+    true
+}
+
+pub fn set_canvas_displays(displays: &[usize]) {
+    let mut displays = displays.to_vec();
+    displays.sort();
+    displays.dedup();
+    *CANVAS_DISPLAYS.lock().unwrap() = displays;
+}
+
+/// Map a pointer position the peer sends to the desktop. The peer sees a
+/// scaled display at its origin with the stream size, and only the shared
+/// window if only a window is shared.
+pub fn map_input_position(x: i32, y: i32) -> (i32, i32) {
+    let (x, y) = STREAM_SCALES
+        .lock()
+        .unwrap()
+        .values()
+        .find(|s| s.contains((x, y), s.stream))
+        .map_or((x, y), |s| s.map((x, y), s.stream, s.native));
+    clamp_to_capture_window(x, y)
+}
+
+/// Map the cursor position on the desktop to the scaled display the peer sees.
+pub fn map_cursor_position(x: i32, y: i32) -> (i32, i32) {
+    STREAM_SCALES
+        .lock()
+        .unwrap()
+        .values()
+        .find(|s| s.contains((x, y), s.native))
+        .map_or((x, y), |s| s.map((x, y), s.native, s.stream))
+}
+
+/// Keys and clicks go to the window with the focus, which is not the one the
+/// peer sees if only a window is shared. That window is activated first, and
+/// the input is dropped if it cannot be.
+pub fn focus_capture_window() -> bool {
+    #[cfg(target_os = "linux")]
+    {
+        if !is_x11() {
+            // the portal tells neither where the window is nor whether it has
+            // the focus, so a shared window is view only
+            return Config::get_option("capture-window").is_empty();
+        }
+        let Some(id) = *CAPTURE_WINDOW_ID.lock().unwrap() else {
+            return true;
+        };
+        if let Err(e) = scrap::activate_window(id) {
+            log::debug!("Drop the input, failed to activate window {}: {}", id, e);
+            return false;
+        }
+    }
+    true
+}
+
+fn clamp_to_capture_window(x: i32, y: i32) -> (i32, i32) {
+    match *CAPTURE_WINDOW_RECT.lock().unwrap() {
+        Some((left, top, w, h)) => (
+            x.clamp(left, left + (w - 1).max(0)),
+            y.clamp(top, top + (h - 1).max(0)),
+        ),
+        None => (x, y),
+    }
+}
+
+/// Log an event of a session, e.g. a click, beside the recordings of the
+/// displays. `detail` must not carry what the user types.
+pub fn record_event(event: &str, detail: &str) {
+    RECORDERS.lock().unwrap().retain(|r| {
+        let Some(r) = r.upgrade() else {
+            return false;
+        };
+        if let Some(r) = r.lock().unwrap().as_mut() {
+            r.write_event(event, detail);
+        }
+        true
+    });
+}
+
+#[inline]
+pub fn is_canvas_display(display: usize) -> bool {
+    CANVAS_DISPLAYS.lock().unwrap().contains(&display)
+}
+
 // Capturer object is expensive, avoiding to create it frequently.
 fn create_capturer(
     privacy_mode_id: i32,
@@ -266,6 +400,8 @@ pub(super) struct CapturerInfo {
     pub current: usize,
     pub privacy_mode_id: i32,
     pub _capturer_privacy_mode_id: i32,
+    // the id of a single window, see `get_window_capturer`
+    pub window: Option<u32>,
     pub capturer: Box<dyn TraitCapturer>,
 }
 
@@ -289,6 +425,10 @@ fn get_capturer(current: usize, portable_service_running: bool) -> ResultType<Ca
         if !is_x11() {
             return super::wayland::get_capturer();
         }
+        let target = Config::get_option("capture-window");
+        if !target.is_empty() {
+            return get_window_capturer(current, &target);
+        }
     }
 
     let mut displays = Display::all()?;
@@ -358,10 +498,285 @@ fn get_capturer(current: usize, portable_service_running: bool) -> ResultType<Ca
         current,
         privacy_mode_id,
         _capturer_privacy_mode_id: capturer_privacy_mode_id,
+        window: None,
         capturer,
     })
 }
 
+// Share a window, by id or application, instead of the display. Wayland asks
+// the user for the window in the portal instead.
+#[cfg(target_os = "linux")]
+fn get_window_capturer(current: usize, target: &str) -> ResultType<CapturerInfo> {
+    let capturer = scrap::WindowCapturer::new(target)
+        .with_context(|| format!("Failed to capture window {}", target))?;
+    let (origin, width, height) = (capturer.origin(), capturer.width(), capturer.height());
+    let id = capturer.id();
+    log::debug!(
+        "window={}, id={}, origin: {:?}, width={}, height={}",
+        target,
+        id,
+        &origin,
+        width,
+        height
+    );
+    Ok(CapturerInfo {
+        origin,
+        width,
+        height,
+        ndisplay: Display::all()?.len(),
+        current,
+        privacy_mode_id: INVALID_PRIVACY_MODE_CONN_ID,
+        _capturer_privacy_mode_id: INVALID_PRIVACY_MODE_CONN_ID,
+        window: Some(id),
+        capturer: Box::new(capturer),
+    })
+}
+
+// Changes covering at most 1/TILES_AREA_DIV of the display are sent as tiles.
+const TILES_AREA_DIV: usize = 16;
+// Let the encoder refine the whole display at least this often.
+const FULL_ENCODE_INTERVAL: Duration = Duration::from_secs(1);
+// In text mode, resend what was encoded lossy once the screen is still this long.
+const TEXT_MODE_IDLE: Duration = Duration::from_millis(500);
+// The least time between two resizes of the stream for the motion.
+const MOTION_RESIZE_HOLD: Duration = Duration::from_secs(10);
+
+/// Changed regions of the captured frames, sent as tiles when small, and
+/// otherwise the only part the encoder works on.
+struct DirtyRegion {
+    tracker: DirtyTracker,
+    tiles: bool,
+    // changed since the last encoded frame
+    unencoded: Vec<Rect>,
+    last_full_encode: Instant,
+    text_mode: bool,
+    // text mode: the last frame (data, width, height, stride, pixfmt) and
+    // whether the peers only have it lossy
+    last_frame: (Vec<u8>, usize, usize, usize, Pixfmt),
+    lossy: bool,
+    last_change: Instant,
+}
+
+impl DirtyRegion {
+    // The recorder only takes encoded frames.
+    fn use_tiles(recording: bool) -> bool {
+        Encoder::use_tiles() && !recording
+    }
+
+    fn new(codec_name: &CodecName, recording: bool, text_mode: bool) -> Option<Self> {
+        let tiles = Self::use_tiles(recording);
+        let active_region = matches!(codec_name, CodecName::VP8 | CodecName::VP9);
+        if !tiles && !active_region {
+            return None;
+        }
+        Some(Self {
+            tracker: DirtyTracker::new(),
+            tiles,
+            unencoded: Vec::new(),
+            last_full_encode: Instant::now(),
+            text_mode: text_mode && tiles,
+            last_frame: (Vec::new(), 0, 0, 0, Pixfmt::BGRA),
+            lossy: false,
+            last_change: Instant::now(),
+        })
+    }
+
+    // `lossy`: the frame goes through the encoder. The peers draw every decoded
+    // frame over their whole image, the lossless tiles sent before included.
+    fn on_change(&mut self, frame: &dyn TraitFrame, lossy: bool) {
+        if !self.text_mode {
+            return;
+        }
+        self.last_change = Instant::now();
+        let (data, w, h, stride, pixfmt) = &mut self.last_frame;
+        data.clear();
+        data.extend_from_slice(frame.data());
+        (*w, *h, *stride, *pixfmt) = (
+            frame.width(),
+            frame.height(),
+            frame.stride()[0],
+            frame.pixfmt(),
+        );
+        self.lossy |= lossy;
+    }
+
+    // The stream size changed, start over with the next frame.
+    fn reset(&mut self) {
+        self.tracker.reset();
+        self.unencoded.clear();
+        self.last_frame.0.clear();
+        self.lossy = false;
+    }
+
+    // Text mode: the screen is still, send the lossy frame again as tiles.
+    fn refine(&mut self, sp: &GenericService, display: usize) {
+        if let Some(mut vf) = self.refinement() {
+            vf.display = display as _;
+            let mut msg = Message::new();
+            msg.set_video_frame(vf);
+            sp.send_video_frame(msg);
+        }
+    }
+
+    fn refinement(&mut self) -> Option<VideoFrame> {
+        if !self.text_mode || !self.lossy || self.last_change.elapsed() < TEXT_MODE_IDLE {
+            return None;
+        }
+        self.lossy = false;
+        let (data, w, h, stride, pixfmt) = &self.last_frame;
+        let full = Rect {
+            x: 0,
+            y: 0,
+            w: *w,
+            h: *h,
+        };
+        Some(dirty::encode_tiles(data, *w, *h, *stride, *pixfmt, &[full]))
+    }
+}
+
+/// The content motion of a display for the video QoS, from every captured
+/// frame whatever the codec, and whether it is recorded or sent as tiles.
+struct ContentMotion {
+    display: usize,
+    // diffs the frames the dirty region does not
+    tracker: DirtyTracker,
+    detector: MotionDetector,
+}
+
+impl ContentMotion {
+    fn new(display: usize) -> Self {
+        Self {
+            display,
+            tracker: DirtyTracker::new(),
+            detector: MotionDetector::new(),
+        }
+    }
+
+    // `changed` is the part of the display the last captured frame changed.
+    fn on_frame(&mut self, changed: f32) {
+        if let Some(motion) = self.detector.on_frame(Instant::now(), changed) {
+            log::debug!("display {} motion: {:?}", self.display, motion);
+            VIDEO_QOS
+                .lock()
+                .unwrap()
+                .set_motion(self.display, Some(motion));
+        }
+    }
+
+    // A frame the dirty region does not diff. Only frames of 4 bytes per
+    // pixel can be diffed, the motion keeps its state with the others.
+    fn diff(&mut self, frame: &dyn TraitFrame) {
+        if !matches!(frame.pixfmt(), Pixfmt::BGRA | Pixfmt::RGBA) {
+            return;
+        }
+        let (w, h) = (frame.width(), frame.height());
+        let rects = self.tracker.diff(frame.data(), w, h, frame.stride()[0]);
+        let area: usize = rects.iter().map(|r| r.area()).sum();
+        self.on_frame(area as f32 / (w * h).max(1) as f32);
+    }
+}
+
+// A display, window or canvas at `origin` encoded at `stream` instead of its
+// `native` size.
+#[derive(Debug, Clone, Copy)]
+struct StreamScale {
+    origin: (i32, i32),
+    native: (usize, usize),
+    stream: (usize, usize),
+}
+
+impl StreamScale {
+    // `(x, y)` is in a rect of `size` at the origin.
+    fn contains(&self, (x, y): (i32, i32), size: (usize, usize)) -> bool {
+        let (x, y) = (x - self.origin.0, y - self.origin.1);
+        x >= 0 && y >= 0 && (x as usize) < size.0 && (y as usize) < size.1
+    }
+
+    // From a rect of `from` at the origin to one of `to`.
+    fn map(&self, (x, y): (i32, i32), from: (usize, usize), to: (usize, usize)) -> (i32, i32) {
+        let map = |v: i32, o: i32, from: usize, to: usize| {
+            o + ((v - o) as i64 * to as i64 / from.max(1) as i64) as i32
+        };
+        (
+            map(x, self.origin.0, from.0, to.0),
+            map(y, self.origin.1, from.1, to.1),
+        )
+    }
+}
+
+/// Scales the captured frames down to the stream size, and registers the
+/// scale for the peer input while it is in use.
+struct StreamScaler {
+    name: String,
+    scale: StreamScale,
+    frame: ScaledFrame,
+}
+
+impl StreamScaler {
+    fn new(name: String, origin: (i32, i32), native: (usize, usize)) -> Self {
+        Self {
+            name,
+            scale: StreamScale {
+                origin,
+                native,
+                stream: native,
+            },
+            frame: ScaledFrame::new(native.0, native.1),
+        }
+    }
+
+    fn size(&self) -> (usize, usize) {
+        self.scale.stream
+    }
+
+    fn is_scaled(&self) -> bool {
+        self.scale.stream != self.scale.native
+    }
+
+    // `size`, or the native size if the frames cannot be scaled.
+    fn fit(&self, size: (usize, usize)) -> (usize, usize) {
+        if NATIVE_SIZE_ONLY.lock().unwrap().contains(&self.name) {
+            return self.scale.native;
+        }
+        size
+    }
+
+    fn set_size(&mut self, size: (usize, usize)) {
+        self.scale.stream = size;
+        self.frame = ScaledFrame::new(size.0, size.1);
+        let mut scales = STREAM_SCALES.lock().unwrap();
+        if self.is_scaled() {
+            scales.insert(self.name.clone(), self.scale);
+        } else {
+            scales.remove(&self.name);
+        }
+    }
+
+    fn scale<'a>(&'a mut self, frame: &'a dyn TraitFrame) -> ResultType<&'a dyn TraitFrame> {
+        if !self.is_scaled() {
+            return Ok(frame);
+        }
+        if !matches!(frame.pixfmt(), Pixfmt::BGRA | Pixfmt::RGBA) {
+            // e.g. the hardware capturers, restart at the native size
+            log::warn!(
+                "{}: {:?} frames cannot be scaled, send them at the native size",
+                self.name,
+                frame.pixfmt()
+            );
+            NATIVE_SIZE_ONLY.lock().unwrap().insert(self.name.clone());
+            bail!("SWITCH");
+        }
+        self.frame.scale(frame)?;
+        Ok(&self.frame)
+    }
+}
+
+impl Drop for StreamScaler {
+    fn drop(&mut self) {
+        STREAM_SCALES.lock().unwrap().remove(&self.name);
+    }
+}
+
 fn run(vs: VideoService) -> ResultType<()> {
     // Wayland only support one video capturer for now. It is ok to call ensure_inited() here.
     //
@@ -386,7 +801,20 @@ fn run(vs: VideoService) -> ResultType<()> {
     let display_idx = vs.idx;
     let sp = vs.sp;
     let mut c = get_capturer(display_idx, last_portable_service_running)?;
+    if let Some(id) = c.window {
+        *CAPTURE_WINDOW_RECT.lock().unwrap() =
+            Some((c.origin.0, c.origin.1, c.width as _, c.height as _));
+        *CAPTURE_WINDOW_ID.lock().unwrap() = Some(id);
+    }
+    let _window_call_on_ret = SimpleCallOnReturn {
+        b: c.window.is_some(),
+        f: Box::new(|| {
+            *CAPTURE_WINDOW_RECT.lock().unwrap() = None;
+            *CAPTURE_WINDOW_ID.lock().unwrap() = None;
+        }),
+    };
 
+    let mut scaler = StreamScaler::new(sp.name(), c.origin, (c.width, c.height));
     let mut video_qos = VIDEO_QOS.lock().unwrap();
     video_qos.refresh(None);
     let mut spf;
@@ -394,18 +822,40 @@ fn run(vs: VideoService) -> ResultType<()> {
     let abr = VideoQoS::abr_enabled();
     log::info!("initial quality: {quality:?}, abr enabled: {abr}");
     let codec_name = Encoder::negotiated_codec();
-    let recorder = get_recorder(c.width, c.height, &codec_name);
+    let record_size = scaler.fit(video_qos.stream_size(c.width, c.height, false));
+    let recorder = get_recorder(record_size.0, record_size.1, &codec_name);
     let last_recording = recorder.lock().unwrap().is_some() || video_qos.record();
+    let text_mode = video_qos.text_mode();
+    // a recording keeps its size, the motion does not change it
+    let size = scaler.fit(video_qos.stream_size(c.width, c.height, !last_recording));
     drop(video_qos);
-    let encoder_cfg = get_encoder_config(&c, quality, last_recording);
+    scaler.set_size(size);
+    let mut last_requested_size = record_size;
+    let mut last_resize = Instant::now();
+    if c.window.is_some() || scaler.is_scaled() {
+        // the peer maps its input with the rect
+        let msg = make_rect_display_msg(display_idx, c.origin, size.0, size.1);
+        sp.send_shared(Arc::new(msg));
+    }
+    let mut encoder_cfg = get_encoder_config(size.0, size.1, quality, last_recording, text_mode);
+    let text_mode_fallback = text_mode && !DirtyRegion::use_tiles(last_recording);
+    report_text_mode_fallback(&sp, text_mode_fallback);
 
     let mut encoder;
-    let use_i444 = Encoder::use_i444(&encoder_cfg);
+    let use_i444 = encoder_use_i444(&encoder_cfg, text_mode_fallback);
     match Encoder::new(encoder_cfg.clone(), use_i444) {
         Ok(x) => encoder = x,
         Err(err) => bail!("Failed to create encoder: {}", err),
     }
     VIDEO_QOS.lock().unwrap().store_bitrate(encoder.bitrate());
+    crate::metrics::update_encoder(display_idx, |m| {
+        m.codec = format!("{:?}", codec_name);
+        m.bitrate_kbps = encoder.bitrate();
+    });
+    let _metrics_call_on_ret = SimpleCallOnReturn {
+        b: true,
+        f: Box::new(move || crate::metrics::remove_encoder(display_idx)),
+    };
 
     if sp.is_option_true(OPTION_REFRESH) {
         sp.set_option_bool(OPTION_REFRESH, false);
@@ -414,6 +864,7 @@ fn run(vs: VideoService) -> ResultType<()> {
     let mut frame_controller = VideoFrameController::new();
 
     let start = time::Instant::now();
+    let pts_offset = media_pts_offset(start);
     let mut last_check_displays = time::Instant::now();
     #[cfg(windows)]
     let mut try_gdi = 1;
@@ -426,6 +877,12 @@ fn run(vs: VideoService) -> ResultType<()> {
     let mut would_block_count = 0u32;
     let mut yuv = Vec::new();
     let mut mid_data = Vec::new();
+    let mut dirty = DirtyRegion::new(&codec_name, last_recording, text_mode);
+    let mut motion = ContentMotion::new(display_idx);
+    let _motion_call_on_ret = SimpleCallOnReturn {
+        b: true,
+        f: Box::new(move || VIDEO_QOS.lock().unwrap().set_motion(display_idx, None)),
+    };
 
     while sp.ok() {
         #[cfg(windows)]
@@ -438,12 +895,42 @@ fn run(vs: VideoService) -> ResultType<()> {
             quality = video_qos.quality();
             allow_err!(encoder.set_quality(quality));
             video_qos.store_bitrate(encoder.bitrate());
+            crate::metrics::update_encoder(display_idx, |m| m.bitrate_kbps = encoder.bitrate());
         }
         let recording = recorder.lock().unwrap().is_some() || video_qos.record();
-        if recording != last_recording {
+        if recording != last_recording || video_qos.text_mode() != text_mode {
             bail!("SWITCH");
         }
+        let requested_size = scaler.fit(video_qos.stream_size(c.width, c.height, false));
+        let mut size = scaler.fit(video_qos.stream_size(c.width, c.height, !last_recording));
         drop(video_qos);
+        // the motion may flip between video and static content, it only
+        // resizes the stream once the last resize is old enough
+        if requested_size == last_requested_size && last_resize.elapsed() < MOTION_RESIZE_HOLD {
+            size = scaler.size();
+        }
+        last_requested_size = requested_size;
+        if size != scaler.size() {
+            if last_recording {
+                bail!("SWITCH");
+            }
+            last_resize = Instant::now();
+            // in place, a restart would lose the content motion the size depends on
+            log::info!("stream size: {:?} -> {:?}", scaler.size(), size);
+            scaler.set_size(size);
+            encoder_cfg = get_encoder_config(size.0, size.1, quality, last_recording, text_mode);
+            match Encoder::new(encoder_cfg.clone(), use_i444) {
+                Ok(x) => encoder = x,
+                Err(err) => bail!("Failed to create encoder: {}", err),
+            }
+            VIDEO_QOS.lock().unwrap().store_bitrate(encoder.bitrate());
+            crate::metrics::update_encoder(display_idx, |m| m.bitrate_kbps = encoder.bitrate());
+            if let Some(dirty) = dirty.as_mut() {
+                dirty.reset();
+            }
+            let msg = make_rect_display_msg(display_idx, c.origin, size.0, size.1);
+            sp.send_shared(Arc::new(msg));
+        }
 
         if sp.is_option_true(OPTION_REFRESH) {
             let _ = try_broadcast_display_changed(&sp, display_idx, &c);
@@ -456,7 +943,9 @@ fn run(vs: VideoService) -> ResultType<()> {
         if last_portable_service_running != crate::portable_service::client::running() {
             bail!("SWITCH");
         }
-        if Encoder::use_i444(&encoder_cfg) != use_i444 {
+        if encoder_use_i444(&encoder_cfg, text_mode_fallback) != use_i444
+            || (text_mode && DirtyRegion::use_tiles(last_recording) == text_mode_fallback)
+        {
             bail!("SWITCH");
         }
         check_privacy_mode_changed(&sp, c.privacy_mode_id)?;
@@ -486,12 +975,15 @@ fn run(vs: VideoService) -> ResultType<()> {
                     let send_conn_ids = handle_one_frame(
                         display_idx,
                         &sp,
-                        frame,
+                        scaler.scale(&frame)?,
                         &mut yuv,
                         &mut mid_data,
                         ms,
+                        pts_offset,
                         &mut encoder,
                         recorder.clone(),
+                        dirty.as_mut(),
+                        &mut motion,
                     )?;
                     frame_controller.set_send(now, send_conn_ids);
                 }
@@ -506,6 +998,10 @@ fn run(vs: VideoService) -> ResultType<()> {
 
         match res {
             Err(ref e) if e.kind() == WouldBlock => {
+                motion.on_frame(0.);
+                if let Some(dirty) = dirty.as_mut() {
+                    dirty.refine(&sp, display_idx);
+                }
                 #[cfg(windows)]
                 if try_gdi > 0 && !c.is_gdi() {
                     if try_gdi > 3 {
@@ -553,17 +1049,7 @@ fn run(vs: VideoService) -> ResultType<()> {
             }
         }
 
-        let mut fetched_conn_ids = HashSet::new();
-        let timeout_millis = 3_000u64;
-        let wait_begin = Instant::now();
-        while wait_begin.elapsed().as_millis() < timeout_millis as _ {
-            check_privacy_mode_changed(&sp, c.privacy_mode_id)?;
-            frame_controller.try_wait_next(&mut fetched_conn_ids, 300);
-            // break if all connections have received current frame
-            if fetched_conn_ids.len() >= frame_controller.send_conn_ids.len() {
-                break;
-            }
-        }
+        wait_frame_fetched(&sp, &mut frame_controller, c.privacy_mode_id)?;
 
         let elapsed = now.elapsed();
         // may need to enable frame(timeout)
@@ -576,23 +1062,394 @@ fn run(vs: VideoService) -> ResultType<()> {
     Ok(())
 }
 
-fn get_encoder_config(c: &CapturerInfo, quality: Quality, recording: bool) -> EncoderCfg {
+/// The displays of the canvas, side by side as on the desktop, BGRA.
+struct Canvas {
+    data: Vec<u8>,
+    width: usize,
+    height: usize,
+}
+
+impl TraitFrame for Canvas {
+    fn data(&self) -> &[u8] {
+        &self.data
+    }
+
+    fn width(&self) -> usize {
+        self.width
+    }
+
+    fn height(&self) -> usize {
+        self.height
+    }
+
+    fn stride(&self) -> Vec<usize> {
+        vec![self.width * 4]
+    }
+
+    fn pixfmt(&self) -> Pixfmt {
+        Pixfmt::BGRA
+    }
+}
+
+impl Canvas {
+    fn paint(&mut self, x: usize, y: usize, frame: &Frame) -> ResultType<()> {
+        let rgba = match frame.pixfmt() {
+            Pixfmt::BGRA => false,
+            Pixfmt::RGBA => true,
+            pixfmt => bail!("Unsupported pixfmt in canvas: {:?}", pixfmt),
+        };
+        let w = frame.width().min(self.width.saturating_sub(x));
+        let h = frame.height().min(self.height.saturating_sub(y));
+        let (data, stride) = (frame.data(), frame.stride()[0]);
+        if w == 0 || h == 0 || data.len() < (h - 1) * stride + w * 4 {
+            return Ok(());
+        }
+        for row in 0..h {
+            let src = &data[row * stride..row * stride + w * 4];
+            let start = ((y + row) * self.width + x) * 4;
+            let dst = &mut self.data[start..start + w * 4];
+            dst.copy_from_slice(src);
+            if rgba {
+                dst.chunks_exact_mut(4).for_each(|p| p.swap(0, 2));
+            }
+        }
+        Ok(())
+    }
+}
+
+struct CanvasPart {
+    display: usize,
+    origin: (i32, i32),
+    width: usize,
+    height: usize,
+    capturer: Box<dyn TraitCapturer>,
+}
+
+// The canvas is the bounding box of its displays, so that a point in it maps
+// to the desktop like a point in a single display does.
+fn run_canvas(vs: VideoService) -> ResultType<()> {
+    let sp = vs.sp;
+    let displays = CANVAS_DISPLAYS.lock().unwrap().clone();
+    let privacy_mode_id = get_privacy_mode_conn_id().unwrap_or(INVALID_PRIVACY_MODE_CONN_ID);
+    let all = Display::all()?;
+    let ndisplay = all.len();
+    let mut parts = Vec::new();
+    for (idx, display) in all.into_iter().enumerate() {
+        if !displays.contains(&idx) {
+            continue;
+        }
+        let (origin, width, height) = (display.origin(), display.width(), display.height());
+        parts.push(CanvasPart {
+            display: idx,
+            origin,
+            width,
+            height,
+            capturer: create_capturer(privacy_mode_id, display, idx, false)?,
+        });
+    }
+    if parts.is_empty() {
+        bail!("No display for the canvas, displays: {:?}", displays);
+    }
+    let left = parts.iter().map(|p| p.origin.0).min().unwrap_or_default();
+    let top = parts.iter().map(|p| p.origin.1).min().unwrap_or_default();
+    let right = parts.iter().map(|p| p.origin.0 + p.width as i32).max();
+    let bottom = parts.iter().map(|p| p.origin.1 + p.height as i32).max();
+    let (width, height) = (
+        (right.unwrap_or_default() - left) as usize,
+        (bottom.unwrap_or_default() - top) as usize,
+    );
+    let mut canvas = Canvas {
+        data: vec![0; width * height * 4],
+        width,
+        height,
+    };
+    let display_idx = parts[0].display;
+    log::info!(
+        "canvas of displays {:?}, origin: ({}, {}), width={}, height={}",
+        displays,
+        left,
+        top,
+        width,
+        height
+    );
+
+    let mut video_qos = VIDEO_QOS.lock().unwrap();
+    video_qos.refresh(None);
+    let mut spf;
+    let mut quality = video_qos.quality();
+    let text_mode = video_qos.text_mode();
+    // the layout is sent once, so the motion does not change the size
+    let size = video_qos.stream_size(width, height, false);
+    drop(video_qos);
+    let mut scaler = StreamScaler::new(sp.name(), (left, top), (width, height));
+    scaler.set_size(size);
+    for msg in make_canvas_msgs(display_idx, (left, top), (width, height), size, &parts) {
+        sp.send_shared(Arc::new(msg));
+    }
+    let codec_name = Encoder::negotiated_codec();
+    // the canvas is not recorded
+    let encoder_cfg = get_encoder_config(size.0, size.1, quality, false, text_mode);
+    let text_mode_fallback = text_mode && !DirtyRegion::use_tiles(false);
+    report_text_mode_fallback(&sp, text_mode_fallback);
+    let mut encoder;
+    let use_i444 = encoder_use_i444(&encoder_cfg, text_mode_fallback);
+    match Encoder::new(encoder_cfg.clone(), use_i444) {
+        Ok(x) => encoder = x,
+        Err(err) => bail!("Failed to create encoder: {}", err),
+    }
+    VIDEO_QOS.lock().unwrap().store_bitrate(encoder.bitrate());
+    crate::metrics::update_encoder(display_idx, |m| {
+        m.codec = format!("{:?}", codec_name);
+        m.bitrate_kbps = encoder.bitrate();
+    });
+    let _metrics_call_on_ret = SimpleCallOnReturn {
+        b: true,
+        f: Box::new(move || crate::metrics::remove_encoder(display_idx)),
+    };
+
+    if sp.is_option_true(OPTION_REFRESH) {
+        sp.set_option_bool(OPTION_REFRESH, false);
+    }
+
+    let mut frame_controller = VideoFrameController::new();
+    let start = time::Instant::now();
+    let pts_offset = media_pts_offset(start);
+    let mut last_check_displays = time::Instant::now();
+    let mut yuv = Vec::new();
+    let mut mid_data = Vec::new();
+    let mut dirty = DirtyRegion::new(&codec_name, false, text_mode);
+    let mut motion = ContentMotion::new(display_idx);
+    let _motion_call_on_ret = SimpleCallOnReturn {
+        b: true,
+        f: Box::new(move || VIDEO_QOS.lock().unwrap().set_motion(display_idx, None)),
+    };
+    let recorder: Arc<Mutex<Option<Recorder>>> = Default::default();
+
+    while sp.ok() {
+        let mut video_qos = VIDEO_QOS.lock().unwrap();
+        spf = video_qos.spf();
+        if quality != video_qos.quality() {
+            log::debug!("quality: {:?} -> {:?}", quality, video_qos.quality());
+            quality = video_qos.quality();
+            allow_err!(encoder.set_quality(quality));
+            video_qos.store_bitrate(encoder.bitrate());
+            crate::metrics::update_encoder(display_idx, |m| m.bitrate_kbps = encoder.bitrate());
+        }
+        if video_qos.text_mode() != text_mode
+            || video_qos.stream_size(width, height, false) != scaler.size()
+        {
+            bail!("SWITCH");
+        }
+        drop(video_qos);
+
+        if sp.is_option_true(OPTION_REFRESH)
+            || codec_name != Encoder::negotiated_codec()
+            || encoder_use_i444(&encoder_cfg, text_mode_fallback) != use_i444
+            || (text_mode && DirtyRegion::use_tiles(false) == text_mode_fallback)
+            || *CANVAS_DISPLAYS.lock().unwrap() != displays
+        {
+            bail!("SWITCH");
+        }
+        #[cfg(windows)]
+        if crate::portable_service::client::running() {
+            bail!("SWITCH");
+        }
+        check_privacy_mode_changed(&sp, privacy_mode_id)?;
+        #[cfg(windows)]
+        if crate::platform::windows::desktop_changed() {
+            bail!("Desktop changed");
+        }
+        let now = time::Instant::now();
+        if last_check_displays.elapsed().as_millis() > 1000 {
+            last_check_displays = now;
+            for p in parts.iter() {
+                let rect = (p.origin.0, p.origin.1, p.width, p.height);
+                if check_display_changed(ndisplay, p.display, rect).is_some() {
+                    log::info!("Display {} of the canvas changed", p.display);
+                    bail!("SWITCH");
+                }
+            }
+        }
+
+        frame_controller.reset();
+
+        // share the frame interval among the capturers
+        let timeout = spf / parts.len() as u32;
+        let mut changed = false;
+        for p in parts.iter_mut() {
+            match p.capturer.frame(timeout) {
+                Ok(frame) => {
+                    if frame.data().len() != 0 {
+                        let (x, y) = ((p.origin.0 - left) as usize, (p.origin.1 - top) as usize);
+                        canvas.paint(x, y, &frame)?;
+                        changed = true;
+                    }
+                }
+                Err(ref e) if e.kind() == WouldBlock => {}
+                Err(err) => return Err(err.into()),
+            }
+        }
+        if changed {
+            let time = now - start;
+            let ms = (time.as_secs() * 1000 + time.subsec_millis() as u64) as i64;
+            let send_conn_ids = handle_one_frame(
+                display_idx,
+                &sp,
+                scaler.scale(&canvas)?,
+                &mut yuv,
+                &mut mid_data,
+                ms,
+                pts_offset,
+                &mut encoder,
+                recorder.clone(),
+                dirty.as_mut(),
+                &mut motion,
+            )?;
+            frame_controller.set_send(now, send_conn_ids);
+        } else {
+            motion.on_frame(0.);
+            if let Some(dirty) = dirty.as_mut() {
+                dirty.refine(&sp, display_idx);
+            }
+        }
+
+        wait_frame_fetched(&sp, &mut frame_controller, privacy_mode_id)?;
+
+        let elapsed = now.elapsed();
+        if elapsed < spf {
+            std::thread::sleep(spf - elapsed);
+        }
+    }
+
+    Ok(())
+}
+
+// The peer shows the canvas as display `display_idx` with the size of the
+// stream, and splits it with the layout, scaled to the stream as well.
+fn make_canvas_msgs(
+    display_idx: usize,
+    (left, top): (i32, i32),
+    (width, height): (usize, usize),
+    size: (usize, usize),
+    parts: &[CanvasPart],
+) -> Vec<Message> {
+    let switch_display = make_rect_display_msg(display_idx, (left, top), size.0, size.1);
+    let scale_x = |v: i64| (v * size.0 as i64 / width.max(1) as i64) as i32;
+    let scale_y = |v: i64| (v * size.1 as i64 / height.max(1) as i64) as i32;
+
+    let mut misc = Misc::new();
+    misc.set_canvas_layout(CanvasLayout {
+        display: display_idx as _,
+        regions: parts
+            .iter()
+            .map(|p| CanvasRegion {
+                display: p.display as _,
+                x: scale_x((p.origin.0 - left) as _),
+                y: scale_y((p.origin.1 - top) as _),
+                width: scale_x(p.width as _),
+                height: scale_y(p.height as _),
+                ..Default::default()
+            })
+            .collect(),
+        ..Default::default()
+    });
+    let mut layout = Message::new();
+    layout.set_misc(misc);
+    vec![switch_display, layout]
+}
+
+// Show `display_idx` as the rect, for what is captured instead of the display.
+fn make_rect_display_msg(
+    display_idx: usize,
+    (x, y): (i32, i32),
+    width: usize,
+    height: usize,
+) -> Message {
+    let mut misc = Misc::new();
+    misc.set_switch_display(SwitchDisplay {
+        display: display_idx as _,
+        x,
+        y,
+        width: width as _,
+        height: height as _,
+        cursor_embedded: display_service::capture_cursor_embedded(),
+        ..Default::default()
+    });
+    let mut msg_out = Message::new();
+    msg_out.set_misc(misc);
+    msg_out
+}
+
+fn wait_frame_fetched(
+    sp: &GenericService,
+    frame_controller: &mut VideoFrameController,
+    privacy_mode_id: i32,
+) -> ResultType<()> {
+    let mut fetched_conn_ids = HashSet::new();
+    let timeout_millis = 3_000u64;
+    let wait_begin = Instant::now();
+    while wait_begin.elapsed().as_millis() < timeout_millis as _ {
+        check_privacy_mode_changed(sp, privacy_mode_id)?;
+        frame_controller.try_wait_next(&mut fetched_conn_ids, 300);
+        // break if all connections have received current frame
+        if fetched_conn_ids.len() >= frame_controller.send_conn_ids.len() {
+            break;
+        }
+    }
+    Ok(())
+}
+
+// Text mode refines with lossless tiles. Without them the encoder still gets
+// full chroma when the peers can decode it.
+fn encoder_use_i444(encoder_cfg: &EncoderCfg, text_mode_fallback: bool) -> bool {
+    Encoder::use_i444(encoder_cfg) || (text_mode_fallback && Encoder::i444_usable(encoder_cfg))
+}
+
+// Tell the peers once that the text mode they asked for has no lossless tiles.
+fn report_text_mode_fallback(sp: &GenericService, fallback: bool) {
+    let mut reported = TEXT_MODE_FALLBACK.lock().unwrap();
+    if *reported == fallback {
+        return;
+    }
+    *reported = fallback;
+    if !fallback {
+        return;
+    }
+    log::info!("No lossless tiles for text mode, falling back to the encoder");
+    let mut msg = Message::new();
+    msg.set_message_box(MessageBox {
+        msgtype: "nook-nocancel-hasclose".to_owned(),
+        title: "Prompt".to_owned(),
+        text: "text_mode_fallback_tip".to_owned(),
+        link: "".to_owned(),
+        ..Default::default()
+    });
+    sp.send_shared(Arc::new(msg));
+}
+
+fn get_encoder_config(
+    width: usize,
+    height: usize,
+    quality: Quality,
+    recording: bool,
+    text_mode: bool,
+) -> EncoderCfg {
     // https://www.wowza.com/community/t/the-correct-keyframe-interval-in-obs-studio/95162
     let keyframe_interval = if recording { Some(240) } else { None };
     match Encoder::negotiated_codec() {
         scrap::CodecName::H264(name) | scrap::CodecName::H265(name) => {
             EncoderCfg::HW(HwEncoderConfig {
                 name,
-                width: c.width,
-                height: c.height,
+                width,
+                height,
                 quality,
                 keyframe_interval,
             })
         }
         name @ (scrap::CodecName::VP8 | scrap::CodecName::VP9) => {
             EncoderCfg::VPX(VpxEncoderConfig {
-                width: c.width as _,
-                height: c.height as _,
+                width: width as _,
+                height: height as _,
                 quality,
                 codec: if name == scrap::CodecName::VP8 {
                     VpxVideoCodecId::VP8
@@ -600,11 +1457,12 @@ fn get_encoder_config(c: &CapturerInfo, quality: Quality, recording: bool) -> En
                     VpxVideoCodecId::VP9
                 },
                 keyframe_interval,
+                screen_content: text_mode,
             })
         }
         scrap::CodecName::AV1 => EncoderCfg::AOM(AomEncoderConfig {
-            width: c.width as _,
-            height: c.height as _,
+            width: width as _,
+            height: height as _,
             quality,
             keyframe_interval,
         }),
@@ -634,9 +1492,14 @@ fn get_recorder(
             width,
             height,
             format: codec_name.into(),
+            // the audio is captured by another service
+            audio: None,
+            events: !Config::get_option("allow-record-events").is_empty(),
             tx,
         })
-        .map_or(Default::default(), |r| Arc::new(Mutex::new(Some(r))))
+        .map_or(Default::default(), |r| Arc::new(Mutex::new(Some(r))));
+        RECORDERS.lock().unwrap().push(Arc::downgrade(&recorder));
+        recorder
     } else {
         Default::default()
     };
@@ -663,12 +1526,15 @@ fn check_privacy_mode_changed(sp: &GenericService, privacy_mode_id: i32) -> Resu
 fn handle_one_frame(
     display: usize,
     sp: &GenericService,
-    frame: Frame,
+    frame: &dyn TraitFrame,
     yuv: &mut Vec<u8>,
     mid_data: &mut Vec<u8>,
     ms: i64,
+    pts_offset: i64,
     encoder: &mut Encoder,
     recorder: Arc<Mutex<Option<Recorder>>>,
+    dirty: Option<&mut DirtyRegion>,
+    motion: &mut ContentMotion,
 ) -> ResultType<HashSet<i32>> {
     sp.snapshot(|sps| {
         // so that new sub and old sub share the same encoder after switch
@@ -679,17 +1545,54 @@ fn handle_one_frame(
     })?;
 
     let mut send_conn_ids: HashSet<i32> = Default::default();
-    convert_to_yuv(&frame, encoder.yuvfmt(), yuv, mid_data)?;
-    if let Ok(mut vf) = encoder.encode_to_message(yuv, ms) {
-        vf.display = display as _;
-        let mut msg = Message::new();
-        msg.set_video_frame(vf);
-        recorder
-            .lock()
-            .unwrap()
-            .as_mut()
-            .map(|r| r.write_message(&msg));
-        send_conn_ids = sp.send_video_frame(msg);
+    let dirty = dirty.filter(|_| matches!(frame.pixfmt(), Pixfmt::BGRA | Pixfmt::RGBA));
+    if dirty.is_none() {
+        motion.diff(frame);
+    }
+    if let Some(dirty) = dirty {
+        let (w, h, stride) = (frame.width(), frame.height(), frame.stride()[0]);
+        let rects = dirty.tracker.diff(frame.data(), w, h, stride);
+        let area: usize = rects.iter().map(|r| r.area()).sum();
+        motion.on_frame(area as f32 / (w * h).max(1) as f32);
+        if rects.is_empty() {
+            return Ok(send_conn_ids);
+        }
+        if dirty.tiles && area * TILES_AREA_DIV <= w * h {
+            dirty.on_change(frame, false);
+            let mut vf = dirty::encode_tiles(frame.data(), w, h, stride, frame.pixfmt(), &rects);
+            vf.display = display as _;
+            let mut msg = Message::new();
+            msg.set_video_frame(vf);
+            dirty.unencoded.extend(rects);
+            return Ok(sp.send_video_frame(msg));
+        }
+        dirty.on_change(frame, true);
+        if dirty.last_full_encode.elapsed() < FULL_ENCODE_INTERVAL {
+            dirty.unencoded.extend(rects);
+            allow_err!(encoder.set_active_region(Some(&dirty.unencoded)));
+        } else {
+            dirty.last_full_encode = Instant::now();
+            allow_err!(encoder.set_active_region(None));
+        }
+        dirty.unencoded.clear();
+    }
+    convert_to_yuv(frame, encoder.yuvfmt(), yuv, mid_data)?;
+    match encoder.encode_to_message(yuv, ms) {
+        Ok(mut vf) => {
+            vf.display = display as _;
+            vf.pts_offset = pts_offset;
+            let mut msg = Message::new();
+            msg.set_video_frame(vf);
+            recorder
+                .lock()
+                .unwrap()
+                .as_mut()
+                .map(|r| r.write_message(&msg));
+            send_conn_ids = sp.send_video_frame(msg);
+        }
+        // buffered by the encoder, not dropped
+        Err(e) if e.to_string() == scrap::codec::ENCODE_NO_FRAME => {}
+        Err(_) => crate::metrics::update_encoder(display, |m| m.frames_dropped += 1),
     }
     Ok(send_conn_ids)
 }
@@ -734,6 +1637,9 @@ fn try_broadcast_display_changed(
     display_idx: usize,
     cap: &CapturerInfo,
 ) -> ResultType<()> {
+    if cap.window.is_some() {
+        return Ok(());
+    }
     if let Some(display) = check_display_changed(
         cap.ndisplay,
         cap.current,
@@ -787,3 +1693,84 @@ pub fn make_display_changed_msg(
     msg_out.set_misc(misc);
     Some(msg_out)
 }
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    struct TestFrame(Vec<u8>, usize, usize);
+
+    impl TraitFrame for TestFrame {
+        fn data(&self) -> &[u8] {
+            &self.0
+        }
+
+        fn width(&self) -> usize {
+            self.1
+        }
+
+        fn height(&self) -> usize {
+            self.2
+        }
+
+        fn stride(&self) -> Vec<usize> {
+            vec![self.1 * 4]
+        }
+
+        fn pixfmt(&self) -> Pixfmt {
+            Pixfmt::BGRA
+        }
+    }
+
+    fn text_mode_region() -> DirtyRegion {
+        DirtyRegion {
+            tracker: DirtyTracker::new(),
+            tiles: true,
+            unencoded: Vec::new(),
+            last_full_encode: Instant::now(),
+            text_mode: true,
+            last_frame: (Vec::new(), 0, 0, 0, Pixfmt::BGRA),
+            lossy: false,
+            last_change: Instant::now(),
+        }
+    }
+
+    fn idle(region: &mut DirtyRegion) {
+        region.last_change = Instant::now() - TEXT_MODE_IDLE;
+    }
+
+    fn assert_full_frame(vf: VideoFrame, frame: &TestFrame) {
+        let tiles = vf.tiles();
+        assert_eq!(
+            (tiles.width, tiles.height),
+            (frame.1 as i32, frame.2 as i32)
+        );
+        let area: i32 = tiles.tiles.iter().map(|t| t.width * t.height).sum();
+        assert_eq!(area as usize, frame.1 * frame.2);
+    }
+
+    #[test]
+    fn test_text_mode_refine() {
+        let (w, h) = (100, 70);
+        let frame = TestFrame((0..w * h * 4).map(|x| x as u8).collect(), w, h);
+        let mut region = text_mode_region();
+
+        // lossless tiles only, nothing to refine
+        region.on_change(&frame, false);
+        idle(&mut region);
+        assert!(region.refinement().is_none());
+
+        // an encoded frame, however small the change, replaces the whole image
+        region.on_change(&frame, true);
+        assert!(region.refinement().is_none());
+        region.on_change(&frame, false);
+        idle(&mut region);
+        assert_full_frame(region.refinement().unwrap(), &frame);
+        assert!(region.refinement().is_none());
+
+        region.on_change(&frame, true);
+        region.reset();
+        idle(&mut region);
+        assert!(region.refinement().is_none());
+    }
+}
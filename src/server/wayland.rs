@@ -281,6 +281,7 @@ pub(super) fn get_capturer() -> ResultType<super::video_service::CapturerInfo> {
                 current: cap_display_info.current,
                 privacy_mode_id: 0,
                 _capturer_privacy_mode_id: 0,
+                window: None,
                 capturer: Box::new(cap_display_info.capturer.clone()),
             })
         }
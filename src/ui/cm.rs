@@ -64,6 +64,17 @@ impl InvokeUiCM for SciterHandler {
         );
     }
 
+    fn update_input_control_state(&self, client: &crate::ui_cm_interface::Client) {
+        self.call(
+            "updateInputControlState",
+            &make_args!(
+                client.id,
+                client.input_control,
+                client.input_control_requested
+            ),
+        );
+    }
+
     fn file_transfer_log(&self, _action: &str, _log: &str) {}
 }
 
@@ -114,6 +125,10 @@ impl SciterConnectionManager {
         crate::ui_cm_interface::switch_permission(id, name, enabled);
     }
 
+    fn grant_input_control(&self, id: i32) {
+        crate::ui_cm_interface::grant_input_control(id);
+    }
+
     fn close(&self, id: i32) {
         crate::ui_cm_interface::close(id);
     }
@@ -166,6 +181,7 @@ impl sciter::EventHandler for SciterConnectionManager {
         fn quit();
         fn authorize(i32);
         fn switch_permission(i32, String, bool);
+        fn grant_input_control(i32);
         fn send_msg(i32, String);
         fn can_elevate();
         fn elevate_portable(i32);
@@ -113,6 +113,19 @@ impl InvokeUiSession for SciterHandler {
         self.call2("setPermission", &make_args!(name, value));
     }
 
+    fn set_input_control(&self, control: &InputControl) {
+        self.call2(
+            "setInputControl",
+            &make_args!(
+                control.holder.clone(),
+                control.holding,
+                control.requesting,
+                control.requester_id,
+                control.requester.clone()
+            ),
+        );
+    }
+
     fn close_success(&self) {
         self.call2("closeSuccess", &make_args!());
     }
@@ -132,7 +145,8 @@ impl InvokeUiSession for SciterHandler {
                 status
                     .codec_format
                     .map_or(Value::null(), |it| it.to_string().into()),
-                status.chroma.map_or(Value::null(), |it| it.into())
+                status.chroma.map_or(Value::null(), |it| it.into()),
+                status.audio_buffer.map_or(Value::null(), |it| it.into())
             ),
         );
     }
@@ -255,6 +269,20 @@ impl InvokeUiSession for SciterHandler {
         );
     }
 
+    fn set_canvas_layout(&self, layout: &CanvasLayout) {
+        let mut regions = Value::array(0);
+        for r in layout.regions.iter() {
+            let mut region = Value::map();
+            region.set_item("display", r.display);
+            region.set_item("x", r.x);
+            region.set_item("y", r.y);
+            region.set_item("width", r.width);
+            region.set_item("height", r.height);
+            regions.push(region);
+        }
+        self.call("setCanvasLayout", &make_args!(regions));
+    }
+
     fn set_platform_additions(&self, _data: &str) {
         // Ignore for sciter version.
     }
@@ -436,6 +464,7 @@ impl sciter::EventHandler for SciterSession {
         fn read_remote_dir(String, bool);
         fn send_chat(String);
         fn switch_display(i32);
+        fn capture_displays_canvas(i32);
         fn remove_dir_all(i32, String, bool, bool);
         fn confirm_delete_files(i32, i32);
         fn set_no_confirm(i32);
@@ -459,6 +488,18 @@ impl sciter::EventHandler for SciterSession {
         fn save_view_style(String);
         fn save_image_quality(String);
         fn save_custom_image_quality(i32);
+        fn set_stream_resolution(String);
+        fn set_audio_quality(String, String);
+        fn start_macro_recording();
+        fn stop_macro_recording(String);
+        fn play_macro(String, f64);
+        fn stop_macro();
+        fn type_clipboard();
+        fn get_macro_state();
+        fn get_macros();
+        fn get_key_mappings();
+        fn set_key_mappings(String);
+        fn has_gamepad();
         fn refresh_video(i32);
         fn record_screen(bool, i32, i32, i32);
         fn record_status(bool);
@@ -473,6 +514,8 @@ impl sciter::EventHandler for SciterSession {
         fn alternative_codecs();
         fn change_prefer_codec();
         fn restart_remote_device();
+        fn request_input_control(bool);
+        fn grant_input_control(i32);
         fn request_voice_call();
         fn close_voice_call();
         fn version_cmp(String, String);
@@ -530,6 +573,11 @@ impl SciterSession {
         super::get_icon()
     }
 
+    // The first `count` displays side by side in one stream.
+    fn capture_displays_canvas(&self, count: i32) {
+        self.0.capture_displays_canvas((0..count).collect());
+    }
+
     fn alternative_codecs(&self) -> Value {
         let (vp8, av1, h264, h265) = self.0.alternative_codecs();
         let mut v = Value::array(0);
@@ -540,6 +588,42 @@ impl SciterSession {
         v
     }
 
+    // Returns the error, empty if none.
+    fn stop_macro_recording(&self, name: String) -> String {
+        match self.0.stop_macro_recording(name) {
+            Ok(()) => "".to_owned(),
+            Err(e) => e.to_string(),
+        }
+    }
+
+    // Returns the error, empty if none.
+    fn play_macro(&self, name: String, speed: f64) -> String {
+        match self.0.play_macro(name, speed) {
+            Ok(()) => "".to_owned(),
+            Err(e) => e.to_string(),
+        }
+    }
+
+    // Returns the error, empty if none.
+    fn set_key_mappings(&self, text: String) -> String {
+        match self.0.set_key_mappings(&text) {
+            Ok(()) => "".to_owned(),
+            Err(e) => e.to_string(),
+        }
+    }
+
+    fn has_gamepad(&self) -> bool {
+        cfg!(feature = "gamepad")
+    }
+
+    fn get_macros(&self) -> Value {
+        let mut v = Value::array(0);
+        for name in crate::client::input_macro::list() {
+            v.push(name);
+        }
+        v
+    }
+
     fn save_size(&mut self, x: i32, y: i32, w: i32, h: i32) {
         let size = (x, y, w, h);
         let mut config = self.load_config();
@@ -56,6 +56,9 @@ pub struct Client {
     pub from_switch: bool,
     pub in_voice_call: bool,
     pub incoming_voice_call: bool,
+    // the input control token, see "allow-exclusive-input-control"
+    pub input_control: bool,
+    pub input_control_requested: bool,
     #[serde(skip)]
     #[cfg(not(any(target_os = "ios")))]
     tx: UnboundedSender<Data>,
@@ -101,6 +104,8 @@ pub trait InvokeUiCM: Send + Clone + 'static + Sized {
 
     fn update_voice_call_state(&self, client: &Client);
 
+    fn update_input_control_state(&self, client: &Client);
+
     fn file_transfer_log(&self, action: &str, log: &str);
 }
 
@@ -157,6 +162,8 @@ impl<T: InvokeUiCM> ConnectionManager<T> {
             tx,
             in_voice_call: false,
             incoming_voice_call: false,
+            input_control: false,
+            input_control_requested: false,
         };
         CLIENTS
             .write()
@@ -246,6 +253,15 @@ impl<T: InvokeUiCM> ConnectionManager<T> {
             self.ui_handler.update_voice_call_state(client);
         }
     }
+
+    #[cfg(not(any(target_os = "android", target_os = "ios")))]
+    fn input_control_changed(&self, id: i32, holding: bool, requesting: bool) {
+        if let Some(client) = CLIENTS.write().unwrap().get_mut(&id) {
+            client.input_control = holding;
+            client.input_control_requested = requesting;
+            self.ui_handler.update_input_control_state(client);
+        }
+    }
 }
 
 #[inline]
@@ -301,6 +317,14 @@ pub fn switch_permission(id: i32, name: String, enabled: bool) {
     };
 }
 
+#[inline]
+#[cfg(not(any(target_os = "ios")))]
+pub fn grant_input_control(id: i32) {
+    if let Some(client) = CLIENTS.read().unwrap().get(&id) {
+        allow_err!(client.tx.send(Data::GrantInputControl));
+    };
+}
+
 #[cfg(any(target_os = "android", target_os = "ios", feature = "flutter"))]
 #[inline]
 pub fn get_clients_state() -> String {
@@ -476,6 +500,9 @@ impl<T: InvokeUiCM> IpcTaskRunner<T> {
                                 Data::CloseVoiceCall(reason) => {
                                     self.cm.voice_call_closed(self.conn_id, reason.as_str());
                                 }
+                                Data::InputControl { holding, requesting } => {
+                                    self.cm.input_control_changed(self.conn_id, holding, requesting);
+                                }
                                 _ => {
 
                                 }
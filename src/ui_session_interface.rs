@@ -26,10 +26,15 @@ use hbb_common::{
         sync::mpsc,
         time::{Duration as TokioDuration, Instant},
     },
-    Stream,
+    ResultType, Stream,
 };
 
+#[cfg(feature = "gamepad")]
+use crate::client::gamepad::GamepadForwarder;
+#[cfg(not(any(target_os = "android", target_os = "ios")))]
+use crate::client::input_macro::{self, InputMacro};
 use crate::client::io_loop::Remote;
+use crate::client::key_remap;
 use crate::client::{
     check_if_retry, handle_hash, handle_login_error, handle_login_from_ui, handle_test_delay,
     input_os_password, send_mouse, send_pointer_device_event, start_video_audio_threads,
@@ -41,6 +46,9 @@ use crate::keyboard;
 use crate::{client::Data, client::Interface};
 
 const CHANGE_RESOLUTION_VALID_TIMEOUT_SECS: u64 = 15;
+// between the characters typed from the clipboard, if "type-delay" is not set
+#[cfg(not(any(target_os = "android", target_os = "ios")))]
+const TYPE_DELAY_MS: u64 = 20;
 
 #[derive(Clone, Default)]
 pub struct Session<T: InvokeUiSession> {
@@ -55,6 +63,10 @@ pub struct Session<T: InvokeUiSession> {
     pub server_clipboard_enabled: Arc<RwLock<bool>>,
     pub last_change_display: Arc<Mutex<ChangeDisplayRecord>>,
     pub connection_round_state: Arc<Mutex<ConnectionRoundState>>,
+    #[cfg(not(any(target_os = "android", target_os = "ios")))]
+    pub input_macro: Arc<Mutex<InputMacro>>,
+    #[cfg(feature = "gamepad")]
+    pub gamepad: Arc<Mutex<Option<GamepadForwarder>>>,
 }
 
 #[derive(Clone)]
@@ -309,6 +321,38 @@ impl<T: InvokeUiSession> Session<T> {
         if let Some(msg) = msg {
             self.send(Data::Message(msg));
         }
+        #[cfg(feature = "gamepad")]
+        if name == "forward-gamepad" {
+            self.update_gamepad();
+        }
+    }
+
+    /// Forwards the local gamepads if "forward-gamepad" is on, only Linux
+    /// peers replay them.
+    #[cfg(feature = "gamepad")]
+    pub fn update_gamepad(&self) {
+        let mut gamepad = self.gamepad.lock().unwrap();
+        let on =
+            self.peer_platform() == "Linux" && self.get_toggle_option("forward-gamepad".to_owned());
+        if !on {
+            gamepad.take();
+        } else if gamepad.is_none() {
+            let sender = self.sender.clone();
+            *gamepad = Some(GamepadForwarder::start(move |evt| {
+                let mut msg_out = Message::new();
+                msg_out.set_gamepad_event(evt);
+                if let Some(sender) = sender.read().unwrap().as_ref() {
+                    sender.send(Data::Message(msg_out)).ok();
+                }
+            }));
+        }
+    }
+
+    #[cfg(feature = "gamepad")]
+    pub fn handle_gamepad_event(&self, evt: &GamepadEvent) {
+        if let Some(gamepad) = self.gamepad.lock().unwrap().as_ref() {
+            gamepad.rumble(evt);
+        }
     }
 
     pub fn toggle_privacy_mode(&self, impl_key: String, on: bool) {
@@ -411,6 +455,105 @@ impl<T: InvokeUiSession> Session<T> {
         self.send(Data::Message(msg));
     }
 
+    pub fn set_stream_resolution(&self, value: String) {
+        let msg = self.lc.write().unwrap().set_stream_resolution(value);
+        self.send(Data::Message(msg));
+    }
+
+    pub fn set_audio_quality(&self, option: String, value: String) {
+        let msg = self.lc.write().unwrap().set_audio_quality(option, value);
+        self.send(Data::Message(msg));
+    }
+
+    #[cfg(not(any(target_os = "android", target_os = "ios")))]
+    pub fn start_macro_recording(&self) {
+        self.input_macro.lock().unwrap().start_recording();
+    }
+
+    /// Stop recording the input, and save it as the macro `name` unless it is empty.
+    #[cfg(not(any(target_os = "android", target_os = "ios")))]
+    pub fn stop_macro_recording(&self, name: String) -> ResultType<()> {
+        let steps = self.input_macro.lock().unwrap().stop_recording();
+        match steps {
+            Some(steps) if !name.is_empty() => {
+                input_macro::set(&name, &input_macro::format(&steps))
+            }
+            _ => Ok(()),
+        }
+    }
+
+    #[cfg(not(any(target_os = "android", target_os = "ios")))]
+    pub fn play_macro(&self, name: String, speed: f64) -> ResultType<()> {
+        let steps = input_macro::load(&name)?;
+        let session = self.clone();
+        self.input_macro
+            .lock()
+            .unwrap()
+            .play(steps, speed, move |msg| session.send(Data::Message(msg)));
+        Ok(())
+    }
+
+    /// Type `text` a character each `delay` ms, stopped like the macros.
+    #[cfg(not(any(target_os = "android", target_os = "ios")))]
+    pub fn type_text(&self, text: String, delay: u64) {
+        // the seq of the map mode is not handled by the peers
+        let mode = match KeyboardMode::from_str(&self.get_keyboard_mode()) {
+            Ok(KeyboardMode::Translate) => KeyboardMode::Translate,
+            _ => KeyboardMode::Legacy,
+        };
+        let steps = input_macro::text_steps(&text, delay, mode);
+        let session = self.clone();
+        self.input_macro
+            .lock()
+            .unwrap()
+            .play(steps, 1., move |msg| session.send(Data::Message(msg)));
+    }
+
+    /// Type the text of the local clipboard, a character each "type-delay" ms.
+    /// Returns false if there is no text.
+    #[cfg(not(any(target_os = "android", target_os = "ios")))]
+    pub fn type_clipboard(&self) -> bool {
+        let text = crate::common::get_clipboard_text();
+        if text.is_empty() {
+            return false;
+        }
+        let delay = self
+            .get_option("type-delay".to_owned())
+            .parse()
+            .unwrap_or(TYPE_DELAY_MS);
+        self.type_text(text, delay);
+        true
+    }
+
+    #[cfg(not(any(target_os = "android", target_os = "ios")))]
+    pub fn stop_macro(&self) {
+        self.input_macro.lock().unwrap().stop();
+    }
+
+    /// "recording", "playing" or empty.
+    #[cfg(not(any(target_os = "android", target_os = "ios")))]
+    pub fn get_macro_state(&self) -> String {
+        let input_macro = self.input_macro.lock().unwrap();
+        if input_macro.is_recording() {
+            "recording".to_owned()
+        } else if input_macro.is_playing() {
+            "playing".to_owned()
+        } else {
+            "".to_owned()
+        }
+    }
+
+    /// The key mappings of the peer as text, a mapping a line.
+    pub fn get_key_mappings(&self) -> String {
+        key_remap::format(&self.lc.read().unwrap().get_key_mappings())
+    }
+
+    pub fn set_key_mappings(&self, text: &str) -> ResultType<()> {
+        let mappings = key_remap::parse(text)?;
+        self.lc.write().unwrap().set_key_mappings(mappings);
+        Ok(())
+    }
+
     pub fn get_remember(&self) -> bool {
         self.lc.read().unwrap().remember
     }
@@ -460,6 +603,24 @@ impl<T: InvokeUiSession> Session<T> {
         self.send(Data::Message(msg));
     }
 
+    /// Asks for the input control token of the peer, or gives it up.
+    pub fn request_input_control(&self, request: bool) {
+        let mut misc = Misc::new();
+        misc.set_request_input_control(request);
+        let mut msg_out = Message::new();
+        msg_out.set_misc(misc);
+        self.send(Data::Message(msg_out));
+    }
+
+    /// Hands the input control token to another connection of the peer.
+    pub fn grant_input_control(&self, conn_id: i32) {
+        let mut misc = Misc::new();
+        misc.set_grant_input_control(conn_id);
+        let mut msg_out = Message::new();
+        msg_out.set_misc(misc);
+        self.send(Data::Message(msg_out));
+    }
+
     #[cfg(all(feature = "flutter", feature = "plugin_framework"))]
     #[cfg(not(any(target_os = "android", target_os = "ios")))]
     pub fn send_plugin_request(&self, request: PluginRequest) {
@@ -668,11 +829,23 @@ impl<T: InvokeUiSession> Session<T> {
     pub fn send_key_event(&self, evt: &KeyEvent) {
         // mode: legacy(0), map(1), translate(2), auto(3)
 
-        let mut msg = evt.clone();
-        self.swap_modifier_key(&mut msg);
-        let mut msg_out = Message::new();
-        msg_out.set_key_event(msg);
-        self.send(Data::Message(msg_out));
+        #[cfg(not(any(target_os = "android", target_os = "ios")))]
+        if input_macro::is_abort_key(evt) && self.input_macro.lock().unwrap().is_playing() {
+            if evt.down || evt.press {
+                self.stop_macro();
+            }
+            return;
+        }
+
+        let rules = key_remap::rules(&self.lc.read().unwrap().get_key_mappings());
+        let mut peer = self.peer_platform().to_lowercase();
+        peer.retain(|c| !c.is_whitespace());
+        for mut msg in key_remap::remap(&rules, &peer, evt) {
+            self.swap_modifier_key(&mut msg);
+            let mut msg_out = Message::new();
+            msg_out.set_key_event(msg);
+            self.send(Data::Message(msg_out));
+        }
     }
 
     pub fn send_chat(&self, text: String) {
@@ -699,6 +872,19 @@ impl<T: InvokeUiSession> Session<T> {
         self.send(Data::Message(msg_out));
     }
 
+    /// Ask for `set` side by side in one video stream.
+    pub fn capture_displays_canvas(&self, set: Vec<i32>) {
+        let mut misc = Misc::new();
+        misc.set_capture_displays(CaptureDisplays {
+            set,
+            canvas: true,
+            ..Default::default()
+        });
+        let mut msg_out = Message::new();
+        msg_out.set_misc(misc);
+        self.send(Data::Message(msg_out));
+    }
+
     pub fn switch_display(&self, display: i32) {
         let (w, h) = match self.lc.read().unwrap().get_custom_resolution(display) {
             Some((w, h)) => (w, h),
@@ -863,6 +1049,12 @@ impl<T: InvokeUiSession> Session<T> {
         send_pointer_device_event(evt, alt, ctrl, shift, command, self);
     }
 
+    pub fn send_pen_event(&self, evt: PenEvent, alt: bool, ctrl: bool, shift: bool, command: bool) {
+        let mut pointer_evt = PointerDeviceEvent::new();
+        pointer_evt.set_pen_event(evt);
+        send_pointer_device_event(pointer_evt, alt, ctrl, shift, command, self);
+    }
+
     pub fn send_touch_pan_event(
         &self,
         event: &str,
@@ -1084,6 +1276,8 @@ impl<T: InvokeUiSession> Session<T> {
     }
 
     pub fn close(&self) {
+        #[cfg(feature = "gamepad")]
+        self.gamepad.lock().unwrap().take();
         self.send(Data::Close);
     }
 
@@ -1239,10 +1433,12 @@ pub trait InvokeUiSession: Send + Sync + Clone + 'static + Sized + Default {
     fn switch_display(&self, display: &SwitchDisplay);
     fn set_peer_info(&self, peer_info: &PeerInfo); // flutter
     fn set_displays(&self, displays: &Vec<DisplayInfo>);
+    fn set_canvas_layout(&self, layout: &CanvasLayout);
     fn set_platform_additions(&self, data: &str);
     fn on_connected(&self, conn_type: ConnType);
     fn update_privacy_mode(&self);
     fn set_permission(&self, name: &str, value: bool);
+    fn set_input_control(&self, control: &InputControl);
     fn close_success(&self);
     fn update_quality_status(&self, qs: QualityStatus);
     fn set_connection_type(&self, is_secured: bool, direct: bool);
@@ -1310,6 +1506,10 @@ impl<T: InvokeUiSession> Interface for Session<T> {
     }
 
     fn send(&self, data: Data) {
+        #[cfg(not(any(target_os = "android", target_os = "ios")))]
+        if let Data::Message(msg) = &data {
+            self.input_macro.lock().unwrap().record(msg);
+        }
         if let Some(sender) = self.sender.read().unwrap().as_ref() {
             sender.send(data).ok();
         }
@@ -1377,6 +1577,10 @@ impl<T: InvokeUiSession> Interface for Session<T> {
             );
         }
         self.on_connected(self.lc.read().unwrap().conn_type);
+        #[cfg(feature = "gamepad")]
+        if !self.is_file_transfer() && !self.is_port_forward() {
+            self.update_gamepad();
+        }
         #[cfg(windows)]
         {
             let mut path = std::env::temp_dir();
@@ -1564,7 +1768,7 @@ pub async fn io_loop<T: InvokeUiSession>(handler: Session<T>, round: u32) {
     let frame_count_map: Arc<RwLock<HashMap<usize, usize>>> = Default::default();
     let frame_count_map_cl = frame_count_map.clone();
     let ui_handler = handler.ui_handler.clone();
-    let (video_sender, audio_sender, video_queue_map, decode_fps_map, chroma) =
+    let (video_sender, audio_sender, video_queue_map, decode_fps_map, chroma, media_sync) =
         start_video_audio_threads(
             handler.clone(),
             move |display: usize, data: &mut scrap::ImageRgb| {
@@ -1586,6 +1790,7 @@ pub async fn io_loop<T: InvokeUiSession>(handler: Session<T>, round: u32) {
         frame_count_map,
         decode_fps_map,
         chroma,
+        media_sync,
     );
     remote.io_loop(&key, &token, round).await;
     remote.sync_jobs_status_to_local().await;